@@ -0,0 +1,212 @@
+//! A small single-threaded executor for processes, so that kernel operations can be `.await`ed.
+//!
+//! Tasks are polled until none of them can make progress. Then, the executor
+//! does a `WaitAny` syscall on all the `KernelFuture`s that tasks are waiting on,
+//! and wakes the tasks that were waiting on the one that completed.
+
+use alloc::{
+    boxed::Box,
+    collections::{BTreeMap, VecDeque},
+    sync::Arc,
+    task::Wake,
+    vec::Vec,
+};
+use core::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll, Waker},
+};
+
+use spin::Mutex;
+
+use crate::{s_mode::*, KernelFuture, SyscallNumbers, MAX_WAIT_ANY_FUTURES};
+
+type FutureType = Pin<Box<dyn Future<Output = ()>>>;
+
+/// Futures that tasks are waiting on, and the wakers to call when they complete.
+/// It holds at most `MAX_WAIT_ANY_FUTURES` futures, since they're all waited on at once.
+static REACTOR: Mutex<BTreeMap<u64, Vec<Waker>>> = Mutex::new(BTreeMap::new());
+
+impl Future for KernelFuture {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        match KernelFuture::poll(&self) {
+            Ok(Poll::Pending) => {
+                let mut reactor = REACTOR.lock();
+                if !reactor.contains_key(&self.id) && reactor.len() >= MAX_WAIT_ANY_FUTURES {
+                    // There's no room to wait on this one, so the task polls it again
+                    // instead, after the others have had a turn
+                    cx.waker().wake_by_ref();
+                    return Poll::Pending;
+                }
+                do_supervisor_syscall_1(SyscallNumbers::EnableFuture as usize, self.id as usize);
+                let wakers = reactor.entry(self.id).or_default();
+                if !wakers.iter().any(|waker| waker.will_wake(cx.waker())) {
+                    wakers.push(cx.waker().clone());
+                }
+                Poll::Pending
+            }
+            // If the kernel doesn't know about this future, there is nothing to wait for
+            Ok(Poll::Ready(())) | Err(()) => Poll::Ready(()),
+        }
+    }
+}
+
+/// Sleeps until one of the futures registered by pending `KernelFuture`s is complete,
+/// and wakes the tasks that were waiting on it. Returns false if there was nothing to wait for.
+fn wait_for_reactor() -> bool {
+    let ids: Vec<u64> = REACTOR.lock().keys().copied().collect();
+    if ids.is_empty() {
+        return false;
+    }
+    let futures: Vec<KernelFuture> = ids.iter().map(|id| KernelFuture { id: *id }).collect();
+    let futures: Vec<&KernelFuture> = futures.iter().collect();
+    let index = KernelFuture::wait_any(&futures);
+
+    let wakers = REACTOR.lock().remove(&ids[index]).unwrap_or_default();
+    for waker in wakers {
+        waker.wake();
+    }
+    true
+}
+
+/// Forgets the futures that only `waker`'s task was waiting on, once the task is gone
+fn remove_from_reactor(waker: &Waker) {
+    REACTOR.lock().retain(|_, wakers| {
+        wakers.retain(|other| !other.will_wake(waker));
+        !wakers.is_empty()
+    });
+}
+
+/// A task's slot in `Executor::tasks`, and which of the tasks that have had the slot it is.
+/// Wakers of a task that's gone don't wake the one that took its slot.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+struct TaskId {
+    index: usize,
+    generation: u64,
+}
+
+struct TaskWaker {
+    queue: Arc<Mutex<VecDeque<TaskId>>>,
+    id: TaskId,
+}
+
+impl Wake for TaskWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref()
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.queue.lock().push_back(self.id);
+    }
+}
+
+struct Task {
+    future: FutureType,
+    generation: u64,
+    /// Made once, so that the reactor can tell the task's wakers apart from others'
+    waker: Waker,
+}
+
+#[derive(Default)]
+pub struct Executor {
+    tasks: Vec<Option<Task>>,
+    wake_queue: Arc<Mutex<VecDeque<TaskId>>>,
+    next_generation: u64,
+}
+
+impl Executor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn spawn(&mut self, future: impl Future<Output = ()> + 'static) {
+        let index = self
+            .tasks
+            .iter()
+            .position(|task| task.is_none())
+            .unwrap_or_else(|| {
+                self.tasks.push(None);
+                self.tasks.len() - 1
+            });
+        let id = TaskId {
+            index,
+            generation: self.next_generation,
+        };
+        self.next_generation += 1;
+        let waker = Arc::new(TaskWaker {
+            queue: self.wake_queue.clone(),
+            id,
+        })
+        .into();
+        self.tasks[index] = Some(Task {
+            future: Box::pin(future),
+            generation: id.generation,
+            waker,
+        });
+        self.wake_queue.lock().push_back(id);
+    }
+
+    fn poll_task(&mut self, id: TaskId) {
+        let slot = match self.tasks.get_mut(id.index) {
+            Some(slot) => slot,
+            None => return,
+        };
+        let task = match slot {
+            Some(task) if task.generation == id.generation => task,
+            // The task is complete, and the slot may have a new one
+            _ => return,
+        };
+        let mut cx = Context::from_waker(&task.waker);
+        if task.future.as_mut().poll(&mut cx).is_ready() {
+            let task = slot.take().unwrap();
+            remove_from_reactor(&task.waker);
+        }
+    }
+
+    fn is_done(&self) -> bool {
+        !self.tasks.iter().any(|task| task.is_some())
+    }
+
+    /// Runs tasks until all of them are complete.
+    /// Panics if the tasks that are left can't make progress, because none of them was woken
+    /// and none is waiting on a `KernelFuture`.
+    pub fn run(&mut self) {
+        loop {
+            let woken: Vec<TaskId> = self.wake_queue.lock().drain(..).collect();
+            for id in woken.iter() {
+                self.poll_task(*id);
+            }
+            if self.is_done() {
+                return;
+            }
+            if woken.is_empty() && !wait_for_reactor() {
+                // Sleeping here would never return, since nothing in the kernel would wake us
+                panic!("executor deadlock: no task is woken or waiting on a KernelFuture");
+            }
+        }
+    }
+}
+
+impl Drop for Executor {
+    fn drop(&mut self) {
+        for task in self.tasks.iter().flatten() {
+            remove_from_reactor(&task.waker);
+        }
+    }
+}
+
+/// Runs a future to completion in a new executor, and returns its output
+pub fn block_on<T: 'static>(future: impl Future<Output = T> + 'static) -> T {
+    let output = Arc::new(Mutex::new(None));
+    let task_output = output.clone();
+    let mut executor = Executor::new();
+    executor.spawn(async move {
+        let value = future.await;
+        task_output.lock().replace(value);
+    });
+    executor.run();
+    let value = output.lock().take();
+    value.unwrap()
+}
@@ -1,14 +1,21 @@
 #![no_std]
+#![feature(const_btree_new)]
 
+pub mod executor;
 pub mod s_mode;
 
 extern crate alloc;
 
 use core::mem::MaybeUninit;
+use core::time::Duration;
 
-use alloc::boxed::Box;
+use alloc::{boxed::Box, vec::Vec};
 use s_mode::*;
 
+/// The most futures `KernelFuture::wait_any` can wait on at once
+pub use kernel_syscall::MAX_WAIT_ANY_FUTURES;
+pub use kernel_syscall::KERNEL_SENDER;
+
 #[derive(Clone, Debug)]
 pub struct KernelFuture {
     id: u64,
@@ -21,13 +28,25 @@ pub enum SyscallNumbers {
     EnableFuture = 2,
     Sleep = 3,
     PollFuture = 4,
+    WaitAny = 5,
     WaitForInterrupt = 10,
     MoveBufferOut = 0x10,
     BorrowBufferOut = 0x11,
     BorrowMutBufferOut = 0x12,
     CopyBufferOut = 0x13,
+    AllocateQueue = 0x14,
+    FreeQueue = 0x15,
     MapBufferIn = 0x20,
     CopyBufferIn = 0x21,
+    CreateSharedRegion = 0x30,
+    MapSharedRegion = 0x31,
+    CloseSharedRegion = 0x32,
+    SendSharedRegion = 0x33,
+    ReceiveSharedRegion = 0x34,
+    GetRandom = 0x40,
+    ClockGetTime = 0x50,
+    PowerOff = 0x60,
+    Reboot = 0x61,
 }
 
 impl KernelFuture {
@@ -45,6 +64,61 @@ impl KernelFuture {
             do_supervisor_syscall_0(SyscallNumbers::Sleep as usize);
         }
     }
+    /// Sleeps until any of `futures` is complete, and returns its index in the slice.
+    /// At most `MAX_WAIT_ANY_FUTURES` futures can be waited on at once.
+    /// See also the `select!` macro.
+    pub fn wait_any(futures: &[&KernelFuture]) -> usize {
+        assert!(!futures.is_empty());
+        assert!(futures.len() <= MAX_WAIT_ANY_FUTURES);
+        let ids: Vec<u64> = futures.iter().map(|future| future.id).collect();
+        loop {
+            // The kernel returns `ids.len()` if it had to put us to sleep.
+            let ret = do_supervisor_syscall_2(SyscallNumbers::WaitAny as usize, ids.as_ptr() as usize, ids.len());
+            if ret.0 < ids.len() {
+                return ret.0;
+            }
+        }
+    }
+    /// Returns a future that will be complete when external interrupt `interrupt` fires
+    pub fn wait_for_interrupt(interrupt: usize) -> KernelFuture {
+        let ret = do_supervisor_syscall_2(SyscallNumbers::WaitForInterrupt as usize, interrupt, 0);
+        KernelFuture { id: ret.0 as u64 }
+    }
+    /// Makes a future from `wait_for_interrupt` not complete again, until `interrupt` fires
+    /// the next time. Unlike getting a new future, this doesn't use up another future ID.
+    pub fn rearm_for_interrupt(&self, interrupt: usize) {
+        do_supervisor_syscall_3(SyscallNumbers::WaitForInterrupt as usize, interrupt, 0, self.id as usize);
+    }
+}
+
+/// Waits until one of several `KernelFuture`s is complete, and runs the expression for it.
+/// ```ignore
+/// let interrupt = KernelFuture::wait_for_interrupt(10);
+/// let buffer = queue.copy_claim_buffer(&mut data).unwrap_err();
+/// kernel_api::select! {
+///     interrupt => println!("interrupt!"),
+///     buffer => println!("got a buffer!"),
+/// }
+/// ```
+#[macro_export]
+macro_rules! select {
+    (@arm $index:ident; $body:expr $(, $rest:expr)*) => {
+        if $index == 0 {
+            $body
+        } else {
+            $index -= 1;
+            $crate::select!(@arm $index; $($rest),*)
+        }
+    };
+    (@arm $index:ident;) => {
+        unreachable!()
+    };
+    ($($future:expr => $body:expr),+ $(,)?) => {{
+        let futures = [$(&$future as &$crate::KernelFuture),+];
+        #[allow(unused_mut)]
+        let mut index = $crate::KernelFuture::wait_any(&futures);
+        $crate::select!(@arm index; $($body),+)
+    }};
 }
 
 pub struct BufferQueue {
@@ -57,6 +131,29 @@ impl BufferQueue {
             id
         }
     }
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+    /// Gets a queue that nobody else is using. Only this process can claim buffers and
+    /// receive shared regions from it. Returns `None` if the process has too many queues.
+    pub fn allocate() -> Option<BufferQueue> {
+        let ret = do_supervisor_syscall_0(SyscallNumbers::AllocateQueue as usize);
+        if ret.0 == 0 {
+            None
+        } else {
+            Some(Self::new(ret.0 as u64))
+        }
+    }
+    /// Lets the queue be allocated again. Returns false if it wasn't allocated by this process.
+    pub fn free(self) -> bool {
+        let ret = do_supervisor_syscall_1(SyscallNumbers::FreeQueue as usize, self.id as usize);
+        ret.0 == 1
+    }
+    /// Copies `data` into the queue. The future completes when someone claims it.
+    pub fn copy_out_buffer(&self, data: &[u8]) -> KernelFuture {
+        let ret = do_supervisor_syscall_3(SyscallNumbers::CopyBufferOut as usize, data.as_ptr() as usize, data.len(), self.id as usize);
+        KernelFuture { id: ret.0 as u64 }
+    }
     pub fn move_out_buffer(&self, buffer: Box<[MaybeUninit<u8>]>) -> KernelFuture{
         assert!(buffer.as_ptr() as usize & 0xFFF == 0);
         assert!(buffer.len() & 0xFFF == 0);
@@ -76,15 +173,142 @@ impl BufferQueue {
         }
     }
     pub fn copy_claim_buffer(&self, destination: &mut [MaybeUninit<u8>]) -> Result<(usize, usize), KernelFuture> {
+        self.copy_claim_buffer_with_sender(destination)
+            .map(|(real_size, remaining_buffers, _)| (real_size, remaining_buffers))
+    }
+    /// Like `copy_claim_buffer`, but also returns the ID of the process that sent the buffer,
+    /// or `KERNEL_SENDER`. The kernel fills it in, so it can't be made up.
+    pub fn copy_claim_buffer_with_sender(&self, destination: &mut [MaybeUninit<u8>]) -> Result<(usize, usize, u64), KernelFuture> {
         let ret = do_supervisor_syscall_3(SyscallNumbers::CopyBufferIn as usize, destination.as_ptr() as usize, destination.len(), self.id as usize);
         let status = ret.0;
         let real_size = ret.1;
         let remaining_buffers = ret.2;
         let future_id = ret.3;
+        let sender = ret.4;
         if status == 0 {
             return Err(KernelFuture { id: future_id as u64 })
         } else {
-            return Ok((real_size, remaining_buffers))
+            return Ok((real_size, remaining_buffers, sender as u64))
+        }
+    }
+    /// Like `share_claim_buffer`, but waits until there's a buffer in the queue
+    pub async fn share_claim_buffer_async(&self, destination: &mut [MaybeUninit<u8>]) -> (usize, usize) {
+        loop {
+            match self.share_claim_buffer(destination) {
+                Ok(claimed) => return claimed,
+                Err(future) => future.await,
+            }
         }
     }
+    /// Like `copy_claim_buffer`, but waits until there's a buffer in the queue
+    pub async fn copy_claim_buffer_async(&self, destination: &mut [MaybeUninit<u8>]) -> (usize, usize) {
+        loop {
+            match self.copy_claim_buffer(destination) {
+                Ok(claimed) => return claimed,
+                Err(future) => future.await,
+            }
+        }
+    }
+}
+
+/// Memory that can be mapped into several processes at the same time.
+/// Handles only mean something to the process that has them. To share a region, `send` it to
+/// a queue and have the other process `receive` it from there.
+pub struct SharedRegion {
+    handle: usize,
+}
+
+impl SharedRegion {
+    pub const READ: usize = 1 << 1;
+    pub const WRITE: usize = 1 << 2;
+    pub const EXECUTE: usize = 1 << 3;
+
+    /// Creates a zeroed region of at least `size` bytes.
+    /// Returns `None` if `size` is 0 or too large.
+    pub fn new(size: usize) -> Option<SharedRegion> {
+        let ret = do_supervisor_syscall_1(SyscallNumbers::CreateSharedRegion as usize, size);
+        if ret.0 == 0 {
+            None
+        } else {
+            Some(Self { handle: ret.0 })
+        }
+    }
+    /// Takes the region that was sent to `queue` first. Returns `None` if nothing was sent,
+    /// if the queue belongs to another process or if this process already has too much
+    /// shared memory.
+    pub fn receive(queue: &BufferQueue) -> Option<SharedRegion> {
+        let ret = do_supervisor_syscall_1(SyscallNumbers::ReceiveSharedRegion as usize, queue.id() as usize);
+        if ret.0 == 0 {
+            None
+        } else {
+            Some(Self { handle: ret.0 })
+        }
+    }
+    /// Lets the next process that calls `receive` on `queue` have the region too. The region
+    /// counts towards this process's shared memory until then. Returns false if too many
+    /// regions are waiting in the queue already.
+    pub fn send(&self, queue: &BufferQueue) -> bool {
+        let ret = do_supervisor_syscall_2(SyscallNumbers::SendSharedRegion as usize, self.handle, queue.id() as usize);
+        ret.0 == 1
+    }
+    pub fn handle(&self) -> usize {
+        self.handle
+    }
+    /// Maps the region at `address`, which must be page-aligned and not mapped yet.
+    /// `permissions` is a combination of `READ`, `WRITE` and `EXECUTE`, and must include `READ`.
+    /// Returns false if the region doesn't exist or the arguments are invalid.
+    pub fn map(&self, address: *mut u8, permissions: usize) -> bool {
+        let ret = do_supervisor_syscall_3(SyscallNumbers::MapSharedRegion as usize, self.handle, address as usize, permissions);
+        ret.0 == 1
+    }
+}
+
+impl Drop for SharedRegion {
+    // Closes the handle. Mappings of the region stay valid.
+    fn drop(&mut self) {
+        do_supervisor_syscall_1(SyscallNumbers::CloseSharedRegion as usize, self.handle);
+    }
+}
+
+/// Fills `buffer` with random bytes from the kernel's generator. Returns false without
+/// filling it if the generator hasn't got enough entropy yet.
+pub fn get_random(buffer: &mut [u8]) -> bool {
+    let mut filled = 0;
+    // The kernel fills a limited amount at a time
+    while filled < buffer.len() {
+        let rest = &mut buffer[filled..];
+        let ret = do_supervisor_syscall_2(SyscallNumbers::GetRandom as usize, rest.as_mut_ptr() as usize, rest.len());
+        if ret.0 == 0 {
+            // It doesn't become unseeded, so this only happens on the first call
+            return false;
+        }
+        filled += ret.0;
+    }
+    true
+}
+
+/// The clocks that `clock_get_time` can read
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(usize)]
+pub enum Clock {
+    /// The wall-clock time, since the Unix epoch
+    Realtime = 0,
+    /// The time since boot, which never goes backwards
+    Monotonic = 1,
+}
+
+pub fn clock_get_time(clock: Clock) -> Duration {
+    let ret = do_supervisor_syscall_1(SyscallNumbers::ClockGetTime as usize, clock as usize);
+    Duration::new(ret.0 as u64, ret.1 as u32)
+}
+
+/// Powers off the machine. A nonzero `status` says that something failed.
+/// Only returns if the process isn't allowed to.
+pub fn power_off(status: u16) {
+    do_supervisor_syscall_1(SyscallNumbers::PowerOff as usize, status as usize);
+}
+
+/// Reboots the machine. Only returns if the process isn't allowed to.
+pub fn reboot(warm: bool) {
+    do_supervisor_syscall_1(SyscallNumbers::Reboot as usize, warm as usize);
 }
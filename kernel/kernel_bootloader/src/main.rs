@@ -10,6 +10,7 @@ use core::{
 
 use kernel_cpu::{fence_vma, write_satp, write_stvec};
 use kernel_paging::Paging;
+use kernel_util::debug::Uart;
 core::arch::global_asm!(include_str!("../boot.S"));
 
 // Linker symbols
@@ -45,23 +46,8 @@ unsafe fn trap_handler() {
     loop {}
 }
 
-pub struct Uart {
-    address: *mut u8,
-}
-
-impl core::fmt::Write for Uart {
-    fn write_str(&mut self, s: &str) -> core::fmt::Result {
-        for i in s.bytes() {
-            unsafe { self.address.write(i) }
-        }
-        Ok(())
-    }
-}
-
 pub fn get_uart() -> Uart {
-    Uart {
-        address: 0x1000_0000 as _,
-    }
+    Uart::from_address(0x1000_0000 as _)
 }
 
 #[macro_export]
@@ -155,6 +141,16 @@ pub extern "C" fn pre_main(hartid: usize, opaque: usize) {
     println!("Bootloader allocator spans from {:x} to {:x}", start, end);
 
     kernel_allocator::init_from_pointers(start as *const _, end as *const _);
+
+    let (initrd_start, initrd_len) = find_initrd(opaque);
+    if initrd_len != 0 {
+        println!(
+            "Initrd spans from {:x} to {:x}",
+            initrd_start,
+            initrd_start + initrd_len
+        );
+    }
+
     let padded_len = ((ALIGNED_BYTES.len()) / 4096 + 1) * 4096;
 
     // Change the page table mapping
@@ -173,7 +169,7 @@ pub extern "C" fn pre_main(hartid: usize, opaque: usize) {
     unsafe {
         let main = core::mem::transmute::<
             usize,
-            extern "C" fn(usize, usize, usize, usize, usize, usize),
+            extern "C" fn(usize, usize, usize, usize, usize, usize, usize, usize),
         >(0xffffffff80000000);
         main(
             hartid,
@@ -182,6 +178,8 @@ pub extern "C" fn pre_main(hartid: usize, opaque: usize) {
             padded_len,
             &_stack_start as *const _ as usize + gap,
             hart_entry_point as usize,
+            initrd_start,
+            initrd_len,
         );
     }
 }
@@ -191,7 +189,7 @@ pub extern "C" fn hart_entry(hartid: usize) {
     unsafe {
         let main = core::mem::transmute::<
             usize,
-            extern "C" fn(usize, usize, usize, usize, usize, usize),
+            extern "C" fn(usize, usize, usize, usize, usize, usize, usize, usize),
         >(0xffffffff80000000);
         main(
             hartid,
@@ -200,6 +198,8 @@ pub extern "C" fn hart_entry(hartid: usize) {
             0,
             kernel_cpu::read_sp() + 0xffffffc000000000,
             hart_entry_point as usize,
+            0,
+            0,
         );
     }
 }
@@ -224,6 +224,35 @@ static ALIGNED: &AlignedTo<Align4096, [u8]> = &AlignedTo {
 
 static ALIGNED_BYTES: &[u8] = &ALIGNED.bytes;
 
+#[cfg(feature = "embedded_initrd")]
+static ALIGNED_INITRD: &AlignedTo<Align4096, [u8]> = &AlignedTo {
+    _align: [],
+    bytes: *include_bytes!("../../../initrd.img"),
+};
+
+#[cfg(feature = "embedded_initrd")]
+static EMBEDDED_INITRD: &[u8] = &ALIGNED_INITRD.bytes;
+#[cfg(not(feature = "embedded_initrd"))]
+static EMBEDDED_INITRD: &[u8] = &[];
+
+/// Returns the physical address and length of the initrd, or zeroes if there's none.
+/// One passed with QEMU's `-initrd` (in `/chosen` in the device tree) takes priority
+/// over the embedded one.
+fn find_initrd(opaque: usize) -> (usize, usize) {
+    let passed = unsafe { kernel_fdt::Fdt::from_ptr(opaque as *const u8) }
+        .ok()
+        .and_then(|fdt| fdt.initrd())
+        .map(|(start, end)| (start as usize, (end - start) as usize));
+    match passed {
+        Some(initrd) => initrd,
+        // We're identity mapped, so this is also the physical address
+        None if !EMBEDDED_INITRD.is_empty() => {
+            (EMBEDDED_INITRD.as_ptr() as usize, EMBEDDED_INITRD.len())
+        }
+        None => (0, 0),
+    }
+}
+
 #[panic_handler]
 fn panic(info: &core::panic::PanicInfo) -> ! {
     println!("{:?}", "Bootloader Panic");
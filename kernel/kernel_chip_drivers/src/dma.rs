@@ -0,0 +1,50 @@
+// Memory that devices read and write directly.
+// The kernel heap is mapped linearly, so an allocation is physically contiguous and its
+// physical address can be found with `virt_to_phys`.
+// A buffer must outlive every request that uses it. Virtio requests take their buffers, so
+// that dropping a request that's in progress doesn't free memory the device is writing to.
+
+use alloc::boxed::Box;
+use core::ops::{Deref, DerefMut};
+
+use kernel_util::boxed_slice_with_alignment;
+
+pub struct DmaBuffer {
+    data: Box<[u8]>,
+    virt_to_phys: fn(usize) -> usize,
+}
+
+impl DmaBuffer {
+    /// Allocates a zeroed, page-aligned buffer
+    pub fn new(size: usize, virt_to_phys: fn(usize) -> usize) -> Self {
+        Self::with_alignment(size, 4096, virt_to_phys)
+    }
+
+    /// Allocates a zeroed buffer. Small buffers with an alignment of at least their size
+    /// never cross a page boundary.
+    pub fn with_alignment(size: usize, align: usize, virt_to_phys: fn(usize) -> usize) -> Self {
+        Self {
+            data: boxed_slice_with_alignment(size, align, &0),
+            virt_to_phys,
+        }
+    }
+
+    /// Physical address of byte `offset` of the buffer
+    pub fn physical_address(&self, offset: usize) -> usize {
+        (self.virt_to_phys)(self.data.as_ptr() as usize + offset)
+    }
+}
+
+impl Deref for DmaBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+impl DerefMut for DmaBuffer {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        &mut self.data
+    }
+}
@@ -0,0 +1,180 @@
+// Matches devices in the device tree with the drivers that support them.
+// Drivers list the `compatible` strings they support, and get probed with the registers and
+// interrupts of every matching node already worked out, so that they don't need to walk the
+// device tree themselves.
+// See https://github.com/devicetree-org/devicetree-specification/releases/tag/v0.3, chapter 2
+// PCI functions have no nodes, so PCI drivers are matched by vendor and device ID instead,
+// once the host bridge's driver has enumerated its buses.
+
+use alloc::{format, string::String, vec::Vec};
+
+pub use kernel_fdt::Interrupt;
+use kernel_fdt::{AddressSpace, Fdt, Node};
+use kernel_pci::{DeviceId, Function};
+
+/// A range of registers, in the CPU's physical address space
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Region {
+    pub address: usize,
+    pub size: usize,
+}
+
+/// A device tree node that a driver is asked to probe
+pub struct Device<'a> {
+    pub path: String,
+    pub node: Node<'a>,
+    /// The `compatible` string the driver was matched with
+    pub compatible: &'a str,
+    /// `reg`, translated through the `ranges` of the buses above the node.
+    /// Registers that aren't visible to the CPU are left out.
+    pub regions: Vec<Region>,
+    /// From `interrupts-extended`, or `interrupts` and the (possibly inherited) `interrupt-parent`
+    pub interrupts: Vec<Interrupt<'a>>,
+}
+
+impl<'a> Device<'a> {
+    pub fn region(&self, index: usize) -> Result<Region, ProbeError> {
+        self.regions
+            .get(index)
+            .copied()
+            .ok_or(ProbeError::MissingResource)
+    }
+
+    /// The interrupt number of the `index`th interrupt
+    pub fn interrupt(&self, index: usize) -> Result<u32, ProbeError> {
+        self.interrupts
+            .get(index)
+            .and_then(Interrupt::number)
+            .ok_or(ProbeError::MissingResource)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProbeError {
+    /// The node doesn't have the registers or interrupts the driver needs
+    MissingResource,
+    /// There's nothing behind the node, like an empty virtio-mmio slot. This isn't reported.
+    NoDevice,
+    /// The device is there, but couldn't be initialized
+    Failed,
+}
+
+pub struct Driver {
+    pub name: &'static str,
+    pub compatible: &'static [&'static str],
+    pub probe: fn(&Device) -> Result<(), ProbeError>,
+}
+
+pub struct PciDriver {
+    pub name: &'static str,
+    pub ids: &'static [DeviceId],
+    pub probe: fn(&Function<'static>) -> Result<(), ProbeError>,
+}
+
+/// Collects the enabled nodes with a `compatible`, in tree order
+struct Walker<'a> {
+    devices: Vec<(Node<'a>, String, Vec<Region>)>,
+}
+
+impl<'a> Walker<'a> {
+    /// `space` is the address space of `node`'s `reg`, or `None` if it can't be translated
+    /// to the CPU's
+    fn walk(&mut self, node: Node<'a>, path: String, space: Option<&AddressSpace>) {
+        if !node.is_enabled() {
+            return;
+        }
+        let mut regions = Vec::new();
+        if let (Some(reg), Some(space)) = (node.reg(), space) {
+            for entry in reg {
+                if let Some(address) = space.translate(entry.address) {
+                    regions.push(Region {
+                        address: address as usize,
+                        size: entry.size as usize,
+                    });
+                }
+            }
+        }
+        if node.compatible().is_some() {
+            self.devices.push((node, path.clone(), regions));
+        }
+
+        // Without `ranges`, the children's addresses don't mean anything to the CPU
+        let child_space = space.and_then(|space| space.children(&node));
+        for child in node.children() {
+            let child_path = format!("{}/{}", path, child.name());
+            self.walk(child, child_path, child_space.as_ref());
+        }
+    }
+}
+
+/// Probes every enabled node in the device tree that one of `drivers` supports.
+/// Interrupt controllers are probed before everything else. A node's `compatible` strings
+/// are tried in order, so the most specific driver wins.
+pub fn probe_all(fdt: &Fdt, drivers: &[&Driver]) {
+    let mut walker = Walker {
+        devices: Vec::new(),
+    };
+    let root_space = AddressSpace::root();
+    for child in fdt.root().children() {
+        walker.walk(child, format!("/{}", child.name()), Some(&root_space));
+    }
+
+    let mut devices = walker.devices;
+    // Stable, so the tree order is kept otherwise
+    devices.sort_by_key(|(node, ..)| !node.is_interrupt_controller());
+
+    for (node, path, regions) in devices {
+        let matched = node.compatible().and_then(|mut compatible| {
+            compatible.find_map(|compatible| {
+                drivers
+                    .iter()
+                    .find(|driver| driver.compatible.contains(&compatible))
+                    .map(|driver| (compatible, driver))
+            })
+        });
+        let (compatible, driver) = match matched {
+            Some(matched) => matched,
+            None => continue,
+        };
+        let device = Device {
+            path,
+            node,
+            compatible,
+            regions,
+            interrupts: node.interrupts().into_iter().flatten().collect(),
+        };
+        match (driver.probe)(&device) {
+            Ok(()) => log::info!("{}: probed {}", driver.name, device.path),
+            Err(ProbeError::NoDevice) => {}
+            Err(error) => log::warn!(
+                "{}: failed to probe {}: {:?}",
+                driver.name,
+                device.path,
+                error
+            ),
+        }
+    }
+}
+
+/// Probes each of `functions` with the first driver that lists its ID
+pub fn probe_pci(functions: &[Function<'static>], drivers: &[&PciDriver]) {
+    for function in functions {
+        let driver = match drivers
+            .iter()
+            .find(|driver| driver.ids.contains(&function.id))
+        {
+            Some(driver) => driver,
+            None => continue,
+        };
+        match (driver.probe)(function) {
+            Ok(()) => log::info!("{}: probed pci {}", driver.name, function.address),
+            Err(ProbeError::NoDevice) => {}
+            Err(error) => log::warn!(
+                "{}: failed to probe pci {}: {:?}",
+                driver.name,
+                function.address,
+                error
+            ),
+        }
+    }
+}
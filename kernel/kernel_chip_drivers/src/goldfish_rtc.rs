@@ -0,0 +1,66 @@
+// Goldfish real-time clock, as found on QEMU's virt machine ("google,goldfish-rtc").
+// It counts nanoseconds since the Unix epoch in a 64-bit register, read as two halves, and
+// can raise its interrupt when the count reaches an alarm.
+// Reading the low half latches the high half, so the low half has to be read first. Writing
+// the low half of the alarm arms it, so the high half has to be written first.
+// See https://android.googlesource.com/platform/external/qemu/+/master/docs/GOLDFISH-VIRTUAL-HARDWARE.TXT
+
+const TIME_LOW: usize = 0x00;
+const TIME_HIGH: usize = 0x04;
+const ALARM_LOW: usize = 0x08;
+const ALARM_HIGH: usize = 0x0c;
+const IRQ_ENABLED: usize = 0x10;
+const CLEAR_ALARM: usize = 0x14;
+const ALARM_STATUS: usize = 0x18;
+const CLEAR_INTERRUPT: usize = 0x1c;
+
+pub struct GoldfishRtc {
+    base_addr: usize,
+}
+
+impl GoldfishRtc {
+    /// # Safety
+    /// `base_addr` has to be where the RTC's registers are mapped
+    pub unsafe fn new(base_addr: usize) -> Self {
+        Self { base_addr }
+    }
+
+    fn register(&self, offset: usize) -> *mut u32 {
+        (self.base_addr + offset) as *mut u32
+    }
+
+    fn read(&self, offset: usize) -> u32 {
+        unsafe { self.register(offset).read_volatile() }
+    }
+
+    fn write(&self, offset: usize, value: u32) {
+        unsafe { self.register(offset).write_volatile(value) }
+    }
+
+    /// Nanoseconds since the Unix epoch
+    pub fn read_nanos(&self) -> u64 {
+        let low = self.read(TIME_LOW);
+        let high = self.read(TIME_HIGH);
+        (high as u64) << 32 | low as u64
+    }
+
+    /// Raises the interrupt once `read_nanos()` reaches `nanos`, or right away if it already
+    /// has. There's only one alarm, so this replaces the previous one.
+    pub fn set_alarm(&self, nanos: u64) {
+        self.write(ALARM_HIGH, (nanos >> 32) as u32);
+        self.write(ALARM_LOW, nanos as u32);
+        self.write(IRQ_ENABLED, 1);
+    }
+
+    pub fn clear_alarm(&self) {
+        self.write(IRQ_ENABLED, 0);
+        if self.read(ALARM_STATUS) != 0 {
+            self.write(CLEAR_ALARM, 1);
+        }
+    }
+
+    /// Acknowledges the interrupt. The alarm doesn't fire again until it's set again.
+    pub fn handle_interrupt(&self) {
+        self.write(CLEAR_INTERRUPT, 1);
+    }
+}
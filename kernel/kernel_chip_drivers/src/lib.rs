@@ -1,5 +1,14 @@
 #![no_std]
+#![feature(int_log, int_roundings)]
 
 extern crate alloc;
-pub mod fdt;
+pub mod dma;
+pub mod driver;
+pub mod goldfish_rtc;
+pub mod ne2000;
+pub mod net;
+pub mod ns16550a;
 pub mod plic;
+pub mod syscon;
+pub mod text_console;
+pub mod virtio;
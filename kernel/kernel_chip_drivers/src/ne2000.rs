@@ -0,0 +1,478 @@
+// Driver for NE2000-compatible network cards built around the DP8390, like the RTL8029
+// that QEMU's "ne2k_pci" emulates.
+// The card has its own 16KiB of memory, which holds one transmit buffer and a ring of
+// 256-byte pages that received frames are written to. The CPU copies frames in and out of it
+// with "remote DMA" through the data port, one byte at a time.
+// See https://www.ti.com/lit/ds/symlink/dp8390d.pdf and
+// http://www.ethernut.de/pdf/8019as.pdf (RTL8019AS, the ISA version of the RTL8029)
+
+use alloc::{boxed::Box, collections::VecDeque, sync::Arc, vec::Vec};
+use core::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll, Waker},
+};
+
+use async_trait::async_trait;
+use kernel_io::NetDevice;
+use kernel_lock::shared::Mutex;
+
+use crate::net::{
+    NetError, ETHERNET_HEADER_SIZE, ETHERNET_MTU, MAX_FRAME_SIZE, MIN_FRAME_SIZE, RX_QUEUE_SIZE,
+};
+
+// Register offsets, on page 0 unless noted
+const CR: usize = 0x00;
+const PSTART: usize = 0x01;
+const PSTOP: usize = 0x02;
+const BNRY: usize = 0x03;
+const TPSR: usize = 0x04;
+const TBCR0: usize = 0x05;
+const TBCR1: usize = 0x06;
+const ISR: usize = 0x07;
+const RSAR0: usize = 0x08;
+const RSAR1: usize = 0x09;
+const RBCR0: usize = 0x0a;
+const RBCR1: usize = 0x0b;
+const RCR: usize = 0x0c;
+const TCR: usize = 0x0d;
+const DCR: usize = 0x0e;
+const IMR: usize = 0x0f;
+/// Page 1: the station address, 6 registers
+const PAR0: usize = 0x01;
+/// Page 1: the page the card receives the next frame into
+const CURR: usize = 0x07;
+/// Page 1: the multicast filter, 8 registers
+const MAR0: usize = 0x08;
+/// Remote DMA reads and writes card memory through this
+const DATA: usize = 0x10;
+/// Reading this resets the card
+const RESET: usize = 0x1f;
+
+// Command register bits
+const CR_STOP: u8 = 1 << 0;
+const CR_START: u8 = 1 << 1;
+const CR_TRANSMIT: u8 = 1 << 2;
+const CR_DMA_READ: u8 = 1 << 3;
+const CR_DMA_WRITE: u8 = 1 << 4;
+const CR_DMA_ABORT: u8 = 1 << 5;
+const CR_PAGE0: u8 = 0;
+const CR_PAGE1: u8 = 1 << 6;
+
+// Interrupt status and mask register bits
+const ISR_RX: u8 = 1 << 0;
+const ISR_TX: u8 = 1 << 1;
+const ISR_RX_ERROR: u8 = 1 << 2;
+const ISR_TX_ERROR: u8 = 1 << 3;
+const ISR_OVERWRITE: u8 = 1 << 4;
+const ISR_DMA_DONE: u8 = 1 << 6;
+const ISR_RESET: u8 = 1 << 7;
+const INTERRUPTS: u8 = ISR_RX | ISR_TX | ISR_RX_ERROR | ISR_TX_ERROR | ISR_OVERWRITE;
+
+/// Byte-wide DMA, normal operation (not loopback), FIFO threshold of 8 bytes
+const DCR_CONFIG: u8 = 0x48;
+/// Accept broadcast frames
+const RCR_BROADCAST: u8 = 1 << 2;
+/// Accept multicast frames that pass the filter
+const RCR_MULTICAST: u8 = 1 << 3;
+/// Receive into the ring but don't keep anything, while the card is being set up
+const RCR_MONITOR: u8 = 1 << 5;
+const TCR_NORMAL: u8 = 0;
+const TCR_LOOPBACK: u8 = 1 << 1;
+/// Receive status: the frame was received intact
+const RSR_OK: u8 = 1 << 0;
+
+// Card memory layout, in 256-byte pages. The station address PROM is below them.
+const PAGE_SIZE: u16 = 256;
+/// Room for one frame of the largest size
+const TX_START: u8 = 0x40;
+const RX_START: u8 = 0x46;
+const RX_STOP: u8 = 0x80;
+/// Status, next page and length, in front of every received frame
+const RX_HEADER_SIZE: usize = 4;
+
+fn page_address(page: u8) -> u16 {
+    page as u16 * PAGE_SIZE
+}
+
+/// How long to wait for the card to reset or finish a remote DMA transfer
+const SPIN_LIMIT: usize = 100_000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Ne2000Error {
+    /// The card didn't come out of reset, so there's probably nothing there
+    NotResponding,
+}
+
+pub struct Ne2000 {
+    base_addr: usize,
+    mac_address: [u8; 6],
+    /// The page that the next frame will be read from
+    next_page: u8,
+    rx: VecDeque<Vec<u8>>,
+    /// Woken when there's a frame in `rx`
+    rx_wakers: Vec<Waker>,
+    /// Whether the transmit buffer holds a frame that's still being sent
+    transmitting: bool,
+    /// Woken when the transmit buffer is free
+    tx_wakers: Vec<Waker>,
+    /// Frames that were received while `rx` was full, or that the ring lost
+    pub dropped_frames: usize,
+    /// Frames that couldn't be sent, because of collisions or underruns
+    pub tx_errors: usize,
+}
+
+impl Ne2000 {
+    /// Resets the card whose registers are at `base_addr`, reads its MAC address, and starts
+    /// receiving. Interrupts are enabled on the card, and its interrupt should be routed to
+    /// [`Ne2000::handle_interrupt`].
+    pub fn new(base_addr: usize) -> Result<Self, Ne2000Error> {
+        let mut card = Self {
+            base_addr,
+            mac_address: [0; 6],
+            next_page: RX_START + 1,
+            rx: VecDeque::new(),
+            rx_wakers: Vec::new(),
+            transmitting: false,
+            tx_wakers: Vec::new(),
+            dropped_frames: 0,
+            tx_errors: 0,
+        };
+        card.reset()?;
+        card.init();
+        Ok(card)
+    }
+
+    fn read_register(&self, register: usize) -> u8 {
+        unsafe { (self.base_addr as *const u8).add(register).read_volatile() }
+    }
+
+    fn write_register(&self, register: usize, value: u8) {
+        unsafe {
+            (self.base_addr as *mut u8)
+                .add(register)
+                .write_volatile(value)
+        }
+    }
+
+    /// Spins until one of `bits` is set in the interrupt status register
+    fn wait_for(&self, bits: u8) -> Result<(), Ne2000Error> {
+        for _ in 0..SPIN_LIMIT {
+            if self.read_register(ISR) & bits != 0 {
+                return Ok(());
+            }
+        }
+        Err(Ne2000Error::NotResponding)
+    }
+
+    fn reset(&self) -> Result<(), Ne2000Error> {
+        let value = self.read_register(RESET);
+        self.write_register(RESET, value);
+        self.wait_for(ISR_RESET)?;
+        self.write_register(ISR, 0xff);
+        Ok(())
+    }
+
+    fn init(&mut self) {
+        self.write_register(CR, CR_PAGE0 | CR_STOP | CR_DMA_ABORT);
+        self.write_register(DCR, DCR_CONFIG);
+        self.write_register(RBCR0, 0);
+        self.write_register(RBCR1, 0);
+        self.write_register(RCR, RCR_MONITOR);
+        self.write_register(TCR, TCR_LOOPBACK);
+        self.write_register(TPSR, TX_START);
+        self.write_register(PSTART, RX_START);
+        self.write_register(BNRY, RX_START);
+        self.write_register(PSTOP, RX_STOP);
+        self.write_register(IMR, 0);
+        self.write_register(ISR, 0xff);
+
+        // Every byte of the PROM is there twice, since it's 16 bits wide
+        let mut prom = [0; 12];
+        self.read_memory(0, &mut prom);
+        for (byte, pair) in self.mac_address.iter_mut().zip(prom.chunks(2)) {
+            *byte = pair[0];
+        }
+
+        self.write_register(CR, CR_PAGE1 | CR_STOP | CR_DMA_ABORT);
+        for (i, byte) in self.mac_address.iter().enumerate() {
+            self.write_register(PAR0 + i, *byte);
+        }
+        // Multicast frames are filtered by the network stack
+        for i in 0..8 {
+            self.write_register(MAR0 + i, 0xff);
+        }
+        self.write_register(CURR, RX_START + 1);
+        self.next_page = RX_START + 1;
+
+        self.write_register(CR, CR_PAGE0 | CR_START | CR_DMA_ABORT);
+        self.write_register(ISR, 0xff);
+        self.write_register(IMR, INTERRUPTS);
+        self.write_register(TCR, TCR_NORMAL);
+        self.write_register(RCR, RCR_BROADCAST | RCR_MULTICAST);
+    }
+
+    pub fn mac_address(&self) -> [u8; 6] {
+        self.mac_address
+    }
+
+    /// Sets up a remote DMA transfer of `len` bytes at `address` in card memory.
+    /// Transfers wrap around from the end of the receive ring to its start.
+    fn start_dma(&self, address: u16, len: usize, command: u8) {
+        self.write_register(RBCR0, len as u8);
+        self.write_register(RBCR1, (len >> 8) as u8);
+        self.write_register(RSAR0, address as u8);
+        self.write_register(RSAR1, (address >> 8) as u8);
+        self.write_register(CR, CR_PAGE0 | CR_START | command);
+    }
+
+    fn finish_dma(&self) {
+        if self.wait_for(ISR_DMA_DONE).is_err() {
+            log::warn!("ne2000: remote DMA didn't finish");
+        }
+        self.write_register(ISR, ISR_DMA_DONE);
+    }
+
+    fn read_memory(&self, address: u16, buf: &mut [u8]) {
+        self.start_dma(address, buf.len(), CR_DMA_READ);
+        for byte in buf.iter_mut() {
+            *byte = self.read_register(DATA);
+        }
+        self.finish_dma();
+    }
+
+    fn write_memory(&self, address: u16, buf: &[u8]) {
+        self.start_dma(address, buf.len(), CR_DMA_WRITE);
+        for byte in buf {
+            self.write_register(DATA, *byte);
+        }
+        self.finish_dma();
+    }
+
+    fn current_page(&self) -> u8 {
+        self.write_register(CR, CR_PAGE1 | CR_START | CR_DMA_ABORT);
+        let current = self.read_register(CURR);
+        self.write_register(CR, CR_PAGE0 | CR_START | CR_DMA_ABORT);
+        current
+    }
+
+    /// Tells the card that pages up to `next` can be reused. The boundary is the page
+    /// before the next frame, since it can't be equal to the current page.
+    fn release_pages(&mut self, next: u8) {
+        self.next_page = next;
+        let boundary = if next == RX_START {
+            RX_STOP - 1
+        } else {
+            next - 1
+        };
+        self.write_register(BNRY, boundary);
+    }
+
+    /// Copies every frame in the receive ring into `rx`
+    fn receive_frames(&mut self) {
+        while self.next_page != self.current_page() {
+            let page = self.next_page;
+            let mut header = [0; RX_HEADER_SIZE];
+            self.read_memory(page_address(page), &mut header);
+            let status = header[0];
+            let next = header[1];
+            let len = u16::from_le_bytes([header[2], header[3]]) as usize;
+
+            // The length includes the header, and on real cards the 4-byte frame check
+            // sequence too. QEMU's ne2k_pci doesn't append one, and there's nothing that says
+            // which kind of card this is, so frames are passed on with whatever the card
+            // wrote. A trailing FCS is harmless: the protocols above go by the lengths in
+            // their own headers.
+            let lengths =
+                RX_HEADER_SIZE + ETHERNET_HEADER_SIZE..=RX_HEADER_SIZE + MAX_FRAME_SIZE + 4;
+            if !(RX_START..RX_STOP).contains(&next) || !lengths.contains(&len) {
+                // The ring can't be followed anymore, so skip everything that's in it
+                log::warn!(
+                    "ne2000: bad receive header {:x?} at page {:#x}",
+                    header,
+                    page
+                );
+                self.dropped_frames += 1;
+                let current = self.current_page();
+                self.release_pages(current);
+                break;
+            }
+
+            if status & RSR_OK != 0 && self.rx.len() < RX_QUEUE_SIZE {
+                let mut frame = alloc::vec![0; len - RX_HEADER_SIZE];
+                self.read_memory(page_address(page) + RX_HEADER_SIZE as u16, &mut frame);
+                self.rx.push_back(frame);
+            } else {
+                self.dropped_frames += 1;
+            }
+            self.release_pages(next);
+        }
+        if !self.rx.is_empty() {
+            self.rx_wakers.drain(..).for_each(Waker::wake);
+        }
+    }
+
+    /// The ring filled up and the card stopped receiving. It has to be stopped and emptied
+    /// before it can receive again (DP8390 datasheet, section 11.0).
+    fn recover_from_overwrite(&mut self, isr: u8) {
+        self.write_register(CR, CR_PAGE0 | CR_STOP | CR_DMA_ABORT);
+        self.write_register(RBCR0, 0);
+        self.write_register(RBCR1, 0);
+        if self.wait_for(ISR_RESET).is_err() {
+            log::warn!("ne2000: didn't stop after a receive overwrite");
+        }
+        // A frame that was being sent when the card stopped has to be sent again
+        let resend = self.transmitting && isr & (ISR_TX | ISR_TX_ERROR) == 0;
+        self.write_register(TCR, TCR_LOOPBACK);
+        self.write_register(CR, CR_PAGE0 | CR_START | CR_DMA_ABORT);
+        self.receive_frames();
+        self.write_register(ISR, ISR_OVERWRITE);
+        self.write_register(TCR, TCR_NORMAL);
+        if resend {
+            self.write_register(CR, CR_PAGE0 | CR_START | CR_TRANSMIT | CR_DMA_ABORT);
+        }
+    }
+
+    /// Should be called when the card's interrupt fires.
+    /// Moves received frames into the RX queue, and frees the transmit buffer once a frame
+    /// has been sent.
+    pub fn handle_interrupt(&mut self) {
+        let isr = self.read_register(ISR) & INTERRUPTS;
+        // Acknowledged first, so that frames that arrive while the ring is read aren't missed
+        self.write_register(ISR, isr & !ISR_OVERWRITE);
+        if isr & ISR_OVERWRITE != 0 {
+            self.recover_from_overwrite(isr);
+        } else if isr & (ISR_RX | ISR_RX_ERROR) != 0 {
+            self.receive_frames();
+        }
+        if isr & (ISR_TX | ISR_TX_ERROR) != 0 {
+            if isr & ISR_TX_ERROR != 0 {
+                self.tx_errors += 1;
+            }
+            self.transmitting = false;
+            self.tx_wakers.drain(..).for_each(Waker::wake);
+        }
+    }
+
+    /// Starts sending `frame` if the transmit buffer is free. Returns false if it isn't.
+    pub fn try_send(&mut self, frame: &[u8]) -> bool {
+        if self.transmitting {
+            return false;
+        }
+        let address = page_address(TX_START);
+        self.write_memory(address, frame);
+        // Short frames are padded with zeroes rather than whatever was sent before
+        if frame.len() < MIN_FRAME_SIZE {
+            let padding = [0; MIN_FRAME_SIZE];
+            self.write_memory(address + frame.len() as u16, &padding[frame.len()..]);
+        }
+        let len = frame.len().max(MIN_FRAME_SIZE);
+        self.write_register(TPSR, TX_START);
+        self.write_register(TBCR0, len as u8);
+        self.write_register(TBCR1, (len >> 8) as u8);
+        self.write_register(CR, CR_PAGE0 | CR_START | CR_TRANSMIT | CR_DMA_ABORT);
+        self.transmitting = true;
+        true
+    }
+
+    pub fn poll_send(&mut self, cx: &mut Context<'_>, frame: &[u8]) -> Poll<()> {
+        if self.try_send(frame) {
+            Poll::Ready(())
+        } else {
+            add_waker(&mut self.tx_wakers, cx.waker());
+            Poll::Pending
+        }
+    }
+
+    pub fn poll_receive(&mut self, cx: &mut Context<'_>) -> Poll<Vec<u8>> {
+        match self.rx.pop_front() {
+            Some(frame) => Poll::Ready(frame),
+            None => {
+                add_waker(&mut self.rx_wakers, cx.waker());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+/// Adds `waker` to `wakers`, unless it's already there
+fn add_waker(wakers: &mut Vec<Waker>, waker: &Waker) {
+    if !wakers.iter().any(|existing| existing.will_wake(waker)) {
+        wakers.push(waker.clone());
+    }
+}
+
+/// A shared handle to a card, that can be used from async code while the interrupt handler
+/// also has access to it
+#[derive(Clone)]
+pub struct Ne2000Device {
+    pub card: Arc<Mutex<Ne2000>>,
+    pub interrupt: u32,
+}
+
+impl Ne2000Device {
+    pub fn new(card: Ne2000, interrupt: u32) -> Self {
+        Self {
+            card: Arc::new(Mutex::new(card)),
+            interrupt,
+        }
+    }
+}
+
+struct SendFuture<'a> {
+    device: &'a Ne2000Device,
+    frame: &'a [u8],
+}
+
+impl Future for SendFuture<'_> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        self.device.card.lock().poll_send(cx, self.frame)
+    }
+}
+
+struct ReceiveFuture<'a> {
+    device: &'a Ne2000Device,
+}
+
+impl Future for ReceiveFuture<'_> {
+    type Output = Vec<u8>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Vec<u8>> {
+        self.device.card.lock().poll_receive(cx)
+    }
+}
+
+#[async_trait]
+impl NetDevice for Ne2000Device {
+    type Error = NetError;
+
+    fn mac_address(&self) -> [u8; 6] {
+        self.card.lock().mac_address()
+    }
+
+    fn mtu(&self) -> usize {
+        ETHERNET_MTU
+    }
+
+    /// Waits until the transmit buffer is free, and starts sending `frame` from it
+    async fn send(&self, frame: &[u8]) -> Result<(), NetError> {
+        if frame.len() > MAX_FRAME_SIZE {
+            return Err(NetError::FrameTooLarge);
+        }
+        if frame.len() < ETHERNET_HEADER_SIZE {
+            return Err(NetError::FrameTooSmall);
+        }
+        SendFuture {
+            device: self,
+            frame,
+        }
+        .await;
+        Ok(())
+    }
+
+    async fn receive(&self) -> Result<Vec<u8>, NetError> {
+        Ok(ReceiveFuture { device: self }.await)
+    }
+}
@@ -0,0 +1,30 @@
+// What network interface drivers have in common.
+// Drivers implement `kernel_io::NetDevice` with `NetError` as their error, so that the
+// network stack can use any of them.
+
+use crate::virtio::VirtioError;
+
+/// Destination, source and EtherType
+pub const ETHERNET_HEADER_SIZE: usize = 14;
+/// Frames shorter than this have to be padded, not counting the frame check sequence
+pub const MIN_FRAME_SIZE: usize = 60;
+pub const ETHERNET_MTU: usize = 1500;
+pub const MAX_FRAME_SIZE: usize = ETHERNET_HEADER_SIZE + ETHERNET_MTU;
+
+/// Received frames that haven't been taken yet. Newer frames are dropped when it's full.
+pub const RX_QUEUE_SIZE: usize = 64;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NetError {
+    /// The frame is larger than the MTU allows
+    FrameTooLarge,
+    /// The frame is shorter than an Ethernet header
+    FrameTooSmall,
+    Virtio(VirtioError),
+}
+
+impl From<VirtioError> for NetError {
+    fn from(error: VirtioError) -> Self {
+        Self::Virtio(error)
+    }
+}
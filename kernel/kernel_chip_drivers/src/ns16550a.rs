@@ -0,0 +1,323 @@
+// 0BSD
+
+// Driver for "ns16550a"-compatible UARTs, which is what QEMU's virt machine has.
+// Received bytes are put in a ring buffer by the interrupt handler, and bytes to be
+// transmitted are taken from another ring buffer whenever the transmitter is empty.
+// See http://caro.su/msx/ocm_de1/16550.pdf
+
+use alloc::{boxed::Box, sync::Arc, vec::Vec};
+use core::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll, Waker},
+};
+
+use async_trait::async_trait;
+use kernel_lock::shared::Mutex;
+
+// Register offsets
+/// Receiver buffer (read) / transmitter holding register (write)
+const RBR_THR: usize = 0;
+const IER: usize = 1;
+/// Interrupt identification (read) / FIFO control (write)
+const IIR_FCR: usize = 2;
+const LCR: usize = 3;
+const LSR: usize = 5;
+
+// Interrupt enable register bits
+const IER_RX_AVAILABLE: u8 = 1 << 0;
+const IER_TX_EMPTY: u8 = 1 << 1;
+
+// FIFO control register bits
+const FCR_ENABLE: u8 = 1 << 0;
+const FCR_CLEAR_RX: u8 = 1 << 1;
+const FCR_CLEAR_TX: u8 = 1 << 2;
+/// Raise the RX interrupt when the FIFO has 14 bytes
+const FCR_TRIGGER_14: u8 = 0b11 << 6;
+
+/// 8 data bits, no parity, 1 stop bit
+const LCR_8N1: u8 = 0b11;
+
+// Line status register bits
+const LSR_DATA_READY: u8 = 1 << 0;
+const LSR_THR_EMPTY: u8 = 1 << 5;
+
+/// Size of the transmitter FIFO
+const TX_FIFO_SIZE: usize = 16;
+
+pub const RING_BUFFER_SIZE: usize = 1024;
+
+/// A fixed-size FIFO of bytes
+pub struct RingBuffer {
+    data: [u8; RING_BUFFER_SIZE],
+    start: usize,
+    len: usize,
+}
+
+impl RingBuffer {
+    pub const fn new() -> Self {
+        Self {
+            data: [0; RING_BUFFER_SIZE],
+            start: 0,
+            len: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.len == RING_BUFFER_SIZE
+    }
+
+    /// Returns false if the buffer was full
+    pub fn push(&mut self, byte: u8) -> bool {
+        if self.is_full() {
+            return false;
+        }
+        self.data[(self.start + self.len) % RING_BUFFER_SIZE] = byte;
+        self.len += 1;
+        true
+    }
+
+    pub fn pop(&mut self) -> Option<u8> {
+        if self.is_empty() {
+            return None;
+        }
+        let byte = self.data[self.start];
+        self.start = (self.start + 1) % RING_BUFFER_SIZE;
+        self.len -= 1;
+        Some(byte)
+    }
+}
+
+impl Default for RingBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub struct Ns16550a {
+    base_addr: usize,
+    rx: RingBuffer,
+    tx: RingBuffer,
+    /// Woken when there's data in `rx`
+    rx_wakers: Vec<Waker>,
+    /// Woken when there's space in `tx`
+    tx_wakers: Vec<Waker>,
+    /// Bytes that were received while `rx` was full
+    pub dropped_bytes: usize,
+}
+
+impl Ns16550a {
+    pub fn new_with_addr(base_addr: usize) -> Self {
+        Self {
+            base_addr,
+            rx: RingBuffer::new(),
+            tx: RingBuffer::new(),
+            rx_wakers: Vec::new(),
+            tx_wakers: Vec::new(),
+            dropped_bytes: 0,
+        }
+    }
+
+    fn read_register(&self, register: usize) -> u8 {
+        unsafe { (self.base_addr as *const u8).add(register).read_volatile() }
+    }
+
+    fn write_register(&self, register: usize, value: u8) {
+        unsafe { (self.base_addr as *mut u8).add(register).write_volatile(value) }
+    }
+
+    /// Enables the FIFOs and the receive interrupt.
+    /// The transmit interrupt is only enabled while there is data to send.
+    pub fn init(&mut self) {
+        self.write_register(LCR, LCR_8N1);
+        self.write_register(
+            IIR_FCR,
+            FCR_ENABLE | FCR_CLEAR_RX | FCR_CLEAR_TX | FCR_TRIGGER_14,
+        );
+        self.write_register(IER, IER_RX_AVAILABLE);
+    }
+
+    /// Should be called when the UART's interrupt fires.
+    /// Moves received bytes into the RX buffer, and refills the transmitter from the TX buffer.
+    pub fn handle_interrupt(&mut self) {
+        while self.read_register(LSR) & LSR_DATA_READY != 0 {
+            let byte = self.read_register(RBR_THR);
+            if !self.rx.push(byte) {
+                self.dropped_bytes += 1;
+            }
+        }
+        if !self.rx.is_empty() {
+            self.rx_wakers.drain(..).for_each(Waker::wake);
+        }
+        self.fill_transmitter();
+    }
+
+    fn fill_transmitter(&mut self) {
+        if self.read_register(LSR) & LSR_THR_EMPTY != 0 {
+            for _ in 0..TX_FIFO_SIZE {
+                match self.tx.pop() {
+                    Some(byte) => self.write_register(RBR_THR, byte),
+                    None => break,
+                }
+            }
+            if !self.tx.is_full() {
+                self.tx_wakers.drain(..).for_each(Waker::wake);
+            }
+        }
+        // Get an interrupt when the transmitter is empty again, but only if there's more to send
+        if self.tx.is_empty() {
+            self.write_register(IER, IER_RX_AVAILABLE);
+        } else {
+            self.write_register(IER, IER_RX_AVAILABLE | IER_TX_EMPTY);
+        }
+    }
+
+    /// Copies received bytes into `buf`. Returns 0 if nothing has been received yet.
+    pub fn try_read(&mut self, buf: &mut [u8]) -> usize {
+        let mut read = 0;
+        while read < buf.len() {
+            match self.rx.pop() {
+                Some(byte) => {
+                    buf[read] = byte;
+                    read += 1;
+                }
+                None => break,
+            }
+        }
+        read
+    }
+
+    /// Queues bytes from `buf` for transmission. Returns 0 if the TX buffer is full.
+    pub fn try_write(&mut self, buf: &[u8]) -> usize {
+        let mut written = 0;
+        for byte in buf {
+            if !self.tx.push(*byte) {
+                break;
+            }
+            written += 1;
+        }
+        if written != 0 {
+            self.fill_transmitter();
+        }
+        written
+    }
+
+    /// Adds bytes to the RX buffer as if they had been received, for other input devices
+    /// that type on the same console
+    pub fn push_received(&mut self, data: &[u8]) {
+        for byte in data {
+            if !self.rx.push(*byte) {
+                self.dropped_bytes += 1;
+            }
+        }
+        if !self.rx.is_empty() {
+            self.rx_wakers.drain(..).for_each(Waker::wake);
+        }
+    }
+
+    /// Writes `buf` without using interrupts, for when the kernel can't wait (e.g. panics).
+    /// Bytes that are already in the TX buffer are sent first, so that output stays in order.
+    pub fn write_blocking(&mut self, buf: &[u8]) {
+        if !self.tx.is_empty() {
+            while let Some(byte) = self.tx.pop() {
+                self.write_byte_blocking(byte);
+            }
+            self.tx_wakers.drain(..).for_each(Waker::wake);
+            self.write_register(IER, IER_RX_AVAILABLE);
+        }
+        for byte in buf {
+            self.write_byte_blocking(*byte);
+        }
+    }
+
+    fn write_byte_blocking(&self, byte: u8) {
+        while self.read_register(LSR) & LSR_THR_EMPTY == 0 {}
+        self.write_register(RBR_THR, byte);
+    }
+
+    pub fn poll_read(&mut self, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<usize> {
+        match self.try_read(buf) {
+            0 if !buf.is_empty() => {
+                self.rx_wakers.push(cx.waker().clone());
+                Poll::Pending
+            }
+            read => Poll::Ready(read),
+        }
+    }
+
+    pub fn poll_write(&mut self, cx: &mut Context<'_>, buf: &[u8]) -> Poll<usize> {
+        match self.try_write(buf) {
+            0 if !buf.is_empty() => {
+                self.tx_wakers.push(cx.waker().clone());
+                Poll::Pending
+            }
+            written => Poll::Ready(written),
+        }
+    }
+}
+
+/// A shared handle to a UART, that can be used from async code while
+/// the interrupt handler also has access to it.
+#[derive(Clone)]
+pub struct Uart(pub Arc<Mutex<Ns16550a>>);
+
+impl Uart {
+    pub fn new(uart: Ns16550a) -> Self {
+        Self(Arc::new(Mutex::new(uart)))
+    }
+}
+
+struct ReadFuture<'a> {
+    uart: &'a Uart,
+    buf: &'a mut [u8],
+}
+
+impl Future for ReadFuture<'_> {
+    type Output = usize;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<usize> {
+        let this = &mut *self;
+        this.uart.0.lock().poll_read(cx, this.buf)
+    }
+}
+
+struct WriteFuture<'a> {
+    uart: &'a Uart,
+    buf: &'a [u8],
+}
+
+impl Future for WriteFuture<'_> {
+    type Output = usize;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<usize> {
+        self.uart.0.lock().poll_write(cx, self.buf)
+    }
+}
+
+#[async_trait]
+impl kernel_io::Read for Uart {
+    type Error = kernel_io::Error;
+
+    /// Waits until at least one byte has been received
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        Ok(ReadFuture { uart: self, buf }.await)
+    }
+}
+
+#[async_trait]
+impl kernel_io::Write for Uart {
+    type Error = kernel_io::Error;
+
+    /// Waits until there's space in the TX buffer for at least one byte
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        Ok(WriteFuture { uart: self, buf }.await)
+    }
+}
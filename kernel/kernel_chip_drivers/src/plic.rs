@@ -5,118 +5,203 @@
 // contexts" (or "targets") Each hart can have specific external interrupts
 // enabled or disabled for it The context number also depends on whether it's
 // S-mode or M-mode See https://static.dev.sifive.com/SiFive-U5-Coreplex-v1.0.pdf, section 4.1
+//
+// From the PDF:
+// "Interrupt targets are mapped to harts sequentially, with interrupt targets
+//  being added for each hart’s M-mode, H-mode, S-mode, and U-mode contexts
+//  sequentially in that order."
+//
+// Which modes a hart has differs between SoCs (some harts have no S-mode at all), so
+// the contexts aren't worked out from the hart ID. The PLIC's `interrupts-extended`
+// in the device tree has one entry per context, pointing to the local interrupt
+// controller of the hart it belongs to, with the interrupt it raises there.
 
-use kernel_cpu::load_hartid;
-
-#[inline(always)]
-fn get_context_number() -> usize {
-    // From the PDF:
-    // "SiFive cores always supports a machine-mode context for each hart.
-    //  For machine-mode hart contexts, interrupts generated by the PLIC
-    //  appear on meip in the mip register.  SiFive cores can optionally support
-    //  user-level interrupts with a user-mode context for each hart. If
-    //  external interrupts are delegated to the user-mode hart context (by
-    //  setting the appropriate bits in the machine-modemidelegregister),
-    //  then the PLIC interrupts appear on ueip in the uip register. The PLIC
-    //  interrupts for the user-mode hart context always appear on the ueip bit
-    //  in the mipregister, regardless of delegation setting. Interrupt targets
-    //  are mapped to harts sequentially,  with interrupt targets being added
-    //  for each hart’s M-mode, H-mode, S-mode, and U-mode contexts
-    //  sequentially in that order.  For example,if the system has one hart
-    //  with M-mode and U-mode, and two harts with M-mode, S-mode, and U-mode,
-    //  the mappings are as
-    //  follows:""
-
-    // Target 	Hart   	Mode
-    // 0        0		M
-    // 1 		0		U
-    // 2 		1		M
-    // 3 		1		S
-    // 4 		1		U
-    // 5 		2		M
-    // 6 		2		S
-    // 7 		2		U
-
-    // For now we'll assume that each hart always has a M and S mode
-    // We're always on S-mode, so always add 1
-    1 + load_hartid() * 2
+use alloc::vec::Vec;
+
+use kernel_lock::shared::Mutex;
+
+use crate::driver::Interrupt;
+
+/// What a context raises in its hart's local interrupt controller if it's an S-mode context
+const SUPERVISOR_EXTERNAL_INTERRUPT: u32 = 9;
+
+/// The highest interrupt number the PLIC can have
+pub const MAX_INTERRUPT: u32 = 1023;
+
+const PRIORITY_BASE: usize = 0;
+const ENABLE_BASE: usize = 0x2000;
+const ENABLE_STRIDE: usize = 0x80;
+const CONTEXT_BASE: usize = 0x20_0000;
+const CONTEXT_STRIDE: usize = 0x1000;
+const THRESHOLD: usize = 0;
+const CLAIM_COMPLETE: usize = 4;
+
+/// A hart's S-mode context
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Context {
+    pub hart: usize,
+    pub number: usize,
+}
+
+/// Finds the S-mode contexts in the PLIC's `interrupts-extended`. The nth entry is
+/// context n, and its controller is a child of the `cpu` node of its hart.
+pub fn supervisor_contexts(interrupts: &[Interrupt]) -> Vec<Context> {
+    interrupts
+        .iter()
+        .enumerate()
+        .filter(|(_, interrupt)| interrupt.number() == Some(SUPERVISOR_EXTERNAL_INTERRUPT))
+        .filter_map(|(number, interrupt)| {
+            let cpu = interrupt.controller.parent()?;
+            let hart = cpu.reg()?.next()?.address as usize;
+            Some(Context { hart, number })
+        })
+        .collect()
 }
 
-pub struct Plic0 {
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlicError {
+    /// The hart has no S-mode context
+    NoContext(usize),
+    /// Zero, or higher than the number of interrupts the PLIC has
+    BadInterrupt(u32),
+}
+
+pub struct Plic {
     base_addr: usize,
-    context_number: usize,
+    contexts: Vec<Context>,
+    /// From `riscv,ndev`
+    interrupt_count: u32,
+    /// Taken while changing enable bits, since several interrupts share a register
+    enables: Mutex<()>,
 }
 
-impl Plic0 {
-    pub fn new_with_addr(base_addr: usize) -> Self {
-        // size: 0x200000 + (32 * 2) * 0x1000
+impl Plic {
+    pub fn new(base_addr: usize, contexts: Vec<Context>, interrupt_count: u32) -> Self {
         Self {
-            base_addr: base_addr,
-            context_number: get_context_number(),
+            base_addr,
+            contexts,
+            interrupt_count: interrupt_count.min(MAX_INTERRUPT),
+            enables: Mutex::new(()),
+        }
+    }
+
+    /// The harts that interrupts can be sent to
+    pub fn harts(&self) -> impl Iterator<Item = usize> + '_ {
+        self.contexts.iter().map(|context| context.hart)
+    }
+
+    fn context(&self, hart: usize) -> Result<usize, PlicError> {
+        self.contexts
+            .iter()
+            .find(|context| context.hart == hart)
+            .map(|context| context.number)
+            .ok_or(PlicError::NoContext(hart))
+    }
+
+    /// Fails if the PLIC doesn't have `interrupt`
+    pub fn check_interrupt(&self, interrupt: u32) -> Result<(), PlicError> {
+        if interrupt == 0 || interrupt > self.interrupt_count {
+            return Err(PlicError::BadInterrupt(interrupt));
         }
+        Ok(())
     }
 
-    /// Sets the priority of a specific interrupt number.
-    pub fn set_priority(&self, interrupt: u32, priority: u32) {
+    fn register(&self, offset: usize) -> *mut u32 {
+        (self.base_addr + offset) as *mut u32
+    }
+
+    /// Sets the priority of a specific interrupt number. Priority 0 never interrupts.
+    pub fn set_priority(&self, interrupt: u32, priority: u32) -> Result<(), PlicError> {
+        self.check_interrupt(interrupt)?;
+        let register = self.register(PRIORITY_BASE + interrupt as usize * 4);
+        unsafe { register.write_volatile(priority) };
+        Ok(())
+    }
+
+    // From the pdf:
+    // > For each interrupt target, each device’s interrupt can be enabled by
+    // > setting the corresponding bit in that target’s enables registers.
+    // > The enables for a target are accessed as a contiguous array of
+    // > 32×32-bit words, packed the same way as the pending bits.
+    // The enable bit for interrupt N is bit (N % 32) of word (N / 32)
+    fn enable_register(&self, context: usize, interrupt: u32) -> (*mut u32, u32) {
+        let register =
+            self.register(ENABLE_BASE + context * ENABLE_STRIDE + (interrupt / 32) as usize * 4);
+        (register, 1 << (interrupt % 32))
+    }
+
+    fn set_enabled_in(&self, context: usize, interrupt: u32, enable: bool) {
+        let (register, bit) = self.enable_register(context, interrupt);
         unsafe {
-            (self.base_addr as *mut u32)
-                .add(interrupt as usize)
-                .write_volatile(priority)
-        };
+            let value = register.read_volatile();
+            let value = if enable { value | bit } else { value & !bit };
+            register.write_volatile(value);
+        }
+    }
+
+    fn is_enabled_in(&self, context: usize, interrupt: u32) -> bool {
+        let (register, bit) = self.enable_register(context, interrupt);
+        unsafe { register.read_volatile() & bit != 0 }
+    }
+
+    /// Sends `interrupt` to `hart` only. If it was going to another hart, it moves;
+    /// one that has already been claimed there still has to be completed there.
+    pub fn set_affinity(&self, interrupt: u32, hart: usize) -> Result<(), PlicError> {
+        self.check_interrupt(interrupt)?;
+        let target = self.context(hart)?;
+        let _enables = self.enables.lock();
+        // Enable it in the new context first, so that it doesn't get lost in between
+        self.set_enabled_in(target, interrupt, true);
+        for context in &self.contexts {
+            if context.number != target {
+                self.set_enabled_in(context.number, interrupt, false);
+            }
+        }
+        Ok(())
     }
 
-    pub fn set_enabled(&self, interrupt: u32, enable: bool) {
-        // The enable bits are at offset 0x2000 + context * 0x80
-
-        // From the pdf:
-        // > For each interrupt target, each device’s interrupt can be enabled by
-        // > setting the corresponding bit in that target’s enables registers.
-        // > The enables for a target are accessed as a contiguous array of
-        // > 32×32-bit words, packed the same way as the pending bits.
-        // > For each target, bit 0 of enable word 0 represents the non-existent
-        // > interrupt ID 0 and is hardwired to 0.
-        // > Unused interrupt IDs are also hardwired to zero. The enables arrays
-        // > for different targets are packed contiguously in the addressspace.
-
-        // The pending bits are 32 words of 32 bits. The pending bit for interrupt id N
-        // is a bit (N % 32) of word (N / 32)
-        let enables_base = self.base_addr + 0x2000;
-        let target_base = enables_base + self.context_number * 0x80;
-        kernel_util::println!("Base {:x}", target_base);
-        kernel_util::println!("Base {:x}", get_context_number());
-        let target_base = target_base as *mut u32;
-        let this_register = unsafe { target_base.add((interrupt / 32) as usize) };
-        if enable {
-            let flag = (enable as u32) << (interrupt % 32);
-            unsafe {
-                this_register.write_volatile(this_register.read_volatile() | flag);
-            };
-        } else {
-            let flag = !((enable as u32) << (interrupt % 32));
-            unsafe {
-                this_register.write_volatile(this_register.read_volatile() & flag);
-            };
+    /// The hart that `interrupt` is sent to, if it's enabled
+    pub fn affinity(&self, interrupt: u32) -> Option<usize> {
+        self.check_interrupt(interrupt).ok()?;
+        self.contexts
+            .iter()
+            .find(|context| self.is_enabled_in(context.number, interrupt))
+            .map(|context| context.hart)
+    }
+
+    /// Stops sending `interrupt` to any hart
+    pub fn disable(&self, interrupt: u32) -> Result<(), PlicError> {
+        self.check_interrupt(interrupt)?;
+        let _enables = self.enables.lock();
+        for context in &self.contexts {
+            self.set_enabled_in(context.number, interrupt, false);
         }
+        Ok(())
     }
 
-    pub fn set_threshold(&self, threshold: u32) {
-        let threshold_base = self.base_addr + 0x20_0000;
-        let target_base = threshold_base + self.context_number * 0x1000;
-        let target_base = target_base as *mut u32;
-        unsafe { target_base.write_volatile(threshold) };
+    /// `hart` is only interrupted by interrupts with a priority above `threshold`
+    pub fn set_threshold(&self, hart: usize, threshold: u32) -> Result<(), PlicError> {
+        let context = self.context(hart)?;
+        let register = self.register(CONTEXT_BASE + context * CONTEXT_STRIDE + THRESHOLD);
+        unsafe { register.write_volatile(threshold) };
+        Ok(())
     }
 
-    pub fn claim_highest_priority(&self) -> u32 {
-        let cc_base = self.base_addr + 0x20_0004;
-        let target_base = cc_base + self.context_number * 0x1000;
-        let target_base = target_base as *mut u32;
-        unsafe { target_base.read_volatile() }
+    /// Takes the highest priority interrupt that's pending for `hart`, which has to be
+    /// passed to [`Plic::complete`] once it's handled
+    pub fn claim(&self, hart: usize) -> Option<u32> {
+        let context = self.context(hart).ok()?;
+        let register = self.register(CONTEXT_BASE + context * CONTEXT_STRIDE + CLAIM_COMPLETE);
+        match unsafe { register.read_volatile() } {
+            0 => None,
+            interrupt => Some(interrupt),
+        }
     }
 
-    pub fn complete(&self, interrupt: u32) {
-        let cc_base = self.base_addr + 0x20_0004;
-        let target_base = cc_base + self.context_number * 0x1000;
-        let target_base = target_base as *mut u32;
-        unsafe { target_base.write_volatile(interrupt) }
+    pub fn complete(&self, hart: usize, interrupt: u32) {
+        if let Ok(context) = self.context(hart) {
+            let register = self.register(CONTEXT_BASE + context * CONTEXT_STRIDE + CLAIM_COMPLETE);
+            unsafe { register.write_volatile(interrupt) };
+        }
     }
 }
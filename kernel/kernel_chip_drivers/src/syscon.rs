@@ -0,0 +1,52 @@
+// A register in a system controller ("syscon") that powers off or resets the machine when a
+// value is written to it, as described by "syscon-poweroff" and "syscon-reboot" nodes.
+// See https://www.kernel.org/doc/Documentation/devicetree/bindings/power/reset/syscon-poweroff.txt
+// and https://www.kernel.org/doc/Documentation/devicetree/bindings/power/reset/syscon-reboot.txt
+//
+// On QEMU's virt machine, the register is the SiFive test device's ("sifive,test0"), which
+// makes QEMU exit. Its other values make QEMU exit with a status code.
+
+/// Powers off with status 0 on the SiFive test device
+pub const SIFIVE_TEST_PASS: u32 = 0x5555;
+/// Powers off with status 1 on the SiFive test device, or `(code << 1) | 1` with the code
+/// in the upper 16 bits
+pub const SIFIVE_TEST_FAIL: u32 = 0x3333;
+pub const SIFIVE_TEST_RESET: u32 = 0x7777;
+
+pub struct SysconRegister {
+    address: usize,
+    mask: u32,
+    value: u32,
+}
+
+impl SysconRegister {
+    /// Writes `value` to the bits in `mask` of the register at `address`
+    ///
+    /// # Safety
+    /// `address` has to be where the register is mapped
+    pub unsafe fn new(address: usize, mask: u32, value: u32) -> Self {
+        Self {
+            address,
+            mask,
+            value,
+        }
+    }
+
+    /// Usually doesn't return, but the machine may take a moment to power off
+    pub fn trigger(&self) {
+        self.write(self.value);
+    }
+
+    /// Writes `value` instead of the value from the device tree
+    pub fn write(&self, value: u32) {
+        let register = self.address as *mut u32;
+        unsafe {
+            let value = if self.mask == u32::MAX {
+                value
+            } else {
+                register.read_volatile() & !self.mask | value & self.mask
+            };
+            register.write_volatile(value);
+        }
+    }
+}
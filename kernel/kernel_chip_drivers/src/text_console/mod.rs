@@ -0,0 +1,175 @@
+// A text console drawn on a framebuffer with a built-in bitmap font.
+// Pixels are 32 bits, little endian, with blue in the lowest byte and the highest byte unused
+// (B8G8R8X8). The console remembers which rows of pixels it changed, so that only those have
+// to be sent to the display.
+
+pub mod font;
+
+use core::{
+    fmt,
+    ops::{DerefMut, Range},
+};
+
+use font::{GLYPH_HEIGHT, GLYPH_WIDTH};
+
+const BYTES_PER_PIXEL: usize = 4;
+const TAB_WIDTH: usize = 8;
+
+/// Drawn for characters that aren't ASCII
+const NOT_ASCII: u8 = 0xff;
+
+pub const LIGHT_GRAY: u32 = 0x00aa_aaaa;
+pub const BLACK: u32 = 0x0000_0000;
+
+pub struct TextConsole<B> {
+    buffer: B,
+    /// In pixels
+    width: usize,
+    /// Every pixel of the font is drawn as a square of this size
+    scale: usize,
+    columns: usize,
+    rows: usize,
+    column: usize,
+    row: usize,
+    foreground: u32,
+    background: u32,
+    /// Rows of pixels that changed since the last `take_damage`
+    damage: Option<Range<usize>>,
+}
+
+impl<B: DerefMut<Target = [u8]>> TextConsole<B> {
+    /// Clears `buffer`, which holds `width` by `height` pixels, and starts writing at its top
+    /// left corner
+    pub fn new(buffer: B, width: usize, height: usize, scale: usize) -> Self {
+        assert!(buffer.len() >= width * height * BYTES_PER_PIXEL);
+        let scale = scale.max(1);
+        let mut console = Self {
+            buffer,
+            width,
+            scale,
+            columns: (width / (GLYPH_WIDTH * scale)).max(1),
+            rows: (height / (GLYPH_HEIGHT * scale)).max(1),
+            column: 0,
+            row: 0,
+            foreground: LIGHT_GRAY,
+            background: BLACK,
+            damage: None,
+        };
+        console.clear();
+        console
+    }
+
+    pub fn buffer(&self) -> &B {
+        &self.buffer
+    }
+
+    /// The size of the console in characters, as `(columns, rows)`
+    pub fn size(&self) -> (usize, usize) {
+        (self.columns, self.rows)
+    }
+
+    pub fn set_colors(&mut self, foreground: u32, background: u32) {
+        self.foreground = foreground;
+        self.background = background;
+    }
+
+    /// Fills the console with the background color and moves to the top left corner
+    pub fn clear(&mut self) {
+        let background = self.background.to_le_bytes();
+        let used = self.rows * self.line_size();
+        for pixel in self.buffer[..used].chunks_exact_mut(BYTES_PER_PIXEL) {
+            pixel.copy_from_slice(&background);
+        }
+        self.column = 0;
+        self.row = 0;
+        self.damage_rows(0, self.rows);
+    }
+
+    /// Returns the rows of pixels that changed since the last call, if any did
+    pub fn take_damage(&mut self) -> Option<Range<usize>> {
+        self.damage.take()
+    }
+
+    /// Handles carriage returns, newlines, tabs and backspaces. Other control characters are
+    /// drawn like any other character that isn't in the font.
+    pub fn write_byte(&mut self, byte: u8) {
+        match byte {
+            b'\r' => self.column = 0,
+            b'\n' => self.new_line(),
+            b'\t' => {
+                self.column = (self.column / TAB_WIDTH + 1) * TAB_WIDTH;
+                if self.column >= self.columns {
+                    self.new_line();
+                }
+            }
+            0x08 => self.column = self.column.saturating_sub(1),
+            _ => {
+                if self.column >= self.columns {
+                    self.new_line();
+                }
+                self.draw(byte);
+                self.column += 1;
+            }
+        }
+    }
+
+    /// Bytes in one row of characters
+    fn line_size(&self) -> usize {
+        self.width * GLYPH_HEIGHT * self.scale * BYTES_PER_PIXEL
+    }
+
+    fn damage_rows(&mut self, first_row: usize, end_row: usize) {
+        let cell_height = GLYPH_HEIGHT * self.scale;
+        let rows = first_row * cell_height..end_row * cell_height;
+        self.damage = Some(match self.damage.take() {
+            Some(damage) => damage.start.min(rows.start)..damage.end.max(rows.end),
+            None => rows,
+        });
+    }
+
+    fn new_line(&mut self) {
+        self.column = 0;
+        if self.row + 1 < self.rows {
+            self.row += 1;
+            return;
+        }
+        // Scroll everything up by one row, and clear the last one
+        let line_size = self.line_size();
+        let used = self.rows * line_size;
+        self.buffer.copy_within(line_size..used, 0);
+        let background = self.background.to_le_bytes();
+        for pixel in self.buffer[used - line_size..used].chunks_exact_mut(BYTES_PER_PIXEL) {
+            pixel.copy_from_slice(&background);
+        }
+        self.damage_rows(0, self.rows);
+    }
+
+    fn draw(&mut self, byte: u8) {
+        let glyph = font::glyph(byte);
+        let left = self.column * GLYPH_WIDTH * self.scale;
+        let top = self.row * GLYPH_HEIGHT * self.scale;
+        for y in 0..GLYPH_HEIGHT * self.scale {
+            let bits = glyph[y / self.scale];
+            let line = (top + y) * self.width;
+            for x in 0..GLYPH_WIDTH * self.scale {
+                let color = if bits & (1 << (x / self.scale)) != 0 {
+                    self.foreground
+                } else {
+                    self.background
+                };
+                let offset = (line + left + x) * BYTES_PER_PIXEL;
+                self.buffer[offset..offset + BYTES_PER_PIXEL].copy_from_slice(&color.to_le_bytes());
+            }
+        }
+        self.damage_rows(self.row, self.row + 1);
+    }
+}
+
+impl<B: DerefMut<Target = [u8]>> fmt::Write for TextConsole<B> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            self.write_byte(if c.is_ascii() { c as u8 } else { NOT_ASCII });
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,243 @@
+// virtio-blk driver.
+// Every request is a header, the data, and a status byte that the device writes.
+// Large reads and writes are split into several requests which are all submitted at once.
+// See https://docs.oasis-open.org/virtio/virtio/v1.1/csprd01/virtio-v1.1-csprd01.html#x1-2390002
+
+use alloc::{boxed::Box, vec::Vec};
+use core::task::Waker;
+
+use async_trait::async_trait;
+use kernel_io::BlockDevice;
+
+use super::{
+    device_id, join_all, QueueBuffer, SharedVirtioDevice, VirtioDevice, VirtioError, VirtioMmio,
+};
+use crate::dma::DmaBuffer;
+
+/// virtio-blk always addresses the disk in 512-byte sectors
+pub const SECTOR_SIZE: usize = 512;
+
+const VIRTIO_BLK_F_RO: u64 = 1 << 5;
+const VIRTIO_BLK_F_FLUSH: u64 = 1 << 9;
+
+const VIRTIO_BLK_T_IN: u32 = 0;
+const VIRTIO_BLK_T_OUT: u32 = 1;
+const VIRTIO_BLK_T_FLUSH: u32 = 4;
+
+const VIRTIO_BLK_S_OK: u8 = 0;
+const VIRTIO_BLK_S_UNSUPP: u8 = 2;
+
+/// Offset of the capacity (in sectors) in the configuration space
+const CONFIG_CAPACITY: usize = 0;
+
+/// Largest amount of data in a single request
+const MAX_REQUEST_SIZE: usize = 64 * 1024;
+
+const QUEUE_SIZE: u16 = 128;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BlockError {
+    /// The device reported an error
+    Io,
+    /// The device doesn't support this kind of request
+    Unsupported,
+    ReadOnly,
+    /// The request goes past the end of the device
+    OutOfRange,
+    /// The buffer isn't a whole number of sectors
+    UnalignedBuffer,
+    Virtio(VirtioError),
+}
+
+impl From<VirtioError> for BlockError {
+    fn from(error: VirtioError) -> Self {
+        Self::Virtio(error)
+    }
+}
+
+pub struct VirtioBlock {
+    device: SharedVirtioDevice,
+    capacity: u64,
+    read_only: bool,
+    virt_to_phys: fn(usize) -> usize,
+}
+
+impl VirtioBlock {
+    pub fn new(
+        transport: VirtioMmio,
+        interrupt: u32,
+        virt_to_phys: fn(usize) -> usize,
+        wake_on_interrupt: fn(u32, Waker),
+    ) -> Result<Self, VirtioError> {
+        assert!(transport.device_id() == device_id::BLOCK);
+        let device = VirtioDevice::new(
+            transport,
+            interrupt,
+            |_offered| VIRTIO_BLK_F_RO | VIRTIO_BLK_F_FLUSH,
+            1,
+            QUEUE_SIZE,
+            virt_to_phys,
+            wake_on_interrupt,
+        )?;
+        let capacity = device.transport.read_config::<u64>(CONFIG_CAPACITY);
+        let read_only = device.features & VIRTIO_BLK_F_RO != 0;
+        Ok(Self {
+            device: device.into_shared(),
+            capacity,
+            read_only,
+            virt_to_phys,
+        })
+    }
+
+    /// The underlying device, so that its interrupts can be acknowledged
+    pub fn device(&self) -> &SharedVirtioDevice {
+        &self.device
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    fn supports_flush(&self) -> bool {
+        self.device.lock().features & VIRTIO_BLK_F_FLUSH != 0
+    }
+
+    /// Creates the header and status byte for a request. The header is at offset 0 and the
+    /// status at offset 16.
+    fn request_header(&self, kind: u32, sector: u64) -> DmaBuffer {
+        let mut header = DmaBuffer::with_alignment(32, 32, self.virt_to_phys);
+        header[0..4].copy_from_slice(&kind.to_le_bytes());
+        header[8..16].copy_from_slice(&sector.to_le_bytes());
+        // Anything other than OK, so that we notice if the device didn't write it
+        header[16] = 0xff;
+        header
+    }
+
+    fn check_range(&self, start: u64, len: usize) -> Result<(), BlockError> {
+        if !len.is_multiple_of(SECTOR_SIZE) {
+            return Err(BlockError::UnalignedBuffer);
+        }
+        let end = start.checked_add((len / SECTOR_SIZE) as u64);
+        match end {
+            Some(end) if end <= self.capacity => Ok(()),
+            _ => Err(BlockError::OutOfRange),
+        }
+    }
+
+    /// Sends one request for each chunk, all at once, waits for all of them, and gives the
+    /// chunks back
+    async fn transfer(
+        &self,
+        kind: u32,
+        start: u64,
+        chunks: Vec<DmaBuffer>,
+    ) -> Result<Vec<DmaBuffer>, BlockError> {
+        let mut requests = Vec::new();
+        let mut sector = start;
+        for chunk in chunks {
+            let header = self.request_header(kind, sector);
+            sector += (chunk.len() / SECTOR_SIZE) as u64;
+            let buffers = alloc::vec![
+                QueueBuffer {
+                    address: header.physical_address(0),
+                    len: 16,
+                    device_writable: false,
+                },
+                QueueBuffer {
+                    address: chunk.physical_address(0),
+                    len: chunk.len() as u32,
+                    device_writable: kind == VIRTIO_BLK_T_IN,
+                },
+                QueueBuffer {
+                    address: header.physical_address(16),
+                    len: 1,
+                    device_writable: true,
+                },
+            ];
+            let memory = alloc::vec![header, chunk];
+            requests.push(VirtioDevice::request(&self.device, 0, buffers, memory));
+        }
+        let mut chunks = Vec::new();
+        for result in join_all(requests).await {
+            let (_, mut memory) = result?;
+            let chunk = memory.pop().unwrap();
+            check_status(&memory[0])?;
+            chunks.push(chunk);
+        }
+        Ok(chunks)
+    }
+}
+
+fn check_status(header: &DmaBuffer) -> Result<(), BlockError> {
+    match header[16] {
+        VIRTIO_BLK_S_OK => Ok(()),
+        VIRTIO_BLK_S_UNSUPP => Err(BlockError::Unsupported),
+        _ => Err(BlockError::Io),
+    }
+}
+
+#[async_trait]
+impl BlockDevice for VirtioBlock {
+    type Error = BlockError;
+
+    fn block_size(&self) -> usize {
+        SECTOR_SIZE
+    }
+
+    fn capacity(&self) -> u64 {
+        self.capacity
+    }
+
+    async fn read_blocks(&self, start: u64, buf: &mut [u8]) -> Result<(), BlockError> {
+        self.check_range(start, buf.len())?;
+        let chunks: Vec<DmaBuffer> = buf
+            .chunks(MAX_REQUEST_SIZE)
+            .map(|chunk| DmaBuffer::new(chunk.len(), self.virt_to_phys))
+            .collect();
+        let chunks = self.transfer(VIRTIO_BLK_T_IN, start, chunks).await?;
+        for (chunk, data) in buf.chunks_mut(MAX_REQUEST_SIZE).zip(chunks.iter()) {
+            chunk.copy_from_slice(data);
+        }
+        Ok(())
+    }
+
+    async fn write_blocks(&self, start: u64, buf: &[u8]) -> Result<(), BlockError> {
+        if self.read_only {
+            return Err(BlockError::ReadOnly);
+        }
+        self.check_range(start, buf.len())?;
+        let chunks: Vec<DmaBuffer> = buf
+            .chunks(MAX_REQUEST_SIZE)
+            .map(|chunk| {
+                let mut data = DmaBuffer::new(chunk.len(), self.virt_to_phys);
+                data.copy_from_slice(chunk);
+                data
+            })
+            .collect();
+        self.transfer(VIRTIO_BLK_T_OUT, start, chunks).await?;
+        Ok(())
+    }
+
+    async fn flush(&self) -> Result<(), BlockError> {
+        if !self.supports_flush() {
+            // Writes go straight to the disk
+            return Ok(());
+        }
+        let header = self.request_header(VIRTIO_BLK_T_FLUSH, 0);
+        let buffers = alloc::vec![
+            QueueBuffer {
+                address: header.physical_address(0),
+                len: 16,
+                device_writable: false,
+            },
+            QueueBuffer {
+                address: header.physical_address(16),
+                len: 1,
+                device_writable: true,
+            },
+        ];
+        let (_, memory) =
+            VirtioDevice::request(&self.device, 0, buffers, alloc::vec![header]).await?;
+        check_status(&memory[0])
+    }
+}
@@ -0,0 +1,286 @@
+// virtio-gpu driver, for 2D only.
+// A framebuffer is a resource on the host, backed by guest memory and shown on a scanout.
+// Drawing happens in guest memory, and the changed part is transferred to the resource and
+// flushed to the display. Every command is a request on the control queue, and the device
+// answers each with a response that has a type.
+// See https://docs.oasis-open.org/virtio/virtio/v1.1/csprd01/virtio-v1.1-csprd01.html#x1-3200007
+
+use alloc::{vec, vec::Vec};
+use core::{
+    ops::{Deref, DerefMut},
+    sync::atomic::{AtomicU32, Ordering},
+    task::Waker,
+};
+
+use super::{device_id, QueueBuffer, SharedVirtioDevice, VirtioDevice, VirtioError, VirtioMmio};
+use crate::dma::DmaBuffer;
+
+// Commands
+const CMD_GET_DISPLAY_INFO: u32 = 0x0100;
+const CMD_RESOURCE_CREATE_2D: u32 = 0x0101;
+const CMD_SET_SCANOUT: u32 = 0x0103;
+const CMD_RESOURCE_FLUSH: u32 = 0x0104;
+const CMD_TRANSFER_TO_HOST_2D: u32 = 0x0105;
+const CMD_RESOURCE_ATTACH_BACKING: u32 = 0x0106;
+
+// Responses
+const RESP_OK_NODATA: u32 = 0x1100;
+const RESP_OK_DISPLAY_INFO: u32 = 0x1101;
+
+/// Every command and response starts with a header: type, flags, fence ID and context ID
+const HEADER_SIZE: usize = 24;
+const MAX_SCANOUTS: usize = 16;
+/// A rectangle, and whether the scanout is enabled and its flags
+const DISPLAY_ONE_SIZE: usize = 24;
+
+/// 32 bits per pixel, with blue in the lowest byte and the highest byte unused
+const FORMAT_B8G8R8X8_UNORM: u32 = 2;
+pub const BYTES_PER_PIXEL: usize = 4;
+
+const CONTROL_QUEUE: u16 = 0;
+const QUEUE_SIZE: u16 = 64;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GpuError {
+    /// The device answered a command with this response type instead of the expected one
+    Response(u32),
+    Virtio(VirtioError),
+}
+
+impl From<VirtioError> for GpuError {
+    fn from(error: VirtioError) -> Self {
+        Self::Virtio(error)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Rect {
+    fn encode(&self, command: &mut Vec<u8>) {
+        for field in [self.x, self.y, self.width, self.height] {
+            command.extend_from_slice(&field.to_le_bytes());
+        }
+    }
+}
+
+/// A resource on the host
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Resource {
+    pub id: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// A resource and the guest memory behind it, which holds its pixels row by row
+pub struct Framebuffer {
+    pub resource: Resource,
+    memory: DmaBuffer,
+}
+
+impl Deref for Framebuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.memory
+    }
+}
+
+impl DerefMut for Framebuffer {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        &mut self.memory
+    }
+}
+
+pub struct VirtioGpu {
+    device: SharedVirtioDevice,
+    next_resource_id: AtomicU32,
+    virt_to_phys: fn(usize) -> usize,
+}
+
+impl VirtioGpu {
+    pub fn new(
+        transport: VirtioMmio,
+        interrupt: u32,
+        virt_to_phys: fn(usize) -> usize,
+        wake_on_interrupt: fn(u32, Waker),
+    ) -> Result<Self, VirtioError> {
+        assert!(transport.device_id() == device_id::GPU);
+        // The cursor queue isn't used
+        let device = VirtioDevice::new(
+            transport,
+            interrupt,
+            |_offered| 0,
+            1,
+            QUEUE_SIZE,
+            virt_to_phys,
+            wake_on_interrupt,
+        )?;
+        Ok(Self {
+            device: device.into_shared(),
+            // 0 means no resource
+            next_resource_id: AtomicU32::new(1),
+            virt_to_phys,
+        })
+    }
+
+    /// The underlying device, so that its interrupts can be acknowledged
+    pub fn device(&self) -> &SharedVirtioDevice {
+        &self.device
+    }
+
+    /// Sends `command` and waits for a response of type `expected`, of which it returns the
+    /// part after the header
+    async fn command(
+        &self,
+        command: &[u8],
+        expected: u32,
+        response_size: usize,
+    ) -> Result<Vec<u8>, GpuError> {
+        // Aligned to their size, so that they don't cross a page boundary
+        let mut request = DmaBuffer::with_alignment(
+            command.len(),
+            command.len().next_power_of_two(),
+            self.virt_to_phys,
+        );
+        request.copy_from_slice(command);
+        let response_size = HEADER_SIZE + response_size;
+        let response = DmaBuffer::with_alignment(
+            response_size,
+            response_size.next_power_of_two(),
+            self.virt_to_phys,
+        );
+        let buffers = vec![
+            QueueBuffer {
+                address: request.physical_address(0),
+                len: request.len() as u32,
+                device_writable: false,
+            },
+            QueueBuffer {
+                address: response.physical_address(0),
+                len: response.len() as u32,
+                device_writable: true,
+            },
+        ];
+        let (_, mut memory) = VirtioDevice::request(
+            &self.device,
+            CONTROL_QUEUE,
+            buffers,
+            vec![request, response],
+        )
+        .await?;
+        let response = memory.pop().unwrap();
+        let kind = read_u32(&response, 0);
+        if kind != expected {
+            return Err(GpuError::Response(kind));
+        }
+        Ok(response[HEADER_SIZE..].to_vec())
+    }
+
+    /// The size of every display that's enabled, by scanout
+    pub async fn display_info(&self) -> Result<Vec<(u32, Rect)>, GpuError> {
+        let response = self
+            .command(
+                &header(CMD_GET_DISPLAY_INFO),
+                RESP_OK_DISPLAY_INFO,
+                MAX_SCANOUTS * DISPLAY_ONE_SIZE,
+            )
+            .await?;
+        let displays = response
+            .chunks_exact(DISPLAY_ONE_SIZE)
+            .enumerate()
+            .filter(|(_, display)| read_u32(display, 16) != 0)
+            .map(|(scanout, display)| {
+                let rect = Rect {
+                    x: read_u32(display, 0),
+                    y: read_u32(display, 4),
+                    width: read_u32(display, 8),
+                    height: read_u32(display, 12),
+                };
+                (scanout as u32, rect)
+            })
+            .collect();
+        Ok(displays)
+    }
+
+    /// Creates a `width` by `height` resource, backs it with zeroed memory, and shows it on
+    /// `scanout`
+    pub async fn create_framebuffer(
+        &self,
+        scanout: u32,
+        width: u32,
+        height: u32,
+    ) -> Result<Framebuffer, GpuError> {
+        let resource = Resource {
+            id: self.next_resource_id.fetch_add(1, Ordering::Relaxed),
+            width,
+            height,
+        };
+
+        let mut command = header(CMD_RESOURCE_CREATE_2D);
+        for field in [resource.id, FORMAT_B8G8R8X8_UNORM, width, height] {
+            command.extend_from_slice(&field.to_le_bytes());
+        }
+        self.command(&command, RESP_OK_NODATA, 0).await?;
+
+        // The kernel heap is physically contiguous, so one entry covers all of it
+        let memory = DmaBuffer::new(
+            width as usize * height as usize * BYTES_PER_PIXEL,
+            self.virt_to_phys,
+        );
+        let mut command = header(CMD_RESOURCE_ATTACH_BACKING);
+        command.extend_from_slice(&resource.id.to_le_bytes());
+        command.extend_from_slice(&1u32.to_le_bytes());
+        command.extend_from_slice(&(memory.physical_address(0) as u64).to_le_bytes());
+        command.extend_from_slice(&(memory.len() as u32).to_le_bytes());
+        command.extend_from_slice(&0u32.to_le_bytes());
+        self.command(&command, RESP_OK_NODATA, 0).await?;
+
+        let mut command = header(CMD_SET_SCANOUT);
+        Rect {
+            x: 0,
+            y: 0,
+            width,
+            height,
+        }
+        .encode(&mut command);
+        command.extend_from_slice(&scanout.to_le_bytes());
+        command.extend_from_slice(&resource.id.to_le_bytes());
+        self.command(&command, RESP_OK_NODATA, 0).await?;
+        Ok(Framebuffer { resource, memory })
+    }
+
+    /// Copies `rect` from the resource's memory to the host, and updates the display with it
+    pub async fn flush(&self, resource: Resource, rect: Rect) -> Result<(), GpuError> {
+        let mut command = header(CMD_TRANSFER_TO_HOST_2D);
+        rect.encode(&mut command);
+        let offset =
+            (rect.y as u64 * resource.width as u64 + rect.x as u64) * BYTES_PER_PIXEL as u64;
+        command.extend_from_slice(&offset.to_le_bytes());
+        command.extend_from_slice(&resource.id.to_le_bytes());
+        command.extend_from_slice(&0u32.to_le_bytes());
+        self.command(&command, RESP_OK_NODATA, 0).await?;
+
+        let mut command = header(CMD_RESOURCE_FLUSH);
+        rect.encode(&mut command);
+        command.extend_from_slice(&resource.id.to_le_bytes());
+        command.extend_from_slice(&0u32.to_le_bytes());
+        self.command(&command, RESP_OK_NODATA, 0).await?;
+        Ok(())
+    }
+}
+
+fn header(kind: u32) -> Vec<u8> {
+    let mut header = vec![0; HEADER_SIZE];
+    header[0..4].copy_from_slice(&kind.to_le_bytes());
+    header
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
@@ -0,0 +1,183 @@
+// virtio-input driver.
+// The device sends evdev events (a type, a code and a value) through the event queue, which
+// is kept full of empty buffers. What the device is and which events it sends is read from
+// the configuration space, after picking the question with `select` and `subsel`.
+// See https://docs.oasis-open.org/virtio/virtio/v1.1/csprd01/virtio-v1.1-csprd01.html#x1-3390008
+
+use alloc::{collections::BTreeMap, string::String, vec::Vec};
+use core::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll, Waker},
+};
+
+use kernel_lock::shared::Mutex;
+
+use super::{device_id, QueueBuffer, SharedVirtioDevice, VirtioDevice, VirtioError, VirtioMmio};
+use crate::dma::DmaBuffer;
+
+// Offsets in the configuration space
+const CONFIG_SELECT: usize = 0;
+const CONFIG_SUBSEL: usize = 1;
+const CONFIG_SIZE: usize = 2;
+const CONFIG_DATA: usize = 8;
+
+// Values of `select`
+const CFG_ID_NAME: u8 = 0x01;
+/// Which codes the device sends for the event type in `subsel`
+const CFG_EV_BITS: u8 = 0x11;
+
+// Event types
+/// Ends a group of events that happened at the same time
+pub const EV_SYN: u16 = 0;
+pub const EV_KEY: u16 = 1;
+
+const EVENT_SIZE: usize = 8;
+
+const EVENT_QUEUE: u16 = 0;
+const QUEUE_SIZE: u16 = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InputEvent {
+    pub kind: u16,
+    pub code: u16,
+    pub value: u32,
+}
+
+pub struct VirtioInput {
+    device: SharedVirtioDevice,
+    /// Empty buffers in the event queue, by token
+    buffers: Mutex<BTreeMap<u16, DmaBuffer>>,
+    name: String,
+    has_keys: bool,
+    virt_to_phys: fn(usize) -> usize,
+}
+
+impl VirtioInput {
+    pub fn new(
+        transport: VirtioMmio,
+        interrupt: u32,
+        virt_to_phys: fn(usize) -> usize,
+        wake_on_interrupt: fn(u32, Waker),
+    ) -> Result<Self, VirtioError> {
+        assert!(transport.device_id() == device_id::INPUT);
+        // The status queue, for LEDs, isn't used
+        let device = VirtioDevice::new(
+            transport,
+            interrupt,
+            |_offered| 0,
+            1,
+            QUEUE_SIZE,
+            virt_to_phys,
+            wake_on_interrupt,
+        )?;
+        let name = read_config(&device.transport, CFG_ID_NAME, 0);
+        let name = String::from_utf8_lossy(&name).into_owned();
+        let has_keys = !read_config(&device.transport, CFG_EV_BITS, EV_KEY as u8).is_empty();
+
+        // The event queue is only filled once events are read, so that devices that are
+        // never read don't send any
+        Ok(Self {
+            device: device.into_shared(),
+            buffers: Mutex::new(BTreeMap::new()),
+            name,
+            has_keys,
+            virt_to_phys,
+        })
+    }
+
+    /// The underlying device, so that its interrupts can be acknowledged
+    pub fn device(&self) -> &SharedVirtioDevice {
+        &self.device
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Whether the device sends key events, which keyboards do
+    pub fn has_keys(&self) -> bool {
+        self.has_keys
+    }
+
+    /// Waits until there are events, and returns all of them
+    pub async fn read_events(&self) -> Vec<InputEvent> {
+        ReadFuture { input: self }.await
+    }
+
+    /// Puts empty buffers in the event queue until it's full
+    fn fill_event_queue(&self, buffers: &mut BTreeMap<u16, DmaBuffer>, device: &mut VirtioDevice) {
+        let mut added = false;
+        while device.queue(EVENT_QUEUE).free_descriptor_count() >= 1 {
+            let buffer = DmaBuffer::with_alignment(EVENT_SIZE, EVENT_SIZE, self.virt_to_phys);
+            let part = QueueBuffer {
+                address: buffer.physical_address(0),
+                len: EVENT_SIZE as u32,
+                device_writable: true,
+            };
+            match device.queue(EVENT_QUEUE).add(&[part]) {
+                Ok(token) => {
+                    buffers.insert(token, buffer);
+                    added = true;
+                }
+                Err(_) => break,
+            }
+        }
+        if added {
+            device.transport.notify(EVENT_QUEUE);
+        }
+    }
+
+    fn poll_events(&self, cx: &mut Context<'_>) -> Poll<Vec<InputEvent>> {
+        let mut buffers = self.buffers.lock();
+        let mut device = self.device.lock();
+        // Register the waker before checking, so that an interrupt that
+        // comes in between isn't missed.
+        device.wake_on_interrupt(cx.waker());
+        let queue = device.queue(EVENT_QUEUE);
+        queue.collect_used();
+        let mut events = Vec::new();
+        for (token, len) in queue.take_completions() {
+            let buffer = match buffers.remove(&token) {
+                Some(buffer) => buffer,
+                None => continue,
+            };
+            if (len as usize) < EVENT_SIZE {
+                continue;
+            }
+            events.push(InputEvent {
+                kind: u16::from_le_bytes([buffer[0], buffer[1]]),
+                code: u16::from_le_bytes([buffer[2], buffer[3]]),
+                value: u32::from_le_bytes([buffer[4], buffer[5], buffer[6], buffer[7]]),
+            });
+        }
+        self.fill_event_queue(&mut buffers, &mut device);
+        if events.is_empty() {
+            Poll::Pending
+        } else {
+            Poll::Ready(events)
+        }
+    }
+}
+
+/// Asks the configuration space about `select` and `subsel`, and returns the answer
+fn read_config(transport: &VirtioMmio, select: u8, subsel: u8) -> Vec<u8> {
+    transport.write_config::<u8>(CONFIG_SELECT, select);
+    transport.write_config::<u8>(CONFIG_SUBSEL, subsel);
+    let size = transport.read_config::<u8>(CONFIG_SIZE) as usize;
+    (0..size)
+        .map(|i| transport.read_config::<u8>(CONFIG_DATA + i))
+        .collect()
+}
+
+struct ReadFuture<'a> {
+    input: &'a VirtioInput,
+}
+
+impl Future for ReadFuture<'_> {
+    type Output = Vec<InputEvent>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Vec<InputEvent>> {
+        self.input.poll_events(cx)
+    }
+}
@@ -0,0 +1,208 @@
+// The virtio-mmio transport. Both the legacy (version 1) and modern (version 2) register layouts
+// are supported, since QEMU uses the legacy one by default.
+// See https://docs.oasis-open.org/virtio/virtio/v1.1/csprd01/virtio-v1.1-csprd01.html#x1-1440002
+
+use super::{queue::VirtQueue, status, VirtioError, VIRTIO_F_VERSION_1};
+
+const MAGIC_VALUE: usize = 0x000;
+const VERSION: usize = 0x004;
+const DEVICE_ID: usize = 0x008;
+const VENDOR_ID: usize = 0x00c;
+const DEVICE_FEATURES: usize = 0x010;
+const DEVICE_FEATURES_SEL: usize = 0x014;
+const DRIVER_FEATURES: usize = 0x020;
+const DRIVER_FEATURES_SEL: usize = 0x024;
+/// Legacy only
+const GUEST_PAGE_SIZE: usize = 0x028;
+const QUEUE_SEL: usize = 0x030;
+const QUEUE_NUM_MAX: usize = 0x034;
+const QUEUE_NUM: usize = 0x038;
+/// Legacy only
+const QUEUE_ALIGN: usize = 0x03c;
+/// Legacy only
+const QUEUE_PFN: usize = 0x040;
+const QUEUE_READY: usize = 0x044;
+const QUEUE_NOTIFY: usize = 0x050;
+const INTERRUPT_STATUS: usize = 0x060;
+const INTERRUPT_ACK: usize = 0x064;
+const STATUS: usize = 0x070;
+const QUEUE_DESC_LOW: usize = 0x080;
+const QUEUE_DESC_HIGH: usize = 0x084;
+const QUEUE_DRIVER_LOW: usize = 0x090;
+const QUEUE_DRIVER_HIGH: usize = 0x094;
+const QUEUE_DEVICE_LOW: usize = 0x0a0;
+const QUEUE_DEVICE_HIGH: usize = 0x0a4;
+const CONFIG: usize = 0x100;
+
+/// "virt" in little endian
+const MAGIC: u32 = 0x7472_6976;
+
+pub const PAGE_SIZE: usize = 4096;
+
+pub struct VirtioMmio {
+    base_addr: usize,
+    version: u32,
+}
+
+impl VirtioMmio {
+    /// Returns None if there's no virtio device at `base_addr`.
+    /// QEMU has several virtio-mmio slots, and the empty ones have a device ID of 0.
+    pub fn probe(base_addr: usize) -> Option<Self> {
+        let this = Self {
+            base_addr,
+            version: 0,
+        };
+        if this.read(MAGIC_VALUE) != MAGIC || this.read(DEVICE_ID) == 0 {
+            return None;
+        }
+        let version = this.read(VERSION);
+        if version != 1 && version != 2 {
+            return None;
+        }
+        Some(Self { base_addr, version })
+    }
+
+    fn read(&self, register: usize) -> u32 {
+        unsafe { ((self.base_addr + register) as *const u32).read_volatile() }
+    }
+
+    fn write(&self, register: usize, value: u32) {
+        unsafe { ((self.base_addr + register) as *mut u32).write_volatile(value) }
+    }
+
+    pub fn is_legacy(&self) -> bool {
+        self.version == 1
+    }
+
+    pub fn device_id(&self) -> u32 {
+        self.read(DEVICE_ID)
+    }
+
+    pub fn vendor_id(&self) -> u32 {
+        self.read(VENDOR_ID)
+    }
+
+    pub fn status(&self) -> u32 {
+        self.read(STATUS)
+    }
+
+    fn add_status(&self, bits: u32) {
+        self.write(STATUS, self.status() | bits);
+    }
+
+    fn device_features(&self) -> u64 {
+        self.write(DEVICE_FEATURES_SEL, 0);
+        let low = self.read(DEVICE_FEATURES) as u64;
+        self.write(DEVICE_FEATURES_SEL, 1);
+        let high = self.read(DEVICE_FEATURES) as u64;
+        high << 32 | low
+    }
+
+    fn set_driver_features(&self, features: u64) {
+        self.write(DRIVER_FEATURES_SEL, 0);
+        self.write(DRIVER_FEATURES, features as u32);
+        self.write(DRIVER_FEATURES_SEL, 1);
+        self.write(DRIVER_FEATURES, (features >> 32) as u32);
+    }
+
+    /// Resets the device and negotiates features. `select_features` gets the features the
+    /// device offers and returns the ones the driver wants. Returns the negotiated features.
+    /// After this, the driver should set up its queues and call `driver_ok`.
+    pub fn init(&self, select_features: impl FnOnce(u64) -> u64) -> Result<u64, VirtioError> {
+        self.write(STATUS, 0);
+        self.add_status(status::ACKNOWLEDGE);
+        self.add_status(status::DRIVER);
+
+        let offered = self.device_features();
+        let mut features = select_features(offered) & offered;
+        if !self.is_legacy() {
+            // Modern devices refuse to work with drivers that don't accept this
+            features |= offered & VIRTIO_F_VERSION_1;
+        }
+        self.set_driver_features(features);
+
+        if self.is_legacy() {
+            self.write(GUEST_PAGE_SIZE, PAGE_SIZE as u32);
+        } else {
+            self.add_status(status::FEATURES_OK);
+            if self.status() & status::FEATURES_OK == 0 {
+                self.add_status(status::FAILED);
+                return Err(VirtioError::FeaturesNotAccepted);
+            }
+        }
+        Ok(features)
+    }
+
+    /// Creates queue `index` with at most `max_size` descriptors, and gives it to the device
+    pub fn setup_queue(
+        &self,
+        index: u16,
+        max_size: u16,
+        virt_to_phys: fn(usize) -> usize,
+    ) -> Result<VirtQueue, VirtioError> {
+        self.write(QUEUE_SEL, index as u32);
+        let device_max = self.read(QUEUE_NUM_MAX);
+        if device_max == 0 {
+            return Err(VirtioError::QueueUnavailable(index));
+        }
+        let already_used = if self.is_legacy() {
+            self.read(QUEUE_PFN) != 0
+        } else {
+            self.read(QUEUE_READY) != 0
+        };
+        if already_used {
+            return Err(VirtioError::QueueUnavailable(index));
+        }
+
+        // Sizes must be a power of 2
+        let size = 1u16 << (device_max.min(max_size as u32).ilog2());
+        let queue = VirtQueue::new(index, size, virt_to_phys);
+        self.write(QUEUE_NUM, size as u32);
+        if self.is_legacy() {
+            self.write(QUEUE_ALIGN, PAGE_SIZE as u32);
+            self.write(QUEUE_PFN, (queue.descriptor_table_address() / PAGE_SIZE) as u32);
+        } else {
+            let (desc, driver, device) = (
+                queue.descriptor_table_address() as u64,
+                queue.available_ring_address() as u64,
+                queue.used_ring_address() as u64,
+            );
+            self.write(QUEUE_DESC_LOW, desc as u32);
+            self.write(QUEUE_DESC_HIGH, (desc >> 32) as u32);
+            self.write(QUEUE_DRIVER_LOW, driver as u32);
+            self.write(QUEUE_DRIVER_HIGH, (driver >> 32) as u32);
+            self.write(QUEUE_DEVICE_LOW, device as u32);
+            self.write(QUEUE_DEVICE_HIGH, (device >> 32) as u32);
+            self.write(QUEUE_READY, 1);
+        }
+        Ok(queue)
+    }
+
+    /// Tells the device that the driver is ready
+    pub fn driver_ok(&self) {
+        self.add_status(status::DRIVER_OK);
+    }
+
+    /// Tells the device that there are new buffers in queue `index`
+    pub fn notify(&self, index: u16) {
+        self.write(QUEUE_NOTIFY, index as u32);
+    }
+
+    /// Acknowledges the interrupt, so that the device stops raising it.
+    /// Returns the interrupt status bits (1 = used buffer, 2 = configuration change).
+    pub fn ack_interrupt(&self) -> u32 {
+        let status = self.read(INTERRUPT_STATUS);
+        self.write(INTERRUPT_ACK, status);
+        status
+    }
+
+    /// Reads a value from the device-specific configuration space
+    pub fn read_config<T: Copy>(&self, offset: usize) -> T {
+        unsafe { ((self.base_addr + CONFIG + offset) as *const T).read_volatile() }
+    }
+
+    /// Writes a value into the device-specific configuration space
+    pub fn write_config<T: Copy>(&self, offset: usize, value: T) {
+        unsafe { ((self.base_addr + CONFIG + offset) as *mut T).write_volatile(value) }
+    }
+}
@@ -0,0 +1,249 @@
+// Virtio devices over the MMIO transport.
+// A `VirtioDevice` owns the transport and its queues, and lets device drivers make requests
+// with `VirtioDevice::request`, which completes when the device's interrupt says the request
+// is done.
+
+pub mod block;
+pub mod gpu;
+pub mod input;
+pub mod mmio;
+pub mod net;
+pub mod queue;
+pub mod rng;
+
+use alloc::{sync::Arc, vec::Vec};
+use core::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll, Waker},
+};
+
+use kernel_lock::shared::Mutex;
+pub use mmio::VirtioMmio;
+pub use queue::{QueueBuffer, VirtQueue};
+
+use crate::dma::DmaBuffer;
+
+/// Device status bits
+pub mod status {
+    pub const ACKNOWLEDGE: u32 = 1;
+    pub const DRIVER: u32 = 2;
+    pub const DRIVER_OK: u32 = 4;
+    pub const FEATURES_OK: u32 = 8;
+    pub const DEVICE_NEEDS_RESET: u32 = 64;
+    pub const FAILED: u32 = 128;
+}
+
+/// Device IDs from the specification
+pub mod device_id {
+    pub const NETWORK: u32 = 1;
+    pub const BLOCK: u32 = 2;
+    pub const CONSOLE: u32 = 3;
+    pub const ENTROPY: u32 = 4;
+    pub const GPU: u32 = 16;
+    pub const INPUT: u32 = 18;
+}
+
+pub const VIRTIO_F_VERSION_1: u64 = 1 << 32;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VirtioError {
+    /// The device didn't accept the features we selected
+    FeaturesNotAccepted,
+    /// The queue doesn't exist or is already in use
+    QueueUnavailable(u16),
+    /// There aren't enough free descriptors for the request
+    QueueFull,
+    EmptyRequest,
+}
+
+pub struct VirtioDevice {
+    pub transport: VirtioMmio,
+    pub interrupt: u32,
+    pub features: u64,
+    queues: Vec<VirtQueue>,
+    /// Makes a waker get woken the next time an external interrupt fires
+    wake_on_interrupt: fn(u32, Waker),
+    /// Wakers that are already registered for the next interrupt
+    waiting: Vec<Waker>,
+}
+
+pub type SharedVirtioDevice = Arc<Mutex<VirtioDevice>>;
+
+/// What a request returns: the amount of bytes the device wrote, and the request's memory
+pub type RequestResult = Result<(u32, Vec<DmaBuffer>), VirtioError>;
+
+impl VirtioDevice {
+    /// Initializes the device, negotiating features with `select_features`, and sets up
+    /// `queue_count` queues with at most `max_queue_size` descriptors each.
+    pub fn new(
+        transport: VirtioMmio,
+        interrupt: u32,
+        select_features: impl FnOnce(u64) -> u64,
+        queue_count: u16,
+        max_queue_size: u16,
+        virt_to_phys: fn(usize) -> usize,
+        wake_on_interrupt: fn(u32, Waker),
+    ) -> Result<Self, VirtioError> {
+        let features = transport.init(select_features)?;
+        let queues = (0..queue_count)
+            .map(|index| transport.setup_queue(index, max_queue_size, virt_to_phys))
+            .collect::<Result<Vec<_>, _>>()?;
+        transport.driver_ok();
+        Ok(Self {
+            transport,
+            interrupt,
+            features,
+            queues,
+            wake_on_interrupt,
+            waiting: Vec::new(),
+        })
+    }
+
+    pub fn into_shared(self) -> SharedVirtioDevice {
+        Arc::new(Mutex::new(self))
+    }
+
+    pub fn queue(&mut self, index: u16) -> &mut VirtQueue {
+        &mut self.queues[index as usize]
+    }
+
+    /// Wakes `waker` the next time the device's interrupt fires. A waker that's already
+    /// registered isn't registered again.
+    pub fn wake_on_interrupt(&mut self, waker: &Waker) {
+        if !self.waiting.iter().any(|waiting| waiting.will_wake(waker)) {
+            self.waiting.push(waker.clone());
+            (self.wake_on_interrupt)(self.interrupt, waker.clone());
+        }
+    }
+
+    /// Should be called when the device's interrupt fires. Acknowledges the interrupt and
+    /// collects finished requests from all queues.
+    pub fn handle_interrupt(&mut self) {
+        self.transport.ack_interrupt();
+        // The interrupt wakes all of them
+        self.waiting.clear();
+        for queue in self.queues.iter_mut() {
+            queue.collect_used();
+        }
+    }
+
+    /// Sends a request made of `buffers` through queue `queue`. `memory` is where the
+    /// buffers are, and is given back when the request is complete, with the amount of bytes
+    /// the device wrote.
+    ///
+    /// If the future is dropped before that, the queue keeps `memory` until the device has
+    /// finished with it.
+    pub fn request(
+        device: &SharedVirtioDevice,
+        queue: u16,
+        buffers: Vec<QueueBuffer>,
+        memory: Vec<DmaBuffer>,
+    ) -> RequestFuture {
+        RequestFuture {
+            device: device.clone(),
+            queue,
+            state: RequestState::NotSubmitted(buffers),
+            memory,
+        }
+    }
+}
+
+enum RequestState {
+    NotSubmitted(Vec<QueueBuffer>),
+    Submitted(u16),
+    Done,
+}
+
+pub struct RequestFuture {
+    device: SharedVirtioDevice,
+    queue: u16,
+    state: RequestState,
+    memory: Vec<DmaBuffer>,
+}
+
+impl Future for RequestFuture {
+    type Output = RequestResult;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = &mut *self;
+        let mut device = this.device.lock();
+        // Register the waker before checking, so that an interrupt that
+        // comes in between isn't missed.
+        device.wake_on_interrupt(cx.waker());
+
+        let queue_index = this.queue;
+        match &this.state {
+            RequestState::NotSubmitted(buffers) => {
+                match device.queue(queue_index).add(buffers) {
+                    Ok(token) => {
+                        this.state = RequestState::Submitted(token);
+                        device.transport.notify(queue_index);
+                    }
+                    // Wait until some other request finishes
+                    Err(VirtioError::QueueFull) => device.queue(queue_index).collect_used(),
+                    Err(error) => {
+                        this.state = RequestState::Done;
+                        return Poll::Ready(Err(error));
+                    }
+                }
+                Poll::Pending
+            }
+            RequestState::Submitted(token) => {
+                let token = *token;
+                let queue = device.queue(queue_index);
+                queue.collect_used();
+                match queue.take_completion(token) {
+                    Some(len) => {
+                        this.state = RequestState::Done;
+                        Poll::Ready(Ok((len, core::mem::take(&mut this.memory))))
+                    }
+                    None => Poll::Pending,
+                }
+            }
+            RequestState::Done => panic!("RequestFuture polled after completion"),
+        }
+    }
+}
+
+impl Drop for RequestFuture {
+    fn drop(&mut self) {
+        if let RequestState::Submitted(token) = self.state {
+            let memory = core::mem::take(&mut self.memory);
+            self.device.lock().queue(self.queue).abandon(token, memory);
+        }
+    }
+}
+
+/// Waits for several requests at the same time, and returns their results in order
+pub fn join_all(requests: Vec<RequestFuture>) -> JoinAll {
+    JoinAll {
+        results: requests.iter().map(|_| None).collect(),
+        requests,
+    }
+}
+
+pub struct JoinAll {
+    requests: Vec<RequestFuture>,
+    results: Vec<Option<RequestResult>>,
+}
+
+impl Future for JoinAll {
+    type Output = Vec<RequestResult>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = &mut *self;
+        for (request, result) in this.requests.iter_mut().zip(this.results.iter_mut()) {
+            if result.is_none() {
+                if let Poll::Ready(output) = Pin::new(request).poll(cx) {
+                    *result = Some(output);
+                }
+            }
+        }
+        if this.results.iter().all(Option::is_some) {
+            Poll::Ready(this.results.drain(..).map(Option::unwrap).collect())
+        } else {
+            Poll::Pending
+        }
+    }
+}
@@ -0,0 +1,355 @@
+// virtio-net driver.
+// Every frame is preceded by a header, which says whether the checksum of the frame still
+// has to be computed. The receive queue is kept full of empty buffers, which are replaced as
+// received frames are taken out of it. Buffers of sent frames are freed once the device is
+// done with them.
+// See https://docs.oasis-open.org/virtio/virtio/v1.1/csprd01/virtio-v1.1-csprd01.html#x1-1940001
+
+use alloc::{
+    boxed::Box,
+    collections::{BTreeMap, VecDeque},
+    vec::Vec,
+};
+use core::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll, Waker},
+};
+
+use async_trait::async_trait;
+use kernel_io::{checksum, NetDevice};
+use kernel_lock::shared::Mutex;
+
+use super::{
+    device_id, QueueBuffer, SharedVirtioDevice, VirtioDevice, VirtioError, VirtioMmio,
+    VIRTIO_F_VERSION_1,
+};
+use crate::{
+    dma::DmaBuffer,
+    net::{NetError, ETHERNET_HEADER_SIZE, ETHERNET_MTU, RX_QUEUE_SIZE},
+};
+
+/// The device can finish checksums of frames we send
+const VIRTIO_NET_F_CSUM: u64 = 1 << 0;
+/// We can finish checksums of frames we receive
+const VIRTIO_NET_F_GUEST_CSUM: u64 = 1 << 1;
+const VIRTIO_NET_F_MTU: u64 = 1 << 3;
+const VIRTIO_NET_F_MAC: u64 = 1 << 5;
+
+const VIRTIO_NET_HDR_F_NEEDS_CSUM: u8 = 1;
+
+// Header fields
+const HEADER_FLAGS: usize = 0;
+const HEADER_CSUM_START: usize = 6;
+const HEADER_CSUM_OFFSET: usize = 8;
+/// Legacy devices leave out the last field, unless mergeable receive buffers are used
+const LEGACY_HEADER_SIZE: usize = 10;
+const HEADER_SIZE: usize = 12;
+
+// Offsets in the configuration space
+const CONFIG_MAC: usize = 0;
+const CONFIG_MTU: usize = 10;
+
+const RECEIVE_QUEUE: u16 = 0;
+const TRANSMIT_QUEUE: u16 = 1;
+const QUEUE_SIZE: u16 = 64;
+
+/// For devices that don't have an address. It's locally administered, so it can't be a
+/// vendor's.
+const DEFAULT_MAC: [u8; 6] = [0x02, 0, 0, 0, 0, 0x01];
+
+struct Buffers {
+    /// Empty buffers in the receive queue, by token
+    rx: BTreeMap<u16, DmaBuffer>,
+    /// Frames that are being sent, by token
+    tx: BTreeMap<u16, DmaBuffer>,
+    received: VecDeque<Vec<u8>>,
+    /// Frames that were received while `received` was full
+    dropped_frames: usize,
+}
+
+pub struct VirtioNet {
+    device: SharedVirtioDevice,
+    buffers: Mutex<Buffers>,
+    mac_address: [u8; 6],
+    mtu: usize,
+    header_size: usize,
+    /// Whether the device finishes checksums of frames we send
+    offloads_checksums: bool,
+    virt_to_phys: fn(usize) -> usize,
+}
+
+impl VirtioNet {
+    pub fn new(
+        transport: VirtioMmio,
+        interrupt: u32,
+        virt_to_phys: fn(usize) -> usize,
+        wake_on_interrupt: fn(u32, Waker),
+    ) -> Result<Self, VirtioError> {
+        assert!(transport.device_id() == device_id::NETWORK);
+        let device = VirtioDevice::new(
+            transport,
+            interrupt,
+            |_offered| {
+                VIRTIO_NET_F_CSUM | VIRTIO_NET_F_GUEST_CSUM | VIRTIO_NET_F_MTU | VIRTIO_NET_F_MAC
+            },
+            2,
+            QUEUE_SIZE,
+            virt_to_phys,
+            wake_on_interrupt,
+        )?;
+        let features = device.features;
+        let mac_address = if features & VIRTIO_NET_F_MAC != 0 {
+            let mut mac = [0; 6];
+            for (i, byte) in mac.iter_mut().enumerate() {
+                *byte = device.transport.read_config::<u8>(CONFIG_MAC + i);
+            }
+            mac
+        } else {
+            DEFAULT_MAC
+        };
+        let mtu = if features & VIRTIO_NET_F_MTU != 0 {
+            device.transport.read_config::<u16>(CONFIG_MTU) as usize
+        } else {
+            ETHERNET_MTU
+        };
+        let header_size = if features & VIRTIO_F_VERSION_1 != 0 {
+            HEADER_SIZE
+        } else {
+            LEGACY_HEADER_SIZE
+        };
+
+        let net = Self {
+            device: device.into_shared(),
+            buffers: Mutex::new(Buffers {
+                rx: BTreeMap::new(),
+                tx: BTreeMap::new(),
+                received: VecDeque::new(),
+                dropped_frames: 0,
+            }),
+            mac_address,
+            mtu,
+            header_size,
+            offloads_checksums: features & VIRTIO_NET_F_CSUM != 0,
+            virt_to_phys,
+        };
+        net.fill_receive_queue(&mut net.buffers.lock(), &mut net.device.lock());
+        Ok(net)
+    }
+
+    /// The underlying device, so that its interrupts can be acknowledged
+    pub fn device(&self) -> &SharedVirtioDevice {
+        &self.device
+    }
+
+    pub fn dropped_frames(&self) -> usize {
+        self.buffers.lock().dropped_frames
+    }
+
+    /// The header and frame are in separate descriptors, since legacy devices want that
+    fn parts(&self, buffer: &DmaBuffer, device_writable: bool) -> [QueueBuffer; 2] {
+        [
+            QueueBuffer {
+                address: buffer.physical_address(0),
+                len: self.header_size as u32,
+                device_writable,
+            },
+            QueueBuffer {
+                address: buffer.physical_address(self.header_size),
+                len: (buffer.len() - self.header_size) as u32,
+                device_writable,
+            },
+        ]
+    }
+
+    /// Puts empty buffers in the receive queue until it's full
+    fn fill_receive_queue(&self, buffers: &mut Buffers, device: &mut VirtioDevice) {
+        let size = self.header_size + ETHERNET_HEADER_SIZE + self.mtu;
+        let mut added = false;
+        while device.queue(RECEIVE_QUEUE).free_descriptor_count() >= 2 {
+            let buffer = DmaBuffer::with_alignment(size, 16, self.virt_to_phys);
+            match device.queue(RECEIVE_QUEUE).add(&self.parts(&buffer, true)) {
+                Ok(token) => {
+                    buffers.rx.insert(token, buffer);
+                    added = true;
+                }
+                Err(_) => break,
+            }
+        }
+        if added {
+            device.transport.notify(RECEIVE_QUEUE);
+        }
+    }
+
+    /// Moves frames the device has received into `received`
+    fn take_received(&self, buffers: &mut Buffers, device: &mut VirtioDevice) {
+        let queue = device.queue(RECEIVE_QUEUE);
+        queue.collect_used();
+        for (token, len) in queue.take_completions() {
+            let buffer = match buffers.rx.remove(&token) {
+                Some(buffer) => buffer,
+                None => continue,
+            };
+            let len = (len as usize).min(buffer.len());
+            if len < self.header_size + ETHERNET_HEADER_SIZE {
+                continue;
+            }
+            if buffers.received.len() >= RX_QUEUE_SIZE {
+                buffers.dropped_frames += 1;
+                continue;
+            }
+            let mut frame = buffer[self.header_size..len].to_vec();
+            if buffer[HEADER_FLAGS] & VIRTIO_NET_HDR_F_NEEDS_CSUM != 0 {
+                // Usually a frame from the host, which never went through a real network
+                let field =
+                    |offset: usize| u16::from_le_bytes([buffer[offset], buffer[offset + 1]]);
+                checksum::fill(
+                    &mut frame,
+                    field(HEADER_CSUM_START) as usize,
+                    field(HEADER_CSUM_OFFSET) as usize,
+                );
+            }
+            buffers.received.push_back(frame);
+        }
+    }
+
+    /// Frees the buffers of frames that have been sent
+    fn reclaim_sent(&self, buffers: &mut Buffers, device: &mut VirtioDevice) {
+        let queue = device.queue(TRANSMIT_QUEUE);
+        queue.collect_used();
+        for (token, _) in queue.take_completions() {
+            buffers.tx.remove(&token);
+        }
+    }
+
+    fn poll_send(
+        &self,
+        cx: &mut Context<'_>,
+        frame: &[u8],
+        checksum: Option<(usize, usize)>,
+    ) -> Poll<Result<(), NetError>> {
+        let mut buffers = self.buffers.lock();
+        let mut device = self.device.lock();
+        // Register the waker before checking, so that an interrupt that
+        // comes in between isn't missed.
+        device.wake_on_interrupt(cx.waker());
+        self.reclaim_sent(&mut buffers, &mut device);
+        if device.queue(TRANSMIT_QUEUE).free_descriptor_count() < 2 {
+            return Poll::Pending;
+        }
+
+        let mut buffer =
+            DmaBuffer::with_alignment(self.header_size + frame.len(), 16, self.virt_to_phys);
+        let (header, data) = buffer.split_at_mut(self.header_size);
+        data.copy_from_slice(frame);
+        match checksum {
+            Some((start, offset)) if self.offloads_checksums => {
+                header[HEADER_FLAGS] = VIRTIO_NET_HDR_F_NEEDS_CSUM;
+                header[HEADER_CSUM_START..HEADER_CSUM_START + 2]
+                    .copy_from_slice(&(start as u16).to_le_bytes());
+                header[HEADER_CSUM_OFFSET..HEADER_CSUM_OFFSET + 2]
+                    .copy_from_slice(&(offset as u16).to_le_bytes());
+            }
+            Some((start, offset)) => checksum::fill(data, start, offset),
+            None => {}
+        }
+        let token = device
+            .queue(TRANSMIT_QUEUE)
+            .add(&self.parts(&buffer, false))?;
+        buffers.tx.insert(token, buffer);
+        device.transport.notify(TRANSMIT_QUEUE);
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_receive(&self, cx: &mut Context<'_>) -> Poll<Vec<u8>> {
+        let mut buffers = self.buffers.lock();
+        let mut device = self.device.lock();
+        device.wake_on_interrupt(cx.waker());
+        self.take_received(&mut buffers, &mut device);
+        self.fill_receive_queue(&mut buffers, &mut device);
+        match buffers.received.pop_front() {
+            Some(frame) => Poll::Ready(frame),
+            None => Poll::Pending,
+        }
+    }
+
+    fn check_size(&self, frame: &[u8]) -> Result<(), NetError> {
+        if frame.len() > ETHERNET_HEADER_SIZE + self.mtu {
+            Err(NetError::FrameTooLarge)
+        } else if frame.len() < ETHERNET_HEADER_SIZE {
+            Err(NetError::FrameTooSmall)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+struct SendFuture<'a> {
+    net: &'a VirtioNet,
+    frame: &'a [u8],
+    /// Where the checksum starts, and where it goes relative to that
+    checksum: Option<(usize, usize)>,
+}
+
+impl Future for SendFuture<'_> {
+    type Output = Result<(), NetError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.net.poll_send(cx, self.frame, self.checksum)
+    }
+}
+
+struct ReceiveFuture<'a> {
+    net: &'a VirtioNet,
+}
+
+impl Future for ReceiveFuture<'_> {
+    type Output = Vec<u8>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Vec<u8>> {
+        self.net.poll_receive(cx)
+    }
+}
+
+#[async_trait]
+impl NetDevice for VirtioNet {
+    type Error = NetError;
+
+    fn mac_address(&self) -> [u8; 6] {
+        self.mac_address
+    }
+
+    fn mtu(&self) -> usize {
+        self.mtu
+    }
+
+    /// Waits until there's room in the transmit queue, and puts `frame` there
+    async fn send(&self, frame: &[u8]) -> Result<(), NetError> {
+        self.check_size(frame)?;
+        SendFuture {
+            net: self,
+            frame,
+            checksum: None,
+        }
+        .await
+    }
+
+    async fn receive(&self) -> Result<Vec<u8>, NetError> {
+        Ok(ReceiveFuture { net: self }.await)
+    }
+
+    async fn send_with_checksum(
+        &self,
+        frame: &[u8],
+        checksum_start: usize,
+        checksum_offset: usize,
+    ) -> Result<(), NetError> {
+        self.check_size(frame)?;
+        SendFuture {
+            net: self,
+            frame,
+            checksum: Some((checksum_start, checksum_offset)),
+        }
+        .await
+    }
+}
@@ -0,0 +1,248 @@
+// Split virtqueues.
+// The descriptor table, available ring and used ring are allocated together using the legacy
+// layout, which also works for modern devices.
+// See https://docs.oasis-open.org/virtio/virtio/v1.1/csprd01/virtio-v1.1-csprd01.html#x1-240006
+
+use alloc::{boxed::Box, collections::BTreeMap, vec::Vec};
+use core::{
+    mem::size_of,
+    sync::atomic::{fence, Ordering},
+};
+
+use kernel_util::boxed_slice_with_alignment;
+
+use super::{mmio::PAGE_SIZE, VirtioError};
+use crate::dma::DmaBuffer;
+
+const DESC_F_NEXT: u16 = 1;
+const DESC_F_WRITE: u16 = 2;
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+struct Descriptor {
+    address: u64,
+    len: u32,
+    flags: u16,
+    next: u16,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct UsedElement {
+    id: u32,
+    len: u32,
+}
+
+/// A buffer that is part of a request, by physical address
+#[derive(Clone, Copy, Debug)]
+pub struct QueueBuffer {
+    pub address: usize,
+    pub len: u32,
+    /// Whether the device writes into this buffer (otherwise, it reads from it)
+    pub device_writable: bool,
+}
+
+impl QueueBuffer {
+    pub fn readable(data: &[u8], virt_to_phys: fn(usize) -> usize) -> Self {
+        Self {
+            address: virt_to_phys(data.as_ptr() as usize),
+            len: data.len() as u32,
+            device_writable: false,
+        }
+    }
+
+    pub fn writable(data: &mut [u8], virt_to_phys: fn(usize) -> usize) -> Self {
+        Self {
+            address: virt_to_phys(data.as_ptr() as usize),
+            len: data.len() as u32,
+            device_writable: true,
+        }
+    }
+}
+
+pub struct VirtQueue {
+    index: u16,
+    size: u16,
+    memory: Box<[u8]>,
+    available_offset: usize,
+    used_offset: usize,
+    virt_to_phys: fn(usize) -> usize,
+    /// Descriptors that aren't part of any request
+    free_descriptors: Vec<u16>,
+    /// Index in the used ring that we haven't looked at yet
+    last_used: u16,
+    /// Requests that the device has completed, by their first descriptor, with the
+    /// amount of bytes the device wrote. In the order the device completed them.
+    completed: Vec<(u16, u32)>,
+    /// Memory of requests that nobody waits for anymore, by their first descriptor.
+    /// The device may still access it, so it's only freed once the request is complete.
+    abandoned: BTreeMap<u16, Vec<DmaBuffer>>,
+}
+
+// SAFETY: The queue's memory is only accessed through `&mut self`
+unsafe impl Send for VirtQueue {}
+
+impl VirtQueue {
+    pub(super) fn new(index: u16, size: u16, virt_to_phys: fn(usize) -> usize) -> Self {
+        let n = size as usize;
+        let available_offset = n * size_of::<Descriptor>();
+        // flags, idx, ring, used_event
+        let available_size = 2 + 2 + 2 * n + 2;
+        let used_offset = (available_offset + available_size).div_ceil(PAGE_SIZE) * PAGE_SIZE;
+        // flags, idx, ring, avail_event
+        let used_size = 2 + 2 + size_of::<UsedElement>() * n + 2;
+        let total_size = (used_offset + used_size).div_ceil(PAGE_SIZE) * PAGE_SIZE;
+
+        Self {
+            index,
+            size,
+            memory: boxed_slice_with_alignment(total_size, PAGE_SIZE, &0),
+            available_offset,
+            used_offset,
+            virt_to_phys,
+            free_descriptors: (0..size).rev().collect(),
+            last_used: 0,
+            completed: Vec::new(),
+            abandoned: BTreeMap::new(),
+        }
+    }
+
+    pub fn index(&self) -> u16 {
+        self.index
+    }
+
+    pub fn size(&self) -> u16 {
+        self.size
+    }
+
+    fn base(&self) -> usize {
+        self.memory.as_ptr() as usize
+    }
+
+    pub fn descriptor_table_address(&self) -> usize {
+        (self.virt_to_phys)(self.base())
+    }
+
+    pub fn available_ring_address(&self) -> usize {
+        (self.virt_to_phys)(self.base() + self.available_offset)
+    }
+
+    pub fn used_ring_address(&self) -> usize {
+        (self.virt_to_phys)(self.base() + self.used_offset)
+    }
+
+    fn descriptor(&mut self, index: u16) -> *mut Descriptor {
+        (self.base() as *mut Descriptor).wrapping_add(index as usize)
+    }
+
+    /// Pointer to the `idx` field of the available ring. The ring itself comes right after it.
+    fn available_idx(&self) -> *mut u16 {
+        (self.base() + self.available_offset + 2) as *mut u16
+    }
+
+    fn used_idx(&self) -> *const u16 {
+        (self.base() + self.used_offset + 2) as *const u16
+    }
+
+    pub fn free_descriptor_count(&self) -> usize {
+        self.free_descriptors.len()
+    }
+
+    /// Puts a request made of `buffers` in the available ring. Device-readable buffers must come
+    /// before device-writable ones. Returns a token to pass to `take_completion`.
+    /// The device has to be notified afterwards.
+    pub fn add(&mut self, buffers: &[QueueBuffer]) -> Result<u16, VirtioError> {
+        if buffers.is_empty() {
+            return Err(VirtioError::EmptyRequest);
+        }
+        if buffers.len() > self.free_descriptors.len() {
+            return Err(VirtioError::QueueFull);
+        }
+        let indices: Vec<u16> = (0..buffers.len())
+            .map(|_| self.free_descriptors.pop().unwrap())
+            .collect();
+        for (i, buffer) in buffers.iter().enumerate() {
+            let mut flags = 0;
+            if buffer.device_writable {
+                flags |= DESC_F_WRITE;
+            }
+            let next = if let Some(next) = indices.get(i + 1) {
+                flags |= DESC_F_NEXT;
+                *next
+            } else {
+                0
+            };
+            let descriptor = Descriptor {
+                address: buffer.address as u64,
+                len: buffer.len,
+                flags,
+                next,
+            };
+            unsafe { self.descriptor(indices[i]).write_volatile(descriptor) };
+        }
+
+        let head = indices[0];
+        unsafe {
+            let idx = self.available_idx().read_volatile();
+            let ring = self.available_idx().add(1);
+            ring.add((idx % self.size) as usize).write_volatile(head);
+            // The device must see the ring entry before the new index
+            fence(Ordering::SeqCst);
+            self.available_idx().write_volatile(idx.wrapping_add(1));
+            fence(Ordering::SeqCst);
+        }
+        Ok(head)
+    }
+
+    /// Moves requests that the device has finished from the used ring into `completed`,
+    /// and frees their descriptors. The memory of abandoned requests is freed instead.
+    pub fn collect_used(&mut self) {
+        loop {
+            fence(Ordering::SeqCst);
+            let used_idx = unsafe { self.used_idx().read_volatile() };
+            if used_idx == self.last_used {
+                return;
+            }
+            let element = unsafe {
+                let ring = self.used_idx().add(1) as *const UsedElement;
+                ring.add((self.last_used % self.size) as usize).read_volatile()
+            };
+            self.last_used = self.last_used.wrapping_add(1);
+
+            let head = element.id as u16;
+            let mut index = head;
+            loop {
+                let descriptor = unsafe { self.descriptor(index).read_volatile() };
+                self.free_descriptors.push(index);
+                if descriptor.flags & DESC_F_NEXT == 0 {
+                    break;
+                }
+                index = descriptor.next;
+            }
+            if self.abandoned.remove(&head).is_none() {
+                self.completed.push((head, element.len));
+            }
+        }
+    }
+
+    /// Gives up on the request with this token, and keeps `memory` alive until the device
+    /// has finished with it
+    pub fn abandon(&mut self, token: u16, memory: Vec<DmaBuffer>) {
+        if self.take_completion(token).is_none() {
+            self.abandoned.insert(token, memory);
+        }
+    }
+
+    /// If the request started by `add` with this token is complete, returns how many
+    /// bytes the device wrote
+    pub fn take_completion(&mut self, token: u16) -> Option<u32> {
+        let index = self.completed.iter().position(|(head, _)| *head == token)?;
+        Some(self.completed.remove(index).1)
+    }
+
+    /// Takes every completed request in the order they were completed, for queues where
+    /// the driver doesn't wait for requests one by one
+    pub fn take_completions(&mut self) -> Vec<(u16, u32)> {
+        core::mem::take(&mut self.completed)
+    }
+}
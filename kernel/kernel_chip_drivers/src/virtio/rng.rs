@@ -0,0 +1,62 @@
+// virtio-rng driver.
+// The device has a single request queue. The driver adds empty buffers, and the device fills
+// them with random bytes; the used length says how many it wrote, which may be fewer than
+// asked for.
+// See https://docs.oasis-open.org/virtio/virtio/v1.1/csprd01/virtio-v1.1-csprd01.html#x1-2700004
+
+use alloc::{vec, vec::Vec};
+use core::task::Waker;
+
+use super::{device_id, QueueBuffer, SharedVirtioDevice, VirtioDevice, VirtioError, VirtioMmio};
+use crate::dma::DmaBuffer;
+
+const REQUEST_QUEUE: u16 = 0;
+const QUEUE_SIZE: u16 = 8;
+
+pub struct VirtioRng {
+    device: SharedVirtioDevice,
+    virt_to_phys: fn(usize) -> usize,
+}
+
+impl VirtioRng {
+    pub fn new(
+        transport: VirtioMmio,
+        interrupt: u32,
+        virt_to_phys: fn(usize) -> usize,
+        wake_on_interrupt: fn(u32, Waker),
+    ) -> Result<Self, VirtioError> {
+        assert!(transport.device_id() == device_id::ENTROPY);
+        let device = VirtioDevice::new(
+            transport,
+            interrupt,
+            |_offered| 0,
+            1,
+            QUEUE_SIZE,
+            virt_to_phys,
+            wake_on_interrupt,
+        )?;
+        Ok(Self {
+            device: device.into_shared(),
+            virt_to_phys,
+        })
+    }
+
+    /// The underlying device, so that its interrupts can be acknowledged
+    pub fn device(&self) -> &SharedVirtioDevice {
+        &self.device
+    }
+
+    /// Reads at most `len` random bytes
+    pub async fn read(&self, len: usize) -> Result<Vec<u8>, VirtioError> {
+        // Aligned to its size, so that it doesn't cross a page boundary
+        let buffer = DmaBuffer::with_alignment(len, len.next_power_of_two(), self.virt_to_phys);
+        let buffers = vec![QueueBuffer {
+            address: buffer.physical_address(0),
+            len: len as u32,
+            device_writable: true,
+        }];
+        let (written, memory) =
+            VirtioDevice::request(&self.device, REQUEST_QUEUE, buffers, vec![buffer]).await?;
+        Ok(memory[0][..(written as usize).min(len)].to_vec())
+    }
+}
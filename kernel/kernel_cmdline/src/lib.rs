@@ -0,0 +1,251 @@
+//! The kernel command line, which comes from `bootargs` in the device tree's `/chosen` node
+//! (QEMU sets it with `-append`).
+//!
+//! It's a list of whitespace-separated arguments, each either `name=value` or a bare `name`.
+//! Values can be put in double quotes to include spaces. Any crate can read an option by
+//! declaring a [`Param`]:
+//!
+//! ```ignore
+//! pub static TIMESLICE: Param<u64> = Param::new("timeslice", 0x0010_0000);
+//! ```
+
+#![cfg_attr(not(test), no_std)]
+
+static CMDLINE: spin::Once<&'static str> = spin::Once::new();
+
+/// Sets the command line. Only the first call has any effect.
+pub fn init(cmdline: &'static str) {
+    CMDLINE.call_once(|| cmdline);
+}
+
+/// The whole command line, or an empty one before [`init`] is called
+pub fn cmdline() -> &'static str {
+    CMDLINE.get().copied().unwrap_or("")
+}
+
+/// Iterates over the `(name, value)` pairs in a command line. `value` is `None` for
+/// arguments without a `=`, and quotes around it are removed.
+pub fn arguments(cmdline: &str) -> Arguments<'_> {
+    Arguments { rest: cmdline }
+}
+
+pub struct Arguments<'a> {
+    rest: &'a str,
+}
+
+impl<'a> Iterator for Arguments<'a> {
+    type Item = (&'a str, Option<&'a str>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let rest = self.rest.trim_start();
+        if rest.is_empty() {
+            self.rest = rest;
+            return None;
+        }
+        let mut in_quotes = false;
+        let end = rest
+            .char_indices()
+            .find(|(_, c)| {
+                if *c == '"' {
+                    in_quotes = !in_quotes;
+                }
+                c.is_whitespace() && !in_quotes
+            })
+            .map(|(index, _)| index)
+            .unwrap_or(rest.len());
+        let (argument, rest) = rest.split_at(end);
+        self.rest = rest;
+
+        Some(match argument.split_once('=') {
+            Some((name, value)) => {
+                let value = value
+                    .strip_prefix('"')
+                    .and_then(|value| value.strip_suffix('"'))
+                    .unwrap_or(value);
+                (name, Some(value))
+            }
+            None => (argument, None),
+        })
+    }
+}
+
+/// A type that a command line option can have
+pub trait Value: Sized {
+    /// `value` is `None` if the option was given without a `=`.
+    /// Returns `None` if the value is invalid.
+    fn parse(value: Option<&'static str>) -> Option<Self>;
+}
+
+/// Flags are true if they're given without a value
+impl Value for bool {
+    fn parse(value: Option<&'static str>) -> Option<Self> {
+        match value {
+            None | Some("1" | "y" | "yes" | "on" | "true") => Some(true),
+            Some("0" | "n" | "no" | "off" | "false") => Some(false),
+            Some(_) => None,
+        }
+    }
+}
+
+impl Value for &'static str {
+    fn parse(value: Option<&'static str>) -> Option<Self> {
+        value
+    }
+}
+
+macro_rules! impl_value_for_integer {
+    ($($t:ty),*) => {
+        $(
+            /// Accepts `0x`, `0o` and `0b` prefixes, and `_` between digits
+            impl Value for $t {
+                fn parse(value: Option<&'static str>) -> Option<Self> {
+                    let value = value?;
+                    let (digits, radix) = if let Some(digits) = value.strip_prefix("0x") {
+                        (digits, 16)
+                    } else if let Some(digits) = value.strip_prefix("0o") {
+                        (digits, 8)
+                    } else if let Some(digits) = value.strip_prefix("0b") {
+                        (digits, 2)
+                    } else {
+                        (value, 10)
+                    };
+                    if digits.starts_with('_') || digits.ends_with('_') {
+                        return None;
+                    }
+                    let mut result: $t = 0;
+                    let mut any_digits = false;
+                    for c in digits.chars().filter(|c| *c != '_') {
+                        let digit = c.to_digit(radix)?;
+                        result = result.checked_mul(radix as $t)?.checked_add(digit as $t)?;
+                        any_digits = true;
+                    }
+                    if any_digits {
+                        Some(result)
+                    } else {
+                        None
+                    }
+                }
+            }
+        )*
+    };
+}
+
+impl_value_for_integer!(u8, u16, u32, u64, usize);
+
+/// A typed command line option. It can be declared as a `static` anywhere and read after
+/// [`init`]. The command line is only parsed the first time [`Param::get`] is called after
+/// that, and the value is kept, since some options are read very often.
+pub struct Param<T> {
+    name: &'static str,
+    default: T,
+    value: spin::Once<T>,
+}
+
+impl<T> Param<T> {
+    pub const fn new(name: &'static str, default: T) -> Self {
+        Self {
+            name,
+            default,
+            value: spin::Once::new(),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+}
+
+impl<T: Value + Copy> Param<T> {
+    /// If the option is given more than once, the last one wins.
+    /// Invalid values are ignored. Before [`init`], this is the default.
+    pub fn get(&self) -> T {
+        match CMDLINE.get() {
+            Some(cmdline) => *self.value.call_once(|| self.get_from(cmdline)),
+            None => self.default,
+        }
+    }
+
+    /// Whether the option was given at all, even if its value is invalid
+    pub fn is_present(&self) -> bool {
+        arguments(cmdline()).any(|(name, _)| name == self.name)
+    }
+
+    fn get_from(&self, cmdline: &'static str) -> T {
+        arguments(cmdline)
+            .filter(|(name, _)| *name == self.name)
+            .filter_map(|(_, value)| T::parse(value))
+            .last()
+            .unwrap_or(self.default)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_arguments() {
+        let arguments: Vec<_> =
+            arguments("  memtest loglevel=7\tinit=\"/bin/sh -l\" a=b=c empty= ").collect();
+        assert_eq!(
+            arguments,
+            [
+                ("memtest", None),
+                ("loglevel", Some("7")),
+                ("init", Some("/bin/sh -l")),
+                ("a", Some("b=c")),
+                ("empty", Some("")),
+            ]
+        );
+        assert_eq!(super::arguments("").count(), 0);
+    }
+
+    #[test]
+    fn parses_integers() {
+        assert_eq!(u64::parse(Some("0x0010_0000")), Some(0x0010_0000));
+        assert_eq!(usize::parse(Some("12")), Some(12));
+        assert_eq!(u8::parse(Some("0b101")), Some(5));
+        assert_eq!(u32::parse(Some("0o17")), Some(0o17));
+        assert_eq!(u8::parse(Some("256")), None);
+        assert_eq!(u32::parse(Some("0x")), None);
+        assert_eq!(u32::parse(Some("_1")), None);
+        assert_eq!(u32::parse(Some("-1")), None);
+        assert_eq!(u32::parse(None), None);
+    }
+
+    #[test]
+    fn parses_flags() {
+        assert_eq!(bool::parse(None), Some(true));
+        assert_eq!(bool::parse(Some("off")), Some(false));
+        assert_eq!(bool::parse(Some("yes")), Some(true));
+        assert_eq!(bool::parse(Some("maybe")), None);
+    }
+
+    #[test]
+    fn params() {
+        static MAXHARTS: Param<usize> = Param::new("maxharts", usize::MAX);
+        static MEMTEST: Param<bool> = Param::new("memtest", false);
+        static INIT: Param<&str> = Param::new("init", "echo");
+
+        let cmdline = "maxharts=2 memtest maxharts=4 init=/sbin/init";
+        assert_eq!(MAXHARTS.get_from(cmdline), 4);
+        assert!(MEMTEST.get_from(cmdline));
+        assert_eq!(INIT.get_from(cmdline), "/sbin/init");
+
+        let cmdline = "maxharts=many memtest=off init";
+        assert_eq!(MAXHARTS.get_from(cmdline), usize::MAX);
+        assert!(!MEMTEST.get_from(cmdline));
+        assert_eq!(INIT.get_from(cmdline), "echo");
+    }
+
+    // The only test that calls `init`, since the command line is global
+    #[test]
+    fn params_are_read_after_init() {
+        static TIMESLICE: Param<u64> = Param::new("timeslice", 0x0010_0000);
+
+        assert_eq!(TIMESLICE.get(), 0x0010_0000);
+        init("timeslice=5 memtest");
+        assert_eq!(TIMESLICE.get(), 5);
+        assert_eq!(TIMESLICE.get(), 5);
+    }
+}
@@ -0,0 +1,101 @@
+//! The BIOS parameter block at the start of the volume, and the FSInfo sector.
+//! See https://academy.cba.mit.edu/classes/networking_communications/SD/FAT.pdf, section 3.
+
+pub(crate) fn read_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+pub(crate) fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+pub(crate) fn write_u16(data: &mut [u8], offset: usize, value: u16) {
+    data[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+}
+
+pub(crate) fn write_u32(data: &mut [u8], offset: usize, value: u32) {
+    data[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+pub const BOOT_SIGNATURE: u16 = 0xAA55;
+pub const FSINFO_LEAD_SIGNATURE: u32 = 0x4161_5252;
+pub const FSINFO_STRUCT_SIGNATURE: u32 = 0x6141_7272;
+pub const FSINFO_TRAIL_SIGNATURE: u32 = 0xAA55_0000;
+
+/// Offsets of the fields in the FSInfo sector
+pub const FSINFO_FREE_COUNT: usize = 488;
+pub const FSINFO_NEXT_FREE: usize = 492;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BootSector {
+    pub bytes_per_sector: u16,
+    pub sectors_per_cluster: u8,
+    pub reserved_sectors: u16,
+    pub fat_count: u8,
+    pub total_sectors: u32,
+    pub fat_size: u32,
+    pub root_cluster: u32,
+    pub fsinfo_sector: u16,
+}
+
+impl BootSector {
+    /// Returns None if the sector doesn't describe a FAT32 volume
+    pub fn parse(sector: &[u8]) -> Option<Self> {
+        if sector.len() < 512 || read_u16(sector, 510) != BOOT_SIGNATURE {
+            return None;
+        }
+        let this = Self {
+            bytes_per_sector: read_u16(sector, 11),
+            sectors_per_cluster: sector[13],
+            reserved_sectors: read_u16(sector, 14),
+            fat_count: sector[16],
+            total_sectors: read_u32(sector, 32),
+            fat_size: read_u32(sector, 36),
+            root_cluster: read_u32(sector, 44),
+            fsinfo_sector: read_u16(sector, 48),
+        };
+        // FAT12 and FAT16 have a fixed-size root directory and a 16-bit FAT size
+        let root_entry_count = read_u16(sector, 17);
+        let fat_size_16 = read_u16(sector, 22);
+        let valid = root_entry_count == 0
+            && fat_size_16 == 0
+            && this.fat_size != 0
+            && this.fat_count != 0
+            && this.bytes_per_sector.is_power_of_two()
+            && this.bytes_per_sector >= 512
+            && this.sectors_per_cluster.is_power_of_two()
+            && this.root_cluster >= 2;
+        if valid {
+            Some(this)
+        } else {
+            None
+        }
+    }
+
+    /// Writes the fields into a boot sector, with everything else set to sensible defaults
+    pub fn write(&self, sector: &mut [u8], volume_id: u32) {
+        sector[..3].copy_from_slice(&[0xEB, 0x58, 0x90]);
+        sector[3..11].copy_from_slice(b"KERNELFS");
+        write_u16(sector, 11, self.bytes_per_sector);
+        sector[13] = self.sectors_per_cluster;
+        write_u16(sector, 14, self.reserved_sectors);
+        sector[16] = self.fat_count;
+        // Media descriptor: fixed disk
+        sector[21] = 0xF8;
+        write_u16(sector, 24, 32);
+        write_u16(sector, 26, 64);
+        write_u32(sector, 32, self.total_sectors);
+        write_u32(sector, 36, self.fat_size);
+        write_u32(sector, 44, self.root_cluster);
+        write_u16(sector, 48, self.fsinfo_sector);
+        // Backup boot sector
+        write_u16(sector, 50, 6);
+        sector[64] = 0x80;
+        // Extended boot signature, meaning that the next 3 fields are present
+        sector[66] = 0x29;
+        write_u32(sector, 67, volume_id);
+        sector[71..82].copy_from_slice(b"NO NAME    ");
+        sector[82..90].copy_from_slice(b"FAT32   ");
+        write_u16(sector, 510, BOOT_SIGNATURE);
+    }
+}
@@ -0,0 +1,467 @@
+//! Directory entries, including long file names.
+//! A directory is a list of 32-byte entries. Each file has a short 8.3 entry, which
+//! can be preceded by long file name entries with up to 13 UTF-16 units each.
+
+use alloc::{format, string::String, vec, vec::Vec};
+
+use crate::{
+    boot_sector::{read_u16, read_u32, write_u16, write_u32},
+    FatError,
+};
+
+pub const ENTRY_SIZE: usize = 32;
+
+pub const ATTR_READ_ONLY: u8 = 0x01;
+pub const ATTR_HIDDEN: u8 = 0x02;
+pub const ATTR_SYSTEM: u8 = 0x04;
+pub const ATTR_VOLUME_ID: u8 = 0x08;
+pub const ATTR_DIRECTORY: u8 = 0x10;
+pub const ATTR_ARCHIVE: u8 = 0x20;
+pub const ATTR_LONG_NAME: u8 = ATTR_READ_ONLY | ATTR_HIDDEN | ATTR_SYSTEM | ATTR_VOLUME_ID;
+
+/// The first byte of an entry that was deleted
+const DELETED: u8 = 0xE5;
+/// Set in the sequence number of the last long name entry, which comes first on disk
+const LAST_LONG_ENTRY: u8 = 0x40;
+/// Offsets of the UTF-16 units in a long name entry
+const LONG_NAME_OFFSETS: [usize; 13] = [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+const MAX_NAME_LEN: usize = 255;
+
+/// Flags in the reserved byte of short entries, used by Windows for
+/// names like `readme.txt` that are lowercase but otherwise valid 8.3 names
+const LOWERCASE_BASE: u8 = 0x08;
+const LOWERCASE_EXTENSION: u8 = 0x10;
+
+/// 1980-01-01, the earliest date FAT can store. There's no clock to get the real one from.
+const DEFAULT_DATE: u16 = (1 << 5) | 1;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirEntry {
+    name: String,
+    attributes: u8,
+    pub(crate) first_cluster: u32,
+    size: u32,
+    /// Index of the short entry in its directory
+    pub(crate) slot: usize,
+    /// Number of long name entries before the short entry
+    lfn_count: usize,
+}
+
+impl DirEntry {
+    pub(crate) fn root(cluster: u32) -> Self {
+        Self {
+            name: String::new(),
+            attributes: ATTR_DIRECTORY,
+            first_cluster: cluster,
+            size: 0,
+            slot: 0,
+            lfn_count: 0,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn attributes(&self) -> u8 {
+        self.attributes
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.attributes & ATTR_DIRECTORY != 0
+    }
+
+    /// Size in bytes. Always 0 for directories.
+    pub fn size(&self) -> u64 {
+        self.size as u64
+    }
+}
+
+/// Splits a path into its parent directory and its last component
+pub fn split_path(path: &str) -> (&str, &str) {
+    let path = path.trim_end_matches('/');
+    match path.rsplit_once('/') {
+        Some((parent, name)) => (parent, name),
+        None => ("", path),
+    }
+}
+
+/// The components of a path, skipping empty ones and `.`
+pub fn components(path: &str) -> impl Iterator<Item = &str> {
+    path.split('/')
+        .filter(|component| !component.is_empty() && *component != ".")
+}
+
+pub fn validate_name<E>(name: &str) -> Result<(), FatError<E>> {
+    let valid = !name.is_empty()
+        && name != "."
+        && name != ".."
+        && name.encode_utf16().count() <= MAX_NAME_LEN
+        && !name.ends_with(' ')
+        && !name.chars().any(|c| c < ' ' || "\"*/:<>?\\|".contains(c));
+    if valid {
+        Ok(())
+    } else {
+        Err(FatError::InvalidName)
+    }
+}
+
+/// Characters other than letters and digits that can be in a short name
+fn is_short_name_char(c: char) -> bool {
+    c.is_ascii_uppercase() || c.is_ascii_digit() || "!#$%&'()-@^_`{}~".contains(c)
+}
+
+/// Returns the short name for `name` if it's a valid 8.3 name, so that it doesn't need
+/// long name entries
+fn exact_short_name(name: &str) -> Option<[u8; 11]> {
+    let (base, extension) = name.split_once('.').unwrap_or((name, ""));
+    if base.is_empty()
+        || base.len() > 8
+        || extension.len() > 3
+        || !base
+            .chars()
+            .chain(extension.chars())
+            .all(is_short_name_char)
+    {
+        return None;
+    }
+    let mut short = [b' '; 11];
+    short[..base.len()].copy_from_slice(base.as_bytes());
+    short[8..8 + extension.len()].copy_from_slice(extension.as_bytes());
+    Some(short)
+}
+
+/// Makes up a short name like `LONGNA~1.TXT` for a name that isn't a valid 8.3 name
+fn generate_short_name(name: &str, exists: impl Fn(&[u8; 11]) -> bool) -> Option<[u8; 11]> {
+    let clean = |part: &str| -> Vec<u8> {
+        part.chars()
+            .filter(|c| *c != ' ' && *c != '.')
+            .map(|c| c.to_ascii_uppercase())
+            .map(|c| if is_short_name_char(c) { c as u8 } else { b'_' })
+            .collect()
+    };
+    let (base, extension) = match name.rfind('.') {
+        Some(dot) if dot > 0 => (clean(&name[..dot]), clean(&name[dot + 1..])),
+        _ => (clean(name), Vec::new()),
+    };
+    let base = if base.is_empty() { vec![b'_'] } else { base };
+
+    let mut short = [b' '; 11];
+    let extension_len = extension.len().min(3);
+    short[8..8 + extension_len].copy_from_slice(&extension[..extension_len]);
+    for n in 1..1_000_000 {
+        let tail = format!("~{}", n);
+        let keep = base.len().min(8 - tail.len());
+        short[..8].fill(b' ');
+        short[..keep].copy_from_slice(&base[..keep]);
+        short[keep..keep + tail.len()].copy_from_slice(tail.as_bytes());
+        if !exists(&short) {
+            return Some(short);
+        }
+    }
+    None
+}
+
+fn short_name_to_string(short: &[u8; 11], flags: u8) -> String {
+    let part = |bytes: &[u8], lowercase: bool| -> String {
+        bytes
+            .iter()
+            .map(|byte| *byte as char)
+            .map(|c| if lowercase { c.to_ascii_lowercase() } else { c })
+            .collect::<String>()
+            .trim_end_matches(' ')
+            .into()
+    };
+    let mut base_bytes = [0; 8];
+    base_bytes.copy_from_slice(&short[..8]);
+    // 0xE5 means the entry is deleted, so names that start with it use 0x05 instead
+    if base_bytes[0] == 0x05 {
+        base_bytes[0] = DELETED;
+    }
+    let base = part(&base_bytes, flags & LOWERCASE_BASE != 0);
+    let extension = part(&short[8..], flags & LOWERCASE_EXTENSION != 0);
+    if extension.is_empty() {
+        base
+    } else {
+        format!("{}.{}", base, extension)
+    }
+}
+
+/// Checksum of a short name, stored in its long name entries
+fn checksum(short: &[u8; 11]) -> u8 {
+    short.iter().fold(0u8, |sum, byte| {
+        ((sum & 1) << 7).wrapping_add(sum >> 1).wrapping_add(*byte)
+    })
+}
+
+pub(crate) fn write_short_entry(
+    entry: &mut [u8],
+    short: &[u8; 11],
+    attributes: u8,
+    first_cluster: u32,
+    size: u32,
+) {
+    entry.fill(0);
+    entry[..11].copy_from_slice(short);
+    entry[11] = attributes;
+    // Creation, last access and last write dates
+    write_u16(entry, 16, DEFAULT_DATE);
+    write_u16(entry, 18, DEFAULT_DATE);
+    write_u16(entry, 24, DEFAULT_DATE);
+    set_entry_cluster(entry, first_cluster);
+    write_u32(entry, 28, size);
+}
+
+/// The first cluster is split in two halves
+pub(crate) fn set_entry_cluster(entry: &mut [u8], cluster: u32) {
+    write_u16(entry, 20, (cluster >> 16) as u16);
+    write_u16(entry, 26, cluster as u16);
+}
+
+fn entry_cluster(entry: &[u8]) -> u32 {
+    (read_u16(entry, 20) as u32) << 16 | read_u16(entry, 26) as u32
+}
+
+/// Long name entries being collected while iterating a directory
+struct LongName {
+    units: Vec<u16>,
+    checksum: u8,
+    /// Sequence number of the next entry. They are stored in reverse order.
+    next: u8,
+    count: usize,
+}
+
+/// The entries of a new file, ready to be written to a directory
+pub(crate) struct RawEntries {
+    bytes: Vec<u8>,
+    name: String,
+    attributes: u8,
+    first_cluster: u32,
+}
+
+impl RawEntries {
+    pub fn new<E>(
+        name: &str,
+        attributes: u8,
+        first_cluster: u32,
+        directory: &Directory,
+    ) -> Result<Self, FatError<E>> {
+        validate_name(name)?;
+        let (short, long) = match exact_short_name(name) {
+            Some(short) => (short, false),
+            None => (
+                generate_short_name(name, |short| directory.has_short_name(short))
+                    .ok_or(FatError::InvalidName)?,
+                true,
+            ),
+        };
+
+        let mut bytes = Vec::new();
+        if long {
+            let units: Vec<u16> = name.encode_utf16().collect();
+            let count = units.len().div_ceil(13);
+            let checksum = checksum(&short);
+            for order in (1..=count).rev() {
+                let mut entry = [0; ENTRY_SIZE];
+                entry[0] = order as u8;
+                if order == count {
+                    entry[0] |= LAST_LONG_ENTRY;
+                }
+                entry[11] = ATTR_LONG_NAME;
+                entry[13] = checksum;
+                for (i, offset) in LONG_NAME_OFFSETS.iter().enumerate() {
+                    // The name is terminated with 0 and padded with 0xFFFF
+                    let index = (order - 1) * 13 + i;
+                    let unit = match index.cmp(&units.len()) {
+                        core::cmp::Ordering::Less => units[index],
+                        core::cmp::Ordering::Equal => 0,
+                        core::cmp::Ordering::Greater => 0xFFFF,
+                    };
+                    write_u16(&mut entry, *offset, unit);
+                }
+                bytes.extend_from_slice(&entry);
+            }
+        }
+        let mut entry = [0; ENTRY_SIZE];
+        write_short_entry(&mut entry, &short, attributes, first_cluster, 0);
+        bytes.extend_from_slice(&entry);
+
+        Ok(Self {
+            bytes,
+            name: name.into(),
+            attributes,
+            first_cluster,
+        })
+    }
+
+    /// Number of slots needed
+    pub fn len(&self) -> usize {
+        self.bytes.len() / ENTRY_SIZE
+    }
+}
+
+/// The contents of a directory, loaded in memory
+pub(crate) struct Directory {
+    pub clusters: Vec<u32>,
+    pub data: Vec<u8>,
+    /// Which clusters were modified and have to be written back
+    dirty: Vec<bool>,
+}
+
+impl Directory {
+    pub fn new(clusters: Vec<u32>, data: Vec<u8>) -> Self {
+        let dirty = vec![false; clusters.len()];
+        Self {
+            clusters,
+            data,
+            dirty,
+        }
+    }
+
+    fn cluster_size(&self) -> usize {
+        self.data.len() / self.clusters.len()
+    }
+
+    fn slots(&self) -> impl Iterator<Item = &[u8]> {
+        self.data.chunks(ENTRY_SIZE)
+    }
+
+    fn mark_dirty(&mut self, slot: usize) {
+        let cluster_size = self.cluster_size();
+        self.dirty[slot * ENTRY_SIZE / cluster_size] = true;
+    }
+
+    pub fn is_dirty(&self, cluster_index: usize) -> bool {
+        self.dirty[cluster_index]
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = DirEntry> {
+        let mut entries = Vec::new();
+        let mut long_name: Option<LongName> = None;
+        for (slot, raw) in self.slots().enumerate() {
+            match raw[0] {
+                // Nothing after this is in use
+                0 => break,
+                DELETED => {
+                    long_name = None;
+                    continue;
+                }
+                _ => {}
+            }
+            let attributes = raw[11];
+            if attributes & 0x3F == ATTR_LONG_NAME {
+                let order = raw[0] & 0x1F;
+                if raw[0] & LAST_LONG_ENTRY != 0 {
+                    long_name = Some(LongName {
+                        units: vec![0; order as usize * 13],
+                        checksum: raw[13],
+                        next: order,
+                        count: 0,
+                    });
+                }
+                long_name = long_name.filter(|long_name| {
+                    order != 0 && long_name.next == order && long_name.checksum == raw[13]
+                });
+                if let Some(long_name) = &mut long_name {
+                    let start = (order as usize - 1) * 13;
+                    for (i, offset) in LONG_NAME_OFFSETS.iter().enumerate() {
+                        long_name.units[start + i] = read_u16(raw, *offset);
+                    }
+                    long_name.next -= 1;
+                    long_name.count += 1;
+                }
+                continue;
+            }
+
+            let long_name = long_name.take();
+            if attributes & ATTR_VOLUME_ID != 0 {
+                continue;
+            }
+            let mut short = [0; 11];
+            short.copy_from_slice(&raw[..11]);
+            let (name, lfn_count) = match long_name {
+                Some(long_name)
+                    if long_name.next == 0 && long_name.checksum == checksum(&short) =>
+                {
+                    let end = long_name
+                        .units
+                        .iter()
+                        .position(|unit| *unit == 0)
+                        .unwrap_or(long_name.units.len());
+                    let name = char::decode_utf16(long_name.units[..end].iter().copied())
+                        .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+                        .collect();
+                    (name, long_name.count)
+                }
+                _ => (short_name_to_string(&short, raw[12]), 0),
+            };
+            entries.push(DirEntry {
+                name,
+                attributes,
+                first_cluster: entry_cluster(raw),
+                size: read_u32(raw, 28),
+                slot,
+                lfn_count,
+            });
+        }
+        entries.into_iter()
+    }
+
+    /// Names are compared case-insensitively, like Windows does
+    pub fn find(&self, name: &str) -> Option<DirEntry> {
+        self.entries()
+            .find(|entry| entry.name.eq_ignore_ascii_case(name))
+    }
+
+    fn has_short_name(&self, short: &[u8; 11]) -> bool {
+        self.slots().take_while(|raw| raw[0] != 0).any(|raw| {
+            raw[0] != DELETED && raw[11] & 0x3F != ATTR_LONG_NAME && raw[..11] == short[..]
+        })
+    }
+
+    /// Returns the first slot of a run of `count` unused slots
+    pub fn find_free_slots(&self, count: usize) -> Option<usize> {
+        let mut run = 0;
+        for (slot, raw) in self.slots().enumerate() {
+            if raw[0] == 0 || raw[0] == DELETED {
+                run += 1;
+                if run == count {
+                    return Some(slot + 1 - count);
+                }
+            } else {
+                run = 0;
+            }
+        }
+        None
+    }
+
+    /// Adds a cluster to the end of the directory. It has to be filled with zeroes already.
+    pub fn extend(&mut self, cluster: u32, cluster_size: usize) {
+        self.clusters.push(cluster);
+        self.data.resize(self.data.len() + cluster_size, 0);
+        self.dirty.push(false);
+    }
+
+    pub fn insert(&mut self, slot: usize, raw: &RawEntries) -> DirEntry {
+        let start = slot * ENTRY_SIZE;
+        self.data[start..start + raw.bytes.len()].copy_from_slice(&raw.bytes);
+        for slot in slot..slot + raw.len() {
+            self.mark_dirty(slot);
+        }
+        DirEntry {
+            name: raw.name.clone(),
+            attributes: raw.attributes,
+            first_cluster: raw.first_cluster,
+            size: 0,
+            slot: slot + raw.len() - 1,
+            lfn_count: raw.len() - 1,
+        }
+    }
+
+    pub fn remove(&mut self, entry: &DirEntry) {
+        for slot in entry.slot - entry.lfn_count..=entry.slot {
+            self.data[slot * ENTRY_SIZE] = DELETED;
+            self.mark_dirty(slot);
+        }
+    }
+}
@@ -0,0 +1,204 @@
+use alloc::{boxed::Box, sync::Arc};
+
+use async_trait::async_trait;
+use kernel_io::{BlockDevice, Read, Seek, SeekFrom, Write};
+
+use crate::{dir::DirEntry, FatError, FileSystem, Result};
+
+/// An open file. Reads and writes start at the cursor and move it forward.
+pub struct File<D: BlockDevice> {
+    fs: Arc<FileSystem<D>>,
+    /// First cluster of the directory that has the file's entry
+    directory: u32,
+    /// Index of the file's entry in the directory
+    slot: usize,
+    first_cluster: u32,
+    size: u32,
+    position: u64,
+    /// The last cluster that was used, and its index in the chain,
+    /// so that sequential accesses don't walk the chain from the start
+    current: Option<(u32, u32)>,
+}
+
+impl<D: BlockDevice + Send + Sync> File<D> {
+    pub(crate) fn new(fs: Arc<FileSystem<D>>, directory: u32, entry: &DirEntry) -> Self {
+        Self {
+            fs,
+            directory,
+            slot: entry.slot,
+            first_cluster: entry.first_cluster,
+            size: entry.size() as u32,
+            position: 0,
+            current: None,
+        }
+    }
+
+    /// Called after the file's clusters have been freed
+    pub(crate) fn truncated(&mut self) {
+        self.first_cluster = 0;
+        self.size = 0;
+        self.position = 0;
+        self.current = None;
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size as u64
+    }
+
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// Returns the `index`th cluster of the file.
+    /// If `allocate` is true, the file is extended if it doesn't have that many clusters yet.
+    async fn cluster(&mut self, index: u32, allocate: bool) -> Result<u32, D::Error> {
+        if self.first_cluster == 0 {
+            if !allocate {
+                return Err(FatError::Corrupted);
+            }
+            self.first_cluster = self.fs.allocate_cluster(None).await?;
+        }
+        let (mut cluster, mut current_index) = match self.current {
+            Some((cluster, current_index)) if current_index <= index => (cluster, current_index),
+            _ => (self.first_cluster, 0),
+        };
+        while current_index < index {
+            cluster = match self.fs.next_cluster(cluster).await? {
+                Some(next) => next,
+                None if allocate => self.fs.allocate_cluster(Some(cluster)).await?,
+                None => return Err(FatError::Corrupted),
+            };
+            current_index += 1;
+        }
+        self.current = Some((cluster, index));
+        Ok(cluster)
+    }
+
+    /// Returns the sector range of the cluster that covers `len` bytes at `position`,
+    /// and the offset of `position` in the first of those sectors
+    fn sectors(&self, cluster: u32, position: u64, len: usize) -> (u64, usize, usize) {
+        let sector_size = self.fs.sector_size as u64;
+        let offset = position % self.fs.cluster_size as u64;
+        let first = offset / sector_size;
+        let last = (offset + len as u64).div_ceil(sector_size);
+        (
+            self.fs.cluster_sector(cluster) + first,
+            (last - first) as usize,
+            (offset % sector_size) as usize,
+        )
+    }
+
+    /// Bytes that can be accessed at the current position without crossing a cluster
+    fn remaining_in_cluster(&self) -> usize {
+        let cluster_size = self.fs.cluster_size as u64;
+        (cluster_size - self.position % cluster_size) as usize
+    }
+
+    async fn read_locked(&mut self, buf: &mut [u8]) -> Result<usize, D::Error> {
+        let available = (self.size as u64).saturating_sub(self.position);
+        let len = (buf.len() as u64).min(available) as usize;
+        let mut read = 0;
+        while read < len {
+            let len_here = (len - read).min(self.remaining_in_cluster());
+            let index = (self.position / self.fs.cluster_size as u64) as u32;
+            let cluster = self.cluster(index, false).await?;
+
+            let (sector, count, offset) = self.sectors(cluster, self.position, len_here);
+            let mut buffer = self.fs.sector_buffer(count);
+            self.fs.read_sectors(sector, &mut buffer).await?;
+            buf[read..read + len_here].copy_from_slice(&buffer[offset..offset + len_here]);
+
+            read += len_here;
+            self.position += len_here as u64;
+        }
+        Ok(read)
+    }
+
+    async fn write_locked(&mut self, buf: &[u8]) -> Result<usize, D::Error> {
+        // Files can't be bigger than 4 GiB
+        let len = (buf.len() as u64).min(u32::MAX as u64 - self.position) as usize;
+        let mut written = 0;
+        while written < len {
+            let len_here = (len - written).min(self.remaining_in_cluster());
+            let index = (self.position / self.fs.cluster_size as u64) as u32;
+            let cluster = match self.cluster(index, true).await {
+                Ok(cluster) => cluster,
+                // Keep what was written so far
+                Err(FatError::NoSpace) if written != 0 => break,
+                Err(e) => return Err(e),
+            };
+
+            let (sector, count, offset) = self.sectors(cluster, self.position, len_here);
+            let mut buffer = self.fs.sector_buffer(count);
+            // Only read the sectors if they aren't going to be overwritten completely
+            if offset != 0 || len_here != buffer.len() {
+                self.fs.read_sectors(sector, &mut buffer).await?;
+            }
+            buffer[offset..offset + len_here].copy_from_slice(&buf[written..written + len_here]);
+            self.fs.write_sectors(sector, &buffer).await?;
+
+            written += len_here;
+            self.position += len_here as u64;
+        }
+        if len != 0 {
+            self.size = self.size.max(self.position as u32);
+            self.fs
+                .update_entry(self.directory, self.slot, self.first_cluster, self.size)
+                .await?;
+        }
+        Ok(written)
+    }
+}
+
+#[async_trait]
+impl<D: BlockDevice + Send + Sync> Read for File<D> {
+    type Error = FatError<D::Error>;
+
+    /// Returns 0 at the end of the file
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, D::Error> {
+        let fs = self.fs.clone();
+        let _guard = fs.lock.lock().await;
+        self.read_locked(buf).await
+    }
+}
+
+#[async_trait]
+impl<D: BlockDevice + Send + Sync> Write for File<D> {
+    type Error = FatError<D::Error>;
+
+    /// Writing past the end of the file extends it
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, D::Error> {
+        let fs = self.fs.clone();
+        let _guard = fs.lock.lock().await;
+        self.write_locked(buf).await
+    }
+}
+
+#[async_trait]
+impl<D: BlockDevice + Send + Sync> Seek for File<D> {
+    type Error = FatError<D::Error>;
+
+    /// Seeking past the end of the file isn't supported, because FAT can't have holes
+    async fn seek(&mut self, pos: SeekFrom) -> Result<u64, D::Error> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => add_offset(self.size as u64, offset),
+            SeekFrom::Current(offset) => add_offset(self.position, offset),
+        };
+        match position {
+            Some(position) if position <= self.size as u64 => {
+                self.position = position;
+                Ok(position)
+            }
+            _ => Err(FatError::InvalidSeek),
+        }
+    }
+}
+
+fn add_offset(base: u64, offset: i64) -> Option<u64> {
+    if offset >= 0 {
+        base.checked_add(offset as u64)
+    } else {
+        base.checked_sub(offset.unsigned_abs())
+    }
+}
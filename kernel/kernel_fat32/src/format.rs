@@ -0,0 +1,128 @@
+//! Creating an empty FAT32 volume
+
+use alloc::vec;
+
+use kernel_io::BlockDevice;
+
+use crate::{
+    boot_sector::{
+        write_u32, BootSector, FSINFO_FREE_COUNT, FSINFO_LEAD_SIGNATURE, FSINFO_NEXT_FREE,
+        FSINFO_STRUCT_SIGNATURE, FSINFO_TRAIL_SIGNATURE,
+    },
+    FatError, Result, END_OF_CHAIN, FIRST_CLUSTER,
+};
+
+const RESERVED_SECTORS: u16 = 32;
+const FAT_COUNT: u8 = 2;
+const FSINFO_SECTOR: u16 = 1;
+const BACKUP_BOOT_SECTOR: u64 = 6;
+/// FAT32 volumes need at least this many clusters, otherwise they would be detected as FAT16
+const MIN_CLUSTERS: u64 = 65525;
+/// Number of sectors zeroed at once
+const ZERO_CHUNK: u64 = 64;
+
+/// Cluster size recommended by Microsoft for a volume with this many 512-byte sectors
+fn recommended_sectors_per_cluster(total_sectors: u64) -> u64 {
+    if total_sectors <= 532_480 {
+        1
+    } else if total_sectors <= 16_777_216 {
+        8
+    } else if total_sectors <= 33_554_432 {
+        16
+    } else if total_sectors <= 67_108_864 {
+        32
+    } else {
+        64
+    }
+}
+
+/// Writes an empty FAT32 filesystem to the whole device.
+/// The device has to be at least around 33 MiB big.
+pub async fn format<D: BlockDevice>(device: &D) -> Result<(), D::Error> {
+    let sector_size = device.block_size();
+    if !(512..=4096).contains(&sector_size) || !sector_size.is_power_of_two() {
+        return Err(FatError::NotFat32);
+    }
+    let total_sectors = device.capacity().min(u32::MAX as u64);
+    let cluster_size =
+        recommended_sectors_per_cluster(total_sectors * sector_size as u64 / 512) * 512;
+    let sectors_per_cluster = (cluster_size / sector_size as u64).max(1) as u8;
+
+    // The FATs need an entry for each cluster, plus the two reserved ones.
+    // That means fat_size * entries_per_sector >= clusters + 2, where
+    // clusters = (data_sectors - fat_count * fat_size) / sectors_per_cluster
+    let data_sectors = total_sectors.saturating_sub(RESERVED_SECTORS as u64);
+    let entries_per_sector = sector_size as u64 / 4;
+    let divisor = entries_per_sector * sectors_per_cluster as u64 + FAT_COUNT as u64;
+    let fat_size = (data_sectors + 2 * sectors_per_cluster as u64).div_ceil(divisor);
+    let cluster_count =
+        data_sectors.saturating_sub(FAT_COUNT as u64 * fat_size) / sectors_per_cluster as u64;
+    if cluster_count < MIN_CLUSTERS {
+        return Err(FatError::NoSpace);
+    }
+
+    let boot = BootSector {
+        bytes_per_sector: sector_size as u16,
+        sectors_per_cluster,
+        reserved_sectors: RESERVED_SECTORS,
+        fat_count: FAT_COUNT,
+        total_sectors: total_sectors as u32,
+        fat_size: fat_size as u32,
+        root_cluster: FIRST_CLUSTER,
+        fsinfo_sector: FSINFO_SECTOR,
+    };
+
+    // Clear the reserved sectors, the FATs and the root directory
+    let root_start = RESERVED_SECTORS as u64 + FAT_COUNT as u64 * fat_size;
+    let zeroes = vec![0; ZERO_CHUNK as usize * sector_size];
+    let mut sector = 0;
+    let end = root_start + sectors_per_cluster as u64;
+    while sector < end {
+        let count = (end - sector).min(ZERO_CHUNK);
+        device
+            .write_blocks(sector, &zeroes[..count as usize * sector_size])
+            .await
+            .map_err(FatError::Device)?;
+        sector += count;
+    }
+
+    let mut buffer = vec![0; sector_size];
+    boot.write(&mut buffer, 0x4b45_524e);
+    for sector in [0, BACKUP_BOOT_SECTOR] {
+        device
+            .write_blocks(sector, &buffer)
+            .await
+            .map_err(FatError::Device)?;
+    }
+
+    let mut fsinfo = vec![0; sector_size];
+    write_u32(&mut fsinfo, 0, FSINFO_LEAD_SIGNATURE);
+    write_u32(&mut fsinfo, 484, FSINFO_STRUCT_SIGNATURE);
+    // The free cluster count isn't kept up to date, so it's left as unknown
+    write_u32(&mut fsinfo, FSINFO_FREE_COUNT, u32::MAX);
+    write_u32(&mut fsinfo, FSINFO_NEXT_FREE, FIRST_CLUSTER + 1);
+    write_u32(&mut fsinfo, 508, FSINFO_TRAIL_SIGNATURE);
+    for sector in [
+        FSINFO_SECTOR as u64,
+        BACKUP_BOOT_SECTOR + FSINFO_SECTOR as u64,
+    ] {
+        device
+            .write_blocks(sector, &fsinfo)
+            .await
+            .map_err(FatError::Device)?;
+    }
+
+    // The first two entries are reserved. The first one has the media descriptor in
+    // its low byte. The third one is the root directory, which is a single cluster.
+    let mut fat = vec![0; sector_size];
+    write_u32(&mut fat, 0, 0x0FFF_FFF8);
+    write_u32(&mut fat, 4, END_OF_CHAIN);
+    write_u32(&mut fat, 8, END_OF_CHAIN);
+    for i in 0..FAT_COUNT as u64 {
+        device
+            .write_blocks(RESERVED_SECTORS as u64 + i * fat_size, &fat)
+            .await
+            .map_err(FatError::Device)?;
+    }
+    device.flush().await.map_err(FatError::Device)
+}
@@ -0,0 +1,653 @@
+//! A read-write FAT32 filesystem on top of a [`BlockDevice`].
+//! Paths are `/`-separated and relative to the root of the volume.
+//! See https://academy.cba.mit.edu/classes/networking_communications/SD/FAT.pdf
+
+#![cfg_attr(not(test), no_std)]
+
+extern crate alloc;
+
+pub mod boot_sector;
+pub mod dir;
+pub mod file;
+pub mod format;
+mod lock;
+#[cfg(test)]
+mod tests;
+
+use alloc::{sync::Arc, vec, vec::Vec};
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+use boot_sector::{
+    read_u32, write_u32, BootSector, FSINFO_FREE_COUNT, FSINFO_LEAD_SIGNATURE, FSINFO_NEXT_FREE,
+};
+pub use dir::DirEntry;
+pub use file::File;
+pub use format::format;
+use kernel_io::{file::VfsError, BlockDevice};
+use lock::AsyncLock;
+
+/// Only the low 28 bits of a FAT entry are used
+const ENTRY_MASK: u32 = 0x0FFF_FFFF;
+/// Entries at or above this mark the end of a cluster chain
+const END_OF_CHAIN_MIN: u32 = 0x0FFF_FFF8;
+pub const END_OF_CHAIN: u32 = 0x0FFF_FFFF;
+/// The first cluster of the data region
+const FIRST_CLUSTER: u32 = 2;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FatError<E> {
+    /// The block device returned an error
+    Device(E),
+    /// The volume doesn't have a FAT32 boot sector
+    NotFat32,
+    NotFound,
+    NotADirectory,
+    IsADirectory,
+    AlreadyExists,
+    DirectoryNotEmpty,
+    /// There are no free clusters left
+    NoSpace,
+    InvalidName,
+    /// Tried to seek before the start or past the end of a file
+    InvalidSeek,
+    /// The filesystem structures are inconsistent
+    Corrupted,
+}
+
+pub type Result<T, E> = core::result::Result<T, FatError<E>>;
+
+/// Lets files on FAT volumes be used as [`kernel_io::file::File`]s
+impl<E> From<FatError<E>> for VfsError {
+    fn from(error: FatError<E>) -> Self {
+        match error {
+            FatError::NotFound => Self::NotFound,
+            FatError::NotADirectory => Self::NotADirectory,
+            FatError::IsADirectory => Self::IsADirectory,
+            FatError::AlreadyExists => Self::AlreadyExists,
+            FatError::DirectoryNotEmpty => Self::DirectoryNotEmpty,
+            FatError::NoSpace => Self::NoSpace,
+            FatError::InvalidName => Self::InvalidName,
+            FatError::InvalidSeek => Self::InvalidSeek,
+            FatError::Device(_) | FatError::NotFat32 | FatError::Corrupted => Self::Io,
+        }
+    }
+}
+
+pub struct FileSystem<D: BlockDevice> {
+    device: D,
+    sector_size: u32,
+    cluster_size: u32,
+    /// Number of device blocks in a sector
+    blocks_per_sector: u64,
+    sectors_per_cluster: u32,
+    fat_start: u64,
+    fat_size: u32,
+    fat_count: u32,
+    data_start: u64,
+    cluster_count: u32,
+    root_cluster: u32,
+    /// Where to start looking for free clusters
+    next_free: AtomicU32,
+    fsinfo_sector: u64,
+    /// Whether the FSInfo sector has a free cluster count, which
+    /// has to be marked as unknown before the FAT is changed
+    free_count_known: AtomicBool,
+    /// Held by every operation, so that the FAT and directories are
+    /// never modified by two of them at the same time
+    lock: AsyncLock,
+}
+
+impl<D: BlockDevice + Send + Sync> FileSystem<D> {
+    /// Reads the boot sector of `device` and checks that it's a FAT32 volume
+    pub async fn mount(device: D) -> Result<Self, D::Error> {
+        let block_size = device.block_size();
+        let mut first = vec![0; block_size.max(512)];
+        device
+            .read_blocks(0, &mut first)
+            .await
+            .map_err(FatError::Device)?;
+        let boot = BootSector::parse(&first).ok_or(FatError::NotFat32)?;
+
+        let sector_size = boot.bytes_per_sector as u32;
+        if !(sector_size as usize).is_multiple_of(block_size) {
+            return Err(FatError::NotFat32);
+        }
+        let sectors_per_cluster = boot.sectors_per_cluster as u32;
+        let fat_start = boot.reserved_sectors as u64;
+        let data_start = fat_start + boot.fat_count as u64 * boot.fat_size as u64;
+        let cluster_count = ((boot.total_sectors as u64).saturating_sub(data_start)
+            / sectors_per_cluster as u64) as u32;
+        // The FAT has to be big enough to hold an entry for each cluster
+        let fat_entries = boot.fat_size as u64 * sector_size as u64 / 4;
+        if cluster_count < 65525 || (cluster_count as u64 + FIRST_CLUSTER as u64) > fat_entries {
+            return Err(FatError::NotFat32);
+        }
+
+        let fs = Self {
+            device,
+            sector_size,
+            cluster_size: sector_size * sectors_per_cluster,
+            blocks_per_sector: (sector_size as usize / block_size) as u64,
+            sectors_per_cluster,
+            fat_start,
+            fat_size: boot.fat_size,
+            fat_count: boot.fat_count as u32,
+            data_start,
+            cluster_count,
+            root_cluster: boot.root_cluster,
+            next_free: AtomicU32::new(FIRST_CLUSTER),
+            fsinfo_sector: boot.fsinfo_sector as u64,
+            free_count_known: AtomicBool::new(false),
+            lock: AsyncLock::new(),
+        };
+
+        // The FSInfo sector has a hint for where the free clusters start
+        if boot.fsinfo_sector != 0 && boot.fsinfo_sector != 0xFFFF {
+            let mut fsinfo = fs.sector_buffer(1);
+            fs.read_sectors(boot.fsinfo_sector as u64, &mut fsinfo)
+                .await?;
+            if read_u32(&fsinfo, 0) == FSINFO_LEAD_SIGNATURE {
+                let next_free = read_u32(&fsinfo, FSINFO_NEXT_FREE);
+                if fs.is_valid_cluster(next_free) {
+                    fs.next_free.store(next_free, Ordering::Relaxed);
+                }
+                fs.free_count_known.store(
+                    read_u32(&fsinfo, FSINFO_FREE_COUNT) != u32::MAX,
+                    Ordering::Relaxed,
+                );
+            }
+        }
+        Ok(fs)
+    }
+
+    pub fn device(&self) -> &D {
+        &self.device
+    }
+
+    pub fn into_device(self) -> D {
+        self.device
+    }
+
+    /// Size of a cluster in bytes
+    pub fn cluster_size(&self) -> u32 {
+        self.cluster_size
+    }
+
+    pub fn cluster_count(&self) -> u32 {
+        self.cluster_count
+    }
+
+    /// Opens an existing file for reading and writing
+    pub async fn open(self: &Arc<Self>, path: &str) -> Result<File<D>, D::Error> {
+        let _guard = self.lock.lock().await;
+        let (parent, entry) = self.find(path).await?;
+        if entry.is_dir() {
+            return Err(FatError::IsADirectory);
+        }
+        Ok(File::new(self.clone(), parent, &entry))
+    }
+
+    /// Opens a file for reading and writing, creating it if it doesn't exist
+    /// and truncating it if it does
+    pub async fn create(self: &Arc<Self>, path: &str) -> Result<File<D>, D::Error> {
+        let _guard = self.lock.lock().await;
+        let (parent_path, name) = dir::split_path(path);
+        let parent = self.find_directory(parent_path).await?;
+        let mut directory = self.load_directory(parent).await?;
+        let entry = match directory.find(name) {
+            Some(entry) if entry.is_dir() => return Err(FatError::IsADirectory),
+            Some(entry) => {
+                if entry.first_cluster != 0 {
+                    self.free_chain(entry.first_cluster).await?;
+                }
+                self.update_entry(parent, entry.slot, 0, 0).await?;
+                entry
+            }
+            None => {
+                self.add_entry(&mut directory, name, dir::ATTR_ARCHIVE, 0)
+                    .await?
+            }
+        };
+        let mut file = File::new(self.clone(), parent, &entry);
+        file.truncated();
+        Ok(file)
+    }
+
+    /// Creates an empty directory. Its parent has to exist already.
+    pub async fn create_dir(&self, path: &str) -> Result<(), D::Error> {
+        let _guard = self.lock.lock().await;
+        let (parent_path, name) = dir::split_path(path);
+        let parent = self.find_directory(parent_path).await?;
+        let mut directory = self.load_directory(parent).await?;
+        if directory.find(name).is_some() {
+            return Err(FatError::AlreadyExists);
+        }
+        dir::validate_name(name)?;
+
+        let cluster = self.allocate_cluster(None).await?;
+        // Every directory other than the root starts with `.` and `..`.
+        // `..` points to cluster 0 when the parent is the root.
+        let mut contents = vec![0; self.cluster_size as usize];
+        let parent_entry_cluster = if parent == self.root_cluster {
+            0
+        } else {
+            parent
+        };
+        dir::write_short_entry(
+            &mut contents[0..32],
+            b".          ",
+            dir::ATTR_DIRECTORY,
+            cluster,
+            0,
+        );
+        dir::write_short_entry(
+            &mut contents[32..64],
+            b"..         ",
+            dir::ATTR_DIRECTORY,
+            parent_entry_cluster,
+            0,
+        );
+        self.write_cluster(cluster, &contents).await?;
+
+        if let Err(e) = self
+            .add_entry(&mut directory, name, dir::ATTR_DIRECTORY, cluster)
+            .await
+        {
+            self.free_chain(cluster).await?;
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Lists a directory, without the `.` and `..` entries
+    pub async fn read_dir(&self, path: &str) -> Result<Vec<DirEntry>, D::Error> {
+        let _guard = self.lock.lock().await;
+        let cluster = self.find_directory(path).await?;
+        let directory = self.load_directory(cluster).await?;
+        Ok(directory
+            .entries()
+            .filter(|entry| entry.name() != "." && entry.name() != "..")
+            .collect())
+    }
+
+    /// Removes a file, or a directory if it's empty
+    pub async fn remove(&self, path: &str) -> Result<(), D::Error> {
+        let _guard = self.lock.lock().await;
+        let (parent, entry) = self.find(path).await?;
+        if entry.is_dir() {
+            let contents = self.load_directory(entry.first_cluster).await?;
+            if contents
+                .entries()
+                .any(|entry| entry.name() != "." && entry.name() != "..")
+            {
+                return Err(FatError::DirectoryNotEmpty);
+            }
+        }
+        let mut directory = self.load_directory(parent).await?;
+        directory.remove(&entry);
+        self.store_directory(&directory).await?;
+        if entry.first_cluster != 0 {
+            self.free_chain(entry.first_cluster).await?;
+        }
+        Ok(())
+    }
+
+    /// Moves a file or directory. Fails if something already exists at `to`.
+    pub async fn rename(&self, from: &str, to: &str) -> Result<(), D::Error> {
+        let _guard = self.lock.lock().await;
+        let (from_parent, entry) = self.find(from).await?;
+        let (to_parent_path, to_name) = dir::split_path(to);
+        let to_parent = self.find_directory(to_parent_path).await?;
+        // A directory can't be moved inside itself
+        if entry.is_dir() && self.is_inside(to_parent, entry.first_cluster).await? {
+            return Err(FatError::InvalidName);
+        }
+        let mut to_directory = self.load_directory(to_parent).await?;
+        if let Some(existing) = to_directory.find(to_name) {
+            // Only the case of the name is being changed
+            if !(to_parent == from_parent && existing.slot == entry.slot) {
+                return Err(FatError::AlreadyExists);
+            }
+        }
+
+        let new_entry = self
+            .add_entry(
+                &mut to_directory,
+                to_name,
+                entry.attributes(),
+                entry.first_cluster,
+            )
+            .await?;
+        self.update_entry(
+            to_parent,
+            new_entry.slot,
+            entry.first_cluster,
+            entry.size() as u32,
+        )
+        .await?;
+        // Load it again, because it might be the same directory that was just modified
+        let mut from_directory = self.load_directory(from_parent).await?;
+        from_directory.remove(&entry);
+        self.store_directory(&from_directory).await?;
+
+        if entry.is_dir() && from_parent != to_parent {
+            let moved = self.load_directory(entry.first_cluster).await?;
+            let dot_dot = moved.find("..").ok_or(FatError::Corrupted)?;
+            let parent_entry_cluster = if to_parent == self.root_cluster {
+                0
+            } else {
+                to_parent
+            };
+            self.update_entry(entry.first_cluster, dot_dot.slot, parent_entry_cluster, 0)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Returns the directory entry of a file or directory.
+    /// The root directory doesn't have one, so a placeholder is returned for it.
+    pub async fn metadata(&self, path: &str) -> Result<DirEntry, D::Error> {
+        let _guard = self.lock.lock().await;
+        if dir::components(path).next().is_none() {
+            return Ok(DirEntry::root(self.root_cluster));
+        }
+        Ok(self.find(path).await?.1)
+    }
+
+    pub async fn flush(&self) -> Result<(), D::Error> {
+        self.device.flush().await.map_err(FatError::Device)
+    }
+
+    /// Returns the cluster of the directory that contains `path`, and its entry there
+    async fn find(&self, path: &str) -> Result<(u32, DirEntry), D::Error> {
+        let (parent_path, name) = dir::split_path(path);
+        let parent = self.find_directory(parent_path).await?;
+        let directory = self.load_directory(parent).await?;
+        let entry = directory.find(name).ok_or(FatError::NotFound)?;
+        Ok((parent, entry))
+    }
+
+    /// Returns the first cluster of the directory at `path`
+    async fn find_directory(&self, path: &str) -> Result<u32, D::Error> {
+        let mut cluster = self.root_cluster;
+        for component in dir::components(path) {
+            let directory = self.load_directory(cluster).await?;
+            let entry = directory.find(component).ok_or(FatError::NotFound)?;
+            if !entry.is_dir() {
+                return Err(FatError::NotADirectory);
+            }
+            // `..` entries that point to the root have cluster 0
+            cluster = match entry.first_cluster {
+                0 => self.root_cluster,
+                cluster => cluster,
+            };
+        }
+        Ok(cluster)
+    }
+
+    /// Whether the directory at `cluster` is `ancestor` or inside it
+    async fn is_inside(&self, mut cluster: u32, ancestor: u32) -> Result<bool, D::Error> {
+        loop {
+            if cluster == ancestor {
+                return Ok(true);
+            }
+            if cluster == self.root_cluster {
+                return Ok(false);
+            }
+            let directory = self.load_directory(cluster).await?;
+            cluster = match directory
+                .find("..")
+                .ok_or(FatError::Corrupted)?
+                .first_cluster
+            {
+                0 => self.root_cluster,
+                parent => parent,
+            };
+        }
+    }
+
+    async fn load_directory(&self, first_cluster: u32) -> Result<dir::Directory, D::Error> {
+        let clusters = self.chain(first_cluster).await?;
+        let mut data = vec![0; clusters.len() * self.cluster_size as usize];
+        for (cluster, buffer) in clusters
+            .iter()
+            .zip(data.chunks_mut(self.cluster_size as usize))
+        {
+            self.read_cluster(*cluster, buffer).await?;
+        }
+        Ok(dir::Directory::new(clusters, data))
+    }
+
+    /// Writes back the clusters of a directory that were modified
+    async fn store_directory(&self, directory: &dir::Directory) -> Result<(), D::Error> {
+        for (index, cluster) in directory.clusters.iter().enumerate() {
+            if directory.is_dirty(index) {
+                let start = index * self.cluster_size as usize;
+                self.write_cluster(
+                    *cluster,
+                    &directory.data[start..start + self.cluster_size as usize],
+                )
+                .await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Adds the entries for `name` to a directory, extending it if there's no room
+    async fn add_entry(
+        &self,
+        directory: &mut dir::Directory,
+        name: &str,
+        attributes: u8,
+        first_cluster: u32,
+    ) -> Result<DirEntry, D::Error> {
+        let raw = dir::RawEntries::new(name, attributes, first_cluster, directory)?;
+        let slot = loop {
+            match directory.find_free_slots(raw.len()) {
+                Some(slot) => break slot,
+                None => {
+                    let last = *directory.clusters.last().ok_or(FatError::Corrupted)?;
+                    let cluster = self.allocate_cluster(Some(last)).await?;
+                    directory.extend(cluster, self.cluster_size as usize);
+                }
+            }
+        };
+        let entry = directory.insert(slot, &raw);
+        self.store_directory(directory).await?;
+        Ok(entry)
+    }
+
+    /// Changes the first cluster and size stored in the short entry at `slot`
+    async fn update_entry(
+        &self,
+        directory_cluster: u32,
+        slot: usize,
+        first_cluster: u32,
+        size: u32,
+    ) -> Result<(), D::Error> {
+        let offset = slot as u64 * dir::ENTRY_SIZE as u64;
+        let cluster = self
+            .cluster_at(
+                directory_cluster,
+                (offset / self.cluster_size as u64) as u32,
+            )
+            .await?;
+        let offset = (offset % self.cluster_size as u64) as u32;
+        let sector = self.cluster_sector(cluster) + (offset / self.sector_size) as u64;
+        let offset = (offset % self.sector_size) as usize;
+
+        let mut buffer = self.sector_buffer(1);
+        self.read_sectors(sector, &mut buffer).await?;
+        dir::set_entry_cluster(&mut buffer[offset..offset + dir::ENTRY_SIZE], first_cluster);
+        write_u32(&mut buffer, offset + 28, size);
+        self.write_sectors(sector, &buffer).await
+    }
+
+    fn sector_buffer(&self, sectors: usize) -> Vec<u8> {
+        vec![0; sectors * self.sector_size as usize]
+    }
+
+    async fn read_sectors(&self, sector: u64, buf: &mut [u8]) -> Result<(), D::Error> {
+        self.device
+            .read_blocks(sector * self.blocks_per_sector, buf)
+            .await
+            .map_err(FatError::Device)
+    }
+
+    async fn write_sectors(&self, sector: u64, buf: &[u8]) -> Result<(), D::Error> {
+        self.device
+            .write_blocks(sector * self.blocks_per_sector, buf)
+            .await
+            .map_err(FatError::Device)
+    }
+
+    fn is_valid_cluster(&self, cluster: u32) -> bool {
+        (FIRST_CLUSTER..self.cluster_count + FIRST_CLUSTER).contains(&cluster)
+    }
+
+    /// The first sector of a cluster
+    fn cluster_sector(&self, cluster: u32) -> u64 {
+        self.data_start + (cluster - FIRST_CLUSTER) as u64 * self.sectors_per_cluster as u64
+    }
+
+    async fn read_cluster(&self, cluster: u32, buf: &mut [u8]) -> Result<(), D::Error> {
+        self.read_sectors(self.cluster_sector(cluster), buf).await
+    }
+
+    async fn write_cluster(&self, cluster: u32, buf: &[u8]) -> Result<(), D::Error> {
+        self.write_sectors(self.cluster_sector(cluster), buf).await
+    }
+
+    /// Returns the sector of the first FAT that contains the entry for
+    /// `cluster`, and the offset of the entry in it
+    fn fat_position(&self, cluster: u32) -> (u64, usize) {
+        let offset = cluster as u64 * 4;
+        (
+            self.fat_start + offset / self.sector_size as u64,
+            (offset % self.sector_size as u64) as usize,
+        )
+    }
+
+    async fn fat_entry(&self, cluster: u32) -> Result<u32, D::Error> {
+        let (sector, offset) = self.fat_position(cluster);
+        let mut buffer = self.sector_buffer(1);
+        self.read_sectors(sector, &mut buffer).await?;
+        Ok(read_u32(&buffer, offset) & ENTRY_MASK)
+    }
+
+    /// Writes an entry to every copy of the FAT
+    async fn set_fat_entry(&self, cluster: u32, value: u32) -> Result<(), D::Error> {
+        self.forget_free_count().await?;
+        let (sector, offset) = self.fat_position(cluster);
+        let mut buffer = self.sector_buffer(1);
+        for fat in 0..self.fat_count {
+            let sector = sector + fat as u64 * self.fat_size as u64;
+            self.read_sectors(sector, &mut buffer).await?;
+            // The high 4 bits are reserved and have to be preserved
+            let old = read_u32(&buffer, offset);
+            write_u32(
+                &mut buffer,
+                offset,
+                (old & !ENTRY_MASK) | (value & ENTRY_MASK),
+            );
+            self.write_sectors(sector, &buffer).await?;
+        }
+        Ok(())
+    }
+
+    /// The free cluster count in the FSInfo sector isn't kept up to date, so it's
+    /// marked as unknown, which makes other systems count the free clusters again
+    async fn forget_free_count(&self) -> Result<(), D::Error> {
+        if !self.free_count_known.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+        let mut fsinfo = self.sector_buffer(1);
+        self.read_sectors(self.fsinfo_sector, &mut fsinfo).await?;
+        write_u32(&mut fsinfo, FSINFO_FREE_COUNT, u32::MAX);
+        self.write_sectors(self.fsinfo_sector, &fsinfo).await?;
+        self.free_count_known.store(false, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Returns the cluster after `cluster` in its chain, or None if it's the last one
+    async fn next_cluster(&self, cluster: u32) -> Result<Option<u32>, D::Error> {
+        match self.fat_entry(cluster).await? {
+            next if next >= END_OF_CHAIN_MIN => Ok(None),
+            next if self.is_valid_cluster(next) => Ok(Some(next)),
+            _ => Err(FatError::Corrupted),
+        }
+    }
+
+    /// Returns the `index`th cluster in the chain that starts at `first_cluster`
+    async fn cluster_at(&self, first_cluster: u32, index: u32) -> Result<u32, D::Error> {
+        let mut cluster = first_cluster;
+        for _ in 0..index {
+            cluster = self
+                .next_cluster(cluster)
+                .await?
+                .ok_or(FatError::Corrupted)?;
+        }
+        Ok(cluster)
+    }
+
+    /// Returns all the clusters in a chain
+    async fn chain(&self, first_cluster: u32) -> Result<Vec<u32>, D::Error> {
+        if !self.is_valid_cluster(first_cluster) {
+            return Err(FatError::Corrupted);
+        }
+        let mut clusters = vec![first_cluster];
+        while let Some(next) = self.next_cluster(*clusters.last().unwrap()).await? {
+            // A chain can't be longer than the number of clusters, unless it has a loop
+            if clusters.len() > self.cluster_count as usize {
+                return Err(FatError::Corrupted);
+            }
+            clusters.push(next);
+        }
+        Ok(clusters)
+    }
+
+    /// Finds a free cluster, marks it as the end of a chain and fills it with zeroes.
+    /// If `previous` is given, the new cluster is appended after it.
+    async fn allocate_cluster(&self, previous: Option<u32>) -> Result<u32, D::Error> {
+        let start = self.next_free.load(Ordering::Relaxed);
+        let mut buffer = self.sector_buffer(1);
+        let mut loaded_sector = None;
+        let mut found = None;
+        for i in 0..self.cluster_count {
+            let cluster = FIRST_CLUSTER + (start - FIRST_CLUSTER + i) % self.cluster_count;
+            let (sector, offset) = self.fat_position(cluster);
+            if loaded_sector != Some(sector) {
+                self.read_sectors(sector, &mut buffer).await?;
+                loaded_sector = Some(sector);
+            }
+            if read_u32(&buffer, offset) & ENTRY_MASK == 0 {
+                found = Some(cluster);
+                break;
+            }
+        }
+        let cluster = found.ok_or(FatError::NoSpace)?;
+
+        self.write_cluster(cluster, &vec![0; self.cluster_size as usize])
+            .await?;
+        self.set_fat_entry(cluster, END_OF_CHAIN).await?;
+        if let Some(previous) = previous {
+            self.set_fat_entry(previous, cluster).await?;
+        }
+        self.next_free.store(
+            FIRST_CLUSTER + (cluster + 1 - FIRST_CLUSTER) % self.cluster_count,
+            Ordering::Relaxed,
+        );
+        Ok(cluster)
+    }
+
+    /// Marks all the clusters in a chain as free
+    async fn free_chain(&self, first_cluster: u32) -> Result<(), D::Error> {
+        for cluster in self.chain(first_cluster).await? {
+            self.set_fat_entry(cluster, 0).await?;
+        }
+        if first_cluster < self.next_free.load(Ordering::Relaxed) {
+            self.next_free.store(first_cluster, Ordering::Relaxed);
+        }
+        Ok(())
+    }
+}
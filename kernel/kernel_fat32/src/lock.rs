@@ -0,0 +1,69 @@
+//! A minimal async lock that serializes operations on the filesystem.
+//! kernel_lock can't be used here because it depends on the CPU, and
+//! this crate has to be testable on the host.
+
+use alloc::vec::Vec;
+use core::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll, Waker},
+};
+
+struct LockState {
+    locked: bool,
+    wakers: Vec<Waker>,
+}
+
+pub(crate) struct AsyncLock {
+    state: spin::Mutex<LockState>,
+}
+
+impl AsyncLock {
+    pub const fn new() -> Self {
+        Self {
+            state: spin::Mutex::new(LockState {
+                locked: false,
+                wakers: Vec::new(),
+            }),
+        }
+    }
+
+    pub fn lock(&self) -> LockFuture<'_> {
+        LockFuture { lock: self }
+    }
+}
+
+pub(crate) struct LockFuture<'a> {
+    lock: &'a AsyncLock,
+}
+
+impl<'a> Future for LockFuture<'a> {
+    type Output = LockGuard<'a>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = self.lock.state.lock();
+        if state.locked {
+            state.wakers.push(cx.waker().clone());
+            Poll::Pending
+        } else {
+            state.locked = true;
+            Poll::Ready(LockGuard { lock: self.lock })
+        }
+    }
+}
+
+pub(crate) struct LockGuard<'a> {
+    lock: &'a AsyncLock,
+}
+
+impl Drop for LockGuard<'_> {
+    fn drop(&mut self) {
+        let wakers = {
+            let mut state = self.lock.state.lock();
+            state.locked = false;
+            core::mem::take(&mut state.wakers)
+        };
+        // Everyone retries; the ones that lose register themselves again
+        wakers.into_iter().for_each(Waker::wake);
+    }
+}
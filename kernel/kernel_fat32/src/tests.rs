@@ -0,0 +1,395 @@
+use std::{
+    boxed::Box,
+    future::Future,
+    pin::Pin,
+    string::String,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, RawWaker, RawWakerVTable, Waker},
+    vec,
+    vec::Vec,
+};
+
+use async_trait::async_trait;
+use kernel_io::{BlockDevice, Read, Seek, SeekFrom, Write};
+
+use crate::{
+    boot_sector::{read_u32, FSINFO_FREE_COUNT},
+    format, FatError, FileSystem,
+};
+
+/// A disk image in memory
+struct RamDisk {
+    data: Mutex<Vec<u8>>,
+}
+
+impl RamDisk {
+    fn new(size: usize) -> Self {
+        Self::from_image(vec![0; size])
+    }
+
+    fn from_image(data: Vec<u8>) -> Self {
+        Self {
+            data: Mutex::new(data),
+        }
+    }
+
+    fn into_image(self) -> Vec<u8> {
+        self.data.into_inner().unwrap()
+    }
+}
+
+#[async_trait]
+impl BlockDevice for RamDisk {
+    type Error = ();
+
+    fn block_size(&self) -> usize {
+        512
+    }
+
+    fn capacity(&self) -> u64 {
+        self.data.lock().unwrap().len() as u64 / 512
+    }
+
+    async fn read_blocks(&self, start: u64, buf: &mut [u8]) -> Result<(), ()> {
+        let data = self.data.lock().unwrap();
+        let start = start as usize * 512;
+        buf.copy_from_slice(data.get(start..start + buf.len()).ok_or(())?);
+        Ok(())
+    }
+
+    async fn write_blocks(&self, start: u64, buf: &[u8]) -> Result<(), ()> {
+        let mut data = self.data.lock().unwrap();
+        let start = start as usize * 512;
+        data.get_mut(start..start + buf.len())
+            .ok_or(())?
+            .copy_from_slice(buf);
+        Ok(())
+    }
+
+    async fn flush(&self) -> Result<(), ()> {
+        Ok(())
+    }
+}
+
+/// Runs a future to completion. Nothing here actually waits, so it can just be polled in a loop.
+fn block_on<F: Future>(future: F) -> F::Output {
+    fn noop_raw_waker() -> RawWaker {
+        fn clone(_: *const ()) -> RawWaker {
+            noop_raw_waker()
+        }
+        fn noop(_: *const ()) {}
+        static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, noop, noop, noop);
+        RawWaker::new(core::ptr::null(), &VTABLE)
+    }
+    let waker = unsafe { Waker::from_raw(noop_raw_waker()) };
+    let mut context = Context::from_waker(&waker);
+    let mut future = Box::pin(future);
+    loop {
+        if let Poll::Ready(output) = Pin::as_mut(&mut future).poll(&mut context) {
+            return output;
+        }
+    }
+}
+
+const DISK_SIZE: usize = 64 * 1024 * 1024;
+
+fn new_filesystem() -> Arc<FileSystem<RamDisk>> {
+    let disk = RamDisk::new(DISK_SIZE);
+    block_on(format(&disk)).unwrap();
+    Arc::new(block_on(FileSystem::mount(disk)).unwrap())
+}
+
+fn write_file(fs: &Arc<FileSystem<RamDisk>>, path: &str, contents: &[u8]) {
+    block_on(async {
+        let mut file = fs.create(path).await.unwrap();
+        let mut written = 0;
+        while written < contents.len() {
+            written += file.write(&contents[written..]).await.unwrap();
+        }
+    })
+}
+
+fn read_file(fs: &Arc<FileSystem<RamDisk>>, path: &str) -> Vec<u8> {
+    block_on(async {
+        let mut file = fs.open(path).await.unwrap();
+        file.read_to_end_new().await.unwrap().1
+    })
+}
+
+fn names(fs: &FileSystem<RamDisk>, path: &str) -> Vec<String> {
+    let mut names: Vec<String> = block_on(fs.read_dir(path))
+        .unwrap()
+        .iter()
+        .map(|entry| entry.name().into())
+        .collect();
+    names.sort();
+    names
+}
+
+#[test]
+fn format_and_mount() {
+    let fs = new_filesystem();
+    assert!(fs.cluster_count() >= 65525);
+    assert!(names(&fs, "/").is_empty());
+}
+
+#[test]
+fn rejects_other_filesystems() {
+    let disk = RamDisk::new(DISK_SIZE);
+    assert!(matches!(
+        block_on(FileSystem::mount(disk)),
+        Err(FatError::NotFat32)
+    ));
+}
+
+#[test]
+fn write_and_read_back() {
+    let fs = new_filesystem();
+    // Spans several clusters, and doesn't end at a sector boundary
+    let contents: Vec<u8> = (0..20000u32).map(|i| (i * 7) as u8).collect();
+    write_file(&fs, "DATA.BIN", &contents);
+    assert_eq!(read_file(&fs, "DATA.BIN"), contents);
+    assert_eq!(block_on(fs.metadata("data.bin")).unwrap().size(), 20000);
+}
+
+#[test]
+fn persists_across_mounts() {
+    let fs = new_filesystem();
+    block_on(fs.create_dir("etc")).unwrap();
+    write_file(&fs, "etc/hostname", b"kernel\n");
+
+    let image = Arc::try_unwrap(fs).ok().unwrap().into_device().into_image();
+    let fs = Arc::new(block_on(FileSystem::mount(RamDisk::from_image(image))).unwrap());
+    assert_eq!(read_file(&fs, "/etc/hostname"), b"kernel\n");
+}
+
+#[test]
+fn long_file_names() {
+    let fs = new_filesystem();
+    write_file(&fs, "A rather long file name.text", b"1");
+    write_file(&fs, "A rather long file name.json", b"2");
+    write_file(&fs, "lowercase.txt", b"3");
+    write_file(&fs, "ÜNICODE ✓.md", b"4");
+    assert_eq!(
+        names(&fs, ""),
+        [
+            "A rather long file name.json",
+            "A rather long file name.text",
+            "lowercase.txt",
+            "ÜNICODE ✓.md"
+        ]
+    );
+    assert_eq!(read_file(&fs, "a rather long FILE name.text"), b"1");
+    assert_eq!(read_file(&fs, "A rather long file name.json"), b"2");
+    assert_eq!(read_file(&fs, "ÜNICODE ✓.md"), b"4");
+}
+
+#[test]
+fn nested_directories() {
+    let fs = new_filesystem();
+    block_on(fs.create_dir("a")).unwrap();
+    block_on(fs.create_dir("a/b")).unwrap();
+    write_file(&fs, "a/b/c.txt", b"nested");
+    assert_eq!(names(&fs, "a"), ["b"]);
+    assert_eq!(names(&fs, "a/b"), ["c.txt"]);
+    assert_eq!(read_file(&fs, "a/b/../b/./c.txt"), b"nested");
+    assert!(block_on(fs.metadata("a/b")).unwrap().is_dir());
+    assert!(matches!(
+        block_on(fs.create_dir("a/b")),
+        Err(FatError::AlreadyExists)
+    ));
+    assert!(matches!(
+        block_on(fs.open("a/b/c.txt/d")),
+        Err(FatError::NotADirectory)
+    ));
+    assert!(matches!(
+        block_on(fs.open("a/b")),
+        Err(FatError::IsADirectory)
+    ));
+}
+
+#[test]
+fn directories_grow() {
+    let fs = new_filesystem();
+    // Each of these takes 3 entries, so they don't fit in a single 512-byte cluster
+    for i in 0..100 {
+        write_file(&fs, &std::format!("file number {}", i), &[i as u8]);
+    }
+    assert_eq!(names(&fs, "/").len(), 100);
+    for i in 0..100 {
+        assert_eq!(
+            read_file(&fs, &std::format!("file number {}", i)),
+            [i as u8]
+        );
+    }
+}
+
+#[test]
+fn remove_frees_clusters() {
+    let fs = new_filesystem();
+    block_on(fs.create_dir("dir")).unwrap();
+    write_file(&fs, "dir/big file", &vec![1; 100_000]);
+    assert!(matches!(
+        block_on(fs.remove("dir")),
+        Err(FatError::DirectoryNotEmpty)
+    ));
+    block_on(fs.remove("dir/big file")).unwrap();
+    block_on(fs.remove("dir")).unwrap();
+    assert!(names(&fs, "/").is_empty());
+    assert!(matches!(
+        block_on(fs.open("dir/big file")),
+        Err(FatError::NotFound)
+    ));
+
+    // The freed clusters are used again
+    let first = block_on(fs.metadata("/")).unwrap().first_cluster;
+    write_file(&fs, "again", &vec![2; 512]);
+    assert_eq!(
+        block_on(fs.metadata("again")).unwrap().first_cluster,
+        first + 1
+    );
+}
+
+#[test]
+fn rename() {
+    let fs = new_filesystem();
+    block_on(fs.create_dir("from")).unwrap();
+    block_on(fs.create_dir("to")).unwrap();
+    block_on(fs.create_dir("from/inner")).unwrap();
+    write_file(&fs, "from/inner/file", b"contents");
+
+    block_on(fs.rename("from/inner", "to/moved")).unwrap();
+    assert!(names(&fs, "from").is_empty());
+    assert_eq!(names(&fs, "to"), ["moved"]);
+    assert_eq!(read_file(&fs, "to/moved/file"), b"contents");
+    // `..` has to point to the new parent
+    assert_eq!(names(&fs, "to/moved/.."), ["moved"]);
+
+    block_on(fs.rename("to/moved/file", "to/moved/FILE")).unwrap();
+    assert_eq!(names(&fs, "to/moved"), ["FILE"]);
+    write_file(&fs, "other", b"");
+    assert!(matches!(
+        block_on(fs.rename("other", "to/moved/file")),
+        Err(FatError::AlreadyExists)
+    ));
+    assert!(matches!(
+        block_on(fs.rename("to", "to/moved/to")),
+        Err(FatError::InvalidName)
+    ));
+}
+
+#[test]
+fn create_truncates() {
+    let fs = new_filesystem();
+    write_file(&fs, "file", &vec![1; 5000]);
+    write_file(&fs, "file", b"short");
+    assert_eq!(read_file(&fs, "file"), b"short");
+}
+
+#[test]
+fn seek_and_overwrite() {
+    let fs = new_filesystem();
+    write_file(&fs, "file", b"hello world");
+    block_on(async {
+        let mut file = fs.open("file").await.unwrap();
+        assert_eq!(file.seek(SeekFrom::Start(6)).await.unwrap(), 6);
+        file.write(b"there").await.unwrap();
+        assert_eq!(file.seek(SeekFrom::Current(-5)).await.unwrap(), 6);
+        let mut buffer = [0; 5];
+        file.read(&mut buffer).await.unwrap();
+        assert_eq!(&buffer, b"there");
+        assert_eq!(file.seek(SeekFrom::End(-11)).await.unwrap(), 0);
+        assert!(matches!(
+            file.seek(SeekFrom::End(1)).await,
+            Err(FatError::InvalidSeek)
+        ));
+        assert!(matches!(
+            file.seek(SeekFrom::Current(-1)).await,
+            Err(FatError::InvalidSeek)
+        ));
+        // Writing at the end extends the file
+        file.seek(SeekFrom::End(0)).await.unwrap();
+        file.write(b"!").await.unwrap();
+    });
+    assert_eq!(read_file(&fs, "file"), b"hello there!");
+}
+
+#[test]
+fn invalid_names() {
+    let fs = new_filesystem();
+    for name in ["", "what?", "star*", "..", "trailing "] {
+        assert!(block_on(fs.create(name)).is_err(), "{:?}", name);
+    }
+}
+
+/// A volume that another implementation formatted and put files in. See fixtures/README.md.
+fn fixture() -> Arc<FileSystem<RamDisk>> {
+    let compressed = include_bytes!("../fixtures/fat32.img.gz");
+    let mut decoder = flate2::read::GzDecoder::new(&compressed[..]);
+    let mut image = Vec::new();
+    std::io::Read::read_to_end(&mut decoder, &mut image).unwrap();
+    Arc::new(block_on(FileSystem::mount(RamDisk::from_image(image))).unwrap())
+}
+
+#[test]
+fn fixture_mounts() {
+    let fs = fixture();
+    assert_eq!(fs.cluster_size(), 512);
+    assert_eq!(fs.cluster_count(), 72584);
+    assert_eq!(
+        names(&fs, "/"),
+        ["A long file name.txt", "HELLO.TXT", "docs", "empty"]
+    );
+    assert_eq!(names(&fs, "docs"), ["deep", "readme.md"]);
+    assert_eq!(names(&fs, "docs/deep"), ["deeper"]);
+}
+
+#[test]
+fn fixture_files() {
+    let fs = fixture();
+    assert_eq!(read_file(&fs, "HELLO.TXT"), b"Hello from the fixture!\n");
+    assert_eq!(read_file(&fs, "hello.txt"), b"Hello from the fixture!\n");
+    assert_eq!(read_file(&fs, "A long file name.txt"), b"long\n");
+    assert_eq!(read_file(&fs, "docs/readme.md"), b"# Fixture\n");
+    assert_eq!(read_file(&fs, "empty"), b"");
+    // Spans several clusters
+    let contents: Vec<u8> = (0..3000u32).map(|i| (i * 7) as u8).collect();
+    assert_eq!(read_file(&fs, "/docs/deep/deeper/file.bin"), contents);
+    assert!(block_on(fs.metadata("docs/deep")).unwrap().is_dir());
+    assert_eq!(block_on(fs.metadata("empty")).unwrap().first_cluster, 0);
+    // `..` in a directory under the root points to cluster 0
+    assert_eq!(names(&fs, "docs/.."), names(&fs, "/"));
+}
+
+#[test]
+fn fixture_modify() {
+    let fs = fixture();
+    write_file(&fs, "docs/deep/new file.txt", &vec![3; 2000]);
+    block_on(fs.remove("HELLO.TXT")).unwrap();
+    block_on(fs.rename("docs/readme.md", "README")).unwrap();
+
+    let image = Arc::try_unwrap(fs).ok().unwrap().into_device().into_image();
+    let fs = Arc::new(block_on(FileSystem::mount(RamDisk::from_image(image))).unwrap());
+    assert_eq!(read_file(&fs, "docs/deep/new file.txt"), vec![3; 2000]);
+    assert_eq!(read_file(&fs, "README"), b"# Fixture\n");
+    assert!(matches!(
+        block_on(fs.open("HELLO.TXT")),
+        Err(FatError::NotFound)
+    ));
+}
+
+#[test]
+fn fixture_free_count_is_forgotten() {
+    let fs = fixture();
+    let free_count = |fs: &FileSystem<RamDisk>| {
+        let image = fs.device().data.lock().unwrap();
+        read_u32(&image[512..1024], FSINFO_FREE_COUNT)
+    };
+    // The formatter counted them
+    assert_eq!(free_count(&fs), 72571);
+    // Reading doesn't change anything
+    read_file(&fs, "docs/deep/deeper/file.bin");
+    assert_eq!(free_count(&fs), 72571);
+    write_file(&fs, "new", b"data");
+    assert_eq!(free_count(&fs), u32::MAX);
+}
@@ -0,0 +1,7 @@
+#!/bin/sh
+# Replaces qemu-virt.dtb with the tree that the installed QEMU builds, and prints the QEMU
+# version to record in README.md
+set -e
+cd "$(dirname "$0")"
+qemu-system-riscv64 -M virt,dumpdtb=qemu-virt.dtb -smp 1 -m 128M -nographic
+qemu-system-riscv64 --version | head -n 1
@@ -0,0 +1,241 @@
+#!/usr/bin/env python3
+"""Writes qemu-virt.dtb: the tree QEMU 8.2 gives `qemu-system-riscv64 -M virt -smp 1 -m 128M`,
+in the order QEMU adds nodes and properties. See README.md for what's left out."""
+
+import struct
+
+CPU0 = 1
+CPU0_INTC = 2
+PLIC = 3
+TEST = 4
+
+PCIE_IRQ = 0x20
+VIRTIO_IRQ = 1
+VIRTIO_COUNT = 8
+
+
+def cells(*values):
+    return b"".join(struct.pack(">I", value) for value in values)
+
+
+def string(*values):
+    return b"".join(value.encode() + b"\0" for value in values)
+
+
+FLAG = b""
+
+
+def node(name, properties, children=()):
+    return (name, properties, list(children))
+
+
+def interrupt_map():
+    entries = []
+    for slot in range(4):
+        for pin in range(4):
+            irq = PCIE_IRQ + (pin + slot) % 4
+            entries += [slot << 11, 0, 0, pin + 1, PLIC, irq]
+    return cells(*entries)
+
+
+ISA_EXTENSIONS = [
+    "i", "m", "a", "f", "d", "c", "h", "zicbom", "zicboz", "zicntr", "zicsr", "zifencei",
+    "zihintntl", "zihintpause", "zihpm", "zawrs", "zfa", "zca", "zcd", "zba", "zbb", "zbc",
+    "zbs", "sstc", "svadu",
+]
+
+virtio = [
+    node(
+        "virtio_mmio@%x" % (0x10001000 + slot * 0x1000),
+        [
+            ("interrupts", cells(VIRTIO_IRQ + slot)),
+            ("interrupt-parent", cells(PLIC)),
+            ("reg", cells(0, 0x10001000 + slot * 0x1000, 0, 0x1000)),
+            ("compatible", string("virtio,mmio")),
+        ],
+    )
+    # QEMU adds the slots from the last one, so they end up in reverse order
+    for slot in reversed(range(VIRTIO_COUNT))
+]
+
+tree = node(
+    "",
+    [
+        ("#address-cells", cells(2)),
+        ("#size-cells", cells(2)),
+        ("compatible", string("riscv-virtio")),
+        ("model", string("riscv-virtio,qemu")),
+    ],
+    [
+        node("poweroff", [
+            ("value", cells(0x5555)),
+            ("offset", cells(0)),
+            ("regmap", cells(TEST)),
+            ("compatible", string("syscon-poweroff")),
+        ]),
+        node("reboot", [
+            ("value", cells(0x7777)),
+            ("offset", cells(0)),
+            ("regmap", cells(TEST)),
+            ("compatible", string("syscon-reboot")),
+        ]),
+        node("platform-bus@4000000", [
+            ("interrupt-parent", cells(PLIC)),
+            ("ranges", cells(0, 0, 0x4000000, 0x2000000)),
+            ("#address-cells", cells(1)),
+            ("#size-cells", cells(1)),
+            ("compatible", string("qemu,platform", "simple-bus")),
+        ]),
+        node("memory@80000000", [
+            ("device_type", string("memory")),
+            ("reg", cells(0, 0x80000000, 0, 0x8000000)),
+        ]),
+        node("cpus", [
+            ("#address-cells", cells(1)),
+            ("#size-cells", cells(0)),
+            ("timebase-frequency", cells(10_000_000)),
+        ], [
+            node("cpu@0", [
+                ("phandle", cells(CPU0)),
+                ("device_type", string("cpu")),
+                ("reg", cells(0)),
+                ("status", string("okay")),
+                ("compatible", string("riscv")),
+                ("riscv,cbop-block-size", cells(64)),
+                ("riscv,cboz-block-size", cells(64)),
+                ("riscv,cbom-block-size", cells(64)),
+                ("riscv,isa-extensions", string(*ISA_EXTENSIONS)),
+                ("riscv,isa-base", string("rv64i")),
+                ("riscv,isa", string("rv64imafdch_" + "_".join(ISA_EXTENSIONS[7:]))),
+                ("mmu-type", string("riscv,sv57")),
+            ], [
+                node("interrupt-controller", [
+                    ("#interrupt-cells", cells(1)),
+                    ("interrupt-controller", FLAG),
+                    ("compatible", string("riscv,cpu-intc")),
+                    ("phandle", cells(CPU0_INTC)),
+                ]),
+            ]),
+            node("cpu-map", [], [
+                node("cluster0", [], [
+                    node("core0", [("cpu", cells(CPU0))]),
+                ]),
+            ]),
+        ]),
+        node("fw-cfg@10100000", [
+            ("dma-coherent", FLAG),
+            ("reg", cells(0, 0x10100000, 0, 0x18)),
+            ("compatible", string("qemu,fw-cfg-mmio")),
+        ]),
+        node("flash@20000000", [
+            ("bank-width", cells(4)),
+            ("reg", cells(0, 0x20000000, 0, 0x2000000, 0, 0x22000000, 0, 0x2000000)),
+            ("compatible", string("cfi-flash")),
+        ]),
+        node("chosen", [
+            ("stdout-path", string("/soc/serial@10000000")),
+        ]),
+        node("soc", [
+            ("#address-cells", cells(2)),
+            ("#size-cells", cells(2)),
+            ("compatible", string("simple-bus")),
+            ("ranges", FLAG),
+        ], [
+            node("rtc@101000", [
+                ("interrupts", cells(11)),
+                ("interrupt-parent", cells(PLIC)),
+                ("reg", cells(0, 0x101000, 0, 0x1000)),
+                ("compatible", string("google,goldfish-rtc")),
+            ]),
+            node("serial@10000000", [
+                ("interrupts", cells(10)),
+                ("interrupt-parent", cells(PLIC)),
+                ("clock-frequency", cells(0x384000)),
+                ("reg", cells(0, 0x10000000, 0, 0x100)),
+                ("compatible", string("ns16550a")),
+            ]),
+            node("test@100000", [
+                ("phandle", cells(TEST)),
+                ("reg", cells(0, 0x100000, 0, 0x1000)),
+                ("compatible", string("sifive,test1", "sifive,test0", "syscon")),
+            ]),
+            *virtio,
+            node("plic@c000000", [
+                ("phandle", cells(PLIC)),
+                ("riscv,ndev", cells(0x5f)),
+                ("reg", cells(0, 0xc000000, 0, 0x600000)),
+                ("interrupts-extended", cells(CPU0_INTC, 11, CPU0_INTC, 9)),
+                ("interrupt-controller", FLAG),
+                ("compatible", string("sifive,plic-1.0.0", "riscv,plic0")),
+                ("#address-cells", cells(0)),
+                ("#interrupt-cells", cells(1)),
+            ]),
+            node("clint@2000000", [
+                ("interrupts-extended", cells(CPU0_INTC, 3, CPU0_INTC, 7)),
+                ("reg", cells(0, 0x2000000, 0, 0x10000)),
+                ("compatible", string("sifive,clint0", "riscv,clint0")),
+            ]),
+            node("pci@30000000", [
+                ("interrupt-map-mask", cells(0x1800, 0, 0, 7)),
+                ("interrupt-map", interrupt_map()),
+                ("ranges", cells(
+                    0x1000000, 0, 0, 0, 0x3000000, 0, 0x10000,
+                    0x2000000, 0, 0x40000000, 0, 0x40000000, 0, 0x40000000,
+                    0x3000000, 0x4, 0, 0x4, 0, 0x4, 0,
+                )),
+                ("reg", cells(0, 0x30000000, 0, 0x10000000)),
+                ("dma-coherent", FLAG),
+                ("bus-range", cells(0, 0xff)),
+                ("linux,pci-domain", cells(0)),
+                ("device_type", string("pci")),
+                ("compatible", string("pci-host-ecam-generic")),
+                ("#size-cells", cells(2)),
+                ("#interrupt-cells", cells(1)),
+                ("#address-cells", cells(3)),
+            ]),
+        ]),
+    ],
+)
+
+
+def flatten(tree):
+    structure = bytearray()
+    strings = bytearray()
+    offsets = {}
+
+    def pad():
+        structure.extend(bytes(-len(structure) % 4))
+
+    def emit(node):
+        name, properties, children = node
+        structure.extend(cells(1) + string(name))
+        pad()
+        for property, value in properties:
+            if property not in offsets:
+                offsets[property] = len(strings)
+                strings.extend(string(property))
+            structure.extend(cells(3, len(value), offsets[property]) + value)
+            pad()
+        for child in children:
+            emit(child)
+        structure.extend(cells(2))
+
+    emit(tree)
+    structure.extend(cells(9))
+
+    header_size = 40
+    # Just the terminating entry
+    reservations = bytes(16)
+    structure_offset = header_size + len(reservations)
+    strings_offset = structure_offset + len(structure)
+    total_size = strings_offset + len(strings)
+    header = struct.pack(
+        ">10I", 0xD00DFEED, total_size, structure_offset, strings_offset, header_size,
+        17, 16, 0, len(strings), len(structure),
+    )
+    return header + reservations + bytes(structure) + bytes(strings)
+
+
+if __name__ == "__main__":
+    with open("qemu-virt.dtb", "wb") as file:
+        file.write(flatten(tree))
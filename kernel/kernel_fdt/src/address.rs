@@ -0,0 +1,55 @@
+//! Translating `reg` addresses to the CPU's physical addresses, through the `ranges` of the
+//! buses above a node.
+//! See https://github.com/devicetree-org/devicetree-specification/releases/tag/v0.3, 2.3.8
+
+use alloc::vec::Vec;
+
+use crate::{Node, Range};
+
+/// The address space that a bus's children have their `reg` in. Spaces are made while
+/// walking down the tree, since a node's space depends on every bus above it.
+#[derive(Debug)]
+pub struct AddressSpace<'p> {
+    /// `None` if it's the same as the parent's, which is what an empty `ranges` means
+    ranges: Option<Vec<Range>>,
+    /// `None` for the root, whose address space is the CPU's
+    parent: Option<&'p AddressSpace<'p>>,
+}
+
+impl AddressSpace<'static> {
+    /// The space that the root's children are in, which is the CPU's even though the root
+    /// has no `ranges`
+    pub fn root() -> Self {
+        Self {
+            ranges: None,
+            parent: None,
+        }
+    }
+}
+
+impl<'p> AddressSpace<'p> {
+    /// The space that `node`'s children are in, if `node`'s `reg` is in `self`. `None` if
+    /// `node` has no `ranges`, so the children's addresses don't mean anything to the CPU.
+    pub fn children<'s>(&'s self, node: &Node) -> Option<AddressSpace<'s>> {
+        let ranges = node.ranges()?;
+        let empty = node
+            .property("ranges")
+            .is_some_and(|ranges| ranges.is_empty());
+        Some(AddressSpace {
+            ranges: (!empty).then(|| ranges.collect()),
+            parent: Some(self),
+        })
+    }
+
+    /// Where the CPU sees `address`, or `None` if a bus on the way doesn't forward it
+    pub fn translate(&self, address: u64) -> Option<u64> {
+        let address = match &self.ranges {
+            None => address,
+            Some(ranges) => ranges.iter().find_map(|range| range.to_parent(address))?,
+        };
+        match self.parent {
+            Some(parent) => parent.translate(address),
+            None => Some(address),
+        }
+    }
+}
@@ -0,0 +1,178 @@
+//! Writes device trees, either from scratch or by copying parts of an existing one,
+//! for example to hand a modified tree to a guest.
+
+use alloc::vec::Vec;
+
+use crate::{
+    align4, MemoryReservation, Node, FDT_BEGIN_NODE, FDT_END, FDT_END_NODE, FDT_PROP, HEADER_SIZE,
+    LAST_COMPATIBLE_VERSION, MAGIC, VERSION,
+};
+
+/// Nodes are written in order: open one with [`FdtBuilder::begin_node`], add its properties
+/// and then its children, and close it with [`FdtBuilder::end_node`]. The first node is the
+/// root, whose name is empty.
+#[derive(Default)]
+pub struct FdtBuilder {
+    structure: Vec<u8>,
+    strings: Vec<u8>,
+    reservations: Vec<MemoryReservation>,
+    boot_cpu: u32,
+    /// How many nodes are open
+    depth: usize,
+}
+
+impl FdtBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn boot_cpu(&mut self, id: u32) -> &mut Self {
+        self.boot_cpu = id;
+        self
+    }
+
+    pub fn reserve_memory(&mut self, address: u64, size: u64) -> &mut Self {
+        self.reservations.push(MemoryReservation { address, size });
+        self
+    }
+
+    fn push_u32(&mut self, value: u32) {
+        self.structure.extend_from_slice(&value.to_be_bytes());
+    }
+
+    fn pad(&mut self) {
+        self.structure.resize(align4(self.structure.len()), 0);
+    }
+
+    /// Returns the offset of `name` in the strings block, adding it if it isn't there
+    fn string_offset(&mut self, name: &str) -> u32 {
+        let mut offset = 0;
+        for string in self.strings.split(|byte| *byte == 0) {
+            if string == name.as_bytes() && offset + string.len() < self.strings.len() {
+                return offset as u32;
+            }
+            offset += string.len() + 1;
+        }
+        let offset = self.strings.len();
+        self.strings.extend_from_slice(name.as_bytes());
+        self.strings.push(0);
+        offset as u32
+    }
+
+    pub fn begin_node(&mut self, name: &str) -> &mut Self {
+        self.push_u32(FDT_BEGIN_NODE);
+        self.structure.extend_from_slice(name.as_bytes());
+        self.structure.push(0);
+        self.pad();
+        self.depth += 1;
+        self
+    }
+
+    pub fn end_node(&mut self) -> &mut Self {
+        assert!(self.depth > 0, "end_node without begin_node");
+        self.push_u32(FDT_END_NODE);
+        self.depth -= 1;
+        self
+    }
+
+    pub fn property(&mut self, name: &str, value: &[u8]) -> &mut Self {
+        assert!(self.depth > 0, "properties have to be inside a node");
+        let name_offset = self.string_offset(name);
+        self.push_u32(FDT_PROP);
+        self.push_u32(value.len() as u32);
+        self.push_u32(name_offset);
+        self.structure.extend_from_slice(value);
+        self.pad();
+        self
+    }
+
+    /// A property without a value, like `interrupt-controller`
+    pub fn flag(&mut self, name: &str) -> &mut Self {
+        self.property(name, &[])
+    }
+
+    pub fn property_u32(&mut self, name: &str, value: u32) -> &mut Self {
+        self.property(name, &value.to_be_bytes())
+    }
+
+    pub fn property_u64(&mut self, name: &str, value: u64) -> &mut Self {
+        self.property(name, &value.to_be_bytes())
+    }
+
+    pub fn property_cells(&mut self, name: &str, cells: &[u32]) -> &mut Self {
+        let value: Vec<u8> = cells.iter().flat_map(|cell| cell.to_be_bytes()).collect();
+        self.property(name, &value)
+    }
+
+    pub fn property_str(&mut self, name: &str, value: &str) -> &mut Self {
+        self.property_strings(name, &[value])
+    }
+
+    pub fn property_strings(&mut self, name: &str, values: &[&str]) -> &mut Self {
+        let mut value = Vec::new();
+        for string in values {
+            value.extend_from_slice(string.as_bytes());
+            value.push(0);
+        }
+        self.property(name, &value)
+    }
+
+    /// Copies the properties of `node`, but not its children
+    pub fn copy_properties(&mut self, node: &Node) -> &mut Self {
+        for property in node.properties() {
+            self.property(property.name, property.value);
+        }
+        self
+    }
+
+    /// Copies `node` and everything in it
+    pub fn copy_node(&mut self, node: &Node) -> &mut Self {
+        self.begin_node(node.name());
+        self.copy_properties(node);
+        for child in node.children() {
+            self.copy_node(&child);
+        }
+        self.end_node()
+    }
+
+    pub fn finish(mut self) -> Vec<u8> {
+        assert_eq!(self.depth, 0, "a node wasn't ended");
+        self.push_u32(FDT_END);
+
+        // The reservation block has to be 8-byte aligned, and the header's size already is
+        let reservations_offset = HEADER_SIZE;
+        let structure_offset = reservations_offset + (self.reservations.len() + 1) * 16;
+        let strings_offset = structure_offset + self.structure.len();
+        let total_size = strings_offset + self.strings.len();
+
+        let mut blob = Vec::with_capacity(total_size);
+        for field in [
+            MAGIC,
+            total_size as u32,
+            structure_offset as u32,
+            strings_offset as u32,
+            reservations_offset as u32,
+            VERSION,
+            LAST_COMPATIBLE_VERSION,
+            self.boot_cpu,
+            self.strings.len() as u32,
+            self.structure.len() as u32,
+        ] {
+            blob.extend_from_slice(&field.to_be_bytes());
+        }
+        for reservation in self
+            .reservations
+            .iter()
+            .chain(core::iter::once(&MemoryReservation {
+                address: 0,
+                size: 0,
+            }))
+        {
+            blob.extend_from_slice(&reservation.address.to_be_bytes());
+            blob.extend_from_slice(&reservation.size.to_be_bytes());
+        }
+        blob.extend_from_slice(&self.structure);
+        blob.extend_from_slice(&self.strings);
+        blob
+    }
+}
@@ -0,0 +1,333 @@
+//! A flattened device tree parser that borrows from the blob, and a builder for new ones.
+//! The whole structure block is checked when the tree is opened, so malformed trees are
+//! rejected up front and the accessors never panic.
+//! See https://github.com/devicetree-org/devicetree-specification/releases/tag/v0.3, chapter 5
+
+#![cfg_attr(not(test), no_std)]
+
+extern crate alloc;
+
+mod address;
+pub mod builder;
+mod node;
+mod property;
+#[cfg(test)]
+mod tests;
+
+pub use address::AddressSpace;
+pub use builder::FdtBuilder;
+pub use node::{Children, Interrupt, Interrupts, Node, Range, Ranges, Reg, RegEntry, Status};
+pub use property::{Cells, Property, StringList};
+
+const MAGIC: u32 = 0xd00d_feed;
+const HEADER_SIZE: usize = 40;
+/// The oldest version whose layout we can read
+const LAST_COMPATIBLE_VERSION: u32 = 16;
+/// The version that [`FdtBuilder`] writes
+const VERSION: u32 = 17;
+
+const FDT_BEGIN_NODE: u32 = 1;
+const FDT_END_NODE: u32 = 2;
+const FDT_PROP: u32 = 3;
+const FDT_NOP: u32 = 4;
+const FDT_END: u32 = 9;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FdtError {
+    BadMagic,
+    /// The tree needs a newer parser than this one
+    UnsupportedVersion(u32),
+    /// An offset or size points outside the blob
+    Truncated,
+    /// The structure block has unbalanced nodes or unknown tokens
+    BadStructure,
+    /// A node or property name isn't a valid string
+    BadString,
+}
+
+pub type Result<T> = core::result::Result<T, FdtError>;
+
+pub(crate) fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset.checked_add(4)?)?;
+    Some(u32::from_be_bytes(bytes.try_into().ok()?))
+}
+
+pub(crate) fn read_u64(data: &[u8], offset: usize) -> Option<u64> {
+    let bytes = data.get(offset..offset.checked_add(8)?)?;
+    Some(u64::from_be_bytes(bytes.try_into().ok()?))
+}
+
+pub(crate) fn align4(offset: usize) -> usize {
+    (offset + 3) & !3
+}
+
+/// A string up to the first NUL
+fn read_str(data: &[u8], offset: usize) -> Result<&str> {
+    let rest = data.get(offset..).ok_or(FdtError::Truncated)?;
+    let end = rest
+        .iter()
+        .position(|byte| *byte == 0)
+        .ok_or(FdtError::BadString)?;
+    core::str::from_utf8(&rest[..end]).map_err(|_| FdtError::BadString)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Token<'a> {
+    BeginNode(&'a str),
+    EndNode,
+    Property { name: &'a str, value: &'a [u8] },
+    End,
+}
+
+/// Reads the structure block token by token, skipping `FDT_NOP`s
+#[derive(Clone)]
+pub(crate) struct Cursor<'a> {
+    fdt: Fdt<'a>,
+    pub(crate) position: usize,
+}
+
+impl<'a> Cursor<'a> {
+    pub(crate) fn new(fdt: Fdt<'a>, position: usize) -> Self {
+        Self { fdt, position }
+    }
+
+    pub(crate) fn next_token(&mut self) -> Result<Token<'a>> {
+        let structure = self.fdt.structure;
+        loop {
+            let token = read_u32(structure, self.position).ok_or(FdtError::Truncated)?;
+            self.position += 4;
+            match token {
+                FDT_NOP => continue,
+                FDT_BEGIN_NODE => {
+                    let name = read_str(structure, self.position)?;
+                    self.position = align4(self.position + name.len() + 1);
+                    return Ok(Token::BeginNode(name));
+                }
+                FDT_END_NODE => return Ok(Token::EndNode),
+                FDT_PROP => {
+                    let len = read_u32(structure, self.position).ok_or(FdtError::Truncated)?;
+                    let name_offset =
+                        read_u32(structure, self.position + 4).ok_or(FdtError::Truncated)?;
+                    let start = self.position + 8;
+                    let value = structure
+                        .get(start..start + len as usize)
+                        .ok_or(FdtError::Truncated)?;
+                    let name = read_str(self.fdt.strings, name_offset as usize)?;
+                    self.position = align4(start + len as usize);
+                    return Ok(Token::Property { name, value });
+                }
+                FDT_END => return Ok(Token::End),
+                _ => return Err(FdtError::BadStructure),
+            }
+        }
+    }
+
+    /// Skips the rest of the node whose properties or children the cursor is in
+    pub(crate) fn skip_node(&mut self) -> Result<()> {
+        let mut depth = 1;
+        while depth > 0 {
+            match self.next_token()? {
+                Token::BeginNode(_) => depth += 1,
+                Token::EndNode => depth -= 1,
+                Token::Property { .. } => {}
+                Token::End => return Err(FdtError::BadStructure),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A memory range that the OS must not use, from the memory reservation block
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryReservation {
+    pub address: u64,
+    pub size: u64,
+}
+
+#[derive(Clone, Copy)]
+pub struct Fdt<'a> {
+    data: &'a [u8],
+    structure: &'a [u8],
+    strings: &'a [u8],
+    reservations: &'a [u8],
+    boot_cpu: u32,
+}
+
+impl<'a> core::fmt::Debug for Fdt<'a> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Fdt")
+            .field("total_size", &self.data.len())
+            .finish()
+    }
+}
+
+impl<'a> Fdt<'a> {
+    pub fn new(data: &'a [u8]) -> Result<Self> {
+        let field = |index: usize| read_u32(data, index * 4).ok_or(FdtError::Truncated);
+        if field(0)? != MAGIC {
+            return Err(FdtError::BadMagic);
+        }
+        let total_size = field(1)? as usize;
+        let (structure_offset, strings_offset) = (field(2)? as usize, field(3)? as usize);
+        let reservations_offset = field(4)? as usize;
+        let last_compatible_version = field(6)?;
+        if last_compatible_version > VERSION {
+            return Err(FdtError::UnsupportedVersion(last_compatible_version));
+        }
+        let (strings_size, structure_size) = (field(8)? as usize, field(9)? as usize);
+
+        let data = data.get(..total_size).ok_or(FdtError::Truncated)?;
+        let block = |offset: usize, size: usize| {
+            data.get(offset..offset.checked_add(size).ok_or(FdtError::Truncated)?)
+                .ok_or(FdtError::Truncated)
+        };
+        let fdt = Self {
+            data,
+            structure: block(structure_offset, structure_size)?,
+            strings: block(strings_offset, strings_size)?,
+            reservations: data.get(reservations_offset..).ok_or(FdtError::Truncated)?,
+            boot_cpu: field(7)?,
+        };
+        fdt.validate()?;
+        Ok(fdt)
+    }
+
+    /// Reads a tree whose size is only known from its header, like the one the firmware
+    /// passes to the kernel.
+    ///
+    /// # Safety
+    /// `ptr` has to point to a device tree that's readable and unchanged for `'a`
+    pub unsafe fn from_ptr(ptr: *const u8) -> Result<Self> {
+        let header = core::slice::from_raw_parts(ptr, HEADER_SIZE);
+        if read_u32(header, 0) != Some(MAGIC) {
+            return Err(FdtError::BadMagic);
+        }
+        let total_size = read_u32(header, 4).ok_or(FdtError::Truncated)? as usize;
+        Self::new(core::slice::from_raw_parts(
+            ptr,
+            total_size.max(HEADER_SIZE),
+        ))
+    }
+
+    /// Walks the whole structure block once, so that later accesses can't fail
+    fn validate(&self) -> Result<()> {
+        let mut cursor = Cursor::new(*self, 0);
+        if !matches!(cursor.next_token()?, Token::BeginNode(_)) {
+            return Err(FdtError::BadStructure);
+        }
+        cursor.skip_node()?;
+        match cursor.next_token()? {
+            Token::End => {}
+            _ => return Err(FdtError::BadStructure),
+        }
+        self.memory_reservations_checked()
+            .try_for_each(|reservation| reservation.map(|_| ()))
+    }
+
+    /// The blob, up to the size in its header
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.data
+    }
+
+    pub fn total_size(&self) -> usize {
+        self.data.len()
+    }
+
+    /// The physical ID of the CPU that boots
+    pub fn boot_cpu(&self) -> u32 {
+        self.boot_cpu
+    }
+
+    fn memory_reservations_checked(&self) -> impl Iterator<Item = Result<MemoryReservation>> + 'a {
+        let reservations = self.reservations;
+        let mut offset = 0;
+        let mut done = false;
+        core::iter::from_fn(move || {
+            if done {
+                return None;
+            }
+            let entry = read_u64(reservations, offset).zip(read_u64(reservations, offset + 8));
+            offset += 16;
+            match entry {
+                None => {
+                    done = true;
+                    Some(Err(FdtError::Truncated))
+                }
+                Some((0, 0)) => {
+                    done = true;
+                    None
+                }
+                Some((address, size)) => Some(Ok(MemoryReservation { address, size })),
+            }
+        })
+    }
+
+    pub fn memory_reservations(&self) -> impl Iterator<Item = MemoryReservation> + 'a {
+        self.memory_reservations_checked()
+            .filter_map(|entry| entry.ok())
+    }
+
+    pub fn root(&self) -> Node<'a> {
+        let mut cursor = Cursor::new(*self, 0);
+        let name = match cursor.next_token() {
+            Ok(Token::BeginNode(name)) => name,
+            _ => "",
+        };
+        Node::new(*self, name, cursor.position, None)
+    }
+
+    /// Every node, depth first, starting with the root
+    pub fn all_nodes(&self) -> impl Iterator<Item = Node<'a>> + 'a {
+        let mut stack = alloc::vec![self.root()];
+        core::iter::from_fn(move || {
+            let node = stack.pop()?;
+            let first = stack.len();
+            stack.extend(node.children());
+            // Children are popped from the end, so reverse them to keep the tree order
+            stack[first..].reverse();
+            Some(node)
+        })
+    }
+
+    /// Finds a node by its full path. Unit addresses can be left out of the path if
+    /// there's only one node with that name, like `/cpus/cpu` for `/cpus/cpu@0`.
+    pub fn find_node(&self, path: &str) -> Option<Node<'a>> {
+        let mut node = self.root();
+        for component in path.split('/').filter(|component| !component.is_empty()) {
+            node = node.child(component)?;
+        }
+        Some(node)
+    }
+
+    pub fn find_phandle(&self, phandle: u32) -> Option<Node<'a>> {
+        self.all_nodes()
+            .find(|node| node.phandle() == Some(phandle))
+    }
+
+    /// The first enabled node compatible with any of `compatible`
+    pub fn find_compatible(&self, compatible: &[&str]) -> Option<Node<'a>> {
+        self.all_nodes().find(|node| {
+            node.is_enabled()
+                && compatible
+                    .iter()
+                    .any(|compatible| node.is_compatible(compatible))
+        })
+    }
+
+    pub fn chosen(&self) -> Option<Node<'a>> {
+        self.find_node("/chosen")
+    }
+
+    /// The kernel command line, from `/chosen`
+    pub fn bootargs(&self) -> Option<&'a str> {
+        self.chosen()?.property("bootargs")?.as_str()
+    }
+
+    /// Where the bootloader put the initial ramdisk, as a physical start and end address
+    pub fn initrd(&self) -> Option<(u64, u64)> {
+        let chosen = self.chosen()?;
+        let start = chosen.property("linux,initrd-start")?.as_u64()?;
+        let end = chosen.property("linux,initrd-end")?.as_u64()?;
+        Some((start, end))
+    }
+}
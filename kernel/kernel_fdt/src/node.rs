@@ -0,0 +1,410 @@
+//! Nodes, and the standard properties that are read from them
+
+use crate::{Cells, Cursor, Fdt, Property, StringList, Token};
+
+/// Defaults from the specification, for nodes without `#address-cells` or `#size-cells`
+const DEFAULT_ADDRESS_CELLS: u32 = 2;
+const DEFAULT_SIZE_CELLS: u32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Parent {
+    /// Where the parent's properties start, which identifies it
+    properties: usize,
+    address_cells: u32,
+    size_cells: u32,
+}
+
+#[derive(Clone, Copy)]
+pub struct Node<'a> {
+    fdt: Fdt<'a>,
+    name: &'a str,
+    /// Offset in the structure block where this node's properties start
+    properties: usize,
+    parent: Option<Parent>,
+}
+
+impl<'a> core::fmt::Debug for Node<'a> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Node").field("name", &self.name).finish()
+    }
+}
+
+impl<'a> PartialEq for Node<'a> {
+    fn eq(&self, other: &Self) -> bool {
+        core::ptr::eq(self.fdt.as_bytes(), other.fdt.as_bytes())
+            && self.properties == other.properties
+    }
+}
+
+impl<'a> Eq for Node<'a> {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status<'a> {
+    Okay,
+    Disabled,
+    /// Running, but shouldn't be used, usually because firmware owns it
+    Reserved,
+    /// Broken. The string after `fail-` says why, if it's there.
+    Fail(&'a str),
+    Other(&'a str),
+}
+
+impl<'a> Node<'a> {
+    pub(crate) fn new(
+        fdt: Fdt<'a>,
+        name: &'a str,
+        properties: usize,
+        parent: Option<&Node<'a>>,
+    ) -> Self {
+        Self {
+            fdt,
+            name,
+            properties,
+            parent: parent.map(|parent| Parent {
+                properties: parent.properties,
+                address_cells: parent.address_cells(),
+                size_cells: parent.size_cells(),
+            }),
+        }
+    }
+
+    /// The tree this node is in
+    pub fn fdt(&self) -> Fdt<'a> {
+        self.fdt
+    }
+
+    /// The full name, like `serial@10000000`. The root's name is empty.
+    pub fn name(&self) -> &'a str {
+        self.name
+    }
+
+    /// The name without the unit address, like `serial`
+    pub fn base_name(&self) -> &'a str {
+        self.name.split('@').next().unwrap_or(self.name)
+    }
+
+    pub fn unit_address(&self) -> Option<&'a str> {
+        self.name.split_once('@').map(|(_, address)| address)
+    }
+
+    pub fn properties(&self) -> impl Iterator<Item = Property<'a>> + 'a {
+        let mut cursor = Cursor::new(self.fdt, self.properties);
+        core::iter::from_fn(move || match cursor.next_token() {
+            Ok(Token::Property { name, value }) => Some(Property { name, value }),
+            _ => None,
+        })
+    }
+
+    pub fn property(&self, name: &str) -> Option<Property<'a>> {
+        self.properties().find(|property| property.name == name)
+    }
+
+    pub fn children(&self) -> Children<'a> {
+        let mut cursor = Cursor::new(self.fdt, self.properties);
+        // Skip the properties
+        let mut position = cursor.position;
+        while let Ok(Token::Property { .. }) = cursor.next_token() {
+            position = cursor.position;
+        }
+        Children {
+            parent: *self,
+            cursor: Cursor::new(self.fdt, position),
+            done: false,
+        }
+    }
+
+    /// Finds a child by its full name, or by its name without the unit address
+    pub fn child(&self, name: &str) -> Option<Node<'a>> {
+        self.children()
+            .find(|child| child.name == name)
+            .or_else(|| {
+                if name.contains('@') {
+                    return None;
+                }
+                self.children().find(|child| child.base_name() == name)
+            })
+    }
+
+    /// This has to search the tree, since nodes only know where their parent is
+    pub fn parent(&self) -> Option<Node<'a>> {
+        let parent = self.parent?;
+        self.fdt
+            .all_nodes()
+            .find(|node| node.properties == parent.properties)
+    }
+
+    pub fn compatible(&self) -> Option<StringList<'a>> {
+        Some(self.property("compatible")?.as_strings())
+    }
+
+    pub fn is_compatible(&self, compatible: &str) -> bool {
+        self.compatible()
+            .map(|mut list| list.any(|c| c == compatible))
+            .unwrap_or(false)
+    }
+
+    /// Nodes without a `status` are okay
+    pub fn status(&self) -> Status<'a> {
+        match self.property("status").and_then(|status| status.as_str()) {
+            None | Some("okay" | "ok") => Status::Okay,
+            Some("disabled") => Status::Disabled,
+            Some("reserved") => Status::Reserved,
+            Some("fail") => Status::Fail(""),
+            Some(status) => match status.strip_prefix("fail-") {
+                Some(reason) => Status::Fail(reason),
+                None => Status::Other(status),
+            },
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.status() == Status::Okay
+    }
+
+    pub fn phandle(&self) -> Option<u32> {
+        self.property("phandle")
+            .or_else(|| self.property("linux,phandle"))?
+            .as_u32()
+    }
+
+    /// How many cells the addresses in the children's `reg` have
+    pub fn address_cells(&self) -> u32 {
+        self.property("#address-cells")
+            .and_then(|cells| cells.as_u32())
+            .unwrap_or(DEFAULT_ADDRESS_CELLS)
+    }
+
+    /// How many cells the sizes in the children's `reg` have
+    pub fn size_cells(&self) -> u32 {
+        self.property("#size-cells")
+            .and_then(|cells| cells.as_u32())
+            .unwrap_or(DEFAULT_SIZE_CELLS)
+    }
+
+    /// How many cells an interrupt specifier for this interrupt controller has
+    pub fn interrupt_cells(&self) -> Option<u32> {
+        self.property("#interrupt-cells")?.as_u32()
+    }
+
+    pub fn is_interrupt_controller(&self) -> bool {
+        self.property("interrupt-controller").is_some()
+    }
+
+    /// The `reg` entries, in the parent's address space. These are bus addresses, which
+    /// are only physical addresses if no bus above the node has a non-empty `ranges`.
+    pub fn reg(&self) -> Option<Reg<'a>> {
+        let reg = self.property("reg")?;
+        let (address_cells, size_cells) = match self.parent {
+            Some(parent) => (parent.address_cells, parent.size_cells),
+            None => (DEFAULT_ADDRESS_CELLS, DEFAULT_SIZE_CELLS),
+        };
+        Some(Reg {
+            cells: reg.cells(),
+            address_cells: address_cells as usize,
+            size_cells: size_cells as usize,
+        })
+    }
+
+    /// The `ranges` entries, which map the children's addresses to the parent's. `None` if
+    /// there's no `ranges`, which means the children's addresses can't be translated. An
+    /// empty `ranges` has no entries, but means the addresses are the same on both sides.
+    pub fn ranges(&self) -> Option<Ranges<'a>> {
+        let ranges = self.property("ranges")?;
+        let parent_address_cells = match self.parent {
+            Some(parent) => parent.address_cells,
+            None => DEFAULT_ADDRESS_CELLS,
+        };
+        Some(Ranges {
+            cells: ranges.cells(),
+            child_address_cells: self.address_cells() as usize,
+            parent_address_cells: parent_address_cells as usize,
+            size_cells: self.size_cells() as usize,
+        })
+    }
+
+    /// The controller this node's `interrupts` go to, which can be inherited from its parents
+    pub fn interrupt_parent(&self) -> Option<Node<'a>> {
+        match self.property("interrupt-parent") {
+            Some(phandle) => self.fdt.find_phandle(phandle.as_phandle()?),
+            None => self.parent()?.interrupt_parent(),
+        }
+    }
+
+    /// From `interrupts-extended` if the node has it, otherwise from `interrupts`
+    pub fn interrupts(&self) -> Option<Interrupts<'a>> {
+        if let Some(extended) = self.property("interrupts-extended") {
+            return Some(Interrupts {
+                fdt: self.fdt,
+                rest: extended.cells(),
+                controller: None,
+            });
+        }
+        let interrupts = self.property("interrupts")?;
+        let controller = self.interrupt_parent()?;
+        let cells = controller.interrupt_cells()? as usize;
+        Some(Interrupts {
+            fdt: self.fdt,
+            rest: interrupts.cells(),
+            controller: Some((controller, cells)),
+        })
+    }
+}
+
+pub struct Children<'a> {
+    parent: Node<'a>,
+    cursor: Cursor<'a>,
+    done: bool,
+}
+
+impl<'a> Iterator for Children<'a> {
+    type Item = Node<'a>;
+
+    fn next(&mut self) -> Option<Node<'a>> {
+        if self.done {
+            return None;
+        }
+        match self.cursor.next_token() {
+            Ok(Token::BeginNode(name)) => {
+                let child = Node::new(
+                    self.parent.fdt,
+                    name,
+                    self.cursor.position,
+                    Some(&self.parent),
+                );
+                if self.cursor.skip_node().is_err() {
+                    self.done = true;
+                }
+                Some(child)
+            }
+            _ => {
+                self.done = true;
+                None
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RegEntry {
+    pub address: u64,
+    /// Zero if the parent's `#size-cells` is zero
+    pub size: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct Reg<'a> {
+    cells: Cells<'a>,
+    address_cells: usize,
+    size_cells: usize,
+}
+
+impl<'a> Iterator for Reg<'a> {
+    type Item = RegEntry;
+
+    fn next(&mut self) -> Option<RegEntry> {
+        if self.address_cells == 0 {
+            return None;
+        }
+        let (address, rest) = self.cells.split_at(self.address_cells)?;
+        let (size, rest) = rest.split_at(self.size_cells)?;
+        self.cells = rest;
+        Some(RegEntry {
+            address: address.to_u64(),
+            size: size.to_u64(),
+        })
+    }
+}
+
+/// An entry of `ranges`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Range {
+    /// In the node's children's address space
+    pub child: u64,
+    /// In the node's own address space, which its `reg` is in
+    pub parent: u64,
+    pub size: u64,
+}
+
+impl Range {
+    /// Where `address` in the child's address space is in the parent's, if it's in the range
+    pub fn to_parent(&self, address: u64) -> Option<u64> {
+        let offset = address.checked_sub(self.child)?;
+        (offset < self.size).then(|| self.parent + offset)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Ranges<'a> {
+    cells: Cells<'a>,
+    child_address_cells: usize,
+    parent_address_cells: usize,
+    size_cells: usize,
+}
+
+impl<'a> Iterator for Ranges<'a> {
+    type Item = Range;
+
+    fn next(&mut self) -> Option<Range> {
+        if self.child_address_cells + self.parent_address_cells + self.size_cells == 0 {
+            return None;
+        }
+        let (child, rest) = self.cells.split_at(self.child_address_cells)?;
+        let (parent, rest) = rest.split_at(self.parent_address_cells)?;
+        let (size, rest) = rest.split_at(self.size_cells)?;
+        self.cells = rest;
+        Some(Range {
+            child: child.to_u64(),
+            parent: parent.to_u64(),
+            size: size.to_u64(),
+        })
+    }
+}
+
+/// An interrupt that a device sends to an interrupt controller
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Interrupt<'a> {
+    pub controller: Node<'a>,
+    /// Its format depends on the controller
+    pub specifier: Cells<'a>,
+}
+
+impl<'a> Interrupt<'a> {
+    /// The first cell of the specifier, which is the interrupt number for the PLIC and the
+    /// harts' local interrupt controllers
+    pub fn number(&self) -> Option<u32> {
+        self.specifier.get(0)
+    }
+}
+
+pub struct Interrupts<'a> {
+    fdt: Fdt<'a>,
+    rest: Cells<'a>,
+    /// The controller and its `#interrupt-cells` for `interrupts`. `None` for
+    /// `interrupts-extended`, where every specifier starts with its controller's phandle.
+    controller: Option<(Node<'a>, usize)>,
+}
+
+impl<'a> Iterator for Interrupts<'a> {
+    type Item = Interrupt<'a>;
+
+    fn next(&mut self) -> Option<Interrupt<'a>> {
+        let (controller, cells) = match self.controller {
+            Some(controller) => controller,
+            None => {
+                let (phandle, rest) = self.rest.split_at(1)?;
+                self.rest = rest;
+                let controller = self.fdt.find_phandle(phandle.get(0)?)?;
+                (controller, controller.interrupt_cells()? as usize)
+            }
+        };
+        if cells == 0 {
+            return None;
+        }
+        let (specifier, rest) = self.rest.split_at(cells)?;
+        self.rest = rest;
+        Some(Interrupt {
+            controller,
+            specifier,
+        })
+    }
+}
@@ -0,0 +1,146 @@
+//! Property values and the types they can be read as
+
+use crate::read_u32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Property<'a> {
+    pub name: &'a str,
+    pub value: &'a [u8],
+}
+
+impl<'a> Property<'a> {
+    pub fn is_empty(&self) -> bool {
+        self.value.is_empty()
+    }
+
+    /// A single cell
+    pub fn as_u32(&self) -> Option<u32> {
+        if self.value.len() != 4 {
+            return None;
+        }
+        read_u32(self.value, 0)
+    }
+
+    /// One or two cells
+    pub fn as_u64(&self) -> Option<u64> {
+        match self.value.len() {
+            4 => self.as_u32().map(u64::from),
+            8 => crate::read_u64(self.value, 0),
+            _ => None,
+        }
+    }
+
+    pub fn as_usize(&self) -> Option<usize> {
+        self.as_u64().and_then(|value| value.try_into().ok())
+    }
+
+    /// A reference to another node. Use [`crate::Fdt::find_phandle`] to get the node.
+    pub fn as_phandle(&self) -> Option<u32> {
+        self.as_u32()
+    }
+
+    /// A single NUL-terminated string
+    pub fn as_str(&self) -> Option<&'a str> {
+        let (last, string) = self.value.split_last()?;
+        if *last != 0 || string.contains(&0) {
+            return None;
+        }
+        core::str::from_utf8(string).ok()
+    }
+
+    /// Several NUL-terminated strings one after the other, like `compatible`
+    pub fn as_strings(&self) -> StringList<'a> {
+        StringList { rest: self.value }
+    }
+
+    pub fn cells(&self) -> Cells<'a> {
+        Cells::new(self.value)
+    }
+}
+
+/// The strings in a string list property. Strings that aren't valid UTF-8 are skipped.
+#[derive(Debug, Clone)]
+pub struct StringList<'a> {
+    rest: &'a [u8],
+}
+
+impl<'a> Iterator for StringList<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<&'a str> {
+        loop {
+            if self.rest.is_empty() {
+                return None;
+            }
+            let end = self
+                .rest
+                .iter()
+                .position(|byte| *byte == 0)
+                .unwrap_or(self.rest.len());
+            let string = &self.rest[..end];
+            self.rest = self.rest.get(end + 1..).unwrap_or(&[]);
+            if let Ok(string) = core::str::from_utf8(string) {
+                return Some(string);
+            }
+        }
+    }
+}
+
+/// Big-endian 32-bit cells. Trailing bytes that don't make up a whole cell are ignored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cells<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Cells<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Self {
+            bytes: &bytes[..bytes.len() / 4 * 4],
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.bytes.len() / 4
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    pub fn get(&self, index: usize) -> Option<u32> {
+        read_u32(self.bytes, index.checked_mul(4)?)
+    }
+
+    /// Splits off the first `count` cells
+    pub fn split_at(&self, count: usize) -> Option<(Cells<'a>, Cells<'a>)> {
+        if count > self.len() {
+            return None;
+        }
+        let (first, rest) = self.bytes.split_at(count * 4);
+        Some((Cells { bytes: first }, Cells { bytes: rest }))
+    }
+
+    /// Reads the cells as one number. Only the last two cells are used, since numbers
+    /// with more cells (like PCI addresses) have flags in the first ones.
+    pub fn to_u64(&self) -> u64 {
+        let skip = self.len().saturating_sub(2);
+        self.skip(skip)
+            .fold(0, |number, cell| (number << 32) | u64::from(cell))
+    }
+}
+
+impl<'a> Iterator for Cells<'a> {
+    type Item = u32;
+
+    fn next(&mut self) -> Option<u32> {
+        let cell = read_u32(self.bytes, 0)?;
+        self.bytes = &self.bytes[4..];
+        Some(cell)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.len(), Some(self.len()))
+    }
+}
+
+impl<'a> ExactSizeIterator for Cells<'a> {}
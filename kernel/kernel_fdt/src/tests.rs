@@ -0,0 +1,666 @@
+use std::{vec, vec::Vec};
+
+use crate::{AddressSpace, Fdt, FdtBuilder, FdtError, MemoryReservation, Node, Range, Status};
+
+const CPU0_INTC: u32 = 1;
+const CPU1_INTC: u32 = 2;
+const PLIC: u32 = 3;
+
+/// A tree laid out like the one QEMU generates for `-machine virt -smp 2`
+fn virt_tree() -> Vec<u8> {
+    let mut builder = FdtBuilder::new();
+    builder
+        .reserve_memory(0x8000_0000, 0x4_0000)
+        .boot_cpu(0)
+        .begin_node("")
+        .property_u32("#address-cells", 2)
+        .property_u32("#size-cells", 2)
+        .property_str("compatible", "riscv-virtio")
+        .property_str("model", "riscv-virtio,qemu");
+
+    builder
+        .begin_node("chosen")
+        .property_str("bootargs", "loglevel=debug init=echo")
+        .property_str("stdout-path", "/soc/serial@10000000")
+        .property_u64("linux,initrd-start", 0x8400_0000)
+        .property_u32("linux,initrd-end", 0x8410_0000)
+        .end_node();
+
+    builder
+        .begin_node("memory@80000000")
+        .property_str("device_type", "memory")
+        .property_cells("reg", &[0, 0x8000_0000, 0, 0x800_0000])
+        .end_node();
+
+    builder
+        .begin_node("cpus")
+        .property_u32("#address-cells", 1)
+        .property_u32("#size-cells", 0)
+        .property_u32("timebase-frequency", 10_000_000);
+    for (hart, intc) in [(0, CPU0_INTC), (1, CPU1_INTC)] {
+        builder
+            .begin_node(&format!("cpu@{}", hart))
+            .property_str("device_type", "cpu")
+            .property_u32("reg", hart)
+            .property_str("status", "okay")
+            .property_str("compatible", "riscv")
+            .property_str("riscv,isa", "rv64imafdcsu")
+            .property_str("mmu-type", "riscv,sv48")
+            .begin_node("interrupt-controller")
+            .property_u32("#interrupt-cells", 1)
+            .flag("interrupt-controller")
+            .property_str("compatible", "riscv,cpu-intc")
+            .property_u32("phandle", intc)
+            .end_node()
+            .end_node();
+    }
+    builder.end_node();
+
+    builder
+        .begin_node("soc")
+        .property_u32("#address-cells", 2)
+        .property_u32("#size-cells", 2)
+        .property_str("compatible", "simple-bus")
+        .flag("ranges");
+    builder
+        .begin_node("plic@c000000")
+        .property_u32("phandle", PLIC)
+        .property_u32("riscv,ndev", 0x35)
+        .property_cells("reg", &[0, 0x0c00_0000, 0, 0x60_0000])
+        .property_cells(
+            "interrupts-extended",
+            &[CPU0_INTC, 11, CPU0_INTC, 9, CPU1_INTC, 11, CPU1_INTC, 9],
+        )
+        .flag("interrupt-controller")
+        .property_strings("compatible", &["sifive,plic-1.0.0", "riscv,plic0"])
+        .property_u32("#interrupt-cells", 1)
+        .property_u32("#address-cells", 0)
+        .end_node();
+    builder
+        .begin_node("serial@10000000")
+        .property_u32("interrupts", 10)
+        .property_u32("interrupt-parent", PLIC)
+        .property_u32("clock-frequency", 0x384000)
+        .property_cells("reg", &[0, 0x1000_0000, 0, 0x100])
+        .property_str("compatible", "ns16550a")
+        .end_node();
+    for slot in 0..8 {
+        builder
+            .begin_node(&format!("virtio_mmio@{:x}", 0x1000_1000 + slot * 0x1000))
+            .property_u32("interrupts", 1 + slot)
+            .property_u32("interrupt-parent", PLIC)
+            .property_cells("reg", &[0, 0x1000_1000 + slot * 0x1000, 0, 0x1000])
+            .property_str("compatible", "virtio,mmio")
+            .end_node();
+    }
+    builder
+        .begin_node("test@100000")
+        .property_cells("reg", &[0, 0x10_0000, 0, 0x1000])
+        .property_strings("compatible", &["sifive,test1", "sifive,test0", "syscon"])
+        .end_node();
+    builder.end_node();
+
+    builder.end_node();
+    builder.finish()
+}
+
+#[test]
+fn finds_nodes() {
+    let blob = virt_tree();
+    let fdt = Fdt::new(&blob).unwrap();
+    assert_eq!(fdt.total_size(), blob.len());
+    assert_eq!(fdt.root().name(), "");
+
+    let serial = fdt.find_node("/soc/serial@10000000").unwrap();
+    assert_eq!(serial.name(), "serial@10000000");
+    assert_eq!(serial.base_name(), "serial");
+    assert_eq!(serial.unit_address(), Some("10000000"));
+    assert_eq!(serial.parent().unwrap().name(), "soc");
+    assert_eq!(fdt.root().parent(), None);
+
+    // The unit address can be left out
+    assert_eq!(fdt.find_node("/cpus/cpu").unwrap().name(), "cpu@0");
+    assert_eq!(fdt.find_node("/cpus/cpu@1").unwrap().name(), "cpu@1");
+    assert!(fdt.find_node("/cpus/cpu@2").is_none());
+    assert_eq!(fdt.find_node("/"), Some(fdt.root()));
+
+    let names: Vec<&str> = fdt.all_nodes().map(|node| node.name()).take(6).collect();
+    assert_eq!(
+        names,
+        [
+            "",
+            "chosen",
+            "memory@80000000",
+            "cpus",
+            "cpu@0",
+            "interrupt-controller"
+        ]
+    );
+    assert_eq!(fdt.all_nodes().count(), 20);
+}
+
+#[test]
+fn reads_properties() {
+    let blob = virt_tree();
+    let fdt = Fdt::new(&blob).unwrap();
+    let root = fdt.root();
+    assert_eq!(
+        root.property("model").unwrap().as_str(),
+        Some("riscv-virtio,qemu")
+    );
+    assert_eq!(root.address_cells(), 2);
+    assert!(root.property("missing").is_none());
+
+    let cpus = fdt.find_node("/cpus").unwrap();
+    assert_eq!(
+        cpus.property("timebase-frequency").unwrap().as_usize(),
+        Some(10_000_000)
+    );
+    // Not a string
+    assert_eq!(cpus.property("timebase-frequency").unwrap().as_str(), None);
+    // The defaults from the specification
+    assert_eq!(fdt.find_node("/chosen").unwrap().address_cells(), 2);
+    assert_eq!(fdt.find_node("/chosen").unwrap().size_cells(), 1);
+
+    assert_eq!(fdt.bootargs(), Some("loglevel=debug init=echo"));
+    // The start is written with two cells and the end with one
+    assert_eq!(fdt.initrd(), Some((0x8400_0000, 0x8410_0000)));
+}
+
+#[test]
+fn compatible_and_status() {
+    let blob = virt_tree();
+    let fdt = Fdt::new(&blob).unwrap();
+    let test = fdt.find_node("/soc/test@100000").unwrap();
+    let compatible: Vec<&str> = test.compatible().unwrap().collect();
+    assert_eq!(compatible, ["sifive,test1", "sifive,test0", "syscon"]);
+    assert!(test.is_compatible("syscon"));
+    assert!(!test.is_compatible("sifive,test"));
+    assert_eq!(
+        fdt.find_compatible(&["riscv,plic0"]).unwrap().name(),
+        "plic@c000000"
+    );
+    assert!(fdt.find_compatible(&["nothing"]).is_none());
+
+    assert_eq!(test.status(), Status::Okay);
+    assert_eq!(fdt.find_node("/cpus/cpu@0").unwrap().status(), Status::Okay);
+
+    let mut builder = FdtBuilder::new();
+    builder
+        .begin_node("")
+        .begin_node("a")
+        .property_str("status", "disabled")
+        .property_str("compatible", "ns16550a")
+        .end_node()
+        .begin_node("b")
+        .property_str("status", "fail-no-clock")
+        .end_node()
+        .begin_node("c")
+        .property_str("status", "reserved")
+        .property_str("compatible", "ns16550a")
+        .end_node()
+        .end_node();
+    let blob = builder.finish();
+    let fdt = Fdt::new(&blob).unwrap();
+    assert_eq!(fdt.find_node("/a").unwrap().status(), Status::Disabled);
+    assert_eq!(
+        fdt.find_node("/b").unwrap().status(),
+        Status::Fail("no-clock")
+    );
+    assert_eq!(fdt.find_node("/c").unwrap().status(), Status::Reserved);
+    // Only enabled nodes are found
+    assert!(fdt.find_compatible(&["ns16550a"]).is_none());
+}
+
+#[test]
+fn reg() {
+    let blob = virt_tree();
+    let fdt = Fdt::new(&blob).unwrap();
+    let serial = fdt.find_node("/soc/serial@10000000").unwrap();
+    let reg: Vec<_> = serial
+        .reg()
+        .unwrap()
+        .map(|entry| (entry.address, entry.size))
+        .collect();
+    assert_eq!(reg, [(0x1000_0000, 0x100)]);
+
+    // `/cpus` has no sizes
+    let cpu = fdt.find_node("/cpus/cpu@1").unwrap();
+    let reg: Vec<_> = cpu
+        .reg()
+        .unwrap()
+        .map(|entry| (entry.address, entry.size))
+        .collect();
+    assert_eq!(reg, [(1, 0)]);
+
+    let memory = fdt.find_node("/memory").unwrap();
+    assert_eq!(memory.reg().unwrap().next().unwrap().size, 0x800_0000);
+    assert!(fdt.find_node("/soc").unwrap().reg().is_none());
+}
+
+#[test]
+fn interrupts() {
+    let blob = virt_tree();
+    let fdt = Fdt::new(&blob).unwrap();
+    let plic = fdt.find_phandle(PLIC).unwrap();
+    assert_eq!(plic.name(), "plic@c000000");
+    assert_eq!(plic.phandle(), Some(PLIC));
+    assert!(plic.is_interrupt_controller());
+    assert_eq!(plic.interrupt_cells(), Some(1));
+
+    let serial = fdt.find_node("/soc/serial").unwrap();
+    assert_eq!(serial.interrupt_parent(), Some(plic));
+    let interrupts: Vec<_> = serial.interrupts().unwrap().collect();
+    assert_eq!(interrupts.len(), 1);
+    assert_eq!(interrupts[0].controller, plic);
+    assert_eq!(interrupts[0].number(), Some(10));
+
+    // The PLIC's own interrupts go to the harts' controllers
+    let interrupts: Vec<_> = plic
+        .interrupts()
+        .unwrap()
+        .map(|interrupt| {
+            (
+                interrupt.controller.phandle().unwrap(),
+                interrupt.number().unwrap(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        interrupts,
+        [
+            (CPU0_INTC, 11),
+            (CPU0_INTC, 9),
+            (CPU1_INTC, 11),
+            (CPU1_INTC, 9)
+        ]
+    );
+    let intc = interrupts[0].0;
+    assert_eq!(
+        fdt.find_phandle(intc).unwrap().parent().unwrap().name(),
+        "cpu@0"
+    );
+    assert!(fdt.find_phandle(100).is_none());
+}
+
+#[test]
+fn inherited_interrupt_parent() {
+    let mut builder = FdtBuilder::new();
+    builder
+        .begin_node("")
+        .property_u32("interrupt-parent", 1)
+        .begin_node("gic")
+        .property_u32("phandle", 1)
+        .property_u32("#interrupt-cells", 3)
+        .flag("interrupt-controller")
+        .end_node()
+        .begin_node("bus")
+        .begin_node("device")
+        .property_cells("interrupts", &[0, 5, 4, 0, 6, 4])
+        .end_node()
+        .end_node()
+        .end_node();
+    let blob = builder.finish();
+    let fdt = Fdt::new(&blob).unwrap();
+    let device = fdt.find_node("/bus/device").unwrap();
+    assert_eq!(device.interrupt_parent().unwrap().name(), "gic");
+    let specifiers: Vec<Vec<u32>> = device
+        .interrupts()
+        .unwrap()
+        .map(|interrupt| interrupt.specifier.collect())
+        .collect();
+    assert_eq!(specifiers, [vec![0, 5, 4], vec![0, 6, 4]]);
+}
+
+#[test]
+fn header() {
+    let blob = virt_tree();
+    let fdt = Fdt::new(&blob).unwrap();
+    assert_eq!(fdt.boot_cpu(), 0);
+    let reservations: Vec<_> = fdt.memory_reservations().collect();
+    assert_eq!(
+        reservations,
+        [MemoryReservation {
+            address: 0x8000_0000,
+            size: 0x4_0000
+        }]
+    );
+
+    let fdt = unsafe { Fdt::from_ptr(blob.as_ptr()) }.unwrap();
+    assert_eq!(fdt.total_size(), blob.len());
+}
+
+#[test]
+fn rejects_bad_trees() {
+    let blob = virt_tree();
+    assert_eq!(Fdt::new(&blob[..20]).unwrap_err(), FdtError::Truncated);
+    assert_eq!(
+        Fdt::new(&blob[..blob.len() - 1]).unwrap_err(),
+        FdtError::Truncated
+    );
+
+    let mut bad_magic = blob.clone();
+    bad_magic[0] = 0;
+    assert_eq!(Fdt::new(&bad_magic).unwrap_err(), FdtError::BadMagic);
+
+    let mut too_new = blob.clone();
+    too_new[24..28].copy_from_slice(&18u32.to_be_bytes());
+    assert_eq!(
+        Fdt::new(&too_new).unwrap_err(),
+        FdtError::UnsupportedVersion(18)
+    );
+
+    // Replace the token after the root's name (a property) with an unknown one
+    let structure_offset = u32::from_be_bytes(blob[8..12].try_into().unwrap()) as usize;
+    let mut bad_token = blob.clone();
+    bad_token[structure_offset + 8..structure_offset + 12].copy_from_slice(&7u32.to_be_bytes());
+    assert_eq!(Fdt::new(&bad_token).unwrap_err(), FdtError::BadStructure);
+
+    // An unclosed root: turn its FDT_END_NODE into an FDT_NOP
+    let mut builder = FdtBuilder::new();
+    builder.begin_node("").end_node();
+    let mut unbalanced = builder.finish();
+    let structure_offset = u32::from_be_bytes(unbalanced[8..12].try_into().unwrap()) as usize;
+    unbalanced[structure_offset + 8..structure_offset + 12].copy_from_slice(&4u32.to_be_bytes());
+    assert_eq!(Fdt::new(&unbalanced).unwrap_err(), FdtError::BadStructure);
+}
+
+#[test]
+fn modifies_a_tree() {
+    let blob = virt_tree();
+    let fdt = Fdt::new(&blob).unwrap();
+
+    // Copy everything but the command line and the test device
+    let mut builder = FdtBuilder::new();
+    builder.begin_node("").copy_properties(&fdt.root());
+    for child in fdt.root().children() {
+        if child.name() == "chosen" {
+            builder
+                .begin_node("chosen")
+                .property_str("bootargs", "console=hvc0")
+                .end_node();
+        } else if child.name() == "soc" {
+            builder.begin_node("soc").copy_properties(&child);
+            for device in child
+                .children()
+                .filter(|device| device.base_name() != "test")
+            {
+                builder.copy_node(&device);
+            }
+            builder.end_node();
+        } else {
+            builder.copy_node(&child);
+        }
+    }
+    builder.end_node();
+    let copy = builder.finish();
+    let copy = Fdt::new(&copy).unwrap();
+
+    assert_eq!(copy.bootargs(), Some("console=hvc0"));
+    assert!(copy.find_node("/soc/test").is_none());
+    assert_eq!(copy.all_nodes().count(), fdt.all_nodes().count() - 1);
+    let plic = copy.find_compatible(&["riscv,plic0"]).unwrap();
+    assert_eq!(plic.interrupts().unwrap().count(), 4);
+    assert_eq!(
+        copy.find_node("/soc/virtio_mmio@10008000")
+            .unwrap()
+            .interrupts()
+            .unwrap()
+            .next()
+            .unwrap()
+            .number(),
+        Some(8)
+    );
+}
+
+#[test]
+fn builder_shares_strings() {
+    let mut builder = FdtBuilder::new();
+    builder
+        .begin_node("")
+        .property_u32("reg", 1)
+        .begin_node("a")
+        .property_u32("reg", 2)
+        .property_u32("re", 3)
+        .end_node()
+        .end_node();
+    let blob = builder.finish();
+    let strings_size = u32::from_be_bytes(blob[32..36].try_into().unwrap());
+    assert_eq!(strings_size as usize, "reg\0re\0".len());
+    let fdt = Fdt::new(&blob).unwrap();
+    let a = fdt.find_node("/a").unwrap();
+    assert_eq!(a.property("reg").unwrap().as_u32(), Some(2));
+    assert_eq!(a.property("re").unwrap().as_u32(), Some(3));
+}
+
+/// What QEMU's `virt` machine passes to the firmware. See fixtures/README.md.
+const QEMU_VIRT: &[u8] = include_bytes!("../fixtures/qemu-virt.dtb");
+
+/// The CPU addresses of every enabled node's registers, walking down the tree the way the
+/// drivers do
+fn translated_regs(fdt: &Fdt) -> Vec<(String, Vec<u64>)> {
+    fn walk(
+        node: Node,
+        path: String,
+        space: Option<&AddressSpace>,
+        out: &mut Vec<(String, Vec<u64>)>,
+    ) {
+        if let (Some(reg), Some(space)) = (node.reg(), space) {
+            let addresses = reg
+                .filter_map(|entry| space.translate(entry.address))
+                .collect();
+            out.push((path.clone(), addresses));
+        }
+        let children = space.and_then(|space| space.children(&node));
+        for child in node.children() {
+            let child_path = format!("{}/{}", path, child.name());
+            walk(child, child_path, children.as_ref(), out);
+        }
+    }
+    let mut out = Vec::new();
+    let root = AddressSpace::root();
+    for child in fdt.root().children() {
+        walk(child, format!("/{}", child.name()), Some(&root), &mut out);
+    }
+    out
+}
+
+#[test]
+fn qemu_virt_reg() {
+    let fdt = Fdt::new(QEMU_VIRT).unwrap();
+    let regs = translated_regs(&fdt);
+    let reg = |path: &str| {
+        regs.iter()
+            .find(|(node, _)| node == path)
+            .map(|(_, addresses)| addresses.clone())
+    };
+    assert_eq!(reg("/memory@80000000"), Some(vec![0x8000_0000]));
+    assert_eq!(reg("/flash@20000000"), Some(vec![0x2000_0000, 0x2200_0000]));
+    assert_eq!(reg("/soc/serial@10000000"), Some(vec![0x1000_0000]));
+    assert_eq!(reg("/soc/plic@c000000"), Some(vec![0x0c00_0000]));
+    assert_eq!(reg("/soc/pci@30000000"), Some(vec![0x3000_0000]));
+    // `/cpus` has no `ranges`, so hart IDs aren't addresses
+    assert_eq!(reg("/cpus/cpu@0"), None);
+
+    let size = |path: &str| {
+        fdt.find_node(path)
+            .unwrap()
+            .reg()
+            .unwrap()
+            .next()
+            .unwrap()
+            .size
+    };
+    assert_eq!(size("/soc/plic@c000000"), 0x60_0000);
+    assert_eq!(size("/soc/virtio_mmio@10001000"), 0x1000);
+
+    // Every device on the SoC bus is where its unit address says it is
+    let soc: Vec<_> = regs
+        .iter()
+        .filter(|(path, _)| path.starts_with("/soc/"))
+        .collect();
+    assert_eq!(soc.len(), 14);
+    for (path, addresses) in soc {
+        let unit_address = path.split_once('@').unwrap().1;
+        assert_eq!(
+            addresses[0],
+            u64::from_str_radix(unit_address, 16).unwrap(),
+            "{}",
+            path
+        );
+    }
+}
+
+#[test]
+fn qemu_virt_interrupts() {
+    let fdt = Fdt::new(QEMU_VIRT).unwrap();
+    let plic = fdt.find_compatible(&["riscv,plic0"]).unwrap();
+    let intc = fdt.find_compatible(&["riscv,cpu-intc"]).unwrap();
+    assert_eq!(intc.parent().unwrap().name(), "cpu@0");
+
+    let number = |path: &str| {
+        let interrupts: Vec<_> = fdt.find_node(path).unwrap().interrupts().unwrap().collect();
+        assert_eq!(interrupts.len(), 1);
+        assert_eq!(interrupts[0].controller, plic);
+        interrupts[0].number().unwrap()
+    };
+    assert_eq!(number("/soc/serial"), 10);
+    assert_eq!(number("/soc/rtc"), 11);
+    for slot in 0..8 {
+        assert_eq!(
+            number(&format!(
+                "/soc/virtio_mmio@{:x}",
+                0x1000_1000 + slot * 0x1000
+            )),
+            1 + slot
+        );
+    }
+
+    // The PLIC's and the CLINT's interrupts go to the hart's controller
+    let local = |path: &str| -> Vec<u32> {
+        fdt.find_node(path)
+            .unwrap()
+            .interrupts()
+            .unwrap()
+            .map(|interrupt| {
+                assert_eq!(interrupt.controller, intc);
+                interrupt.number().unwrap()
+            })
+            .collect()
+    };
+    assert_eq!(local("/soc/plic"), [11, 9]);
+    assert_eq!(local("/soc/clint"), [3, 7]);
+    // The platform bus has no devices, but says where their interrupts would go
+    let platform_bus = fdt.find_node("/platform-bus").unwrap();
+    assert_eq!(platform_bus.interrupt_parent(), Some(plic));
+}
+
+#[test]
+fn qemu_virt_phandles() {
+    let fdt = Fdt::new(QEMU_VIRT).unwrap();
+    let name = |phandle| fdt.find_phandle(phandle).map(|node| node.name());
+    assert_eq!(name(1), Some("cpu@0"));
+    assert_eq!(name(2), Some("interrupt-controller"));
+    assert_eq!(name(3), Some("plic@c000000"));
+    assert_eq!(name(4), Some("test@100000"));
+    assert_eq!(name(5), None);
+
+    // The syscon nodes that power off and reboot through the test device
+    for (path, value) in [("/poweroff", 0x5555), ("/reboot", 0x7777)] {
+        let node = fdt.find_node(path).unwrap();
+        let regmap = node.property("regmap").unwrap().as_phandle().unwrap();
+        assert!(fdt
+            .find_phandle(regmap)
+            .unwrap()
+            .is_compatible("sifive,test0"));
+        assert_eq!(node.property("value").unwrap().as_u32(), Some(value));
+    }
+    let core = fdt.find_node("/cpus/cpu-map/cluster0/core0").unwrap();
+    let cpu = core.property("cpu").unwrap().as_phandle().unwrap();
+    assert_eq!(fdt.find_phandle(cpu), fdt.find_node("/cpus/cpu@0"));
+}
+
+#[test]
+fn qemu_virt_ranges() {
+    let fdt = Fdt::new(QEMU_VIRT).unwrap();
+    let root = AddressSpace::root();
+
+    // An empty `ranges` is the same address space
+    let soc = fdt.find_node("/soc").unwrap();
+    assert_eq!(soc.ranges().unwrap().count(), 0);
+    assert_eq!(root.children(&soc).unwrap().translate(0x1234), Some(0x1234));
+    // No `ranges` at all can't be translated
+    assert!(fdt.find_node("/cpus").unwrap().ranges().is_none());
+    assert!(root.children(&fdt.find_node("/cpus").unwrap()).is_none());
+
+    let platform_bus = fdt.find_node("/platform-bus").unwrap();
+    assert_eq!(
+        platform_bus.ranges().unwrap().collect::<Vec<_>>(),
+        [Range {
+            child: 0,
+            parent: 0x400_0000,
+            size: 0x200_0000
+        }]
+    );
+    let space = root.children(&platform_bus).unwrap();
+    assert_eq!(space.translate(0x1000), Some(0x400_1000));
+    assert_eq!(space.translate(0x1ff_ffff), Some(0x5ff_ffff));
+    assert_eq!(space.translate(0x200_0000), None);
+
+    // PCI addresses have 3 cells, with the space in the first one
+    let pci = fdt.find_node("/soc/pci").unwrap();
+    let ranges: Vec<_> = pci
+        .ranges()
+        .unwrap()
+        .map(|range| (range.child, range.parent, range.size))
+        .collect();
+    assert_eq!(
+        ranges,
+        [
+            (0, 0x300_0000, 0x1_0000),
+            (0x4000_0000, 0x4000_0000, 0x4000_0000),
+            (0x4_0000_0000, 0x4_0000_0000, 0x4_0000_0000)
+        ]
+    );
+}
+
+#[test]
+fn nested_ranges() {
+    let mut builder = FdtBuilder::new();
+    builder
+        .begin_node("")
+        .property_u32("#address-cells", 2)
+        .property_u32("#size-cells", 2)
+        .begin_node("outer@80000000")
+        .property_u32("#address-cells", 1)
+        .property_u32("#size-cells", 1)
+        .property_cells("ranges", &[0x0, 0, 0x8000_0000, 0x10_0000])
+        .begin_node("inner@1000")
+        .property_u32("#address-cells", 1)
+        .property_u32("#size-cells", 1)
+        .property_cells("ranges", &[0x0, 0x1000, 0x100, 0x500, 0x2000, 0x100])
+        .begin_node("device@10")
+        .property_cells("reg", &[0x10, 0x10, 0x520, 0x10, 0x1000, 0x10])
+        .end_node()
+        .end_node()
+        .end_node()
+        .end_node();
+    let blob = builder.finish();
+    let fdt = Fdt::new(&blob).unwrap();
+    assert_eq!(
+        translated_regs(&fdt),
+        [(
+            "/outer@80000000/inner@1000/device@10".into(),
+            vec![0x8000_1010, 0x8000_2020]
+        )]
+    );
+
+    // Addresses outside of every range aren't forwarded
+    let outer = fdt.find_node("/outer").unwrap();
+    let inner = fdt.find_node("/outer/inner").unwrap();
+    let root = AddressSpace::root();
+    let outer_space = root.children(&outer).unwrap();
+    let inner_space = outer_space.children(&inner).unwrap();
+    assert_eq!(inner_space.translate(0xff), Some(0x8000_10ff));
+    assert_eq!(inner_space.translate(0x100), None);
+}
@@ -0,0 +1,41 @@
+//! The Internet checksum, which IPv4, ICMP, UDP and TCP use.
+//! See https://www.rfc-editor.org/rfc/rfc1071
+
+/// Adds `data` to a one's complement sum, as big-endian 16-bit words. An odd last byte is
+/// padded with a zero, so only the last part of a checksummed range can have an odd length.
+pub fn add(sum: u32, data: &[u8]) -> u32 {
+    let mut sum = sum as u64;
+    let mut words = data.chunks_exact(2);
+    for word in &mut words {
+        sum += u16::from_be_bytes([word[0], word[1]]) as u64;
+    }
+    if let [last] = words.remainder() {
+        sum += (*last as u64) << 8;
+    }
+    fold(sum) as u32
+}
+
+fn fold(mut sum: u64) -> u16 {
+    while sum >> 16 != 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    sum as u16
+}
+
+/// The checksum that goes in a header, for a sum that includes the checksum field as 0.
+/// Checking a received header's checksum by summing all of it, field included, gives 0.
+pub fn finish(sum: u32) -> u16 {
+    !fold(sum as u64)
+}
+
+/// Computes the checksum of `data[start..]` and stores it at `start + offset`, where the sum
+/// of the pseudo-header already is. This is what devices that offload checksums do.
+/// Does nothing if the field isn't inside `data`.
+pub fn fill(data: &mut [u8], start: usize, offset: usize) {
+    let field = match start.checked_add(offset) {
+        Some(field) if field + 2 <= data.len() => field,
+        _ => return,
+    };
+    let checksum = finish(add(0, &data[start..]));
+    data[field..field + 2].copy_from_slice(&checksum.to_be_bytes());
+}
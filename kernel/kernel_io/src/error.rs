@@ -0,0 +1,35 @@
+use core::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IoErrorKind {
+    UnexpectedEof,
+    InvalidData,
+    WouldBlock,
+    Other,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IoError {
+    kind: IoErrorKind,
+}
+
+impl IoError {
+    pub fn new_simple(kind: IoErrorKind) -> Self {
+        Self { kind }
+    }
+    pub fn kind(&self) -> IoErrorKind {
+        self.kind
+    }
+}
+
+impl fmt::Display for IoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&self.kind, f)
+    }
+}
+
+impl From<core::str::Utf8Error> for IoError {
+    fn from(_: core::str::Utf8Error) -> Self {
+        Self::new_simple(IoErrorKind::InvalidData)
+    }
+}
@@ -0,0 +1,38 @@
+//! Open files, whatever filesystem they are on. They are here rather than in the VFS so that
+//! processes can keep them in their file tables without depending on any filesystem.
+
+use crate::{Read, Seek, Write};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VfsError {
+    NotFound,
+    NotADirectory,
+    IsADirectory,
+    AlreadyExists,
+    DirectoryNotEmpty,
+    /// There's no space left on the filesystem
+    NoSpace,
+    InvalidName,
+    InvalidSeek,
+    ReadOnly,
+    /// Tried to rename across filesystems
+    CrossDevice,
+    /// A filesystem is mounted there
+    Busy,
+    BadFileDescriptor,
+    /// The filesystem doesn't support the operation
+    Unsupported,
+    /// The underlying device failed, or the filesystem is corrupted
+    Io,
+}
+
+/// An open file
+pub trait File:
+    Read<Error = VfsError> + Write<Error = VfsError> + Seek<Error = VfsError> + Send + Sync
+{
+}
+
+impl<T> File for T where
+    T: Read<Error = VfsError> + Write<Error = VfsError> + Seek<Error = VfsError> + Send + Sync
+{
+}
@@ -3,10 +3,14 @@
 
 extern crate alloc;
 
+pub mod checksum;
+pub mod error;
+pub mod file;
+
 use alloc::{boxed::Box, string::String, vec::Vec};
 
 use async_trait::async_trait;
-use kernel_syscall_abi::filesystem::{IoError as Error, IoErrorKind as ErrorKind};
+pub use error::{IoError as Error, IoErrorKind as ErrorKind};
 pub type Result<T> = core::result::Result<T, Error>;
 
 #[async_trait]
@@ -107,3 +111,125 @@ pub trait Write {
 
     async fn write(&mut self, buf: &[u8]) -> core::result::Result<usize, Self::Error>;
 }
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SeekFrom {
+    Start(u64),
+    End(i64),
+    Current(i64),
+}
+
+#[async_trait]
+pub trait Seek {
+    type Error: Send + Sync;
+
+    /// Moves the cursor and returns its new position from the start
+    async fn seek(&mut self, pos: SeekFrom) -> core::result::Result<u64, Self::Error>;
+}
+
+/// A device that is read and written in fixed-size blocks, like a disk.
+/// Methods take `&self` so that several requests can be in flight at the same time.
+#[async_trait]
+pub trait BlockDevice {
+    type Error: Send + Sync;
+
+    /// Size of a block in bytes
+    fn block_size(&self) -> usize;
+    /// Size of the device in blocks
+    fn capacity(&self) -> u64;
+    /// Reads `buf.len() / block_size()` blocks starting at block `start`.
+    /// `buf.len()` must be a multiple of the block size.
+    async fn read_blocks(&self, start: u64, buf: &mut [u8]) -> core::result::Result<(), Self::Error>;
+    /// Writes `buf.len() / block_size()` blocks starting at block `start`.
+    /// `buf.len()` must be a multiple of the block size.
+    async fn write_blocks(&self, start: u64, buf: &[u8]) -> core::result::Result<(), Self::Error>;
+    /// Waits until all completed writes are stored persistently
+    async fn flush(&self) -> core::result::Result<(), Self::Error>;
+}
+
+#[async_trait]
+impl<T: BlockDevice + Send + Sync + ?Sized> BlockDevice for alloc::sync::Arc<T> {
+    type Error = T::Error;
+
+    fn block_size(&self) -> usize {
+        (**self).block_size()
+    }
+
+    fn capacity(&self) -> u64 {
+        (**self).capacity()
+    }
+
+    async fn read_blocks(&self, start: u64, buf: &mut [u8]) -> core::result::Result<(), Self::Error> {
+        (**self).read_blocks(start, buf).await
+    }
+
+    async fn write_blocks(&self, start: u64, buf: &[u8]) -> core::result::Result<(), Self::Error> {
+        (**self).write_blocks(start, buf).await
+    }
+
+    async fn flush(&self) -> core::result::Result<(), Self::Error> {
+        (**self).flush().await
+    }
+}
+
+/// A network interface that sends and receives Ethernet frames.
+/// Frames start with the Ethernet header and don't include the frame check sequence.
+/// Methods take `&self` so that frames can be sent while another task waits for one.
+#[async_trait]
+pub trait NetDevice {
+    type Error: Send + Sync;
+
+    /// The hardware address that unicast frames are received on
+    fn mac_address(&self) -> [u8; 6];
+    /// Largest payload of a frame, not counting the Ethernet header
+    fn mtu(&self) -> usize;
+    /// Waits until the device has taken `frame` to send it
+    async fn send(&self, frame: &[u8]) -> core::result::Result<(), Self::Error>;
+    /// Waits for the next frame that was received
+    async fn receive(&self) -> core::result::Result<Vec<u8>, Self::Error>;
+    /// Like [`NetDevice::send`], but the checksum of the bytes from `checksum_start` on is
+    /// filled in at `checksum_start + checksum_offset`, as [`checksum::fill`] does. Devices
+    /// that can compute checksums themselves do it there.
+    async fn send_with_checksum(
+        &self,
+        frame: &[u8],
+        checksum_start: usize,
+        checksum_offset: usize,
+    ) -> core::result::Result<(), Self::Error> {
+        let mut frame = frame.to_vec();
+        checksum::fill(&mut frame, checksum_start, checksum_offset);
+        self.send(&frame).await
+    }
+}
+
+#[async_trait]
+impl<T: NetDevice + Send + Sync + ?Sized> NetDevice for alloc::sync::Arc<T> {
+    type Error = T::Error;
+
+    fn mac_address(&self) -> [u8; 6] {
+        (**self).mac_address()
+    }
+
+    fn mtu(&self) -> usize {
+        (**self).mtu()
+    }
+
+    async fn send(&self, frame: &[u8]) -> core::result::Result<(), Self::Error> {
+        (**self).send(frame).await
+    }
+
+    async fn receive(&self) -> core::result::Result<Vec<u8>, Self::Error> {
+        (**self).receive().await
+    }
+
+    async fn send_with_checksum(
+        &self,
+        frame: &[u8],
+        checksum_start: usize,
+        checksum_offset: usize,
+    ) -> core::result::Result<(), Self::Error> {
+        (**self)
+            .send_with_checksum(frame, checksum_start, checksum_offset)
+            .await
+    }
+}
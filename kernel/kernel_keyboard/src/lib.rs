@@ -0,0 +1,180 @@
+//! Turns key presses into the bytes a terminal would send for them, with a US layout.
+//! Keys are identified by Linux's evdev keycodes, which virtio-input uses too.
+//! Enter sends a carriage return and Backspace sends DEL, like on a serial console, and the
+//! arrow keys and the keys above them send ANSI escape sequences.
+//! See https://github.com/torvalds/linux/blob/master/include/uapi/linux/input-event-codes.h
+
+#![cfg_attr(not(test), no_std)]
+
+extern crate alloc;
+
+#[cfg(test)]
+mod tests;
+
+use alloc::vec::Vec;
+
+// Keycodes that aren't in `US_LAYOUT`
+const KEY_LEFTCTRL: u16 = 29;
+const KEY_LEFTSHIFT: u16 = 42;
+const KEY_RIGHTSHIFT: u16 = 54;
+const KEY_CAPSLOCK: u16 = 58;
+const KEY_KP7: u16 = 71;
+const KEY_KPDOT: u16 = 83;
+const KEY_KPENTER: u16 = 96;
+const KEY_RIGHTCTRL: u16 = 97;
+const KEY_KPSLASH: u16 = 98;
+const KEY_HOME: u16 = 102;
+const KEY_UP: u16 = 103;
+const KEY_PAGEUP: u16 = 104;
+const KEY_LEFT: u16 = 105;
+const KEY_RIGHT: u16 = 106;
+const KEY_END: u16 = 107;
+const KEY_DOWN: u16 = 108;
+const KEY_PAGEDOWN: u16 = 109;
+const KEY_INSERT: u16 = 110;
+const KEY_DELETE: u16 = 111;
+
+// Values of key events
+pub const KEY_RELEASED: u32 = 0;
+pub const KEY_PRESSED: u32 = 1;
+pub const KEY_REPEATED: u32 = 2;
+
+/// Characters without and with shift, by keycode. 0 means the key doesn't have one.
+static US_LAYOUT: [(u8, u8); 58] = [
+    (0, 0),
+    (0x1b, 0x1b), // Escape
+    (b'1', b'!'),
+    (b'2', b'@'),
+    (b'3', b'#'),
+    (b'4', b'$'),
+    (b'5', b'%'),
+    (b'6', b'^'),
+    (b'7', b'&'),
+    (b'8', b'*'),
+    (b'9', b'('),
+    (b'0', b')'),
+    (b'-', b'_'),
+    (b'=', b'+'),
+    (0x7f, 0x7f), // Backspace
+    (b'\t', b'\t'),
+    (b'q', b'Q'),
+    (b'w', b'W'),
+    (b'e', b'E'),
+    (b'r', b'R'),
+    (b't', b'T'),
+    (b'y', b'Y'),
+    (b'u', b'U'),
+    (b'i', b'I'),
+    (b'o', b'O'),
+    (b'p', b'P'),
+    (b'[', b'{'),
+    (b']', b'}'),
+    (b'\r', b'\r'), // Enter
+    (0, 0),         // Left control
+    (b'a', b'A'),
+    (b's', b'S'),
+    (b'd', b'D'),
+    (b'f', b'F'),
+    (b'g', b'G'),
+    (b'h', b'H'),
+    (b'j', b'J'),
+    (b'k', b'K'),
+    (b'l', b'L'),
+    (b';', b':'),
+    (b'\'', b'"'),
+    (b'`', b'~'),
+    (0, 0), // Left shift
+    (b'\\', b'|'),
+    (b'z', b'Z'),
+    (b'x', b'X'),
+    (b'c', b'C'),
+    (b'v', b'V'),
+    (b'b', b'B'),
+    (b'n', b'N'),
+    (b'm', b'M'),
+    (b',', b'<'),
+    (b'.', b'>'),
+    (b'/', b'?'),
+    (0, 0),       // Right shift
+    (b'*', b'*'), // Keypad
+    (0, 0),       // Left alt
+    (b' ', b' '),
+];
+
+/// The keypad from 7 to the dot, as if num lock is on
+static KEYPAD: &[u8; 13] = b"789-456+1230.";
+
+#[derive(Default)]
+pub struct Keyboard {
+    left_shift: bool,
+    right_shift: bool,
+    left_control: bool,
+    right_control: bool,
+    caps_lock: bool,
+}
+
+impl Keyboard {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Handles a key event, and appends what it types to `output`
+    pub fn key_event(&mut self, code: u16, value: u32, output: &mut Vec<u8>) {
+        let pressed = value != KEY_RELEASED;
+        match code {
+            KEY_LEFTSHIFT => self.left_shift = pressed,
+            KEY_RIGHTSHIFT => self.right_shift = pressed,
+            KEY_LEFTCTRL => self.left_control = pressed,
+            KEY_RIGHTCTRL => self.right_control = pressed,
+            KEY_CAPSLOCK if value == KEY_PRESSED => self.caps_lock = !self.caps_lock,
+            _ if pressed => self.type_key(code, output),
+            _ => {}
+        }
+    }
+
+    fn type_key(&self, code: u16, output: &mut Vec<u8>) {
+        let escape_sequence: &[u8] = match code {
+            KEY_UP => b"\x1b[A",
+            KEY_DOWN => b"\x1b[B",
+            KEY_RIGHT => b"\x1b[C",
+            KEY_LEFT => b"\x1b[D",
+            KEY_HOME => b"\x1b[H",
+            KEY_END => b"\x1b[F",
+            KEY_INSERT => b"\x1b[2~",
+            KEY_DELETE => b"\x1b[3~",
+            KEY_PAGEUP => b"\x1b[5~",
+            KEY_PAGEDOWN => b"\x1b[6~",
+            _ => b"",
+        };
+        if !escape_sequence.is_empty() {
+            output.extend_from_slice(escape_sequence);
+            return;
+        }
+
+        let byte = match code {
+            KEY_KP7..=KEY_KPDOT => KEYPAD[(code - KEY_KP7) as usize],
+            KEY_KPENTER => b'\r',
+            KEY_KPSLASH => b'/',
+            _ => match US_LAYOUT.get(code as usize) {
+                Some(&(normal, shifted)) => {
+                    // Caps lock only affects letters, and shift undoes it
+                    let caps = self.caps_lock && normal.is_ascii_lowercase();
+                    if (self.left_shift || self.right_shift) != caps {
+                        shifted
+                    } else {
+                        normal
+                    }
+                }
+                None => 0,
+            },
+        };
+        if byte == 0 {
+            return;
+        }
+        if (self.left_control || self.right_control) && byte.is_ascii_alphabetic() {
+            output.push(byte.to_ascii_lowercase() - b'a' + 1);
+        } else {
+            output.push(byte);
+        }
+    }
+}
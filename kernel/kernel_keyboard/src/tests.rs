@@ -0,0 +1,149 @@
+use std::vec::Vec;
+
+use crate::*;
+
+const KEY_ESC: u16 = 1;
+const KEY_1: u16 = 2;
+const KEY_BACKSPACE: u16 = 14;
+const KEY_Q: u16 = 16;
+const KEY_ENTER: u16 = 28;
+const KEY_A: u16 = 30;
+const KEY_C: u16 = 46;
+const KEY_SLASH: u16 = 53;
+const KEY_SPACE: u16 = 57;
+const KEY_F1: u16 = 59;
+const KEY_KP1: u16 = 79;
+
+/// What pressing and releasing each of `keys` in turn types
+fn tap(keyboard: &mut Keyboard, keys: &[u16]) -> Vec<u8> {
+    let mut output = Vec::new();
+    for &key in keys {
+        keyboard.key_event(key, KEY_PRESSED, &mut output);
+        keyboard.key_event(key, KEY_RELEASED, &mut output);
+    }
+    output
+}
+
+/// What typing `keys` while holding down `modifier` types
+fn with(keyboard: &mut Keyboard, modifier: u16, keys: &[u16]) -> Vec<u8> {
+    let mut output = Vec::new();
+    keyboard.key_event(modifier, KEY_PRESSED, &mut output);
+    output.extend(tap(keyboard, keys));
+    keyboard.key_event(modifier, KEY_RELEASED, &mut output);
+    output
+}
+
+#[test]
+fn plain_keys() {
+    let mut keyboard = Keyboard::new();
+    assert_eq!(
+        tap(&mut keyboard, &[KEY_Q, KEY_A, KEY_1, KEY_SLASH, KEY_SPACE]),
+        b"qa1/ "
+    );
+    assert_eq!(
+        tap(&mut keyboard, &[KEY_ENTER, KEY_BACKSPACE, KEY_ESC]),
+        b"\r\x7f\x1b"
+    );
+    // Keys without a character, and keycodes past the end of the layout
+    assert_eq!(tap(&mut keyboard, &[KEY_F1, KEY_LEFTSHIFT, 0, 500]), b"");
+}
+
+#[test]
+fn releases_and_repeats() {
+    let mut keyboard = Keyboard::new();
+    let mut output = Vec::new();
+    keyboard.key_event(KEY_A, KEY_PRESSED, &mut output);
+    keyboard.key_event(KEY_A, KEY_REPEATED, &mut output);
+    keyboard.key_event(KEY_A, KEY_REPEATED, &mut output);
+    keyboard.key_event(KEY_A, KEY_RELEASED, &mut output);
+    assert_eq!(output, b"aaa");
+}
+
+#[test]
+fn shift() {
+    let mut keyboard = Keyboard::new();
+    assert_eq!(
+        with(&mut keyboard, KEY_LEFTSHIFT, &[KEY_A, KEY_1, KEY_SLASH]),
+        b"A!?"
+    );
+    assert_eq!(with(&mut keyboard, KEY_RIGHTSHIFT, &[KEY_Q]), b"Q");
+    // Releasing shift goes back to lowercase
+    assert_eq!(tap(&mut keyboard, &[KEY_A]), b"a");
+
+    // Both shifts are tracked on their own, so releasing one keeps the other held down
+    let mut output = Vec::new();
+    keyboard.key_event(KEY_LEFTSHIFT, KEY_PRESSED, &mut output);
+    keyboard.key_event(KEY_RIGHTSHIFT, KEY_PRESSED, &mut output);
+    keyboard.key_event(KEY_LEFTSHIFT, KEY_RELEASED, &mut output);
+    output.extend(tap(&mut keyboard, &[KEY_A]));
+    assert_eq!(output, b"A");
+}
+
+#[test]
+fn caps_lock() {
+    let mut keyboard = Keyboard::new();
+    tap(&mut keyboard, &[KEY_CAPSLOCK]);
+    // Only letters are affected, and shift undoes it
+    assert_eq!(tap(&mut keyboard, &[KEY_A, KEY_1]), b"A1");
+    assert_eq!(with(&mut keyboard, KEY_LEFTSHIFT, &[KEY_A, KEY_1]), b"a!");
+
+    // Holding caps lock down doesn't toggle it again
+    let mut output = Vec::new();
+    keyboard.key_event(KEY_CAPSLOCK, KEY_PRESSED, &mut output);
+    keyboard.key_event(KEY_CAPSLOCK, KEY_REPEATED, &mut output);
+    keyboard.key_event(KEY_CAPSLOCK, KEY_RELEASED, &mut output);
+    assert!(output.is_empty());
+    assert_eq!(tap(&mut keyboard, &[KEY_A]), b"a");
+}
+
+#[test]
+fn control() {
+    let mut keyboard = Keyboard::new();
+    assert_eq!(
+        with(&mut keyboard, KEY_LEFTCTRL, &[KEY_C, KEY_A]),
+        b"\x03\x01"
+    );
+    // Caps lock doesn't change the control character
+    tap(&mut keyboard, &[KEY_CAPSLOCK]);
+    assert_eq!(with(&mut keyboard, KEY_RIGHTCTRL, &[KEY_C]), b"\x03");
+    // Control doesn't change keys that aren't letters
+    assert_eq!(with(&mut keyboard, KEY_LEFTCTRL, &[KEY_1]), b"1");
+}
+
+#[test]
+fn escape_sequences() {
+    let mut keyboard = Keyboard::new();
+    assert_eq!(
+        tap(&mut keyboard, &[KEY_UP, KEY_DOWN, KEY_RIGHT, KEY_LEFT]),
+        b"\x1b[A\x1b[B\x1b[C\x1b[D"
+    );
+    assert_eq!(
+        tap(
+            &mut keyboard,
+            &[
+                KEY_HOME,
+                KEY_END,
+                KEY_INSERT,
+                KEY_DELETE,
+                KEY_PAGEUP,
+                KEY_PAGEDOWN
+            ]
+        ),
+        b"\x1b[H\x1b[F\x1b[2~\x1b[3~\x1b[5~\x1b[6~"
+    );
+    // Shift doesn't change them
+    assert_eq!(with(&mut keyboard, KEY_LEFTSHIFT, &[KEY_UP]), b"\x1b[A");
+}
+
+#[test]
+fn keypad() {
+    let mut keyboard = Keyboard::new();
+    assert_eq!(
+        tap(
+            &mut keyboard,
+            &[KEY_KP7, KEY_KP1, KEY_KPDOT, KEY_KPSLASH, KEY_KPENTER]
+        ),
+        b"71./\r"
+    );
+    assert_eq!(tap(&mut keyboard, &[KEY_KP7 + 3, KEY_KP7 + 7]), b"-+");
+}
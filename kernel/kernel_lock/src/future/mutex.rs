@@ -45,7 +45,12 @@ impl<'mutex, T> Future for MutexLockFuture<'mutex, T> {
             // Locking not successful
             // Register the waker
             self.mutex.lock.wakers.lock().push(cx.waker().clone());
-            Poll::Pending
+            // Try again, in case the mutex was unlocked before the waker was registered
+            if self.mutex.lock.locked.try_lock() {
+                Poll::Ready(AsyncMutexGuard { mutex: self.mutex })
+            } else {
+                Poll::Pending
+            }
         }
     }
 }
@@ -64,8 +69,10 @@ impl<T> AsyncMutex<T> {
 
     unsafe fn force_unlock(&self) {
         self.lock.locked.unlock();
-        let waker = self.lock.wakers.lock().remove(0);
-        waker.wake();
+        // Wake everyone, since some of the wakers might belong to futures that already
+        // got the lock. The ones that don't get it this time register themselves again.
+        let wakers = core::mem::take(&mut *self.lock.wakers.lock());
+        wakers.into_iter().for_each(Waker::wake);
     }
 }
 
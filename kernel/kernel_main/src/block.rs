@@ -0,0 +1,67 @@
+//! Block devices found at boot.
+
+use alloc::{sync::Arc, vec::Vec};
+
+use kernel_chip_drivers::virtio::{block::VirtioBlock, device_id};
+use kernel_io::BlockDevice;
+use kernel_vfs::{fat::FatFileSystem, VFS};
+
+use crate::{plic, virt_to_phys, virtio};
+
+pub static BLOCK_DEVICES: spin::RwLock<Vec<Arc<VirtioBlock>>> = spin::RwLock::new(Vec::new());
+
+/// Starts a driver for every virtio-blk device
+pub fn init() {
+    while let Some(device) = virtio::take_device(device_id::BLOCK) {
+        match VirtioBlock::new(
+            device.transport,
+            device.interrupt,
+            virt_to_phys,
+            plic::wake_on_interrupt,
+        ) {
+            Ok(block) => {
+                println!(
+                    "virtio-blk: {} sectors{}",
+                    block.capacity(),
+                    if block.is_read_only() { " (read only)" } else { "" }
+                );
+                virtio::activate_device(block.device().clone());
+                BLOCK_DEVICES.write().push(Arc::new(block));
+            }
+            Err(error) => println!("virtio-blk: failed to initialize: {:?}", error),
+        }
+    }
+}
+
+/// Mounts the first disk if it has a FAT32 filesystem on it.
+/// It goes at `/`, or at `/disk` if the initrd is mounted there.
+pub async fn mount_root() {
+    let disk = match BLOCK_DEVICES.read().first() {
+        Some(disk) => disk.clone(),
+        None => return,
+    };
+    let fs = match FatFileSystem::mount(disk).await {
+        Ok(fs) => fs,
+        Err(error) => {
+            println!("No FAT32 filesystem on the first disk: {:?}", error);
+            return;
+        }
+    };
+    let path = if VFS.mount_points().is_empty() {
+        "/"
+    } else {
+        "/disk"
+    };
+    if let Err(error) = VFS.mount(path, Arc::new(fs)).await {
+        println!("Couldn't mount the first disk at {}: {:?}", path, error);
+        return;
+    }
+    match VFS.readdir(path).await {
+        Ok(entries) => {
+            for entry in entries {
+                println!("{}/{}", path.trim_end_matches('/'), entry.name);
+            }
+        }
+        Err(error) => println!("Couldn't list the root directory: {:?}", error),
+    }
+}
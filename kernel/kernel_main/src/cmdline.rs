@@ -0,0 +1,31 @@
+//! Options for `kernel_main` on the kernel command line
+//! (see [`kernel_cmdline`] for the syntax).
+
+use kernel_cmdline::Param;
+use kernel_fdt::Fdt;
+
+/// Test the heap's memory before using it
+pub static MEMTEST: Param<bool> = Param::new("memtest", false);
+/// Only start this many harts, counting the boot hart
+pub static MAXHARTS: Param<usize> = Param::new("maxharts", usize::MAX);
+/// How long a process runs before it's preempted, in timer ticks
+pub static TIMESLICE: Param<u64> = Param::new("timeslice", 0x0010_0000);
+/// The built-in program to run after the services start. `none` runs nothing.
+pub static INIT: Param<&str> = Param::new("init", "echo");
+/// The hart that external interrupts are sent to. Until it's running, they go to the boot hart.
+pub static IRQAFFINITY: Param<usize> = Param::new("irqaffinity", 0);
+/// What to do after a panic: `halt`, `poweroff` (with a failure status, so that QEMU exits
+/// with one) or `reboot`
+pub static PANIC: Param<&str> = Param::new("panic", "halt");
+
+/// Reads `bootargs` from the device tree at `opaque`.
+/// The device tree has to stay mapped for as long as the kernel runs.
+pub fn init_from_fdt(opaque: usize) {
+    let fdt = match unsafe { Fdt::from_ptr(opaque as _) } {
+        Ok(fdt) => fdt,
+        Err(_) => return,
+    };
+    if let Some(bootargs) = fdt.bootargs() {
+        kernel_cmdline::init(bootargs);
+    }
+}
@@ -0,0 +1,23 @@
+//! The drivers that nodes in the device tree, and functions on the PCI bus, are matched against
+
+use kernel_chip_drivers::driver::{probe_all, Driver, PciDriver};
+use kernel_fdt::Fdt;
+
+use crate::{ne2000, pci, plic, power, rtc, uart, virtio};
+
+static DRIVERS: &[&Driver] = &[
+    &plic::DRIVER,
+    &uart::DRIVER,
+    &virtio::DRIVER,
+    &pci::DRIVER,
+    &rtc::DRIVER,
+    &power::POWER_OFF_DRIVER,
+    &power::REBOOT_DRIVER,
+];
+
+/// Probed by the host bridge's driver, once it has enumerated the bus
+pub static PCI_DRIVERS: &[&PciDriver] = &[&ne2000::DRIVER];
+
+pub fn probe_devices(fdt: &Fdt) {
+    probe_all(fdt, DRIVERS)
+}
@@ -0,0 +1,139 @@
+//! The display, and a text console on it that shows everything the kernel prints.
+
+use core::{
+    fmt::Write,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use kernel_chip_drivers::{
+    text_console::TextConsole,
+    virtio::{
+        device_id,
+        gpu::{Framebuffer, Rect, VirtioGpu},
+    },
+};
+use kernel_lock::shared::Mutex;
+
+use crate::{plic, timer, virt_to_phys, virtio};
+
+/// Every pixel of the font is drawn as a square of this size, which makes 80 by 50
+/// characters on QEMU's default 1280 by 800 display
+const FONT_SCALE: usize = 2;
+/// How often changes on the console are sent to the display, in milliseconds.
+/// Printing can happen anywhere, even where the console task can't be woken from, so the
+/// task checks for changes instead.
+const REFRESH_INTERVAL: u64 = 50;
+
+/// The device, until the console task takes it
+static GPU: spin::Mutex<Option<VirtioGpu>> = spin::Mutex::new(None);
+
+/// Mirrors `println!` once the display is set up
+static CONSOLE: Mutex<Option<TextConsole<Framebuffer>>> = Mutex::new(None);
+/// Whether `CONSOLE` has been set. The kernel prints before its locks can be used, so they're
+/// only touched after that.
+static CONSOLE_READY: AtomicBool = AtomicBool::new(false);
+
+/// Starts a driver for the first virtio-gpu device
+pub fn init() {
+    let device = match virtio::take_device(device_id::GPU) {
+        Some(device) => device,
+        None => return,
+    };
+    match VirtioGpu::new(
+        device.transport,
+        device.interrupt,
+        virt_to_phys,
+        plic::wake_on_interrupt,
+    ) {
+        Ok(gpu) => {
+            virtio::activate_device(gpu.device().clone());
+            *GPU.lock() = Some(gpu);
+        }
+        Err(error) => println!("virtio-gpu: failed to initialize: {:?}", error),
+    }
+}
+
+/// Writes `s` on the console, if there's one
+pub fn write_console(s: &str) {
+    if !CONSOLE_READY.load(Ordering::Acquire) {
+        return;
+    }
+    if let Some(console) = CONSOLE.lock().as_mut() {
+        let _ = console.write_str(s);
+    }
+}
+
+/// Writes `data` on the console, if there's one
+pub fn write_console_bytes(data: &[u8]) {
+    if !CONSOLE_READY.load(Ordering::Acquire) {
+        return;
+    }
+    if let Some(console) = CONSOLE.lock().as_mut() {
+        for byte in data {
+            console.write_byte(*byte);
+        }
+    }
+}
+
+/// Sets up a framebuffer on the first display and a console on it, and keeps the display
+/// up to date with the console
+pub async fn run_console() {
+    let gpu = match GPU.lock().take() {
+        Some(gpu) => gpu,
+        None => return,
+    };
+    let (scanout, display) = match gpu.display_info().await {
+        Ok(displays) if !displays.is_empty() => displays[0],
+        Ok(_) => {
+            println!("virtio-gpu: no display is enabled");
+            return;
+        }
+        Err(error) => {
+            println!("virtio-gpu: can't get the displays: {:?}", error);
+            return;
+        }
+    };
+    let framebuffer = match gpu
+        .create_framebuffer(scanout, display.width, display.height)
+        .await
+    {
+        Ok(framebuffer) => framebuffer,
+        Err(error) => {
+            println!("virtio-gpu: can't create a framebuffer: {:?}", error);
+            return;
+        }
+    };
+    let resource = framebuffer.resource;
+    let console = TextConsole::new(
+        framebuffer,
+        display.width as usize,
+        display.height as usize,
+        FONT_SCALE,
+    );
+    let (columns, rows) = console.size();
+    *CONSOLE.lock() = Some(console);
+    CONSOLE_READY.store(true, Ordering::Release);
+    println!(
+        "virtio-gpu: {}x{} display on scanout {}, {}x{} console",
+        display.width, display.height, scanout, columns, rows
+    );
+
+    loop {
+        let damage = CONSOLE
+            .lock()
+            .as_mut()
+            .and_then(|console| console.take_damage());
+        if let Some(damage) = damage {
+            let rect = Rect {
+                x: 0,
+                y: damage.start as u32,
+                width: resource.width,
+                height: (damage.end - damage.start) as u32,
+            };
+            if let Err(error) = gpu.flush(resource, rect).await {
+                log::debug!("virtio-gpu: flush failed: {:?}", error);
+            }
+        }
+        timer::sleep_until(timer::millis() + REFRESH_INTERVAL).await;
+    }
+}
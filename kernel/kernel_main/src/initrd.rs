@@ -0,0 +1,60 @@
+//! The initial ramdisk, which the bootloader leaves somewhere in physical memory.
+
+use alloc::sync::Arc;
+
+use kernel_vfs::{initrd::Initrd, VFS};
+
+use crate::phys_to_virt;
+
+pub static INITRD: spin::Once<&'static [u8]> = spin::Once::new();
+
+/// `start` is a physical address. A length of zero means there is no initrd.
+pub fn init(start: usize, len: usize) {
+    if len == 0 {
+        return;
+    }
+    INITRD.call_once(|| unsafe {
+        core::slice::from_raw_parts(phys_to_virt(start) as *const u8, len)
+    });
+}
+
+/// QEMU puts the initrd in the middle of RAM, which can be inside the heap. If it is, the
+/// heap becomes the larger of the parts before and after it, so that it doesn't get overwritten.
+pub fn heap_range(start: usize, end: usize) -> (usize, usize) {
+    let initrd = match INITRD.get() {
+        Some(initrd) => initrd.as_ptr_range(),
+        None => return (start, end),
+    };
+    let (initrd_start, initrd_end) = (initrd.start as usize, initrd.end as usize);
+    if initrd_start >= end || initrd_end <= start {
+        return (start, end);
+    }
+    let before = (start, initrd_start & !4095);
+    let after = ((initrd_end + 4095) & !4095, end);
+    let size = |(start, end): (usize, usize)| end.saturating_sub(start);
+    let range = if size(before) >= size(after) {
+        before
+    } else {
+        after
+    };
+    assert!(size(range) > 0, "The initrd leaves no space for the heap");
+    range
+}
+
+/// Mounts the initrd at `/`
+pub async fn mount() {
+    let data = match INITRD.get() {
+        Some(data) => *data,
+        None => return,
+    };
+    let fs = match Initrd::parse(data) {
+        Ok(fs) => fs,
+        Err(error) => {
+            println!("Couldn't parse the initrd: {:?}", error);
+            return;
+        }
+    };
+    if let Err(error) = VFS.mount("/", Arc::new(fs)).await {
+        println!("Couldn't mount the initrd: {:?}", error);
+    }
+}
@@ -0,0 +1,72 @@
+//! Keyboards found at boot. What's typed on them is read like input from the console UART.
+
+use alloc::{boxed::Box, vec::Vec};
+
+use kernel_chip_drivers::virtio::{
+    device_id,
+    input::{VirtioInput, EV_KEY},
+};
+use kernel_keyboard::Keyboard;
+
+use crate::{plic, uart, virt_to_phys, virtio};
+
+static DEVICES: spin::Mutex<Vec<VirtioInput>> = spin::Mutex::new(Vec::new());
+
+/// Starts a driver for every virtio-input device
+pub fn init() {
+    while let Some(device) = virtio::take_device(device_id::INPUT) {
+        match VirtioInput::new(
+            device.transport,
+            device.interrupt,
+            virt_to_phys,
+            plic::wake_on_interrupt,
+        ) {
+            Ok(input) => {
+                if input.has_keys() {
+                    println!("virtio-input: keyboard {:?}", input.name());
+                } else {
+                    println!("virtio-input: ignoring {:?}, it has no keys", input.name());
+                }
+                virtio::activate_device(input.device().clone());
+                DEVICES.lock().push(input);
+            }
+            Err(error) => println!("virtio-input: failed to initialize: {:?}", error),
+        }
+    }
+}
+
+/// Starts a task for every keyboard that turns key presses into console input.
+/// Other devices stay initialized, since the device knows where their queues are, but
+/// their events are never read.
+pub fn run_keyboards() {
+    let mut devices = DEVICES.lock();
+    let mut index = 0;
+    while index < devices.len() {
+        if devices[index].has_keys() {
+            let input = devices.remove(index);
+            crate::HartLocals::current()
+                .local_executor
+                .as_ref()
+                .unwrap()
+                .spawn(Box::new(Box::pin(read_keys(input))));
+        } else {
+            index += 1;
+        }
+    }
+}
+
+async fn read_keys(input: VirtioInput) {
+    let mut keyboard = Keyboard::new();
+    let mut typed = Vec::new();
+    loop {
+        for event in input.read_events().await {
+            if event.kind == EV_KEY {
+                keyboard.key_event(event.code, event.value, &mut typed);
+            }
+        }
+        if !typed.is_empty() {
+            uart::push_input(&typed);
+            typed.clear();
+        }
+    }
+}
@@ -0,0 +1,37 @@
+//! Prints the messages from the `log` crate
+use core::str::FromStr;
+
+use kernel_cmdline::Param;
+use log::{LevelFilter, Log, Metadata, Record};
+
+/// One of `off`, `error`, `warn`, `info`, `debug` or `trace`
+pub static LOGLEVEL: Param<&str> = Param::new("loglevel", "info");
+
+struct Logger;
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            println!("[{}] {}", record.level(), record.args());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: Logger = Logger;
+
+/// Has to be called after the command line is read
+pub fn init() {
+    let level = LevelFilter::from_str(LOGLEVEL.get()).unwrap_or_else(|_| {
+        println!("Unknown log level {:?}", LOGLEVEL.get());
+        LevelFilter::Info
+    });
+    // This only fails if it was already set
+    let _ = log::set_logger(&LOGGER);
+    log::set_max_level(level);
+}
@@ -17,6 +17,9 @@
     asm_sym,
     asm_const
 )]
+#![cfg_attr(feature = "test", feature(custom_test_frameworks))]
+#![cfg_attr(feature = "test", test_runner(crate::testing::run_tests))]
+#![cfg_attr(feature = "test", reexport_test_harness_main = "test_main")]
 
 extern crate alloc;
 
@@ -27,20 +30,17 @@ extern crate kernel_printer;
 
 use alloc::{
     boxed::Box,
-    collections::{BTreeMap, VecDeque},
+    collections::VecDeque,
     rc::Rc,
-    vec::Vec,
 };
 use core::{
     cell::RefCell,
     ffi::c_void,
     hint::black_box,
     sync::atomic::{AtomicUsize, Ordering},
-    task::Waker, fmt::Write,
+    fmt::Write,
 };
 
-use fdt::Fdt;
-use kernel_chip_drivers::plic::Plic0;
 use kernel_cpu::{
     csr::{
         status::{SIE},
@@ -50,24 +50,45 @@ use kernel_cpu::{
     write_satp, write_sie, write_sscratch, write_sstatus, write_stvec, read_satp_flags, load_hartid, read_sp,
 };
 use kernel_executor::{LocalExecutor, SendExecutor, SendExecutorHandle};
+use kernel_fdt::Fdt;
 use kernel_paging::Paging;
-use kernel_process::Process;
+use kernel_process::{capabilities, Process, ProcessContainer};
 use kernel_syscall::do_syscall_and_drop_if_exit;
 use kernel_trap_frame::TrapFrame;
 use kernel_util::{boxed_slice_with_alignment_uninit, boxed_slice_with_alignment, debug::Uart};
 use sbi::SbiError;
 
 use crate::{
-    asm::{do_supervisor_syscall_2, do_supervisor_syscall_0, do_supervisor_syscall_1}, syscall::wait_until_process_is_woken, timer::set_relative_timer,
+    syscall::wait_until_process_is_woken, timer::set_relative_timer,
     trap_handler::handle_come_back_from_process,
 };
 
 pub mod asm;
+pub mod block;
+pub mod cmdline;
+pub mod drivers;
+pub mod gpu;
+pub mod initrd;
+pub mod input;
+pub mod logger;
+pub mod ne2000;
+pub mod net;
 pub mod never_waker;
+pub mod pci;
+pub mod plic;
+pub mod power;
+pub mod random;
+pub mod rtc;
 pub mod std_macros;
 pub mod syscall;
+#[cfg(feature = "test")]
+mod tests;
+#[cfg(feature = "test")]
+pub mod testing;
 pub mod timer;
 pub mod trap_handler;
+pub mod uart;
+pub mod virtio;
 pub mod wait_future;
 
 #[macro_use]
@@ -97,7 +118,6 @@ extern "C" {
 struct HartLocals {
     local_executor: Option<kernel_executor::LocalExecutorHandle>,
     executor: Option<kernel_executor::SendExecutorHandle>,
-    interrupt_notifiers: RefCell<BTreeMap<usize, Vec<Waker>>>,
     unhandled_interrupts: RefCell<usize>,
 }
 
@@ -198,6 +218,8 @@ pub fn main(
     kernel_len: usize,
     _stack_start_virtual: usize,
     hart_entry_point: usize,
+    initrd_start: usize,
+    initrd_len: usize,
 ) -> ! {
     black_box(&debug_test_fn());
     if SV_BITS.load(Ordering::Acquire) != 0 {
@@ -214,6 +236,10 @@ pub fn main(
     
     kernel_util::debug::Uart::from_address(0x1000_0000 as *mut u8).write_str("Reached early kernel code.\n");
 
+    cmdline::init_from_fdt(opaque);
+    logger::init();
+    println!("Command line: {:?}", kernel_cmdline::cmdline());
+
     let kernel_phys = unsafe {
         let mut table = ((read_satp() << 12) as *mut Table<_>).as_mut().unwrap();
         let sv39 = Sv39 {
@@ -227,13 +253,13 @@ pub fn main(
     
     
     let gap = GAP.load(Ordering::Relaxed);
-    println!("Physical address of kernel: {:x}", kernel_phys);
-    println!("Gap: {:x}", gap);
-    println!("Computed virtual address of kernel: {:x}", kernel_phys + gap);
-    println!("Kernel length in bytes: 0x{:x}", kernel_len);
-    println!("Computer kernel end: {:x}", kernel_phys + gap + kernel_len);
-    println!("Stack virtual address: {:x}", _stack_start_virtual);
-    println!("Stack pointer: {:x}", read_sp());
+    log::debug!("Physical address of kernel: {:x}", kernel_phys);
+    log::debug!("Gap: {:x}", gap);
+    log::debug!("Computed virtual address of kernel: {:x}", kernel_phys + gap);
+    log::debug!("Kernel length in bytes: 0x{:x}", kernel_len);
+    log::debug!("Computer kernel end: {:x}", kernel_phys + gap + kernel_len);
+    log::debug!("Stack virtual address: {:x}", _stack_start_virtual);
+    log::debug!("Stack pointer: {:x}", read_sp());
     
     // kernel_phys + GAP.load(Ordering::Relaxed);
     // Every time i've tried changing this, it's cursed.
@@ -241,7 +267,11 @@ pub fn main(
     //assert!(start > kernel_phys + GAP.load(Ordering::Relaxed));
     //assert!(kernel_len < 0x50_0000);
     let end: usize = 0xffffffc08700_0000;
-    //unsafe { do_memory_probe(start, end) }
+    initrd::init(initrd_start, initrd_len);
+    let (start, end) = initrd::heap_range(start, end);
+    if cmdline::MEMTEST.get() {
+        unsafe { do_memory_probe(start, end) }
+    }
     kernel_allocator::init_from_pointers(start as *const _, end as *const _);
 
     println!("{:?}", "Reached kernel!");
@@ -326,8 +356,13 @@ async fn async_main(hartid: usize, opaque: usize, hart_entry_point: usize) -> !
 
     setup_hart_state_and_metadata(hartid);
 
+    // The other harts need the PLIC to be found first
+    let fdt = unsafe { Fdt::from_ptr(opaque as _) }.unwrap();
+    timer::init_from_fdt(&fdt);
+    drivers::probe_devices(&fdt);
+
     // Spawn all harts
-    for hart_id in 0.. {
+    for hart_id in 0..cmdline::MAXHARTS.get() {
         let stack = boxed_slice_with_alignment_uninit::<u8>(4096, 4096);
         let stack_addr = stack.as_ptr_range().end as *mut usize;
         let stack_addr = unsafe { stack_addr.offset(-1) };
@@ -351,10 +386,7 @@ async fn async_main(hartid: usize, opaque: usize, hart_entry_point: usize) -> !
 
     println!(
         "ISA: {}",
-        unsafe { Fdt::from_ptr(opaque as _) }
-            .unwrap()
-            .cpus()
-            .next()
+        fdt.find_node("/cpus/cpu")
             .unwrap()
             .property("riscv,isa")
             .unwrap()
@@ -447,146 +479,108 @@ async fn common_hart_code() -> ! {
     unsafe { (*read_sscratch()).satp = satp }
     unsafe { (*read_sscratch()).kernel_satp = satp }
 
-    // Create the PLIC instance
-    let plic = Plic0::new_with_addr(GAP.load(Ordering::Relaxed) + 0x0c00_0000);
-
-    plic.set_threshold(0);
-    plic.set_priority(10, 3);
-    unsafe {
-        (0x1000_0001 as *mut u8)
-            .add(GAP.load(Ordering::Relaxed))
-            .write_volatile(1)
-    }
+    plic::init_hart();
 
     println!("{:?}", "ready plic");
     
     if load_hartid() != 0 {
-        HartLocals::current().local_executor.as_ref().unwrap().spawn(Box::new(Box::pin(async {
-            
-            assert!(read_sie() == 0);
-            fn test() {
-                enable_interrupts();
-                loop {
-                    println!("{:?}", "a calculatioN!");
-                    kernel_cpu::wfi();
-                }
-            }
-            disable_interrupts();
-            let mut process = Process::new_supervisor(
-                |mut process| {
-                    process.name = Some(alloc::string::String::from("hello world"));
-                    unsafe {
-                        let table = kernel_paging::Table::<8>::from_satp(process.trap_frame.satp, phys_to_virt).as_ref().unwrap().clone();
-                        let table = table.clone_with(phys_to_virt, virt_to_phys);
-                        process.trap_frame.satp = table.to_satp_base_addr(virt_to_phys) | read_satp_flags();
-                        process.page_table = Some(alloc::sync::Arc::new(table));
-                    };
-                },
-                test,
-                phys_to_virt,
-                virt_to_phys,
-            );
-            //process.lock().trap_frame.satp = q;
-
+        fn test() {
+            enable_interrupts();
             loop {
-                // Make sure the process is ready for waking up
-                wait_until_process_is_woken(&process).await;
-                set_relative_timer(0x00010_0000);
-                // This has the SIE bit disabled because
-                // the interrupt will get triggered in the idle task.
-                process.lock().trap_frame.sie = (!read_sip()) & 0x022;
-                process.lock().switch_to_and_come_back();
-                process = do_syscall_and_drop_if_exit(process, |p| {
-                    handle_come_back_from_process(Some(p))
-                })
-                .unwrap();
+                println!("{:?}", "a calculatioN!");
+                kernel_cpu::wfi();
             }
-        })));
+        }
+        spawn_process("hello world", test, 0);
     } else {
-
-        plic.set_enabled(10, true);
+        random::init();
+        block::init();
+        net::init();
+        gpu::init();
+        input::init();
         HartLocals::current()
             .local_executor
             .as_ref()
             .unwrap()
             .spawn(Box::new(Box::pin(async {
-                assert!(read_sie() == 0);
-                fn test() {
-                    enable_interrupts();
-                    
-                    use alloc::string::String;
-                    
-                    fn getchar() -> char {
-                        let id = unsafe { do_supervisor_syscall_2(10, 10, 0) };
-                        unsafe { do_supervisor_syscall_1(2, id.0) };
-                        do_supervisor_syscall_0(3);
-                        
-                        (unsafe {
-                            ((0x1000_0000 as *const u8).add(GAP.load(Ordering::Relaxed)))
-                                .read_volatile()
-                        }) as char
-                    }
-                    fn readline() -> String {
-                    	let mut string = String::new();
-                    	loop {
-                            let c = getchar();
-							let c = if c == '\r' { '\n' } else { c };
-							if c == 0x7f as char {
-								// 0x7f == delete
-								// 0x08 == backspace (go back 1 column)
-								if !string.is_empty() {
-									print!("\x08 \x08");
-									string.pop();
-								}
-							} else {
-								string.push(c);
-								print!("{}", c);
-							}
-							if c == '\n' {
-							    return string;
-							}
-                    	}
-                    }
-                    
-                    loop {
-                        let r = readline();
-                        println!("You typed: {:?}", r);
-                    }
-                    unreachable!();
-                    loop {}
-                }
-                disable_interrupts();
-                let mut process = Process::new_supervisor(
-                    |mut process| {
-                        process.name = Some(alloc::string::String::from("hello world"));
-                        unsafe {
-                            let table = kernel_paging::Table::<8>::from_satp(process.trap_frame.satp, phys_to_virt).as_ref().unwrap().clone();
-                            let table = table.clone_with(phys_to_virt, virt_to_phys);
-                            process.trap_frame.satp = table.to_satp_base_addr(virt_to_phys) | read_satp_flags();
-                            println!("{:x}", process.trap_frame.satp);
-                            process.page_table = Some(alloc::sync::Arc::new(table));
-                        };
-                    },
-                    test,
-                    phys_to_virt,
-                    virt_to_phys,
-                );
-                //process.lock().trap_frame.satp = q;
-
-                loop {
-                    // Make sure the process is ready for waking up
-                    wait_until_process_is_woken(&process).await;
-                    set_relative_timer(0x0010_0000);
-                    // This has the SIE bit disabled because
-                    // the interrupt will get triggered in the idle task.
-                    process.lock().trap_frame.sie = (!read_sip()) & 0x022;
-                    process.lock().switch_to_and_come_back();
-                    process = do_syscall_and_drop_if_exit(process, |p| {
-                        handle_come_back_from_process(Some(p))
-                    })
-                    .unwrap();
-                }
+                initrd::mount().await;
+                block::mount_root().await;
             })));
+        HartLocals::current()
+            .local_executor
+            .as_ref()
+            .unwrap()
+            .spawn(Box::new(Box::pin(net::run_stack())));
+        HartLocals::current()
+            .local_executor
+            .as_ref()
+            .unwrap()
+            .spawn(Box::new(Box::pin(gpu::run_console())));
+        HartLocals::current()
+            .local_executor
+            .as_ref()
+            .unwrap()
+            .spawn(Box::new(Box::pin(random::run_entropy())));
+        input::run_keyboards();
+        #[cfg(not(feature = "test"))]
+        fn test() {
+            enable_interrupts();
+            
+            use alloc::string::String;
+            use kernel_services::{name_service::NameServiceClient, queues::allocate_queue, serial::SerialClient};
+            use kernel_rpc::Connection;
+            
+            let names = NameServiceClient::connect(allocate_queue());
+            let uart = names.wait_for("dev/uart0", &allocate_queue()).unwrap().unwrap();
+            let uart = SerialClient::new(Connection::new(kernel_api::BufferQueue::new(uart), allocate_queue()));
+            
+            let readline = || -> String {
+            	let mut string = String::new();
+            	loop {
+                    for c in uart.read(64).unwrap() {
+                        let c = if c == b'\r' { '\n' } else { c as char };
+                        if c == 0x7f as char {
+                            // 0x7f == delete
+                            // 0x08 == backspace (go back 1 column)
+                            if !string.is_empty() {
+                                uart.write_all(b"\x08 \x08").unwrap();
+                                string.pop();
+                            }
+                        } else {
+                            string.push(c);
+                            uart.write_all(c.encode_utf8(&mut [0; 4]).as_bytes()).unwrap();
+                        }
+                        if c == '\n' {
+                            return string;
+                        }
+                    }
+            	}
+            };
+            
+            loop {
+                let r = readline();
+                println!("You typed: {:?}", r);
+            }
+        }
+        fn name_service() {
+            enable_interrupts();
+            kernel_services::name_service::name_service()
+        }
+        let names = spawn_process("name service", name_service, 0);
+        syscall::set_queue_owner(kernel_services::NAME_SERVICE_QUEUE, names);
+        if uart::CONSOLE_UART.get().is_some() {
+            spawn_process("uart service", uart::uart_service, 0);
+        }
+        #[cfg(feature = "test")]
+        spawn_process("kernel tests", testing::tests_process, capabilities::POWER);
+        #[cfg(not(feature = "test"))]
+        match cmdline::INIT.get() {
+            "echo" => {
+                spawn_process("echo", test, capabilities::POWER);
+            }
+            "none" => {}
+            other => println!("Unknown init program {:?}", other),
+        }
     }
     let handle = HartLocals::current()
         .local_executor
@@ -598,6 +592,62 @@ async fn common_hart_code() -> ! {
     loop {}
 }
 
+/// Creates a supervisor process that runs `function` with its own copy of the kernel's page table
+pub fn new_process(name: &str, function: fn()) -> ProcessContainer {
+    Process::new_supervisor(
+        |mut process| {
+            process.name = Some(alloc::string::String::from(name));
+            unsafe {
+                let table = kernel_paging::Table::<8>::from_satp(process.trap_frame.satp, phys_to_virt).as_ref().unwrap().clone();
+                let table = table.clone_with(phys_to_virt, virt_to_phys);
+                process.trap_frame.satp = table.to_satp_base_addr(virt_to_phys) | read_satp_flags();
+                process.page_table = Some(alloc::sync::Arc::new(table));
+            };
+        },
+        function,
+        phys_to_virt,
+        virt_to_phys,
+    )
+}
+
+/// Runs `process` on this hart until it exits
+pub async fn run_process(mut process: ProcessContainer) {
+    let id = process.lock().id;
+    loop {
+        // Make sure the process is ready for waking up
+        wait_until_process_is_woken(&process).await;
+        set_relative_timer(cmdline::TIMESLICE.get());
+        // This has the SIE bit disabled because
+        // the interrupt will get triggered in the idle task.
+        process.lock().trap_frame.sie = (!read_sip()) & 0x022;
+        process.lock().switch_to_and_come_back();
+        process = match do_syscall_and_drop_if_exit(process, |p| {
+            handle_come_back_from_process(Some(p))
+        }) {
+            Some(process) => process,
+            None => {
+                syscall::free_queues_of(id);
+                return;
+            }
+        };
+    }
+}
+
+/// Creates a process with `capabilities` and runs it in this hart's executor. Only init and
+/// the test runner get any. Returns the process's ID.
+pub fn spawn_process(name: &str, function: fn(), capabilities: usize) -> u64 {
+    disable_interrupts();
+    let process = new_process(name, function);
+    process.lock().capabilities = capabilities;
+    let id = process.lock().id;
+    HartLocals::current()
+        .local_executor
+        .as_ref()
+        .unwrap()
+        .spawn(Box::new(Box::pin(run_process(process))));
+    id
+}
+
 pub unsafe fn paging_from_satp(satp: usize) -> Box<dyn Paging> {
     match PagingMode::from_satp(satp) {
         PagingMode::Bare => {
@@ -660,15 +710,23 @@ macro_rules! println
 
 #[panic_handler]
 fn panic(info: &core::panic::PanicInfo) -> ! {
-    println!("{:?}", "Panic!");
+    // Whatever panicked may be holding the console's locks
+    println_u!("{:?}", "Panic!");
 
     let fnomsg = format_args!("<no message>");
     let message = info.message().unwrap_or(&fnomsg);
 
-    println!(
+    println_u!(
         "\"{}\" at \x1b[94m{}\x1b[0m",
         message,
         info.location().unwrap()
     );
-    loop {}
+    #[cfg(feature = "test")]
+    testing::fail();
+    #[cfg(not(feature = "test"))]
+    match cmdline::PANIC.get() {
+        "poweroff" => power::power_off(1),
+        "reboot" => power::reboot(false),
+        _ => loop {},
+    }
 }
@@ -0,0 +1,62 @@
+//! NE2000 cards on the PCI bus, like QEMU's ne2k_pci.
+
+use alloc::{sync::Arc, vec::Vec};
+
+use kernel_chip_drivers::{
+    driver::{PciDriver, ProbeError},
+    ne2000::{Ne2000, Ne2000Device},
+};
+use kernel_pci::{BarKind, DeviceId, Function};
+
+use crate::{net, phys_to_virt, plic};
+
+pub static DRIVER: PciDriver = PciDriver {
+    name: "ne2000",
+    // RTL8029
+    ids: &[DeviceId::new(0x10ec, 0x8029)],
+    probe,
+};
+
+/// Cards that were set up, so that their interrupts can be handled
+static DEVICES: spin::RwLock<Vec<Ne2000Device>> = spin::RwLock::new(Vec::new());
+
+fn probe(function: &Function<'static>) -> Result<(), ProbeError> {
+    let registers = function
+        .bar(0)
+        .filter(|bar| bar.kind == BarKind::Io)
+        .ok_or(ProbeError::MissingResource)?;
+    let interrupt = function.interrupt.ok_or(ProbeError::MissingResource)?;
+    let card = Ne2000::new(phys_to_virt(registers.address as usize)).map_err(|error| {
+        log::warn!("ne2000: can't reset the card: {:?}", error);
+        ProbeError::Failed
+    })?;
+    println!(
+        "NE2000 at {:#x}, interrupt {}",
+        registers.address, interrupt
+    );
+
+    let device = Ne2000Device::new(card, interrupt);
+    // Legacy interrupts can be shared, so only register it once
+    let registered = DEVICES
+        .read()
+        .iter()
+        .any(|device| device.interrupt == interrupt);
+    DEVICES.write().push(device.clone());
+    if !registered {
+        plic::register(interrupt, 2, handle_external_interrupt).map_err(|error| {
+            log::warn!("ne2000: can't enable interrupt {}: {:?}", interrupt, error);
+            ProbeError::Failed
+        })?;
+    }
+    net::add_device(Arc::new(device));
+    Ok(())
+}
+
+/// Registered with the PLIC for the interrupt of every card
+fn handle_external_interrupt(interrupt: u32) {
+    for device in DEVICES.read().iter() {
+        if device.interrupt == interrupt {
+            device.card.lock().handle_interrupt();
+        }
+    }
+}
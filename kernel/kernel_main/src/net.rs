@@ -0,0 +1,555 @@
+//! Network interfaces found at boot, whatever their driver is, and the task that runs the
+//! TCP/IP stack on them and serves the `Network` service.
+
+use alloc::{
+    boxed::Box,
+    collections::{BTreeMap, VecDeque},
+    format,
+    string::String,
+    sync::Arc,
+    task::Wake,
+    vec::Vec,
+};
+use core::{
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicBool, Ordering},
+    task::{Context, Poll, Waker},
+};
+
+use kernel_chip_drivers::{
+    net::NetError,
+    virtio::{device_id, net::VirtioNet},
+};
+use kernel_io::NetDevice;
+use kernel_net::{
+    InterfaceId, Ipv4Address, MacAddress, SocketAddress, SocketError, SocketHandle, SocketKind,
+    Stack,
+};
+use kernel_rpc::to_bytes;
+use kernel_services::{
+    net::{
+        self as service, InterfaceInfo, Network, Readiness, MAX_DATA_SIZE, MAX_SOCKETS_PER_CALLER,
+        MAX_WATCHES_PER_CALLER,
+    },
+    NETWORK_SERVICE_QUEUE,
+};
+use kernel_syscall::KERNEL_SENDER;
+
+use crate::{
+    plic, random,
+    syscall::{claim_copied_buffer, queue_owner, send_copied_buffer, set_queue_owner},
+    timer::{self, Sleep},
+    virt_to_phys, virtio,
+};
+
+pub type SharedNetDevice = Arc<dyn NetDevice<Error = NetError> + Send + Sync>;
+
+/// Interfaces in the order they were found. The first one is `eth0`.
+pub static NET_DEVICES: spin::RwLock<Vec<SharedNetDevice>> = spin::RwLock::new(Vec::new());
+
+/// Called by drivers once an interface is ready to send and receive frames
+pub fn add_device(device: SharedNetDevice) {
+    let mut devices = NET_DEVICES.write();
+    let mac = device.mac_address();
+    println!(
+        "eth{}: {:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}, MTU {}",
+        devices.len(),
+        mac[0],
+        mac[1],
+        mac[2],
+        mac[3],
+        mac[4],
+        mac[5],
+        device.mtu()
+    );
+    devices.push(device);
+}
+
+/// Starts a driver for every virtio-net device. Cards on the PCI bus are set up while it's
+/// probed.
+pub fn init() {
+    while let Some(device) = virtio::take_device(device_id::NETWORK) {
+        match VirtioNet::new(
+            device.transport,
+            device.interrupt,
+            virt_to_phys,
+            plic::wake_on_interrupt,
+        ) {
+            Ok(net) => {
+                virtio::activate_device(net.device().clone());
+                add_device(Arc::new(net));
+            }
+            Err(error) => println!("virtio-net: failed to initialize: {:?}", error),
+        }
+    }
+}
+
+/// How often the stack checks whether the random number generator is seeded before it
+/// starts, in milliseconds
+const RANDOM_RETRY_INTERVAL: u64 = 100;
+
+/// Received frames that the stack hasn't seen yet. More are dropped.
+const MAX_QUEUED_FRAMES: usize = 64;
+
+#[derive(Default)]
+struct Inbox {
+    frames: VecDeque<(InterfaceId, Vec<u8>)>,
+    /// The stack task, if it's waiting
+    waker: Option<Waker>,
+}
+
+static INBOX: spin::Mutex<Inbox> = spin::Mutex::new(Inbox {
+    frames: VecDeque::new(),
+    waker: None,
+});
+
+/// Hands the frames that `device` receives to the stack task
+async fn receive_frames(name: String, id: InterfaceId, device: SharedNetDevice) {
+    loop {
+        let frame = match device.receive().await {
+            Ok(frame) => frame,
+            Err(error) => {
+                log::warn!("{}: can't receive: {:?}", name, error);
+                return;
+            }
+        };
+        let mut inbox = INBOX.lock();
+        if inbox.frames.len() < MAX_QUEUED_FRAMES {
+            inbox.frames.push_back((id, frame));
+        }
+        let waker = inbox.waker.take();
+        drop(inbox);
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+/// What woke the stack task up
+enum Event {
+    Frames,
+    /// A request, and the ID of the process that sent it
+    Request(Vec<u8>, u64),
+    Timer,
+}
+
+/// Wakes the stack task when a request arrives, and says that it has to be registered with
+/// the service queue again
+struct RequestWaker {
+    task: Waker,
+    registered: Arc<AtomicBool>,
+}
+
+impl Wake for RequestWaker {
+    fn wake(self: Arc<Self>) {
+        self.registered.store(false, Ordering::Release);
+        self.task.wake_by_ref();
+    }
+}
+
+/// Waits for frames, a request or the stack's next timer, whichever comes first
+struct NextEvent<'a> {
+    timer: Option<Sleep>,
+    /// Whether a `RequestWaker` is waiting on the service queue. It stays there until a
+    /// request arrives, so this wait doesn't need to add another one.
+    request_waker_registered: &'a Arc<AtomicBool>,
+}
+
+impl Future for NextEvent<'_> {
+    type Output = Event;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Event> {
+        {
+            let mut inbox = INBOX.lock();
+            if !inbox.frames.is_empty() {
+                return Poll::Ready(Event::Frames);
+            }
+            inbox.waker = Some(cx.waker().clone());
+        }
+        let request = if self.request_waker_registered.swap(true, Ordering::AcqRel) {
+            claim_copied_buffer(NETWORK_SERVICE_QUEUE, None)
+        } else {
+            // Marked as registered first, in case a request wakes it right away
+            let waker = Waker::from(Arc::new(RequestWaker {
+                task: cx.waker().clone(),
+                registered: self.request_waker_registered.clone(),
+            }));
+            let request = claim_copied_buffer(NETWORK_SERVICE_QUEUE, Some(&waker));
+            if request.is_some() {
+                // There was a request already, so the waker wasn't registered
+                self.request_waker_registered
+                    .store(false, Ordering::Release);
+            }
+            request
+        };
+        if let Some((request, sender)) = request {
+            return Poll::Ready(Event::Request(request, sender));
+        }
+        match &mut self.timer {
+            Some(timer) => Pin::new(timer).poll(cx).map(|()| Event::Timer),
+            None => Poll::Pending,
+        }
+    }
+}
+
+fn to_wire_address(address: SocketAddress) -> service::SocketAddress {
+    service::SocketAddress {
+        address: address.address.0,
+        port: address.port,
+    }
+}
+
+fn from_wire_address(address: service::SocketAddress) -> SocketAddress {
+    SocketAddress::new(Ipv4Address(address.address), address.port)
+}
+
+fn to_wire_readiness(readiness: kernel_net::Readiness) -> Readiness {
+    Readiness {
+        readable: readiness.readable,
+        writable: readiness.writable,
+    }
+}
+
+fn to_wire_error(error: SocketError) -> service::SocketError {
+    match error {
+        SocketError::WouldBlock => service::SocketError::WouldBlock,
+        SocketError::InvalidSocket => service::SocketError::InvalidSocket,
+        SocketError::InvalidOperation => service::SocketError::InvalidOperation,
+        SocketError::AddressInUse => service::SocketError::AddressInUse,
+        SocketError::AddressNotAvailable => service::SocketError::AddressNotAvailable,
+        SocketError::NotConnected => service::SocketError::NotConnected,
+        SocketError::AlreadyConnected => service::SocketError::AlreadyConnected,
+        SocketError::ConnectionRefused => service::SocketError::ConnectionRefused,
+        SocketError::ConnectionReset => service::SocketError::ConnectionReset,
+        SocketError::TimedOut => service::SocketError::TimedOut,
+        SocketError::NoRoute => service::SocketError::NoRoute,
+        SocketError::MessageTooLarge => service::SocketError::MessageTooLarge,
+    }
+}
+
+struct Watcher {
+    /// The process that asked
+    caller: u64,
+    socket: SocketHandle,
+    interest: Readiness,
+    notify_queue: u64,
+}
+
+struct NetworkServer {
+    stack: Stack,
+    watchers: Vec<Watcher>,
+    /// The process that created or accepted each socket. Other callers can't use it.
+    owners: BTreeMap<u32, u64>,
+    /// The process that sent the request that's being handled
+    caller: u64,
+}
+
+impl NetworkServer {
+    /// The socket, if it belongs to the caller
+    fn owned(&self, socket: u32) -> Result<SocketHandle, service::SocketError> {
+        match self.owners.get(&socket) {
+            Some(owner) if *owner == self.caller => Ok(SocketHandle(socket)),
+            _ => Err(service::SocketError::InvalidSocket),
+        }
+    }
+
+    /// Fails if the caller can't have another socket
+    fn check_socket_limit(&self) -> Result<(), service::SocketError> {
+        let sockets = self
+            .owners
+            .values()
+            .filter(|owner| **owner == self.caller)
+            .count();
+        if sockets >= MAX_SOCKETS_PER_CALLER {
+            return Err(service::SocketError::TooManySockets);
+        }
+        Ok(())
+    }
+
+    /// Tells the watchers whose sockets became ready. Sockets that are gone count as
+    /// ready, so that the next call returns the error.
+    fn notify_watchers(&mut self) {
+        let stack = &self.stack;
+        self.watchers.retain(|watcher| {
+            let readiness = stack.readiness(watcher.socket).map_or(
+                Readiness {
+                    readable: true,
+                    writable: true,
+                },
+                to_wire_readiness,
+            );
+            if !readiness.satisfies(watcher.interest) {
+                return true;
+            }
+            send_copied_buffer(
+                watcher.notify_queue,
+                &to_bytes(&(watcher.socket.0, readiness)),
+            );
+            false
+        });
+    }
+}
+
+impl Network for NetworkServer {
+    fn socket(&mut self, kind: service::SocketKind) -> Result<u32, service::SocketError> {
+        self.check_socket_limit()?;
+        let kind = match kind {
+            service::SocketKind::Udp => SocketKind::Udp,
+            service::SocketKind::Tcp => SocketKind::Tcp,
+            service::SocketKind::Icmp => SocketKind::Icmp,
+        };
+        let socket = self.stack.socket(kind).0;
+        self.owners.insert(socket, self.caller);
+        Ok(socket)
+    }
+
+    fn bind(
+        &mut self,
+        socket: u32,
+        address: service::SocketAddress,
+    ) -> Result<(), service::SocketError> {
+        self.stack
+            .bind(self.owned(socket)?, from_wire_address(address))
+            .map_err(to_wire_error)
+    }
+
+    fn connect(
+        &mut self,
+        socket: u32,
+        remote: service::SocketAddress,
+    ) -> Result<(), service::SocketError> {
+        self.stack
+            .connect(self.owned(socket)?, from_wire_address(remote))
+            .map_err(to_wire_error)
+    }
+
+    fn listen(&mut self, socket: u32, backlog: u32) -> Result<(), service::SocketError> {
+        self.stack
+            .listen(self.owned(socket)?, backlog as usize)
+            .map_err(to_wire_error)
+    }
+
+    fn accept(
+        &mut self,
+        socket: u32,
+    ) -> Result<(u32, service::SocketAddress), service::SocketError> {
+        let listener = self.owned(socket)?;
+        // Checked first, so that the connection stays in the backlog
+        self.check_socket_limit()?;
+        let (connection, remote) = self.stack.accept(listener).map_err(to_wire_error)?;
+        self.owners.insert(connection.0, self.caller);
+        Ok((connection.0, to_wire_address(remote)))
+    }
+
+    fn send(&mut self, socket: u32, data: Vec<u8>) -> Result<u32, service::SocketError> {
+        self.stack
+            .send(self.owned(socket)?, &data)
+            .map(|sent| sent as u32)
+            .map_err(to_wire_error)
+    }
+
+    fn send_to(
+        &mut self,
+        socket: u32,
+        data: Vec<u8>,
+        remote: service::SocketAddress,
+    ) -> Result<u32, service::SocketError> {
+        self.stack
+            .send_to(self.owned(socket)?, &data, from_wire_address(remote))
+            .map(|sent| sent as u32)
+            .map_err(to_wire_error)
+    }
+
+    fn recv(&mut self, socket: u32, max_len: u32) -> Result<Vec<u8>, service::SocketError> {
+        let max_len = (max_len as usize).min(MAX_DATA_SIZE);
+        self.stack
+            .recv(self.owned(socket)?, max_len)
+            .map_err(to_wire_error)
+    }
+
+    fn recv_from(
+        &mut self,
+        socket: u32,
+        max_len: u32,
+    ) -> Result<(Vec<u8>, service::SocketAddress), service::SocketError> {
+        let max_len = (max_len as usize).min(MAX_DATA_SIZE);
+        self.stack
+            .recv_from(self.owned(socket)?, max_len)
+            .map(|(data, source)| (data, to_wire_address(source)))
+            .map_err(to_wire_error)
+    }
+
+    fn close(&mut self, socket: u32) -> Result<(), service::SocketError> {
+        self.stack
+            .close(self.owned(socket)?)
+            .map_err(to_wire_error)?;
+        self.owners.remove(&socket);
+        Ok(())
+    }
+
+    fn local_address(
+        &mut self,
+        socket: u32,
+    ) -> Result<Option<service::SocketAddress>, service::SocketError> {
+        self.stack
+            .local_address(self.owned(socket)?)
+            .map(|local| local.map(to_wire_address))
+            .map_err(to_wire_error)
+    }
+
+    fn watch(
+        &mut self,
+        socket: u32,
+        interest: Readiness,
+        notify_queue: u64,
+    ) -> Result<Readiness, service::SocketError> {
+        let socket = self.owned(socket)?;
+        if queue_owner(notify_queue) != Some(self.caller) {
+            return Err(service::SocketError::InvalidQueue);
+        }
+        let readiness = to_wire_readiness(self.stack.readiness(socket).map_err(to_wire_error)?);
+        let existing = self
+            .watchers
+            .iter()
+            .position(|watcher| watcher.socket == socket && watcher.notify_queue == notify_queue);
+        if readiness.satisfies(interest) {
+            if let Some(index) = existing {
+                self.watchers.remove(index);
+            }
+            return Ok(readiness);
+        }
+        match existing {
+            Some(index) => self.watchers[index].interest = interest,
+            None => {
+                let watches = self
+                    .watchers
+                    .iter()
+                    .filter(|watcher| watcher.caller == self.caller)
+                    .count();
+                if watches >= MAX_WATCHES_PER_CALLER {
+                    return Err(service::SocketError::TooManyWatches);
+                }
+                self.watchers.push(Watcher {
+                    caller: self.caller,
+                    socket,
+                    interest,
+                    notify_queue,
+                });
+            }
+        }
+        Ok(readiness)
+    }
+
+    fn interfaces(&mut self) -> Vec<InterfaceInfo> {
+        self.stack
+            .interfaces()
+            .map(|(_, interface)| InterfaceInfo {
+                name: interface.name().into(),
+                mac_address: interface.mac_address().map(|mac| mac.0),
+                mtu: interface.mtu() as u32,
+                address: interface
+                    .address()
+                    .map(|cidr| (cidr.address.0, cidr.prefix_len)),
+                gateway: interface.gateway().map(|gateway| gateway.0),
+                dns_servers: interface
+                    .dns_servers()
+                    .iter()
+                    .map(|server| server.0)
+                    .collect(),
+            })
+            .collect()
+    }
+}
+
+/// Runs the TCP/IP stack on the interfaces in `NET_DEVICES` and a loopback interface,
+/// getting addresses with DHCP, and serves the `Network` service on
+/// `NETWORK_SERVICE_QUEUE`
+pub async fn run_stack() {
+    set_queue_owner(NETWORK_SERVICE_QUEUE, KERNEL_SENDER);
+    let mut stack = Stack::new();
+    // TCP's initial sequence numbers are only as hard to guess as this secret
+    let mut iss_secret = [0; 32];
+    if !random::fill(&mut iss_secret) {
+        log::warn!("net: waiting for the random number generator to be seeded");
+        while !random::fill(&mut iss_secret) {
+            timer::sleep_until(timer::millis() + RANDOM_RETRY_INTERVAL).await;
+        }
+    }
+    stack.set_iss_secret(iss_secret);
+    let mut devices = Vec::new();
+    for (i, device) in NET_DEVICES.read().iter().enumerate() {
+        let name = format!("eth{}", i);
+        let id = stack.add_ethernet_interface(
+            name.clone(),
+            MacAddress(device.mac_address()),
+            device.mtu(),
+        );
+        stack.start_dhcp(id, timer::millis());
+        crate::HartLocals::current()
+            .local_executor
+            .as_ref()
+            .unwrap()
+            .spawn(Box::new(Box::pin(receive_frames(name, id, device.clone()))));
+        devices.push((id, device.clone()));
+    }
+    let mut server = NetworkServer {
+        stack,
+        watchers: Vec::new(),
+        owners: BTreeMap::new(),
+        caller: 0,
+    };
+    let request_waker_registered = Arc::new(AtomicBool::new(false));
+
+    loop {
+        let next_event = NextEvent {
+            timer: server.stack.poll_at().map(timer::sleep_until),
+            request_waker_registered: &request_waker_registered,
+        };
+        let event = next_event.await;
+        let now = timer::millis();
+        match event {
+            Event::Frames => {
+                let frames = core::mem::take(&mut INBOX.lock().frames);
+                for (id, frame) in frames {
+                    server.stack.receive(id, &frame, now);
+                }
+            }
+            Event::Request(request, sender) => {
+                let version = <NetworkServer as Network>::VERSION;
+                let response = kernel_rpc::handle_request(
+                    &request,
+                    sender,
+                    version,
+                    |header, sender, reader, writer| {
+                        server.caller = sender;
+                        server.dispatch(header.method, reader, writer)
+                    },
+                );
+                if let Some((reply_queue, response)) = response {
+                    send_copied_buffer(reply_queue, &response);
+                }
+            }
+            Event::Timer => {}
+        }
+
+        server.stack.poll(now);
+        while let Some((id, frame)) = server.stack.transmit() {
+            let device = match devices.iter().find(|(device_id, _)| *device_id == id) {
+                Some((_, device)) => device,
+                None => continue,
+            };
+            let result = match frame.checksum {
+                Some((start, offset)) => {
+                    device.send_with_checksum(&frame.data, start, offset).await
+                }
+                None => device.send(&frame.data).await,
+            };
+            if let Err(error) = result {
+                let name = server.stack.interface(id).unwrap().name();
+                log::debug!("{}: can't send: {:?}", name, error);
+            }
+        }
+        server.notify_watchers();
+    }
+}
@@ -0,0 +1,68 @@
+//! The PCIe host bridge, and matching the functions behind it with PCI drivers.
+
+use alloc::{boxed::Box, vec::Vec};
+
+use kernel_chip_drivers::driver::{probe_pci, Device, Driver, ProbeError};
+use kernel_pci::{Ecam, Function, HostBridge};
+
+use crate::{drivers, phys_to_virt};
+
+pub static DRIVER: Driver = Driver {
+    name: "pci-host-ecam-generic",
+    compatible: &["pci-host-ecam-generic"],
+    probe,
+};
+
+/// Every function that was found, whether a driver took it or not
+pub static FUNCTIONS: spin::Once<Vec<Function<'static>>> = spin::Once::new();
+
+fn probe(device: &Device) -> Result<(), ProbeError> {
+    let ecam = device.region(0)?;
+    let host = HostBridge::from_node(&device.node).map_err(|error| {
+        log::warn!("pci: bad host bridge node {}: {:?}", device.path, error);
+        ProbeError::Failed
+    })?;
+    if FUNCTIONS.get().is_some() {
+        log::debug!(
+            "pci: ignoring host bridge at {:#x}, there's already one",
+            ecam.address
+        );
+        return Ok(());
+    }
+    log::debug!(
+        "pci: host bridge at {:#x}, buses {}..={}",
+        ecam.address,
+        host.bus_start,
+        host.bus_end
+    );
+
+    // The bootloader maps all of physical memory, which the ECAM region is part of
+    let config: &'static Ecam = Box::leak(Box::new(unsafe {
+        Ecam::new(phys_to_virt(ecam.address), host.bus_start, host.bus_end)
+    }));
+    let functions = FUNCTIONS.call_once(|| kernel_pci::enumerate(config, &host));
+    for function in functions {
+        log::debug!(
+            "pci: {} {:04x}:{:04x} class {:02x}{:02x}, interrupt {:?}",
+            function.address,
+            function.id.vendor,
+            function.id.device,
+            function.class,
+            function.subclass,
+            function.interrupt
+        );
+        for (index, bar) in function.bars.iter().enumerate() {
+            if let Some(bar) = bar {
+                log::debug!(
+                    "pci:     BAR {}: {:?} at {:#x}, {:#x} bytes",
+                    index,
+                    bar.kind,
+                    bar.address,
+                    bar.size
+                );
+            }
+        }
+    }
+    probe_pci(functions, drivers::PCI_DRIVERS);
+    Ok(())
+}
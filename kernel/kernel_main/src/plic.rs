@@ -0,0 +1,147 @@
+//! The platform-level interrupt controller, which routes external interrupts to harts.
+//! Each interrupt goes to one hart, the `irqaffinity` hart once it's running. Handlers and
+//! wakers are registered per interrupt rather than per hart, so they keep working when an
+//! interrupt moves to another hart.
+
+use alloc::{collections::BTreeMap, vec::Vec};
+use core::{
+    sync::atomic::{AtomicBool, Ordering},
+    task::Waker,
+};
+
+use kernel_chip_drivers::{
+    driver::{Device, Driver, ProbeError},
+    plic::{supervisor_contexts, Plic, PlicError, MAX_INTERRUPT},
+};
+use kernel_cpu::load_hartid;
+use kernel_lock::shared::{Mutex, RwLock};
+
+use crate::{cmdline, phys_to_virt};
+
+/// Called in the trap handler, with the interrupt number, every time the interrupt fires
+pub type Handler = fn(u32);
+
+static PLIC: spin::Once<Plic> = spin::Once::new();
+
+static HANDLERS: RwLock<BTreeMap<u32, Vec<Handler>>> = RwLock::new(BTreeMap::new());
+
+/// Woken the next time the interrupt fires, and then dropped
+static WAKERS: Mutex<BTreeMap<u32, Vec<Waker>>> = Mutex::new(BTreeMap::new());
+
+/// Set when the `irqaffinity` hart can take interrupts. Until then, interrupts go to the
+/// hart that registers them.
+static AFFINITY_HART_ONLINE: AtomicBool = AtomicBool::new(false);
+
+pub static DRIVER: Driver = Driver {
+    name: "plic",
+    compatible: &["sifive,plic-1.0.0", "riscv,plic0"],
+    probe,
+};
+
+/// Only the first PLIC is used
+fn probe(device: &Device) -> Result<(), ProbeError> {
+    let address = device.region(0)?.address;
+    let contexts = supervisor_contexts(&device.interrupts);
+    if contexts.is_empty() {
+        return Err(ProbeError::MissingResource);
+    }
+    for context in &contexts {
+        log::debug!("plic: hart {} is context {}", context.hart, context.number);
+    }
+    let interrupt_count = device
+        .node
+        .property("riscv,ndev")
+        .and_then(|ndev| ndev.as_u32())
+        .unwrap_or(MAX_INTERRUPT);
+    PLIC.call_once(|| Plic::new(phys_to_virt(address), contexts, interrupt_count));
+    Ok(())
+}
+
+fn plic() -> &'static Plic {
+    PLIC.get().expect("No PLIC in the device tree")
+}
+
+/// Lets the PLIC interrupt the current hart. Called once on every hart.
+pub fn init_hart() {
+    let plic = plic();
+    let hart = load_hartid();
+    if let Err(error) = plic.set_threshold(hart, 0) {
+        log::warn!(
+            "plic: hart {} won't get external interrupts: {:?}",
+            hart,
+            error
+        );
+        return;
+    }
+    if hart == cmdline::IRQAFFINITY.get() {
+        let handlers = HANDLERS.read();
+        AFFINITY_HART_ONLINE.store(true, Ordering::Release);
+        for &interrupt in handlers.keys() {
+            if let Err(error) = plic.set_affinity(interrupt, hart) {
+                log::warn!("plic: can't move interrupt {}: {:?}", interrupt, error);
+            }
+        }
+    }
+}
+
+/// Enables `interrupt` with `priority`, and calls `handler` every time it fires.
+/// Priority 0 never interrupts, and interrupts with higher priorities are handled first.
+pub fn register(interrupt: u32, priority: u32, handler: Handler) -> Result<(), PlicError> {
+    let plic = plic();
+    plic.set_priority(interrupt, priority)?;
+    // Held while routing, so that `init_hart` can't miss this interrupt
+    let mut handlers = HANDLERS.write();
+    if plic.affinity(interrupt).is_none() {
+        let hart = if AFFINITY_HART_ONLINE.load(Ordering::Acquire) {
+            cmdline::IRQAFFINITY.get()
+        } else {
+            load_hartid()
+        };
+        plic.set_affinity(interrupt, hart)?;
+    }
+    handlers.entry(interrupt).or_default().push(handler);
+    Ok(())
+}
+
+/// Wakes `waker` the next time `interrupt` fires, on any hart.
+/// This doesn't enable the interrupt; its driver does that with [`register`].
+/// Interrupts that the PLIC doesn't have never fire, so their wakers are dropped.
+pub fn wake_on_interrupt(interrupt: u32, waker: Waker) {
+    if let Err(error) = plic().check_interrupt(interrupt) {
+        log::warn!("plic: can't wait for interrupt {}: {:?}", interrupt, error);
+        return;
+    }
+    let mut wakers = WAKERS.lock();
+    let wakers = wakers.entry(interrupt).or_default();
+    if !wakers.iter().any(|existing| existing.will_wake(&waker)) {
+        wakers.push(waker);
+    }
+}
+
+/// Whether the PLIC has `interrupt`, so that it can fire
+pub fn has_interrupt(interrupt: u32) -> bool {
+    plic().check_interrupt(interrupt).is_ok()
+}
+
+/// Wakes what's waiting for `interrupt`, as if it had fired
+pub fn wake_waiters(interrupt: u32) {
+    let wakers = WAKERS.lock().remove(&interrupt);
+    for waker in wakers.into_iter().flatten() {
+        waker.wake();
+    }
+}
+
+/// Handles every interrupt that's pending for the current hart
+pub fn handle_external_interrupt() {
+    let plic = plic();
+    let hart = load_hartid();
+    while let Some(interrupt) = plic.claim(hart) {
+        if let Some(handlers) = HANDLERS.read().get(&interrupt) {
+            for handler in handlers {
+                handler(interrupt);
+            }
+        }
+        wake_waiters(interrupt);
+        plic.complete(hart, interrupt);
+    }
+}
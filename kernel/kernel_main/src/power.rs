@@ -0,0 +1,129 @@
+//! Powering off and rebooting the machine. The SBI's system reset extension is used if
+//! the SBI implementation has it, and otherwise the `syscon-poweroff` and `syscon-reboot`
+//! registers from the device tree.
+
+use kernel_chip_drivers::{
+    driver::{Device, Driver, ProbeError},
+    syscon::{SysconRegister, SIFIVE_TEST_FAIL},
+};
+use kernel_sbi::{ResetReason, ResetType};
+
+use crate::phys_to_virt;
+
+struct PowerOffRegister {
+    register: SysconRegister,
+    /// Whether the register is the SiFive test device's, which can report a status code
+    is_sifive_test: bool,
+}
+
+static POWER_OFF: spin::Once<PowerOffRegister> = spin::Once::new();
+static REBOOT: spin::Once<SysconRegister> = spin::Once::new();
+
+pub static POWER_OFF_DRIVER: Driver = Driver {
+    name: "syscon-poweroff",
+    compatible: &["syscon-poweroff"],
+    probe: probe_power_off,
+};
+
+pub static REBOOT_DRIVER: Driver = Driver {
+    name: "syscon-reboot",
+    compatible: &["syscon-reboot"],
+    probe: probe_reboot,
+};
+
+/// Reads the register that a `syscon-poweroff` or `syscon-reboot` node points at, and
+/// whether it belongs to the SiFive test device
+fn syscon_register(device: &Device) -> Result<(SysconRegister, bool), ProbeError> {
+    let node = &device.node;
+    let read_u32 = |name: &str| node.property(name).and_then(|property| property.as_u32());
+    let regmap = read_u32("regmap")
+        .and_then(|phandle| node.fdt().find_phandle(phandle))
+        .ok_or(ProbeError::MissingResource)?;
+    // The system controllers on the machines this runs on are children of the root, so
+    // their addresses don't need to be translated
+    let base = regmap
+        .reg()
+        .and_then(|mut reg| reg.next())
+        .ok_or(ProbeError::MissingResource)?
+        .address as usize;
+    let offset = read_u32("offset").ok_or(ProbeError::MissingResource)? as usize;
+    // Without a `value`, `mask` is the value and the whole register is written
+    let (mask, value) = match (read_u32("mask"), read_u32("value")) {
+        (Some(mask), Some(value)) => (mask, value),
+        (None, Some(value)) => (u32::MAX, value),
+        (Some(mask), None) => (u32::MAX, mask),
+        (None, None) => return Err(ProbeError::MissingResource),
+    };
+    let register = unsafe { SysconRegister::new(phys_to_virt(base + offset), mask, value) };
+    Ok((register, regmap.is_compatible("sifive,test0")))
+}
+
+fn probe_power_off(device: &Device) -> Result<(), ProbeError> {
+    let (register, is_sifive_test) = syscon_register(device)?;
+    POWER_OFF.call_once(|| PowerOffRegister {
+        register,
+        is_sifive_test,
+    });
+    Ok(())
+}
+
+fn probe_reboot(device: &Device) -> Result<(), ProbeError> {
+    let (register, _) = syscon_register(device)?;
+    REBOOT.call_once(|| register);
+    Ok(())
+}
+
+// Everything here can run from the panic handler, which may have interrupted something that
+// holds the console's locks, so it prints with `println_u!`, which doesn't take them.
+
+/// Waits for the machine to go away, or stops here if it can't
+fn halt() -> ! {
+    loop {
+        kernel_cpu::wfi();
+    }
+}
+
+/// Powers off the machine. A nonzero `status` says that something failed, which QEMU
+/// passes on as its exit status if it can.
+pub fn power_off(status: u16) -> ! {
+    // The SBI can only say that the shutdown was a failure, not with which status, so the
+    // test device gets the first try at reporting one
+    if let Some(power_off) = POWER_OFF.get() {
+        if power_off.is_sifive_test && status != 0 {
+            let fail = (status as u32) << 16 | SIFIVE_TEST_FAIL;
+            power_off.register.write(fail);
+        }
+    }
+    if kernel_sbi::has_system_reset() {
+        let reason = if status == 0 {
+            ResetReason::NoReason
+        } else {
+            ResetReason::SystemFailure
+        };
+        let error = kernel_sbi::system_reset(ResetType::Shutdown, reason);
+        println_u!("power: SBI shutdown failed: {:?}", error);
+    }
+    match POWER_OFF.get() {
+        Some(power_off) => power_off.register.trigger(),
+        None => println_u!("Can't power off, halting instead"),
+    }
+    halt()
+}
+
+/// Reboots the machine. A warm reboot may keep some of the machine's state, like memory.
+pub fn reboot(warm: bool) -> ! {
+    if kernel_sbi::has_system_reset() {
+        let reset_type = if warm {
+            ResetType::WarmReboot
+        } else {
+            ResetType::ColdReboot
+        };
+        let error = kernel_sbi::system_reset(reset_type, ResetReason::NoReason);
+        println_u!("power: SBI reboot failed: {:?}", error);
+    }
+    match REBOOT.get() {
+        Some(reboot) => reboot.trigger(),
+        None => println_u!("Can't reboot, halting instead"),
+    }
+    halt()
+}
@@ -0,0 +1,87 @@
+//! The kernel's random number generator. It's seeded from timing jitter at boot, and then
+//! reseeded from the first virtio-rng device, if there's one. Until one of them has given it
+//! `SEED_BITS` of entropy, it doesn't give out any random bytes.
+
+use alloc::vec;
+
+use kernel_chip_drivers::virtio::{device_id, rng::VirtioRng};
+use kernel_lock::shared::Mutex;
+use kernel_random::{jitter, Csprng, SEED_BITS};
+
+use crate::{plic, timer, virt_to_phys, virtio};
+
+/// How much is read from the device at a time
+const DEVICE_READ_SIZE: usize = SEED_BITS / 8;
+/// How often entropy from the device is mixed in, in milliseconds
+const RESEED_INTERVAL: u64 = 60_000;
+
+static RNG: Mutex<Csprng> = Mutex::new(Csprng::new());
+
+/// The device, until the entropy task takes it
+static DEVICE: spin::Mutex<Option<VirtioRng>> = spin::Mutex::new(None);
+
+/// Seeds the generator from timing jitter, and starts a driver for the first virtio-rng
+/// device
+pub fn init() {
+    let mut seed = vec![0; SEED_BITS / jitter::BITS_PER_BYTE];
+    let bits = jitter::collect(|| kernel_cpu::read_cycle() as u64, &mut seed);
+    {
+        let mut rng = RNG.lock();
+        // The time since boot isn't secret, but it's different every boot
+        rng.add_entropy(&kernel_cpu::read_time().to_le_bytes(), 0);
+        rng.add_entropy(&seed, bits);
+        if !rng.is_seeded() {
+            log::warn!(
+                "random: timing jitter only gave {} of {} bits, waiting for virtio-rng",
+                bits,
+                SEED_BITS
+            );
+        }
+    }
+
+    let device = match virtio::take_device(device_id::ENTROPY) {
+        Some(device) => device,
+        None => return,
+    };
+    match VirtioRng::new(
+        device.transport,
+        device.interrupt,
+        virt_to_phys,
+        plic::wake_on_interrupt,
+    ) {
+        Ok(rng) => {
+            virtio::activate_device(rng.device().clone());
+            *DEVICE.lock() = Some(rng);
+        }
+        Err(error) => println!("virtio-rng: failed to initialize: {:?}", error),
+    }
+}
+
+/// Mixes entropy from the device into the generator every `RESEED_INTERVAL`
+pub async fn run_entropy() {
+    let device = match DEVICE.lock().take() {
+        Some(device) => device,
+        None => return,
+    };
+    loop {
+        match device.read(DEVICE_READ_SIZE).await {
+            Ok(bytes) => RNG.lock().add_entropy(&bytes, bytes.len() * 8),
+            Err(error) => {
+                println!("virtio-rng: read failed: {:?}", error);
+                return;
+            }
+        }
+        timer::sleep_until(timer::millis() + RESEED_INTERVAL).await;
+    }
+}
+
+/// Fills `buffer` with random bytes. Returns false without touching it if the generator
+/// isn't seeded yet.
+pub fn fill(buffer: &mut [u8]) -> bool {
+    let mut rng = RNG.lock();
+    if !rng.is_seeded() {
+        return false;
+    }
+    rng.fill(buffer);
+    true
+}
@@ -0,0 +1,136 @@
+//! The real-time clock, which gives the wall-clock time, and alarms at wall-clock times.
+//! The wall-clock time is worked out from the `time` CSR and the RTC's time at boot, so
+//! reading it doesn't touch the RTC.
+
+use alloc::collections::BTreeMap;
+use core::{
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicUsize, Ordering},
+    task::{Context, Poll, Waker},
+};
+
+use kernel_chip_drivers::{
+    driver::{Device, Driver, ProbeError},
+    goldfish_rtc::GoldfishRtc,
+};
+use kernel_lock::shared::Mutex;
+
+use crate::{phys_to_virt, plic, timer};
+
+static RTC: spin::Once<GoldfishRtc> = spin::Once::new();
+
+/// The wall-clock time when `timer::monotonic_nanos()` was 0, in nanoseconds since the Unix
+/// epoch. Without an RTC, the wall-clock time starts at the epoch.
+static BOOT_TIME: spin::Once<u64> = spin::Once::new();
+
+/// Waiting alarms by wall-clock time, and by an ID that tells apart the ones with the same
+/// time
+static ALARMS: Mutex<BTreeMap<(u64, usize), Waker>> = Mutex::new(BTreeMap::new());
+static NEXT_ALARM_ID: AtomicUsize = AtomicUsize::new(0);
+
+pub static DRIVER: Driver = Driver {
+    name: "goldfish-rtc",
+    compatible: &["google,goldfish-rtc"],
+    probe,
+};
+
+/// Only the first RTC is used
+fn probe(device: &Device) -> Result<(), ProbeError> {
+    let address = device.region(0)?.address;
+    let interrupt = device.interrupt(0)?;
+    if RTC.get().is_some() {
+        println!("Ignoring RTC at {:#x}, there's already one", address);
+        return Ok(());
+    }
+    let rtc = RTC.call_once(|| unsafe { GoldfishRtc::new(phys_to_virt(address)) });
+    rtc.clear_alarm();
+    let now = rtc.read_nanos();
+    BOOT_TIME.call_once(|| now.saturating_sub(timer::monotonic_nanos()));
+    println!(
+        "RTC at {:#x}, interrupt {}, {} seconds since the epoch",
+        address,
+        interrupt,
+        now / timer::NANOS_PER_SECOND
+    );
+    plic::register(interrupt, 1, handle_external_interrupt).map_err(|error| {
+        log::warn!(
+            "goldfish-rtc: can't enable interrupt {}: {:?}",
+            interrupt,
+            error
+        );
+        ProbeError::Failed
+    })
+}
+
+/// Nanoseconds since the Unix epoch
+pub fn realtime_nanos() -> u64 {
+    BOOT_TIME.get().copied().unwrap_or(0) + timer::monotonic_nanos()
+}
+
+/// Sets the RTC's alarm for the earliest waiting alarm, if there is one
+fn set_next_alarm(rtc: &GoldfishRtc, alarms: &BTreeMap<(u64, usize), Waker>) {
+    match alarms.keys().next() {
+        Some((at, _)) => rtc.set_alarm(*at),
+        None => rtc.clear_alarm(),
+    }
+}
+
+/// Registered with the PLIC for the RTC's interrupt
+fn handle_external_interrupt(_interrupt: u32) {
+    let rtc = match RTC.get() {
+        Some(rtc) => rtc,
+        None => return,
+    };
+    rtc.handle_interrupt();
+    let now = rtc.read_nanos();
+    let expired = {
+        let mut alarms = ALARMS.lock();
+        let later = alarms.split_off(&(now + 1, 0));
+        let expired = core::mem::replace(&mut *alarms, later);
+        set_next_alarm(rtc, &alarms);
+        expired
+    };
+    for waker in expired.into_values() {
+        waker.wake();
+    }
+}
+
+/// Completes once the RTC reaches `at`, in nanoseconds since the Unix epoch.
+/// `None` if there's no RTC.
+pub fn alarm_at(at: u64) -> Option<Alarm> {
+    RTC.get()?;
+    Some(Alarm {
+        at,
+        id: NEXT_ALARM_ID.fetch_add(1, Ordering::Relaxed),
+    })
+}
+
+pub struct Alarm {
+    at: u64,
+    id: usize,
+}
+
+impl Future for Alarm {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let rtc = RTC.get().unwrap();
+        // Added before checking, so that an interrupt that comes in between isn't missed
+        let mut alarms = ALARMS.lock();
+        alarms.insert((self.at, self.id), cx.waker().clone());
+        if rtc.read_nanos() >= self.at {
+            alarms.remove(&(self.at, self.id));
+            return Poll::Ready(());
+        }
+        set_next_alarm(rtc, &alarms);
+        Poll::Pending
+    }
+}
+
+impl Drop for Alarm {
+    fn drop(&mut self) {
+        // The RTC's alarm is left set, and goes off for nothing if this was the earliest
+        ALARMS.lock().remove(&(self.at, self.id));
+    }
+}
@@ -1,27 +1,36 @@
 use alloc::boxed::Box;
+use core::sync::atomic::{AtomicUsize, Ordering};
 
-pub struct Uart {
-    address: *mut u8,
+use kernel_util::debug::Uart;
+
+/// Virtual address of the UART that's printed to. Until the device tree is read,
+/// it's where QEMU's `virt` machine has it.
+pub static PRINTER_ADDRESS: AtomicUsize = AtomicUsize::new(0xffff_ffc0_1000_0000);
+
+pub fn get_uart() -> Uart {
+    Uart::from_address(PRINTER_ADDRESS.load(Ordering::Relaxed) as _)
+}
+
+/// Prints to the UART, and to the text console on the display if there's one
+pub struct Printer {
+    uart: Uart,
 }
 
-impl core::fmt::Write for Uart {
+impl core::fmt::Write for Printer {
     fn write_str(&mut self, s: &str) -> core::fmt::Result {
-        for i in s.bytes() {
-            unsafe { self.address.write(i) }
+        // Once there's a driver, go through it so that bytes it has queued come out first
+        match crate::uart::CONSOLE_UART.get() {
+            Some(console) => console.uart.0.lock().write_blocking(s.as_bytes()),
+            None => core::fmt::Write::write_str(&mut self.uart, s)?,
         }
+        crate::gpu::write_console(s);
         Ok(())
     }
 }
 
-pub fn get_uart() -> Uart {
-    Uart {
-        address: 0xffff_ffc0_1000_0000usize as _,
-    }
-}
-
 #[no_mangle]
 fn get_printer() -> Box<dyn core::fmt::Write> {
-    Box::new(get_uart())
+    Box::new(Printer { uart: get_uart() })
 }
 #[no_mangle]
 fn get_printer_lockfree() -> Box<dyn core::fmt::Write> {
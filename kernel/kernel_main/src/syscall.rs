@@ -1,19 +1,27 @@
+use core::mem::size_of;
 use core::task::Waker;
 
 use alloc::collections::BTreeMap;
 use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
 use kernel_lock::spin::RwLock;
 use kernel_lock::spin::Mutex;
-use kernel_paging::PartialMapping;
-use kernel_process::{Process, ProcessContainer, ProcessState};
+use kernel_paging::{EntryBits, PartialMapping};
+use kernel_process::{capabilities, Process, ProcessContainer, ProcessState};
+use kernel_process::shared_region::SharedRegion;
 use kernel_syscall::SyscallNumbers;
 use kernel_syscall::get_syscall_args;
+use kernel_syscall::KERNEL_SENDER;
+use kernel_syscall::MAX_WAIT_ANY_FUTURES;
+use kernel_services::FIRST_DYNAMIC_QUEUE;
 use kernel_util::boxed_slice_with_alignment;
 use kernel_util::maybe_waker::MaybeWaker;
 use kernel_util::maybe_waker::wake_all_that_are_ready;
 
-use crate::HartLocals;
 use crate::phys_to_virt;
+use crate::timer::NANOS_PER_SECOND;
 
 #[derive(Clone, Debug)]
 pub enum InflightBufferMode {
@@ -34,6 +42,8 @@ pub struct InflightBuffer {
     mode: InflightBufferMode,
     contents: InflightBufferContents,
     claim: Waker,
+    /// The ID of the process that sent the buffer, or `KERNEL_SENDER`
+    sender: u64,
 }
 
 #[derive(Default, Debug)]
@@ -81,6 +91,206 @@ impl BufferQueue {
 
 pub static INFLIGHT_BUFFERS: RwLock<BTreeMap<usize, Mutex<BufferQueue>>> = RwLock::new(BTreeMap::new());
 
+/// The process that allocated each queue with `AllocateQueue`, or that the kernel gave one of
+/// the queues below `FIRST_DYNAMIC_QUEUE` to. Only the owner can take buffers and shared
+/// regions out of a queue.
+pub static QUEUE_OWNERS: RwLock<BTreeMap<u64, u64>> = RwLock::new(BTreeMap::new());
+
+/// The most queues a process can have allocated at once
+const MAX_QUEUES_PER_PROCESS: usize = 64;
+
+/// A shared region waiting in a queue. It counts towards its sender's
+/// `MAX_SHARED_REGION_MEMORY` until it's received.
+#[derive(Debug)]
+pub struct SentSharedRegion {
+    sender: u64,
+    region: Arc<SharedRegion>,
+}
+
+/// Shared regions that were sent to a queue with `SendSharedRegion`, by queue, until a process
+/// takes them with `ReceiveSharedRegion`. Handles belong to one process, so this is the only
+/// way for another process to get at a region.
+pub static SENT_SHARED_REGIONS: Mutex<BTreeMap<u64, VecDeque<SentSharedRegion>>> = Mutex::new(BTreeMap::new());
+
+/// The largest shared region that can be created
+const MAX_SHARED_REGION_SIZE: usize = 16 << 20;
+/// The most memory a process can keep alive in shared regions, by holding handles to them,
+/// mapping them or sending them
+const MAX_SHARED_REGION_MEMORY: usize = 64 << 20;
+/// The most shared regions that can wait in one queue
+const MAX_SENT_SHARED_REGIONS: usize = 16;
+/// Shared regions are mapped below the kernel's mapping of physical memory
+const SHARED_REGION_ADDRESS_LIMIT: usize = 1 << 38;
+
+/// The most bytes `GetRandom` fills at a time
+const MAX_RANDOM_SIZE: usize = 4096;
+
+/// Makes `owner` the only one that can receive from `queue`. For the well-known queues, which
+/// the kernel gives to whoever serves them when it starts them.
+pub fn set_queue_owner(queue: u64, owner: u64) {
+    QUEUE_OWNERS.write().insert(queue, owner);
+}
+
+/// The process that owns `queue`, if any
+pub fn queue_owner(queue: u64) -> Option<u64> {
+    QUEUE_OWNERS.read().get(&queue).copied()
+}
+
+/// Whether process `id` may take buffers and shared regions out of `queue`. Queues from
+/// `FIRST_DYNAMIC_QUEUE` on have to be allocated first.
+fn may_receive(id: u64, queue: u64) -> bool {
+    match QUEUE_OWNERS.read().get(&queue) {
+        Some(owner) => *owner == id,
+        None => queue < FIRST_DYNAMIC_QUEUE,
+    }
+}
+
+/// Frees the queues of process `id`, once it has exited
+pub fn free_queues_of(id: u64) {
+    QUEUE_OWNERS.write().retain(|_, owner| *owner != id);
+}
+
+/// How much memory `process` would keep alive in shared regions with `region` too: the
+/// regions it has handles to or has mapped, and the ones it sent that nobody has received
+/// yet. `sent` is `SENT_SHARED_REGIONS`, which the caller has locked. Each region is counted
+/// once, however many of those it is.
+fn shared_region_memory_with(
+    process: &Process,
+    sent: &BTreeMap<u64, VecDeque<SentSharedRegion>>,
+    region: &Arc<SharedRegion>,
+) -> usize {
+    let in_flight = sent
+        .values()
+        .flatten()
+        .filter(|sent| sent.sender == process.id)
+        .map(|sent| &sent.region);
+    let regions: BTreeMap<usize, usize> = process
+        .shared_region_handles
+        .values()
+        .chain(process.shared_region_mappings.values())
+        .chain(in_flight)
+        .chain(core::iter::once(region))
+        .map(|region| (region.kernel_address(), region.size()))
+        .collect();
+    regions.values().sum()
+}
+
+/// Gives `process` a handle to `region`, unless that takes it over `MAX_SHARED_REGION_MEMORY`.
+/// Returns the handle, or 0 if it didn't get one.
+fn add_shared_region_handle(
+    process: &mut Process,
+    sent: &BTreeMap<u64, VecDeque<SentSharedRegion>>,
+    region: Arc<SharedRegion>,
+) -> usize {
+    if shared_region_memory_with(process, sent, &region) > MAX_SHARED_REGION_MEMORY {
+        return 0;
+    }
+    let handle = process.shared_region_handles.keys().max().map(|h| h + 1).unwrap_or(1);
+    process.shared_region_handles.insert(handle, region);
+    handle
+}
+
+/// Whether `size` bytes at `virtual_addr` are free for mapping a shared region: page-aligned,
+/// below `SHARED_REGION_ADDRESS_LIMIT` and not mapped yet, which also keeps shared regions
+/// from overlapping each other
+fn can_map_shared_region(process: &Process, virtual_addr: usize, size: usize) -> bool {
+    let end = match virtual_addr.checked_add(size) {
+        Some(end) => end,
+        None => return false,
+    };
+    if virtual_addr == 0 || virtual_addr & 4095 != 0 || end > SHARED_REGION_ADDRESS_LIMIT {
+        return false;
+    }
+    let process_page_table = unsafe { crate::paging_from_satp(process.trap_frame.satp) };
+    (virtual_addr..end)
+        .step_by(4096)
+        .all(|page| unsafe { process_page_table.query(page) }.is_err())
+}
+
+/// Reads an array of `count` future IDs at `ids_ptr` in the process's address space.
+/// `None` if there are none, or more than `MAX_WAIT_ANY_FUTURES` of them.
+fn read_future_ids(process: &mut Process, ids_ptr: usize, count: usize) -> Option<Vec<u64>> {
+    if count == 0 || count > MAX_WAIT_ANY_FUTURES {
+        return None;
+    }
+    let size = count.checked_mul(size_of::<u64>())?;
+    let process_page_table = unsafe { crate::paging_from_satp(process.trap_frame.satp) };
+    let partial_mapping = unsafe {
+        process_page_table.copy_partial_mapping(ids_ptr, size)
+    };
+    let bytes = unsafe {
+        partial_mapping.read_iter(phys_to_virt).fold(Vec::new(), |mut accum, new| {
+            accum.extend_from_slice(new);
+            accum
+        })
+    };
+    Some(bytes
+        .chunks_exact(size_of::<u64>())
+        .map(|chunk| u64::from_ne_bytes(chunk.try_into().unwrap()))
+        .collect())
+}
+
+/// Copies `data` into `queue` from kernel code, the way `CopyBufferOut` does for processes.
+/// Nobody is told when it's claimed.
+pub fn send_copied_buffer(queue: u64, data: &[u8]) {
+    let buffer = InflightBuffer {
+        contents: InflightBufferContents::Copied(data.into()),
+        mode: InflightBufferMode::Copied,
+        claim: MaybeWaker::noop().into(),
+        sender: KERNEL_SENDER,
+    };
+    let mut lock = INFLIGHT_BUFFERS.write();
+    if !lock.contains_key(&(queue as usize)) {
+        lock.insert(queue as usize, Mutex::new(Default::default()));
+    }
+    lock.get(&(queue as usize))
+        .unwrap()
+        .lock()
+        .send_buffer(buffer);
+}
+
+/// Takes the next buffer out of `queue` from kernel code, and returns a copy of its contents
+/// and who sent it. If the queue is empty and there's a `waker`, it's woken once when a
+/// buffer arrives.
+pub fn claim_copied_buffer(queue: u64, waker: Option<&Waker>) -> Option<(Vec<u8>, u64)> {
+    let mut lock = INFLIGHT_BUFFERS.write();
+    if !lock.contains_key(&(queue as usize)) {
+        lock.insert(queue as usize, Mutex::new(Default::default()));
+    }
+    let mut queue = lock.get(&(queue as usize)).unwrap().lock();
+    let wake_once: Arc<dyn Fn() -> bool> = match waker {
+        Some(waker) => {
+            let waker = waker.clone();
+            Arc::new(move || {
+                waker.wake_by_ref();
+                // Don't stay in the queue's list after waking
+                false
+            })
+        }
+        // `try_take_buffer` would register a waker
+        None if queue.buffer_amount() == 0 => return None,
+        // Never registered, since there's a buffer to take
+        None => Arc::new(|| false),
+    };
+    queue.try_take_buffer(
+        |_, buffer| {
+            let data = match &buffer.contents {
+                InflightBufferContents::Copied(data) => data.to_vec(),
+                InflightBufferContents::Mapped(partial_mapping) => unsafe {
+                    partial_mapping
+                        .read_iter(phys_to_virt)
+                        .fold(Vec::new(), |mut accum, new| {
+                            accum.extend_from_slice(new);
+                            accum
+                        })
+                },
+            };
+            Ok((data, buffer.sender))
+        },
+        wake_once.into(),
+    )
+}
+
 pub fn handle_syscall(process: &mut Process) {
     process.trap_frame.pc += 2;
     let args = kernel_syscall::get_syscall_args(process);
@@ -102,30 +312,82 @@ pub fn handle_syscall(process: &mut Process) {
         SyscallNumbers::PollFuture => {
             // Poll a future
             let future_id = args[0] as u64;
-            match process.wake_on_paused.lock().is_woken(future_id) {
+            let status = match process.wake_on_paused.lock().is_woken(future_id) {
                 None => 0,
                 Some(false) => 1,
                 Some(true) => 2,
             };
+            get_syscall_args(process)[0] = status;
+        }
+        SyscallNumbers::WaitAny => {
+            // (ids_ptr, count) -> (index, future_id)
+            // If none of the futures is complete, the process is put to sleep until one of them
+            // is, and `count` is returned as the index. The caller should then try again.
+            // Only futures that belong to the process can wake it up. Waiting on no futures,
+            // which would sleep forever, or on more than `MAX_WAIT_ANY_FUTURES` returns
+            // `usize::MAX` as the index.
+            let ids_ptr = args[0];
+            let count = args[1];
+
+            drop(args);
+            let ids = match read_future_ids(process, ids_ptr, count) {
+                Some(ids) => ids,
+                None => {
+                    let args = get_syscall_args(process);
+                    args[0] = usize::MAX;
+                    args[1] = 0;
+                    return;
+                }
+            };
+
+            let mut waker_struct = process.wake_on_paused.lock();
+            let ready = waker_struct.first_woken(&ids);
+            if ready.is_none() {
+                for id in ids.iter() {
+                    waker_struct.enable_source(*id);
+                }
+            }
+            drop(waker_struct);
+
+            let args = get_syscall_args(process);
+            if let Some(index) = ready {
+                args[0] = index;
+                args[1] = ids[index] as usize;
+            } else {
+                args[0] = count;
+                args[1] = 0;
+                process.sleep();
+            }
         }
         SyscallNumbers::WaitForInterrupt => {
             if args[1] != 0 {
                 // Timer interrupt
                 let _for_time = args[1];
             } else {
-                let external_interrupt_number = args[0];
-                
-                let (waker, future_id) = process.waker();
+                // (interrupt, 0, future_id) -> (future_id)
+                // With a nonzero `future_id`, that future is reused instead of making a new
+                // one. The returned ID is 0 if the future doesn't belong to the process, or
+                // if the PLIC doesn't have the interrupt.
+                let external_interrupt_number = u32::try_from(args[0]).ok().filter(|interrupt| crate::plic::has_interrupt(*interrupt));
+                let reused_future_id = args[2] as u64;
+                drop(args);
 
-                let mut interrupt_wakers = HartLocals::current().interrupt_notifiers.borrow_mut();
-                if let Some(vec) = interrupt_wakers.get_mut(&external_interrupt_number) {
-                    vec.push(waker.into());
+                let (waker, future_id) = if external_interrupt_number.is_none() {
+                    (None, 0)
+                } else if reused_future_id == 0 {
+                    let (waker, future_id) = process.waker();
+                    (Some(waker), future_id)
                 } else {
-                    interrupt_wakers.insert(external_interrupt_number, alloc::vec![waker.into()]);
+                    let waker = process.rearmed_waker(reused_future_id);
+                    let future_id = if waker.is_some() { reused_future_id } else { 0 };
+                    (waker, future_id)
+                };
+                if let (Some(interrupt), Some(waker)) = (external_interrupt_number, waker) {
+                    crate::plic::wake_on_interrupt(interrupt, waker.into());
                 }
-                
+
                 let args = kernel_syscall::get_syscall_args(process);
-                
+
                 args[0] = future_id as usize;
             }
         }
@@ -166,6 +428,7 @@ pub fn handle_syscall(process: &mut Process) {
                     contents: InflightBufferContents::Copied(collected_data),
                     mode: InflightBufferMode::Copied,
                     claim: waker.into(),
+                    sender: process.id,
                 }
             } else {
                 assert!(virtual_start_addr & 4095 == 0);
@@ -188,6 +451,7 @@ pub fn handle_syscall(process: &mut Process) {
                     contents: InflightBufferContents::Mapped(partial_mapping),
                     mode,
                     claim: waker.into(),
+                    sender: process.id,
                 }
             };
                 
@@ -209,6 +473,8 @@ pub fn handle_syscall(process: &mut Process) {
         SyscallNumbers::MapBufferIn 
         | SyscallNumbers::CopyBufferIn => {
             // (virtual_addr, max_size, queue_id) -> (status, real_size, remaining_buffers, future_id (or 0), source_id)
+            // `source_id` is the ID of the process that sent the buffer, or `KERNEL_SENDER`.
+            // If the queue belongs to another process, the status and the future ID are 0.
             
             // 0x20 is move the page table mapping into the virtual area specified. This means 
             // that the area may not actually be mapped!
@@ -219,7 +485,11 @@ pub fn handle_syscall(process: &mut Process) {
             
             drop(args);
             
-            let mut closure = |virtual_addr, max_size, queue_id| -> (usize, usize, usize) {
+            let allowed = may_receive(process.id, source_buffer_queue as u64);
+            let mut closure = |virtual_addr, max_size, queue_id| -> (usize, usize, usize, u64) {
+                if !allowed {
+                    return (0, 0, 0, 0);
+                }
                 let mut process_page_table = unsafe { crate::paging_from_satp(process.trap_frame.satp) };
                 let mut lock = INFLIGHT_BUFFERS.read();
                 if let Some(queue_mutex) = lock.get(&queue_id) {
@@ -241,7 +511,7 @@ pub fn handle_syscall(process: &mut Process) {
                                     InflightBufferMode::Borrow => 2,
                                     InflightBufferMode::BorrowMut => 3,
                                     InflightBufferMode::Copied => 4,
-                                }, size, queue.buffer_amount()))
+                                }, size, queue.buffer_amount(), buffer.sender))
                             }
                             InflightBufferContents::Copied(buffer_data) => {
                                 let size = buffer_data.len();
@@ -255,18 +525,18 @@ pub fn handle_syscall(process: &mut Process) {
                                     return Err(buffer);
                                 }
                                 
-                                Ok((4, size, queue.buffer_amount()))
+                                Ok((4, size, queue.buffer_amount(), buffer.sender))
                             }
                         }
-                    }, process.waker().0.into()).unwrap_or((0, 0, 0))
+                    }, process.waker().0.into()).unwrap_or((0, 0, 0, 0))
                 } else {
-                    (0, 0, 0)
+                    (0, 0, 0, 0)
                 }   
             };
-            let (status, real_size, remaining_buffers) = closure(map_to_virtual_address, maximum_size, source_buffer_queue);
+            let (status, real_size, remaining_buffers, sender) = closure(map_to_virtual_address, maximum_size, source_buffer_queue);
             
             let mut set_future_id = 0;
-            if status == 0 {
+            if status == 0 && allowed {
                 let mut lock = INFLIGHT_BUFFERS.write();
                 if let Some(queue_mutex) = lock.get(&source_buffer_queue) {
                     let mut queue = queue_mutex.lock();
@@ -280,8 +550,219 @@ pub fn handle_syscall(process: &mut Process) {
             args[1] = real_size;
             args[2] = remaining_buffers;
             args[3] = set_future_id;
+            args[4] = sender as usize;
             
         },
+        SyscallNumbers::AllocateQueue => {
+            // () -> (queue)
+            // Makes a queue that only this process can receive from. The queue is 0 if the
+            // process already has `MAX_QUEUES_PER_PROCESS` queues.
+            drop(args);
+
+            let mut owners = QUEUE_OWNERS.write();
+            let queue = if owners.values().filter(|owner| **owner == process.id).count() >= MAX_QUEUES_PER_PROCESS {
+                0
+            } else {
+                // The lowest queue that nobody has
+                let mut queue = FIRST_DYNAMIC_QUEUE;
+                for owned in owners.range(FIRST_DYNAMIC_QUEUE..).map(|(queue, _)| *queue) {
+                    if owned != queue {
+                        break;
+                    }
+                    queue += 1;
+                }
+                owners.insert(queue, process.id);
+                queue
+            };
+            drop(owners);
+            get_syscall_args(process)[0] = queue as usize;
+        }
+        SyscallNumbers::FreeQueue => {
+            // (queue) -> (status)
+            // Lets the queue be allocated again. The status is 1 if it was freed and 0 if it
+            // isn't this process's. Buffers and shared regions that are in it stay there.
+            let queue = args[0] as u64;
+            drop(args);
+
+            let mut owners = QUEUE_OWNERS.write();
+            let status = if queue >= FIRST_DYNAMIC_QUEUE && owners.get(&queue) == Some(&process.id) {
+                owners.remove(&queue);
+                1
+            } else {
+                0
+            };
+            drop(owners);
+            get_syscall_args(process)[0] = status;
+        }
+        SyscallNumbers::CreateSharedRegion => {
+            // (size) -> (handle)
+            // The handle is 0 if `size` is 0 or over `MAX_SHARED_REGION_SIZE`, or if the
+            // process would keep more than `MAX_SHARED_REGION_MEMORY` alive.
+            let size = args[0];
+            drop(args);
+
+            let handle = if size == 0 || size > MAX_SHARED_REGION_SIZE {
+                0
+            } else {
+                let sent = SENT_SHARED_REGIONS.lock();
+                add_shared_region_handle(process, &sent, SharedRegion::new(size))
+            };
+            get_syscall_args(process)[0] = handle;
+        }
+        SyscallNumbers::MapSharedRegion => {
+            // (handle, virtual_addr, permissions) -> (status)
+            // `permissions` is a combination of the R, W and X page table bits, and has to
+            // include R, since the region is always readable.
+            // The status is 1 if the region was mapped and 0 if the process has no such handle,
+            // the arguments are invalid or something is already mapped in the way.
+            let handle = args[0];
+            let virtual_addr = args[1];
+            let permissions = args[2];
+            drop(args);
+
+            let region = process.shared_region_handles.get(&handle).cloned();
+            let permission_mask = EntryBits::READ | EntryBits::WRITE | EntryBits::EXECUTE;
+            let valid_permissions = permissions & !permission_mask == 0
+                && permissions & EntryBits::READ != 0;
+
+            let status = match region {
+                Some(region) if valid_permissions && can_map_shared_region(process, virtual_addr, region.size()) => {
+                    // The region is allocated in the kernel's heap, which is also mapped
+                    // in the process's page table, so we can copy the mapping from there.
+                    let mut process_page_table = unsafe { crate::paging_from_satp(process.trap_frame.satp) };
+                    let partial_mapping = unsafe {
+                        process_page_table.copy_partial_mapping(region.kernel_address(), region.size())
+                    };
+                    process_page_table.paste_partial_mapping(virtual_addr, &partial_mapping, permissions | EntryBits::VALID);
+                    process.shared_region_mappings.insert(virtual_addr, region);
+                    1
+                }
+                _ => 0,
+            };
+            get_syscall_args(process)[0] = status;
+        }
+        SyscallNumbers::CloseSharedRegion => {
+            // (handle) -> ()
+            // Existing mappings stay valid. The region is freed once nobody has
+            // a handle or a mapping to it.
+            let handle = args[0];
+            drop(args);
+            process.shared_region_handles.remove(&handle);
+        }
+        SyscallNumbers::SendSharedRegion => {
+            // (handle, queue) -> (status)
+            // Lets whoever calls `ReceiveSharedRegion` on `queue` next have the region too.
+            // Until then, it counts towards this process's `MAX_SHARED_REGION_MEMORY`, even
+            // if the handle is closed. The status is 1 if it was sent and 0 if the process
+            // has no such handle, or `MAX_SENT_SHARED_REGIONS` are waiting in the queue.
+            let handle = args[0];
+            let queue = args[1] as u64;
+            drop(args);
+
+            let mut sent = SENT_SHARED_REGIONS.lock();
+            let waiting = sent.get(&queue).map_or(0, |regions| regions.len());
+            let status = match process.shared_region_handles.get(&handle) {
+                Some(region) if waiting < MAX_SENT_SHARED_REGIONS => {
+                    sent.entry(queue).or_default().push_back(SentSharedRegion {
+                        sender: process.id,
+                        region: region.clone(),
+                    });
+                    1
+                }
+                _ => 0,
+            };
+            drop(sent);
+            get_syscall_args(process)[0] = status;
+        }
+        SyscallNumbers::ReceiveSharedRegion => {
+            // (queue) -> (handle)
+            // Takes the region that was sent to `queue` first, and returns a new handle to it.
+            // The handle is 0 if nothing was sent, if the queue belongs to another process or
+            // if the process would keep more than `MAX_SHARED_REGION_MEMORY` alive. The region
+            // stays in the queue then.
+            let queue = args[0] as u64;
+            drop(args);
+
+            let mut sent = SENT_SHARED_REGIONS.lock();
+            let region = if may_receive(process.id, queue) {
+                sent.get(&queue).and_then(|regions| regions.front()).map(|sent| sent.region.clone())
+            } else {
+                None
+            };
+            let handle = match region {
+                Some(region) => {
+                    let handle = add_shared_region_handle(process, &sent, region);
+                    if handle != 0 {
+                        sent.get_mut(&queue).unwrap().pop_front();
+                    }
+                    handle
+                }
+                None => 0,
+            };
+            if sent.get(&queue).is_some_and(|regions| regions.is_empty()) {
+                sent.remove(&queue);
+            }
+            drop(sent);
+            get_syscall_args(process)[0] = handle;
+        }
+        SyscallNumbers::GetRandom => {
+            // (buffer_ptr, len) -> (filled)
+            // Fills at most `MAX_RANDOM_SIZE` bytes, and returns how many. Fills nothing
+            // until the generator is seeded.
+            let buffer_ptr = args[0];
+            let mut len = args[1].min(MAX_RANDOM_SIZE);
+            drop(args);
+
+            let mut random = vec![0; len];
+            if !crate::random::fill(&mut random) {
+                len = 0;
+            }
+            if len != 0 {
+                let process_page_table = unsafe { crate::paging_from_satp(process.trap_frame.satp) };
+                unsafe {
+                    let mut partial_mapping = process_page_table.copy_partial_mapping(buffer_ptr, len);
+                    partial_mapping.trim_size_to(len);
+                    partial_mapping.overwrite_contents_with(random.into_iter(), phys_to_virt);
+                }
+            }
+            get_syscall_args(process)[0] = len;
+        }
+        SyscallNumbers::ClockGetTime => {
+            // (clock) -> (seconds, nanoseconds)
+            // Clock 0 is the wall-clock time, since the Unix epoch, and 1 is the time since
+            // boot. Other clocks read as 0.
+            let nanos = match args[0] {
+                0 => crate::rtc::realtime_nanos(),
+                1 => crate::timer::monotonic_nanos(),
+                _ => 0,
+            };
+            args[0] = (nanos / NANOS_PER_SECOND) as usize;
+            args[1] = (nanos % NANOS_PER_SECOND) as usize;
+        }
+        SyscallNumbers::PowerOff => {
+            // (status) -> (0)
+            // Only returns if the process doesn't have `capabilities::POWER`
+            let status = args[0] as u16;
+            drop(args);
+            if process.capabilities & capabilities::POWER == 0 {
+                get_syscall_args(process)[0] = 0;
+                return;
+            }
+            println!("Process {:?} is powering off", process.name);
+            crate::power::power_off(status);
+        }
+        SyscallNumbers::Reboot => {
+            // (warm) -> (0)
+            // Only returns if the process doesn't have `capabilities::POWER`
+            let warm = args[0] != 0;
+            drop(args);
+            if process.capabilities & capabilities::POWER == 0 {
+                get_syscall_args(process)[0] = 0;
+                return;
+            }
+            println!("Process {:?} is rebooting", process.name);
+            crate::power::reboot(warm);
+        }
         _ => {
             panic!("Unknown syscall {}!", args.last().unwrap());
         }
@@ -0,0 +1,69 @@
+//! Runs the kernel's `#[test_case]`s in a process once the kernel has booted, and powers off
+//! with the result, so that QEMU exits with status 0 if they all passed.
+//! rustc only collects the tests when it's passed `--test`, so the kernel has to be built
+//! with `cargo rustc --features test -- --test` (see `run_kernel_tests.sh`).
+//! Everything here prints with `println_u!`, since `fail` runs in the panic handler, where
+//! the console's locks may be held, and the results should come out the same way either way.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::{enable_interrupts, power};
+
+static TOTAL: AtomicUsize = AtomicUsize::new(0);
+static PASSED: AtomicUsize = AtomicUsize::new(0);
+/// The test that's running, for the panic handler
+static CURRENT: spin::Mutex<Option<&'static str>> = spin::Mutex::new(None);
+
+pub trait Testable {
+    fn name(&self) -> &'static str;
+    fn run(&self);
+}
+
+impl<T: Fn()> Testable for T {
+    fn name(&self) -> &'static str {
+        core::any::type_name::<T>()
+    }
+
+    fn run(&self) {
+        self()
+    }
+}
+
+/// Called by the test harness with every `#[test_case]`
+pub fn run_tests(tests: &[&dyn Testable]) {
+    TOTAL.store(tests.len(), Ordering::Relaxed);
+    println_u!("running {} tests", tests.len());
+    for test in tests {
+        *CURRENT.lock() = Some(test.name());
+        test.run();
+        println_u!("test {} ... ok", test.name());
+        PASSED.fetch_add(1, Ordering::Relaxed);
+    }
+    println_u!(
+        "test result: ok. {} passed; 0 failed",
+        PASSED.load(Ordering::Relaxed)
+    );
+    power::power_off(0);
+}
+
+/// The process that runs the tests, started instead of the `init` program
+pub fn tests_process() {
+    enable_interrupts();
+    crate::test_main();
+}
+
+/// Called by the panic handler. A panic fails the test that's running, and the rest aren't
+/// run.
+pub fn fail() -> ! {
+    let passed = PASSED.load(Ordering::Relaxed);
+    // Not run if the kernel panicked before the tests started
+    let not_run = TOTAL.load(Ordering::Relaxed).saturating_sub(passed + 1);
+    if let Some(Some(name)) = CURRENT.try_lock().map(|current| *current) {
+        println_u!("test {} ... FAILED", name);
+    }
+    println_u!(
+        "test result: FAILED. {} passed; 1 failed; {} not run",
+        passed, not_run
+    );
+    power::power_off(1);
+}
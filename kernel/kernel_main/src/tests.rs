@@ -0,0 +1,93 @@
+//! Kernel tests, run in a process after boot by [`crate::testing`]
+
+use alloc::{boxed::Box, sync::Arc, vec::Vec};
+use core::mem::MaybeUninit;
+
+use kernel_api::{executor::Executor, BufferQueue, Clock};
+use kernel_paging::Paging;
+use kernel_services::queues::allocate_queue;
+
+use crate::{paging_from_satp, virt_to_phys};
+
+fn received(buffer: &[MaybeUninit<u8>], size: usize) -> Vec<u8> {
+    buffer[..size]
+        .iter()
+        .map(|byte| unsafe { byte.assume_init() })
+        .collect()
+}
+
+#[test_case]
+fn heap_is_mapped_at_its_physical_address() {
+    let value = Box::new(0x1234_5678u32);
+    let address = &*value as *const u32 as usize;
+    let paging = unsafe { paging_from_satp(kernel_cpu::read_satp()) };
+    let physical = unsafe { paging.query_physical_address(address) };
+    assert_eq!(physical.ok(), Some(virt_to_phys(address)));
+}
+
+#[test_case]
+fn null_page_is_unmapped() {
+    let paging = unsafe { paging_from_satp(kernel_cpu::read_satp()) };
+    assert!(unsafe { paging.query(0) }.is_err());
+}
+
+#[test_case]
+fn buffer_queue_copies_data() {
+    let queue = allocate_queue();
+    let claimed = queue.copy_out_buffer(b"hello");
+    let mut buffer = [MaybeUninit::uninit(); 16];
+    let (size, remaining) = queue.copy_claim_buffer(&mut buffer).ok().unwrap();
+    assert_eq!(received(&buffer, size), b"hello");
+    assert_eq!(remaining, 0);
+    claimed.wait_for_complete();
+}
+
+#[test_case]
+fn freed_queues_cant_be_received_from() {
+    let queue = allocate_queue();
+    let id = queue.id();
+    assert!(queue.free());
+    assert!(!BufferQueue::new(id).free());
+    let _claimed = BufferQueue::new(id).copy_out_buffer(b"hello");
+    let mut buffer = [MaybeUninit::uninit(); 16];
+    assert!(BufferQueue::new(id).copy_claim_buffer(&mut buffer).is_err());
+}
+
+#[test_case]
+fn executor_runs_tasks_that_wait_for_each_other() {
+    let queue = allocate_queue().id();
+    let result = Arc::new(spin::Mutex::new(Vec::new()));
+    let task_result = result.clone();
+    let mut executor = Executor::new();
+    // The receiver starts first, so it has to wait for the sender
+    executor.spawn(async move {
+        let mut buffer = [MaybeUninit::uninit(); 16];
+        let (size, _) = BufferQueue::new(queue)
+            .copy_claim_buffer_async(&mut buffer)
+            .await;
+        *task_result.lock() = received(&buffer, size);
+    });
+    executor.spawn(async move {
+        BufferQueue::new(queue).copy_out_buffer(b"ping").await;
+    });
+    executor.run();
+    assert_eq!(*result.lock(), b"ping");
+}
+
+#[test_case]
+fn random_bytes_differ() {
+    let mut first = [0; 32];
+    let mut second = [0; 32];
+    // Fails if neither timing jitter nor virtio-rng has seeded the generator yet
+    assert!(kernel_api::get_random(&mut first));
+    assert!(kernel_api::get_random(&mut second));
+    assert_ne!(first, second);
+}
+
+#[test_case]
+fn monotonic_clock_goes_forward() {
+    let before = kernel_api::clock_get_time(Clock::Monotonic);
+    crate::busy_wait_for(1000);
+    let after = kernel_api::clock_get_time(Clock::Monotonic);
+    assert!(after > before);
+}
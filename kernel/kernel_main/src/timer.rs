@@ -1,5 +1,123 @@
+//! The hart timers, and sleeping until a deadline in kernel tasks.
+
+use alloc::collections::BTreeMap;
+use core::{
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicUsize, Ordering},
+    task::{Context, Poll, Waker},
+};
+
 use kernel_cpu::read_time;
+use kernel_fdt::Fdt;
+
+/// The `time` CSR counts at 10 MHz on QEMU's virt machine
+const DEFAULT_TIMEBASE_FREQUENCY: usize = 10_000_000;
+pub const NANOS_PER_SECOND: u64 = 1_000_000_000;
+const NANOS_PER_MILLISECOND: u64 = 1_000_000;
+
+/// How many times a second the `time` CSR counts
+static TIMEBASE_FREQUENCY: AtomicUsize = AtomicUsize::new(DEFAULT_TIMEBASE_FREQUENCY);
+
+/// Sleepers by deadline in ticks, and by an ID that tells apart the ones with the same
+/// deadline
+static SLEEPERS: spin::Mutex<BTreeMap<(u64, usize), Waker>> = spin::Mutex::new(BTreeMap::new());
+static NEXT_SLEEPER_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// Reads the frequency of the `time` CSR from `/cpus/timebase-frequency`
+pub fn init_from_fdt(fdt: &Fdt) {
+    let frequency = fdt
+        .find_node("/cpus")
+        .and_then(|cpus| cpus.property("timebase-frequency"))
+        .and_then(|frequency| frequency.as_usize())
+        .filter(|&frequency| frequency != 0);
+    match frequency {
+        Some(frequency) => TIMEBASE_FREQUENCY.store(frequency, Ordering::Relaxed),
+        None => log::warn!(
+            "timer: no timebase-frequency, assuming {} Hz",
+            DEFAULT_TIMEBASE_FREQUENCY
+        ),
+    }
+}
+
+fn frequency() -> u128 {
+    TIMEBASE_FREQUENCY.load(Ordering::Relaxed) as u128
+}
+
+pub fn ticks_to_nanos(ticks: u64) -> u64 {
+    (ticks as u128 * NANOS_PER_SECOND as u128 / frequency()) as u64
+}
+
+/// Rounds up, so that `ticks_to_nanos(nanos_to_ticks(nanos)) >= nanos`
+pub fn nanos_to_ticks(nanos: u64) -> u64 {
+    let ticks =
+        (nanos as u128 * frequency() + NANOS_PER_SECOND as u128 - 1) / NANOS_PER_SECOND as u128;
+    ticks.min(u64::MAX as u128) as u64
+}
+
+/// Nanoseconds since the hart booted. The `time` CSR is the same on every hart, so this
+/// is too.
+pub fn monotonic_nanos() -> u64 {
+    ticks_to_nanos(read_time())
+}
+
+/// Milliseconds since the hart booted
+pub fn millis() -> u64 {
+    monotonic_nanos() / NANOS_PER_MILLISECOND
+}
 
+/// Sets this hart's timer to go off in `time` ticks, or before if a sleeper has to be woken
 pub fn set_relative_timer(time: u64) {
-    kernel_sbi::set_absolute_timer(read_time().saturating_add(time));
+    let deadline = read_time().saturating_add(time);
+    let next_sleeper = SLEEPERS.lock().keys().next().map(|(at, _)| *at);
+    kernel_sbi::set_absolute_timer(next_sleeper.map_or(deadline, |at| at.min(deadline)));
+}
+
+/// Wakes the sleepers whose deadline has passed. Called when the timer goes off.
+pub fn wake_expired() {
+    let now = read_time();
+    let expired = {
+        let mut sleepers = SLEEPERS.lock();
+        let later = sleepers.split_off(&(now + 1, 0));
+        core::mem::replace(&mut *sleepers, later)
+    };
+    for waker in expired.into_values() {
+        waker.wake();
+    }
+}
+
+/// Completes once `millis()` reaches `deadline`
+pub fn sleep_until(deadline: u64) -> Sleep {
+    Sleep {
+        deadline: nanos_to_ticks(deadline.saturating_mul(NANOS_PER_MILLISECOND)),
+        id: NEXT_SLEEPER_ID.fetch_add(1, Ordering::Relaxed),
+    }
+}
+
+pub struct Sleep {
+    /// In ticks
+    deadline: u64,
+    id: usize,
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if read_time() >= self.deadline {
+            return Poll::Ready(());
+        }
+        SLEEPERS
+            .lock()
+            .insert((self.deadline, self.id), cx.waker().clone());
+        // The timer is set before switching to a process or idling, and it takes the new
+        // deadline into account then
+        Poll::Pending
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        SLEEPERS.lock().remove(&(self.deadline, self.id));
+    }
 }
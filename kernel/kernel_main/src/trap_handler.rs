@@ -2,9 +2,6 @@ extern "C" {
     fn switch_to_supervisor_frame(a: *mut TrapFrame);
 }
 
-use core::{sync::atomic::Ordering};
-
-use kernel_chip_drivers::plic::Plic0;
 use kernel_cpu::{
     csr::{XCAUSE_DESCRIPTION},
     read_scause, read_sip, read_sscratch, read_sstatus, read_stval, write_sie, write_sip,
@@ -13,10 +10,7 @@ use kernel_paging::{EntryBits, Paging, Sv39};
 use kernel_process::Process;
 use kernel_trap_frame::TrapFrame;
 
-use crate::{
-    loop_forever_black_box, phys_to_virt, syscall::handle_syscall, virt_to_phys,
-    HartLocals, GAP,
-};
+use crate::{loop_forever_black_box, phys_to_virt, syscall::handle_syscall, virt_to_phys};
 
 pub fn handle_interrupt(mut process: Option<&mut Process>, cause: usize) {
     use kernel_cpu::csr::cause::*;
@@ -29,28 +23,13 @@ pub fn handle_interrupt(mut process: Option<&mut Process>, cause: usize) {
     match cause {
         SUPERVISOR_TIMER => {
             kernel_sbi::set_absolute_timer(u64::MAX);
+            crate::timer::wake_expired();
         }
         SUPERVISOR_SOFTWARE => unsafe {
             write_sip(read_sip() & (!kernel_cpu::csr::SSIP));
             handle_syscall(process.as_mut().unwrap());
         },
-        SUPERVISOR_EXTERNAL => {
-            let plic = Plic0::new_with_addr(GAP.load(Ordering::Relaxed) + 0x0c00_0000);
-
-            let id = plic.claim_highest_priority();
-
-            if let Some(e) = HartLocals::current()
-                .interrupt_notifiers
-                .borrow_mut()
-                .remove(&(id as usize))
-            {
-                for waker in e {
-                    waker.wake();
-                }
-            }
-
-            plic.complete(id);
-        }
+        SUPERVISOR_EXTERNAL => crate::plic::handle_external_interrupt(),
         _ => {
             println!("Unknown interrupt: {:?}", cause);
         }
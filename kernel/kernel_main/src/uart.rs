@@ -0,0 +1,133 @@
+//! The console UART, and the process that lets other processes use it through a queue.
+
+use alloc::{vec, vec::Vec};
+use core::sync::atomic::Ordering;
+
+use kernel_api::KernelFuture;
+use kernel_chip_drivers::{
+    driver::{Device, Driver, ProbeError},
+    ns16550a::{Ns16550a, Uart, RING_BUFFER_SIZE},
+};
+use kernel_services::{
+    name_service::NameServiceClient,
+    queues::allocate_queue,
+    serial::Serial,
+};
+
+use crate::{enable_interrupts, gpu, phys_to_virt, plic, std_macros::PRINTER_ADDRESS};
+
+pub struct ConsoleUart {
+    pub uart: Uart,
+    pub interrupt: u32,
+}
+
+pub static CONSOLE_UART: spin::Once<ConsoleUart> = spin::Once::new();
+
+pub static DRIVER: Driver = Driver {
+    name: "ns16550a",
+    compatible: &["ns16550a"],
+    probe,
+};
+
+/// The first UART becomes the console
+fn probe(device: &Device) -> Result<(), ProbeError> {
+    let address = device.region(0)?.address;
+    let interrupt = device.interrupt(0)?;
+    if CONSOLE_UART.get().is_some() {
+        println!("Ignoring UART at {:#x}, there's already a console", address);
+        return Ok(());
+    }
+    println!("UART at {:#x}, interrupt {}", address, interrupt);
+
+    let mut uart = Ns16550a::new_with_addr(phys_to_virt(address));
+    uart.init();
+    PRINTER_ADDRESS.store(phys_to_virt(address), Ordering::Relaxed);
+    CONSOLE_UART.call_once(|| ConsoleUart {
+        uart: Uart::new(uart),
+        interrupt,
+    });
+    plic::register(interrupt, 3, handle_external_interrupt).map_err(|error| {
+        log::warn!("ns16550a: can't enable interrupt {}: {:?}", interrupt, error);
+        ProbeError::Failed
+    })
+}
+
+/// Registered with the PLIC for the console's interrupt
+fn handle_external_interrupt(_interrupt: u32) {
+    if let Some(console) = CONSOLE_UART.get() {
+        console.uart.0.lock().handle_interrupt();
+    }
+}
+
+/// Input from other devices, like keyboards, which is read as if it came from the console
+/// UART. It's dropped if there's no console UART.
+pub fn push_input(data: &[u8]) {
+    if let Some(console) = CONSOLE_UART.get() {
+        console.uart.0.lock().push_received(data);
+        // The UART service waits for the interrupt rather than for data
+        plic::wake_waiters(console.interrupt);
+    }
+}
+
+struct UartServer {
+    console: &'static ConsoleUart,
+    /// Completes on the console's interrupt. It's rearmed for every wait, rather than
+    /// getting a new future each time.
+    interrupt: KernelFuture,
+}
+
+impl UartServer {
+    /// Calls `f` until it returns a nonzero value, sleeping until the next UART interrupt in between
+    fn retry_until_nonzero(&self, mut f: impl FnMut(&mut Ns16550a) -> usize) -> usize {
+        loop {
+            // Rearm the future before trying, so that an interrupt
+            // that comes in between isn't missed.
+            self.interrupt
+                .rearm_for_interrupt(self.console.interrupt as usize);
+            let done = f(&mut self.console.uart.0.lock());
+            if done != 0 {
+                return done;
+            }
+            self.interrupt.wait_for_complete();
+        }
+    }
+}
+
+impl Serial for UartServer {
+    fn write(&mut self, data: Vec<u8>) -> u32 {
+        if data.is_empty() {
+            return 0;
+        }
+        let written = self.retry_until_nonzero(|uart| uart.try_write(&data));
+        // Also show it on the display, where keyboard input is typed
+        gpu::write_console_bytes(&data[..written]);
+        written as u32
+    }
+
+    fn read(&mut self, max_len: u32) -> Vec<u8> {
+        if max_len == 0 {
+            return Vec::new();
+        }
+        // More than the ring buffer holds couldn't be read at once anyway
+        let mut buffer = vec![0; (max_len as usize).min(RING_BUFFER_SIZE)];
+        let read = self.retry_until_nonzero(|uart| uart.try_read(&mut buffer));
+        buffer.truncate(read);
+        buffer
+    }
+}
+
+/// Serves the console UART and registers it as `dev/uart0`
+pub fn uart_service() {
+    enable_interrupts();
+    let console = CONSOLE_UART.get().expect("UART not initialized");
+
+    let names = NameServiceClient::connect(allocate_queue());
+    let queue = allocate_queue();
+    names
+        .register("dev/uart0".into(), queue.id())
+        .unwrap()
+        .unwrap();
+
+    let interrupt = KernelFuture::wait_for_interrupt(console.interrupt as usize);
+    UartServer { console, interrupt }.serve(&queue)
+}
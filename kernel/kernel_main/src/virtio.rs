@@ -0,0 +1,81 @@
+//! Discovery of virtio devices, and routing of their interrupts.
+
+use alloc::vec::Vec;
+
+use kernel_chip_drivers::{
+    driver::{Device, Driver, ProbeError},
+    virtio::{SharedVirtioDevice, VirtioMmio},
+};
+
+use crate::{phys_to_virt, plic};
+
+/// A virtio device that was found in the device tree but hasn't been claimed by a driver yet
+pub struct DiscoveredDevice {
+    pub transport: VirtioMmio,
+    pub interrupt: u32,
+}
+
+/// Devices found by the driver. Drivers take the devices they support out of here.
+pub static DISCOVERED_DEVICES: spin::Mutex<Vec<DiscoveredDevice>> = spin::Mutex::new(Vec::new());
+
+/// Devices that are being used by a driver, so that their interrupts can be acknowledged
+static ACTIVE_DEVICES: spin::RwLock<Vec<SharedVirtioDevice>> = spin::RwLock::new(Vec::new());
+
+pub static DRIVER: Driver = Driver {
+    name: "virtio-mmio",
+    compatible: &["virtio,mmio"],
+    probe,
+};
+
+fn probe(device: &Device) -> Result<(), ProbeError> {
+    let address = device.region(0)?.address;
+    let interrupt = device.interrupt(0)?;
+    // QEMU has slots for devices that aren't there
+    let transport = VirtioMmio::probe(phys_to_virt(address)).ok_or(ProbeError::NoDevice)?;
+    println!(
+        "virtio device {} at {:#x}, interrupt {}",
+        transport.device_id(),
+        address,
+        interrupt
+    );
+    // Slots can share an interrupt, so only register it once
+    let registered = DISCOVERED_DEVICES
+        .lock()
+        .iter()
+        .any(|device| device.interrupt == interrupt);
+    if !registered {
+        plic::register(interrupt, 1, handle_external_interrupt).map_err(|error| {
+            log::warn!("virtio: can't enable interrupt {}: {:?}", interrupt, error);
+            ProbeError::Failed
+        })?;
+    }
+    DISCOVERED_DEVICES.lock().push(DiscoveredDevice {
+        transport,
+        interrupt,
+    });
+    Ok(())
+}
+
+/// Removes the first discovered device with the given device ID
+pub fn take_device(device_id: u32) -> Option<DiscoveredDevice> {
+    let mut discovered = DISCOVERED_DEVICES.lock();
+    let index = discovered
+        .iter()
+        .position(|device| device.transport.device_id() == device_id)?;
+    Some(discovered.remove(index))
+}
+
+/// Makes the trap handler acknowledge `device`'s interrupts
+pub fn activate_device(device: SharedVirtioDevice) {
+    ACTIVE_DEVICES.write().push(device);
+}
+
+/// Registered with the PLIC for the interrupt of every device that was found
+fn handle_external_interrupt(interrupt: u32) {
+    for device in ACTIVE_DEVICES.read().iter() {
+        let mut device = device.lock();
+        if device.interrupt == interrupt {
+            device.handle_interrupt();
+        }
+    }
+}
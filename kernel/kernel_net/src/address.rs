@@ -0,0 +1,158 @@
+//! Link-layer and IPv4 addresses, and how they're written.
+
+use core::fmt;
+
+#[derive(Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct MacAddress(pub [u8; 6]);
+
+impl MacAddress {
+    pub const BROADCAST: MacAddress = MacAddress([0xff; 6]);
+
+    pub fn is_broadcast(&self) -> bool {
+        *self == Self::BROADCAST
+    }
+
+    /// Group addresses have the lowest bit of the first byte set. Broadcast is one of them.
+    pub fn is_multicast(&self) -> bool {
+        self.0[0] & 1 != 0
+    }
+}
+
+impl fmt::Display for MacAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [a, b, c, d, e, g] = self.0;
+        write!(
+            f,
+            "{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
+            a, b, c, d, e, g
+        )
+    }
+}
+
+impl fmt::Debug for MacAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Ipv4Address(pub [u8; 4]);
+
+impl Ipv4Address {
+    pub const UNSPECIFIED: Ipv4Address = Ipv4Address([0; 4]);
+    pub const BROADCAST: Ipv4Address = Ipv4Address([255; 4]);
+    pub const LOCALHOST: Ipv4Address = Ipv4Address([127, 0, 0, 1]);
+
+    pub const fn new(a: u8, b: u8, c: u8, d: u8) -> Self {
+        Self([a, b, c, d])
+    }
+
+    pub fn from_bits(bits: u32) -> Self {
+        Self(bits.to_be_bytes())
+    }
+
+    pub fn to_bits(self) -> u32 {
+        u32::from_be_bytes(self.0)
+    }
+
+    pub fn is_unspecified(&self) -> bool {
+        *self == Self::UNSPECIFIED
+    }
+
+    /// Only the limited broadcast address. See [`Cidr::broadcast`] for the others.
+    pub fn is_broadcast(&self) -> bool {
+        *self == Self::BROADCAST
+    }
+
+    /// Anything in 127.0.0.0/8
+    pub fn is_loopback(&self) -> bool {
+        self.0[0] == 127
+    }
+
+    pub fn is_multicast(&self) -> bool {
+        self.0[0] & 0xf0 == 224
+    }
+}
+
+impl fmt::Display for Ipv4Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [a, b, c, d] = self.0;
+        write!(f, "{}.{}.{}.{}", a, b, c, d)
+    }
+}
+
+impl fmt::Debug for Ipv4Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+/// An address and the length of its network's prefix, like 10.0.2.15/24
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Cidr {
+    pub address: Ipv4Address,
+    pub prefix_len: u8,
+}
+
+impl Cidr {
+    pub fn new(address: Ipv4Address, prefix_len: u8) -> Self {
+        assert!(prefix_len <= 32);
+        Self {
+            address,
+            prefix_len,
+        }
+    }
+
+    /// Returns `None` if the ones in `netmask` aren't contiguous
+    pub fn from_netmask(address: Ipv4Address, netmask: Ipv4Address) -> Option<Self> {
+        let bits = netmask.to_bits();
+        let prefix_len = bits.leading_ones();
+        if bits.checked_shl(prefix_len).unwrap_or(0) != 0 {
+            return None;
+        }
+        Some(Self::new(address, prefix_len as u8))
+    }
+
+    pub fn netmask(&self) -> Ipv4Address {
+        Ipv4Address::from_bits(
+            u32::MAX
+                .checked_shl(32 - self.prefix_len as u32)
+                .unwrap_or(0),
+        )
+    }
+
+    /// Whether `address` is on the same network
+    pub fn contains(&self, address: Ipv4Address) -> bool {
+        let mask = self.netmask().to_bits();
+        address.to_bits() & mask == self.address.to_bits() & mask
+    }
+
+    /// The network's broadcast address
+    pub fn broadcast(&self) -> Ipv4Address {
+        Ipv4Address::from_bits(self.address.to_bits() | !self.netmask().to_bits())
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.address, self.prefix_len)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SocketAddress {
+    pub address: Ipv4Address,
+    pub port: u16,
+}
+
+impl SocketAddress {
+    pub const fn new(address: Ipv4Address, port: u16) -> Self {
+        Self { address, port }
+    }
+}
+
+impl fmt::Display for SocketAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.address, self.port)
+    }
+}
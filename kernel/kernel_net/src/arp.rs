@@ -0,0 +1,119 @@
+//! The neighbor cache: which link-layer address an IPv4 address on the local network has.
+//! Packets to an address that isn't known yet wait here while it's asked for.
+
+use alloc::{collections::BTreeMap, vec::Vec};
+
+use crate::{Frame, Ipv4Address, MacAddress};
+
+/// How long an answer is trusted
+const ENTRY_LIFETIME: u64 = 60_000;
+const RETRY_INTERVAL: u64 = 1000;
+/// After this many unanswered requests, the waiting packets are dropped
+const MAX_REQUESTS: u32 = 3;
+/// Packets waiting for the same address. Later ones are dropped.
+const MAX_WAITING: usize = 16;
+
+enum Neighbor {
+    Known {
+        mac: MacAddress,
+        expires_at: u64,
+    },
+    Asking {
+        /// IP packets to send once the address is known
+        waiting: Vec<Frame>,
+        requests: u32,
+        retry_at: u64,
+    },
+}
+
+#[derive(Default)]
+pub(crate) struct NeighborCache {
+    neighbors: BTreeMap<Ipv4Address, Neighbor>,
+}
+
+impl NeighborCache {
+    pub(crate) fn lookup(&self, address: Ipv4Address, now: u64) -> Option<MacAddress> {
+        match self.neighbors.get(&address) {
+            Some(Neighbor::Known { mac, expires_at }) if *expires_at > now => Some(*mac),
+            _ => None,
+        }
+    }
+
+    /// Keeps `packet` until `address` is known. Returns true if a request has to be sent,
+    /// because nobody was asked yet.
+    pub(crate) fn enqueue(&mut self, address: Ipv4Address, packet: Frame, now: u64) -> bool {
+        // An expired entry is asked for again
+        if !matches!(self.neighbors.get(&address), Some(Neighbor::Asking { .. })) {
+            let asking = Neighbor::Asking {
+                waiting: Vec::new(),
+                requests: 0,
+                retry_at: now,
+            };
+            self.neighbors.insert(address, asking);
+        }
+        if let Some(Neighbor::Asking {
+            waiting,
+            requests,
+            retry_at,
+        }) = self.neighbors.get_mut(&address)
+        {
+            if waiting.len() < MAX_WAITING {
+                waiting.push(packet);
+            }
+            if *requests == 0 {
+                *requests = 1;
+                *retry_at = now + RETRY_INTERVAL;
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Remembers that `address` is at `mac`, and returns the packets that were waiting for it
+    pub(crate) fn insert(&mut self, address: Ipv4Address, mac: MacAddress, now: u64) -> Vec<Frame> {
+        let known = Neighbor::Known {
+            mac,
+            expires_at: now + ENTRY_LIFETIME,
+        };
+        match self.neighbors.insert(address, known) {
+            Some(Neighbor::Asking { waiting, .. }) => waiting,
+            _ => Vec::new(),
+        }
+    }
+
+    /// Returns the addresses to ask again. Forgets expired entries, and the ones that were
+    /// asked for too many times, with their packets.
+    pub(crate) fn poll(&mut self, now: u64) -> Vec<Ipv4Address> {
+        let mut retries = Vec::new();
+        self.neighbors.retain(|address, neighbor| match neighbor {
+            Neighbor::Known { expires_at, .. } => *expires_at > now,
+            Neighbor::Asking {
+                requests, retry_at, ..
+            } => {
+                if *retry_at > now {
+                    true
+                } else if *requests >= MAX_REQUESTS {
+                    log::debug!("arp: {} didn't answer", address);
+                    false
+                } else {
+                    *requests += 1;
+                    *retry_at = now + RETRY_INTERVAL;
+                    retries.push(*address);
+                    true
+                }
+            }
+        });
+        retries
+    }
+
+    /// When `poll` has something to do next
+    pub(crate) fn poll_at(&self) -> Option<u64> {
+        self.neighbors
+            .values()
+            .map(|neighbor| match neighbor {
+                Neighbor::Known { expires_at, .. } => *expires_at,
+                Neighbor::Asking { retry_at, .. } => *retry_at,
+            })
+            .min()
+    }
+}
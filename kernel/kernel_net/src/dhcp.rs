@@ -0,0 +1,354 @@
+//! A DHCP client, enough to get an address from QEMU's user networking or a home router.
+//! Messages are always broadcast, and the client asks for a new lease when the current one
+//! is half over.
+//! See RFC 2131 and RFC 2132 (options).
+
+use alloc::vec::Vec;
+
+use crate::{Cidr, Ipv4Address, MacAddress};
+
+pub(crate) const CLIENT_PORT: u16 = 68;
+pub(crate) const SERVER_PORT: u16 = 67;
+
+const BOOTREQUEST: u8 = 1;
+const BOOTREPLY: u8 = 2;
+const MAGIC_COOKIE: [u8; 4] = [99, 130, 83, 99];
+/// Offset of the options, after the fixed fields and the cookie
+const OPTIONS_OFFSET: usize = 240;
+/// BOOTP relays expect at least this much
+const MIN_MESSAGE_SIZE: usize = 300;
+/// Asks the server to broadcast its replies, since we can't receive unicast without an
+/// address
+const FLAG_BROADCAST: u16 = 0x8000;
+
+// Message types
+const DISCOVER: u8 = 1;
+const OFFER: u8 = 2;
+const REQUEST: u8 = 3;
+const ACK: u8 = 5;
+const NAK: u8 = 6;
+
+// Options
+const OPTION_PAD: u8 = 0;
+const OPTION_SUBNET_MASK: u8 = 1;
+const OPTION_ROUTER: u8 = 3;
+const OPTION_DNS: u8 = 6;
+const OPTION_REQUESTED_ADDRESS: u8 = 50;
+const OPTION_LEASE_TIME: u8 = 51;
+const OPTION_MESSAGE_TYPE: u8 = 53;
+const OPTION_SERVER_ID: u8 = 54;
+const OPTION_PARAMETERS: u8 = 55;
+const OPTION_RENEWAL_TIME: u8 = 58;
+const OPTION_END: u8 = 255;
+
+/// The first retransmission is after this long, and then it doubles
+const FIRST_RETRY: u64 = 2000;
+const MAX_RETRY: u64 = 32_000;
+/// Requests that aren't answered send the client back to discovering
+const MAX_REQUESTS: u32 = 4;
+const INFINITE_LEASE: u32 = u32::MAX;
+
+/// The configuration the server gave us
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Lease {
+    pub address: Cidr,
+    pub router: Option<Ipv4Address>,
+    pub dns_servers: Vec<Ipv4Address>,
+    pub server: Ipv4Address,
+    /// In seconds
+    pub duration: u32,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Event {
+    Configured(Lease),
+    /// The lease ran out or the server took it back
+    Deconfigured,
+}
+
+enum State {
+    Discovering,
+    Requesting {
+        server: Ipv4Address,
+        address: Ipv4Address,
+    },
+    Bound {
+        lease: Lease,
+        renew_at: u64,
+        expires_at: u64,
+    },
+    Renewing {
+        lease: Lease,
+        expires_at: u64,
+    },
+}
+
+pub(crate) struct DhcpClient {
+    state: State,
+    mac: MacAddress,
+    xid: u32,
+    next_send: u64,
+    /// Messages sent in the current state
+    attempts: u32,
+}
+
+/// The fields of a message that the client cares about
+struct Message {
+    xid: u32,
+    your_address: Ipv4Address,
+    message_type: u8,
+    server: Option<Ipv4Address>,
+    subnet_mask: Option<Ipv4Address>,
+    router: Option<Ipv4Address>,
+    dns_servers: Vec<Ipv4Address>,
+    lease_time: Option<u32>,
+    renewal_time: Option<u32>,
+}
+
+fn read_ip(data: &[u8]) -> Option<Ipv4Address> {
+    Some(Ipv4Address(data.get(..4)?.try_into().ok()?))
+}
+
+fn read_u32(data: &[u8]) -> Option<u32> {
+    Some(u32::from_be_bytes(data.get(..4)?.try_into().ok()?))
+}
+
+impl Message {
+    fn parse(data: &[u8], mac: MacAddress) -> Option<Self> {
+        if data.len() < OPTIONS_OFFSET
+            || data[0] != BOOTREPLY
+            || data[28..34] != mac.0
+            || data[236..240] != MAGIC_COOKIE
+        {
+            return None;
+        }
+        let mut message = Self {
+            xid: read_u32(&data[4..])?,
+            your_address: read_ip(&data[16..])?,
+            message_type: 0,
+            server: None,
+            subnet_mask: None,
+            router: None,
+            dns_servers: Vec::new(),
+            lease_time: None,
+            renewal_time: None,
+        };
+        let mut options = &data[OPTIONS_OFFSET..];
+        while let [code, rest @ ..] = options {
+            match *code {
+                OPTION_PAD => options = rest,
+                OPTION_END => break,
+                code => {
+                    let len = *rest.first()? as usize;
+                    let value = rest.get(1..1 + len)?;
+                    match code {
+                        OPTION_MESSAGE_TYPE => message.message_type = *value.first()?,
+                        OPTION_SERVER_ID => message.server = read_ip(value),
+                        OPTION_SUBNET_MASK => message.subnet_mask = read_ip(value),
+                        OPTION_ROUTER => message.router = read_ip(value),
+                        OPTION_DNS => {
+                            message.dns_servers =
+                                value.chunks_exact(4).filter_map(read_ip).collect()
+                        }
+                        OPTION_LEASE_TIME => message.lease_time = read_u32(value),
+                        OPTION_RENEWAL_TIME => message.renewal_time = read_u32(value),
+                        _ => {}
+                    }
+                    options = &rest[1 + len..];
+                }
+            }
+        }
+        Some(message)
+    }
+}
+
+impl DhcpClient {
+    /// `seed` makes the transaction ID different between boots
+    pub(crate) fn new(mac: MacAddress, seed: u32) -> Self {
+        let xid = u32::from_be_bytes([mac.0[2], mac.0[3], mac.0[4], mac.0[5]]) ^ seed;
+        Self {
+            state: State::Discovering,
+            mac,
+            xid,
+            next_send: 0,
+            attempts: 0,
+        }
+    }
+
+    /// The address the client has leased, which must be used as the source of its messages
+    pub(crate) fn address(&self) -> Ipv4Address {
+        match &self.state {
+            State::Bound { lease, .. } | State::Renewing { lease, .. } => lease.address.address,
+            _ => Ipv4Address::UNSPECIFIED,
+        }
+    }
+
+    fn retry_interval(&self) -> u64 {
+        (FIRST_RETRY << self.attempts.min(8)).min(MAX_RETRY)
+    }
+
+    fn enter(&mut self, state: State, now: u64) {
+        self.state = state;
+        self.attempts = 0;
+        self.next_send = now;
+    }
+
+    /// Returns a message to broadcast if it's time to send one, and whether the lease ran out
+    pub(crate) fn poll(&mut self, now: u64) -> (Option<Vec<u8>>, Option<Event>) {
+        let mut event = None;
+        match &self.state {
+            State::Bound {
+                lease,
+                renew_at,
+                expires_at,
+            } if *renew_at <= now => {
+                let renewing = State::Renewing {
+                    lease: lease.clone(),
+                    expires_at: *expires_at,
+                };
+                self.enter(renewing, now);
+            }
+            State::Renewing { expires_at, .. } if *expires_at <= now => {
+                log::info!("dhcp: lease on {} expired", self.address());
+                self.enter(State::Discovering, now);
+                event = Some(Event::Deconfigured);
+            }
+            State::Requesting { .. } if self.attempts >= MAX_REQUESTS && self.next_send <= now => {
+                self.enter(State::Discovering, now);
+            }
+            _ => {}
+        }
+        if self.next_send > now {
+            return (None, event);
+        }
+        let message = match &self.state {
+            State::Discovering => self.message(DISCOVER, None, None, Ipv4Address::UNSPECIFIED),
+            State::Requesting { server, address } => self.message(
+                REQUEST,
+                Some(*address),
+                Some(*server),
+                Ipv4Address::UNSPECIFIED,
+            ),
+            State::Renewing { lease, .. } => {
+                self.message(REQUEST, None, None, lease.address.address)
+            }
+            State::Bound { .. } => return (None, event),
+        };
+        self.next_send = now + self.retry_interval();
+        self.attempts += 1;
+        (Some(message), event)
+    }
+
+    pub(crate) fn poll_at(&self) -> u64 {
+        match &self.state {
+            State::Bound { renew_at, .. } => *renew_at,
+            State::Renewing { expires_at, .. } => self.next_send.min(*expires_at),
+            _ => self.next_send,
+        }
+    }
+
+    /// Handles a message that was sent to the client's port
+    pub(crate) fn process(&mut self, data: &[u8], now: u64) -> Option<Event> {
+        let message = Message::parse(data, self.mac)?;
+        if message.xid != self.xid {
+            return None;
+        }
+        match (&self.state, message.message_type) {
+            (State::Discovering, OFFER) => {
+                let requesting = State::Requesting {
+                    server: message.server?,
+                    address: message.your_address,
+                };
+                self.enter(requesting, now);
+                None
+            }
+            (State::Requesting { .. } | State::Renewing { .. }, ACK) => {
+                let lease = Lease {
+                    address: message
+                        .subnet_mask
+                        .and_then(|mask| Cidr::from_netmask(message.your_address, mask))
+                        .unwrap_or_else(|| Cidr::new(message.your_address, 24)),
+                    router: message.router,
+                    dns_servers: message.dns_servers,
+                    server: message.server?,
+                    duration: message.lease_time.unwrap_or(INFINITE_LEASE),
+                };
+                let (renew_at, expires_at) = if lease.duration == INFINITE_LEASE {
+                    (u64::MAX, u64::MAX)
+                } else {
+                    let renewal = message.renewal_time.unwrap_or(lease.duration / 2);
+                    (
+                        now + renewal as u64 * 1000,
+                        now + lease.duration as u64 * 1000,
+                    )
+                };
+                self.enter(
+                    State::Bound {
+                        lease: lease.clone(),
+                        renew_at,
+                        expires_at,
+                    },
+                    now,
+                );
+                Some(Event::Configured(lease))
+            }
+            (State::Requesting { .. } | State::Renewing { .. }, NAK) => {
+                let event = match self.state {
+                    State::Renewing { .. } => Some(Event::Deconfigured),
+                    _ => None,
+                };
+                self.enter(State::Discovering, now);
+                event
+            }
+            _ => None,
+        }
+    }
+
+    fn message(
+        &self,
+        message_type: u8,
+        requested: Option<Ipv4Address>,
+        server: Option<Ipv4Address>,
+        client_address: Ipv4Address,
+    ) -> Vec<u8> {
+        let mut message = Vec::with_capacity(MIN_MESSAGE_SIZE);
+        // Operation, hardware type (Ethernet), hardware address length and hops
+        message.extend_from_slice(&[BOOTREQUEST, 1, 6, 0]);
+        message.extend_from_slice(&self.xid.to_be_bytes());
+        // Seconds since the client started
+        message.extend_from_slice(&[0, 0]);
+        message.extend_from_slice(&FLAG_BROADCAST.to_be_bytes());
+        message.extend_from_slice(&client_address.0);
+        // Our address, the next server's address and the relay's address are the server's
+        // to fill in
+        message.extend_from_slice(&[0; 12]);
+        message.extend_from_slice(&self.mac.0);
+        message.resize(message.len() + 10, 0);
+        // Server name and boot file name
+        message.resize(message.len() + 64 + 128, 0);
+        message.extend_from_slice(&MAGIC_COOKIE);
+
+        message.extend_from_slice(&[OPTION_MESSAGE_TYPE, 1, message_type]);
+        if let Some(requested) = requested {
+            message.extend_from_slice(&[OPTION_REQUESTED_ADDRESS, 4]);
+            message.extend_from_slice(&requested.0);
+        }
+        if let Some(server) = server {
+            message.extend_from_slice(&[OPTION_SERVER_ID, 4]);
+            message.extend_from_slice(&server.0);
+        }
+        message.extend_from_slice(&[
+            OPTION_PARAMETERS,
+            4,
+            OPTION_SUBNET_MASK,
+            OPTION_ROUTER,
+            OPTION_DNS,
+            OPTION_LEASE_TIME,
+        ]);
+        message.push(OPTION_END);
+        if message.len() < MIN_MESSAGE_SIZE {
+            message.resize(MIN_MESSAGE_SIZE, 0);
+        }
+        message
+    }
+}
@@ -0,0 +1,87 @@
+//! Network interfaces and their IPv4 configuration.
+
+use alloc::{string::String, vec::Vec};
+
+use crate::{arp::NeighborCache, dhcp::DhcpClient, Cidr, Ipv4Address, MacAddress};
+
+pub(crate) const LOOPBACK_MTU: usize = 65535;
+
+pub struct Interface {
+    pub(crate) name: String,
+    /// `None` for loopback, which has no link layer
+    pub(crate) mac: Option<MacAddress>,
+    pub(crate) mtu: usize,
+    pub(crate) address: Option<Cidr>,
+    pub(crate) gateway: Option<Ipv4Address>,
+    pub(crate) dns_servers: Vec<Ipv4Address>,
+    pub(crate) neighbors: NeighborCache,
+    pub(crate) dhcp: Option<DhcpClient>,
+}
+
+impl Interface {
+    pub(crate) fn loopback() -> Self {
+        Self {
+            name: "lo".into(),
+            mac: None,
+            mtu: LOOPBACK_MTU,
+            address: Some(Cidr::new(Ipv4Address::LOCALHOST, 8)),
+            gateway: None,
+            dns_servers: Vec::new(),
+            neighbors: NeighborCache::default(),
+            dhcp: None,
+        }
+    }
+
+    pub(crate) fn ethernet(name: String, mac: MacAddress, mtu: usize) -> Self {
+        Self {
+            name,
+            mac: Some(mac),
+            mtu,
+            address: None,
+            gateway: None,
+            dns_servers: Vec::new(),
+            neighbors: NeighborCache::default(),
+            dhcp: None,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn is_loopback(&self) -> bool {
+        self.mac.is_none()
+    }
+
+    pub fn mac_address(&self) -> Option<MacAddress> {
+        self.mac
+    }
+
+    /// The largest IP packet the interface can send
+    pub fn mtu(&self) -> usize {
+        self.mtu
+    }
+
+    pub fn address(&self) -> Option<Cidr> {
+        self.address
+    }
+
+    pub fn gateway(&self) -> Option<Ipv4Address> {
+        self.gateway
+    }
+
+    pub fn dns_servers(&self) -> &[Ipv4Address] {
+        &self.dns_servers
+    }
+
+    /// Whether a packet sent to `address` is meant for this interface
+    pub(crate) fn accepts(&self, address: Ipv4Address) -> bool {
+        match self.address {
+            _ if address.is_broadcast() => true,
+            // Until DHCP is done, the server may send to the address it's offering
+            None => self.dhcp.is_some(),
+            Some(_) if self.is_loopback() => true,
+            Some(cidr) => address == cidr.address || address == cidr.broadcast(),
+        }
+    }
+}
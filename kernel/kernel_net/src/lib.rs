@@ -0,0 +1,104 @@
+//! A small TCP/IP stack: ARP, IPv4, ICMP echo, UDP and TCP, with a socket-like API, a
+//! loopback interface and a DHCP client.
+//! The stack doesn't do any I/O itself. Received frames are handed to [`Stack::receive`],
+//! frames to send are taken out with [`Stack::transmit`], and [`Stack::poll`] runs timers
+//! and generates segments. Socket operations never block; they return
+//! [`SocketError::WouldBlock`] instead, and [`Stack::readiness`] says when to try again.
+//! Time is given in milliseconds, from any starting point.
+
+#![cfg_attr(not(test), no_std)]
+
+extern crate alloc;
+
+mod address;
+mod arp;
+mod dhcp;
+mod interface;
+mod socket;
+mod stack;
+mod tcp;
+#[cfg(test)]
+mod tests;
+pub mod wire;
+
+use alloc::vec::Vec;
+
+pub use address::{Cidr, Ipv4Address, MacAddress, SocketAddress};
+pub use dhcp::Lease;
+pub use interface::Interface;
+pub use stack::Stack;
+pub use tcp::TcpState;
+
+/// Identifies an interface of a [`Stack`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct InterfaceId(pub usize);
+
+impl InterfaceId {
+    /// Every stack starts with a loopback interface, `lo`
+    pub const LOOPBACK: InterfaceId = InterfaceId(0);
+}
+
+/// Identifies a socket of a [`Stack`]. Handles of closed sockets aren't reused until the
+/// counter wraps around.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SocketHandle(pub u32);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SocketKind {
+    Udp,
+    Tcp,
+    /// Sends echo requests and receives echo replies. Messages include the ICMP header, like
+    /// Linux's ping sockets; the identifier is the socket's port, and the stack fills it in.
+    Icmp,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SocketError {
+    /// The operation can't complete yet. Wait until the socket is ready and try again.
+    WouldBlock,
+    /// There's no socket with that handle
+    InvalidSocket,
+    /// The operation doesn't apply to this kind of socket, or not in its current state
+    InvalidOperation,
+    AddressInUse,
+    /// The address isn't one of the stack's own addresses
+    AddressNotAvailable,
+    NotConnected,
+    AlreadyConnected,
+    ConnectionRefused,
+    ConnectionReset,
+    /// The peer stopped acknowledging what we sent
+    TimedOut,
+    /// No interface can reach the destination
+    NoRoute,
+    /// The datagram doesn't fit in a packet, since IP fragmentation isn't supported
+    MessageTooLarge,
+}
+
+/// Which operations on a socket would make progress instead of returning
+/// [`SocketError::WouldBlock`]. An error counts as both, since the operation would return it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Readiness {
+    /// `recv` or `accept`
+    pub readable: bool,
+    /// `send`
+    pub writable: bool,
+}
+
+/// A frame or packet to send. If `checksum` is set, the checksum of `data[start..]` still
+/// has to be computed and stored at `start + offset`, where the sum of the pseudo-header is.
+/// That's what `NetDevice::send_with_checksum` takes.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Frame {
+    pub data: Vec<u8>,
+    pub checksum: Option<(usize, usize)>,
+}
+
+impl Frame {
+    /// Computes the checksum if the device won't, so that the frame can go on the wire
+    pub fn finish_checksum(&mut self) {
+        if let Some((start, offset)) = self.checksum.take() {
+            kernel_io::checksum::fill(&mut self.data, start, offset);
+        }
+    }
+}
@@ -0,0 +1,110 @@
+//! What the stack keeps for every socket.
+
+use alloc::{collections::VecDeque, vec::Vec};
+
+use crate::{tcp::TcpSocket, Readiness, SocketAddress, SocketError, SocketKind};
+
+/// Datagrams that haven't been read yet. Newer ones are dropped when it's full.
+const DATAGRAM_QUEUE_SIZE: usize = 64;
+
+/// A UDP or ICMP socket. Messages are kept whole, with the address they came from.
+#[derive(Default)]
+pub(crate) struct DatagramSocket {
+    pub(crate) local: Option<SocketAddress>,
+    /// Where `send` sends to, and the only address `recv` receives from
+    pub(crate) remote: Option<SocketAddress>,
+    received: VecDeque<(SocketAddress, Vec<u8>)>,
+}
+
+impl DatagramSocket {
+    pub(crate) fn accepts_from(&self, source: SocketAddress) -> bool {
+        match self.remote {
+            Some(remote) => remote == source,
+            None => true,
+        }
+    }
+
+    pub(crate) fn deliver(&mut self, source: SocketAddress, data: &[u8]) {
+        if self.received.len() < DATAGRAM_QUEUE_SIZE {
+            self.received.push_back((source, data.to_vec()));
+        }
+    }
+
+    /// Datagrams longer than `max_len` are truncated
+    pub(crate) fn recv_from(
+        &mut self,
+        max_len: usize,
+    ) -> Result<(Vec<u8>, SocketAddress), SocketError> {
+        let (source, mut data) = self.received.pop_front().ok_or(SocketError::WouldBlock)?;
+        data.truncate(max_len);
+        Ok((data, source))
+    }
+
+    fn readiness(&self) -> Readiness {
+        Readiness {
+            readable: !self.received.is_empty(),
+            writable: true,
+        }
+    }
+}
+
+pub(crate) enum Socket {
+    Udp(DatagramSocket),
+    Icmp(DatagramSocket),
+    Tcp(TcpSocket),
+}
+
+impl Socket {
+    pub(crate) fn new(kind: SocketKind) -> Self {
+        match kind {
+            SocketKind::Udp => Self::Udp(DatagramSocket::default()),
+            SocketKind::Icmp => Self::Icmp(DatagramSocket::default()),
+            SocketKind::Tcp => Self::Tcp(TcpSocket::new()),
+        }
+    }
+
+    pub(crate) fn kind(&self) -> SocketKind {
+        match self {
+            Self::Udp(_) => SocketKind::Udp,
+            Self::Icmp(_) => SocketKind::Icmp,
+            Self::Tcp(_) => SocketKind::Tcp,
+        }
+    }
+
+    pub(crate) fn local(&self) -> Option<SocketAddress> {
+        match self {
+            Self::Udp(socket) | Self::Icmp(socket) => socket.local,
+            Self::Tcp(socket) => socket.local,
+        }
+    }
+
+    pub(crate) fn remote(&self) -> Option<SocketAddress> {
+        match self {
+            Self::Udp(socket) | Self::Icmp(socket) => socket.remote,
+            Self::Tcp(socket) => socket.remote,
+        }
+    }
+
+    pub(crate) fn set_local(&mut self, local: SocketAddress) {
+        match self {
+            Self::Udp(socket) | Self::Icmp(socket) => socket.local = Some(local),
+            Self::Tcp(socket) => socket.local = Some(local),
+        }
+    }
+
+    /// The port this socket holds, so that nobody else can bind to it. Connections accepted
+    /// from a listener share its port.
+    pub(crate) fn bound_port(&self) -> Option<u16> {
+        match self {
+            Self::Tcp(socket) if socket.parent.is_some() => None,
+            socket => socket.local().map(|local| local.port),
+        }
+    }
+
+    pub(crate) fn readiness(&self) -> Readiness {
+        match self {
+            Self::Udp(socket) | Self::Icmp(socket) => socket.readiness(),
+            Self::Tcp(socket) => socket.readiness(),
+        }
+    }
+}
@@ -0,0 +1,1092 @@
+//! The stack: interfaces, routing between them, and the sockets.
+
+use alloc::{
+    collections::{BTreeMap, VecDeque},
+    string::String,
+    vec::Vec,
+};
+
+use kernel_random::chacha::{self, KEY_SIZE};
+
+use crate::{
+    dhcp::{self, DhcpClient, Event},
+    interface::Interface,
+    socket::Socket,
+    tcp::{self, TcpSocket, TcpState},
+    wire::*,
+    Cidr, Frame, InterfaceId, Ipv4Address, MacAddress, Readiness, SocketAddress, SocketError,
+    SocketHandle, SocketKind,
+};
+
+const FIRST_EPHEMERAL_PORT: u16 = 49152;
+/// Packets sent over loopback can be answered over loopback, and so on. `poll` stops after
+/// this many rounds, and does the rest in the next call.
+const MAX_LOOPBACK_ROUNDS: usize = 64;
+
+struct Entry {
+    socket: Socket,
+    /// The handle was closed. TCP connections live on until they're done with the peer.
+    released: bool,
+}
+
+pub struct Stack {
+    interfaces: Vec<Interface>,
+    sockets: BTreeMap<SocketHandle, Entry>,
+    next_socket: u32,
+    next_port: u16,
+    next_identification: u16,
+    /// The key of the function that initial sequence numbers are made with
+    iss_secret: [u8; KEY_SIZE],
+    /// IP packets that were sent to one of our own addresses
+    loopback: VecDeque<Vec<u8>>,
+    outgoing: VecDeque<(InterfaceId, Frame)>,
+    /// The time of the last call that gave it
+    now: u64,
+}
+
+impl Default for Stack {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A pseudorandom function of `input`: the first bytes of the ChaCha20 block with `key`,
+/// using `input` as the nonce
+fn keyed_hash(key: &[u8; KEY_SIZE], input: &[u8; 12]) -> u32 {
+    let block = chacha::block(key, 0, input);
+    u32::from_le_bytes(block[..4].try_into().unwrap())
+}
+
+/// Whether a socket bound to `local` gets what is sent to `destination`
+fn local_matches(local: SocketAddress, destination: SocketAddress) -> bool {
+    local.port == destination.port
+        && (local.address.is_unspecified()
+            || local.address == destination.address
+            || destination.address.is_broadcast())
+}
+
+impl Stack {
+    /// Creates a stack with only the loopback interface
+    pub fn new() -> Self {
+        Self {
+            interfaces: alloc::vec![Interface::loopback()],
+            sockets: BTreeMap::new(),
+            next_socket: 1,
+            next_port: FIRST_EPHEMERAL_PORT,
+            next_identification: 0,
+            iss_secret: [0; KEY_SIZE],
+            loopback: VecDeque::new(),
+            outgoing: VecDeque::new(),
+            now: 0,
+        }
+    }
+
+    /// Adds an interface without an address. Use `start_dhcp` or `configure` to give it one.
+    /// `mtu` is the largest IP packet it can send.
+    pub fn add_ethernet_interface(
+        &mut self,
+        name: String,
+        mac: MacAddress,
+        mtu: usize,
+    ) -> InterfaceId {
+        self.interfaces.push(Interface::ethernet(name, mac, mtu));
+        InterfaceId(self.interfaces.len() - 1)
+    }
+
+    pub fn interface(&self, id: InterfaceId) -> Option<&Interface> {
+        self.interfaces.get(id.0)
+    }
+
+    pub fn interfaces(&self) -> impl Iterator<Item = (InterfaceId, &Interface)> {
+        self.interfaces
+            .iter()
+            .enumerate()
+            .map(|(i, interface)| (InterfaceId(i), interface))
+    }
+
+    /// Sets the address of an interface by hand, stopping DHCP on it
+    pub fn configure(
+        &mut self,
+        id: InterfaceId,
+        address: Option<Cidr>,
+        gateway: Option<Ipv4Address>,
+    ) {
+        if let Some(interface) = self.interfaces.get_mut(id.0) {
+            interface.dhcp = None;
+            interface.address = address;
+            interface.gateway = gateway;
+        }
+    }
+
+    /// Sets the secret that initial sequence numbers are derived from. It should come from a
+    /// cryptographically secure generator. Until it's set, they can be guessed.
+    pub fn set_iss_secret(&mut self, secret: [u8; KEY_SIZE]) {
+        self.iss_secret = secret;
+    }
+
+    /// Gets the address of an Ethernet interface from a DHCP server. The interface is
+    /// configured during a later `poll` or `receive`.
+    pub fn start_dhcp(&mut self, id: InterfaceId, now: u64) {
+        if let Some(interface) = self.interfaces.get_mut(id.0) {
+            if let Some(mac) = interface.mac {
+                let mut input = [0; 12];
+                input[..6].copy_from_slice(&mac.0);
+                let seed = now as u32 ^ keyed_hash(&self.iss_secret, &input);
+                interface.dhcp = Some(DhcpClient::new(mac, seed));
+                interface.address = None;
+                interface.gateway = None;
+            }
+        }
+    }
+
+    fn apply_dhcp(&mut self, id: InterfaceId, event: Event) {
+        let interface = &mut self.interfaces[id.0];
+        match event {
+            Event::Configured(lease) => {
+                log::info!(
+                    "{}: {} from {}, gateway {:?}",
+                    interface.name,
+                    lease.address,
+                    lease.server,
+                    lease.router
+                );
+                interface.address = Some(lease.address);
+                interface.gateway = lease.router;
+                interface.dns_servers = lease.dns_servers;
+            }
+            Event::Deconfigured => {
+                interface.address = None;
+                interface.gateway = None;
+                interface.dns_servers.clear();
+            }
+        }
+    }
+
+    /// Handles a frame that `interface` received
+    pub fn receive(&mut self, interface: InterfaceId, frame: &[u8], now: u64) {
+        self.now = now;
+        let mac = match self.interfaces.get(interface.0).and_then(|i| i.mac) {
+            Some(mac) => mac,
+            None => return,
+        };
+        let (ethernet, payload) = match EthernetHeader::parse(frame) {
+            Some(parsed) => parsed,
+            None => return,
+        };
+        if ethernet.destination != mac && !ethernet.destination.is_broadcast() {
+            return;
+        }
+        match ethernet.ether_type {
+            ether_type::ARP => self.process_arp(interface, payload),
+            ether_type::IPV4 => self.process_ipv4(interface, payload),
+            _ => {}
+        }
+    }
+
+    /// Takes the next frame to send
+    pub fn transmit(&mut self) -> Option<(InterfaceId, Frame)> {
+        self.outgoing.pop_front()
+    }
+
+    /// Runs timers, sends what sockets have to send and handles loopback traffic
+    pub fn poll(&mut self, now: u64) {
+        self.now = now;
+        for i in 0..self.interfaces.len() {
+            let id = InterfaceId(i);
+            for address in self.interfaces[i].neighbors.poll(now) {
+                self.send_arp_request(id, address);
+            }
+            let (message, event) = match &mut self.interfaces[i].dhcp {
+                Some(dhcp) => dhcp.poll(now),
+                None => continue,
+            };
+            if let Some(event) = event {
+                self.apply_dhcp(id, event);
+            }
+            if let Some(message) = message {
+                self.send_dhcp(id, &message);
+            }
+        }
+        for _ in 0..MAX_LOOPBACK_ROUNDS {
+            self.dispatch_tcp();
+            if self.loopback.is_empty() {
+                break;
+            }
+            for packet in core::mem::take(&mut self.loopback) {
+                self.process_ipv4(InterfaceId::LOOPBACK, &packet);
+            }
+        }
+        self.remove_closed_sockets();
+    }
+
+    /// When `poll` has to be called next, if nothing is received before. It may be in the
+    /// past, meaning right away.
+    pub fn poll_at(&self) -> Option<u64> {
+        if !self.loopback.is_empty() {
+            return Some(self.now);
+        }
+        let sockets = self
+            .sockets
+            .values()
+            .filter_map(|entry| match &entry.socket {
+                Socket::Tcp(socket) => socket.poll_at(),
+                _ => None,
+            });
+        let interfaces = self.interfaces.iter().flat_map(|interface| {
+            [
+                interface.neighbors.poll_at(),
+                interface.dhcp.as_ref().map(|dhcp| dhcp.poll_at()),
+            ]
+        });
+        sockets.chain(interfaces.flatten()).min()
+    }
+
+    // Routing and sending
+
+    fn is_local_address(&self, address: Ipv4Address) -> bool {
+        address.is_loopback()
+            || self
+                .interfaces
+                .iter()
+                .any(|interface| interface.address.map(|cidr| cidr.address) == Some(address))
+    }
+
+    /// The interface to send to `destination` on, and the next hop's address
+    fn route(&self, destination: Ipv4Address) -> Option<(InterfaceId, Ipv4Address)> {
+        if self.is_local_address(destination) {
+            return Some((InterfaceId::LOOPBACK, destination));
+        }
+        let ethernet = || {
+            self.interfaces()
+                .filter(|(_, interface)| !interface.is_loopback())
+        };
+        if destination.is_broadcast() {
+            return ethernet().next().map(|(id, _)| (id, destination));
+        }
+        let on_link = ethernet().find(|(_, interface)| {
+            interface
+                .address
+                .is_some_and(|cidr| cidr.contains(destination))
+        });
+        if let Some((id, _)) = on_link {
+            return Some((id, destination));
+        }
+        ethernet().find_map(|(id, interface)| interface.gateway.map(|gateway| (id, gateway)))
+    }
+
+    /// The address to send from when sending to `destination` on `id`
+    fn source_address(&self, id: InterfaceId, destination: Ipv4Address) -> Ipv4Address {
+        let interface = &self.interfaces[id.0];
+        if interface.is_loopback() && !destination.is_loopback() {
+            // One of our other addresses
+            return destination;
+        }
+        interface
+            .address
+            .map_or(Ipv4Address::UNSPECIFIED, |cidr| cidr.address)
+    }
+
+    /// The largest TCP segment that fits in a packet to `destination`
+    fn mss_for(&self, destination: Ipv4Address) -> Option<usize> {
+        let (id, _) = self.route(destination)?;
+        Some(self.interfaces[id.0].mtu - IPV4_HEADER_SIZE - TCP_HEADER_SIZE)
+    }
+
+    fn next_identification(&mut self) -> u16 {
+        self.next_identification = self.next_identification.wrapping_add(1);
+        self.next_identification
+    }
+
+    /// The initial sequence number for a connection, as in RFC 6528: RFC 793's clock, which
+    /// ticks every 4 microseconds, plus a keyed hash of the connection's addresses. Someone
+    /// who doesn't know the key can't guess it, even from the numbers of other connections.
+    fn iss(&self, local: SocketAddress, remote: SocketAddress) -> u32 {
+        let mut input = [0; 12];
+        input[0..4].copy_from_slice(&local.address.0);
+        input[4..6].copy_from_slice(&local.port.to_be_bytes());
+        input[6..10].copy_from_slice(&remote.address.0);
+        input[10..12].copy_from_slice(&remote.port.to_be_bytes());
+        (self.now as u32)
+            .wrapping_mul(250)
+            .wrapping_add(keyed_hash(&self.iss_secret, &input))
+    }
+
+    /// Builds an IP packet whose payload `write` writes. `checksum_offset` is where the
+    /// payload's checksum field is, if it has a partial checksum.
+    fn ip_packet(
+        &mut self,
+        source: Ipv4Address,
+        destination: Ipv4Address,
+        protocol: u8,
+        payload_len: usize,
+        checksum_offset: Option<usize>,
+        write: impl FnOnce(&mut Vec<u8>, &Ipv4Header),
+    ) -> Frame {
+        let header = Ipv4Header {
+            source,
+            destination,
+            protocol,
+            ttl: Ipv4Header::DEFAULT_TTL,
+            identification: self.next_identification(),
+        };
+        let mut data = Vec::with_capacity(IPV4_HEADER_SIZE + payload_len);
+        header.write(&mut data, payload_len);
+        write(&mut data, &header);
+        Frame {
+            data,
+            checksum: checksum_offset.map(|offset| (IPV4_HEADER_SIZE, offset)),
+        }
+    }
+
+    /// Routes a packet to `destination`. An unspecified `source` is replaced by the
+    /// interface's address.
+    fn send_ip(
+        &mut self,
+        source: Ipv4Address,
+        destination: Ipv4Address,
+        protocol: u8,
+        payload_len: usize,
+        checksum_offset: Option<usize>,
+        write: impl FnOnce(&mut Vec<u8>, &Ipv4Header),
+    ) -> Result<(), SocketError> {
+        let (id, next_hop) = self.route(destination).ok_or(SocketError::NoRoute)?;
+        if IPV4_HEADER_SIZE + payload_len > self.interfaces[id.0].mtu {
+            return Err(SocketError::MessageTooLarge);
+        }
+        let source = if source.is_unspecified() {
+            self.source_address(id, destination)
+        } else {
+            source
+        };
+        let packet = self.ip_packet(
+            source,
+            destination,
+            protocol,
+            payload_len,
+            checksum_offset,
+            write,
+        );
+        self.send_packet(id, next_hop, packet);
+        Ok(())
+    }
+
+    fn send_packet(&mut self, id: InterfaceId, next_hop: Ipv4Address, mut packet: Frame) {
+        let now = self.now;
+        let interface = &mut self.interfaces[id.0];
+        if interface.is_loopback() {
+            packet.finish_checksum();
+            self.loopback.push_back(packet.data);
+            return;
+        }
+        let broadcast = next_hop.is_broadcast()
+            || interface
+                .address
+                .is_some_and(|cidr| cidr.broadcast() == next_hop);
+        if broadcast {
+            self.send_frame(id, MacAddress::BROADCAST, ether_type::IPV4, packet);
+            return;
+        }
+        match interface.neighbors.lookup(next_hop, now) {
+            Some(mac) => self.send_frame(id, mac, ether_type::IPV4, packet),
+            None => {
+                if interface.neighbors.enqueue(next_hop, packet, now) {
+                    self.send_arp_request(id, next_hop);
+                }
+            }
+        }
+    }
+
+    fn send_frame(
+        &mut self,
+        id: InterfaceId,
+        destination: MacAddress,
+        ether_type: u16,
+        packet: Frame,
+    ) {
+        let source = match self.interfaces[id.0].mac {
+            Some(mac) => mac,
+            None => return,
+        };
+        let mut data = Vec::with_capacity(ETHERNET_HEADER_SIZE + packet.data.len());
+        EthernetHeader {
+            destination,
+            source,
+            ether_type,
+        }
+        .write(&mut data);
+        data.extend_from_slice(&packet.data);
+        let checksum = packet
+            .checksum
+            .map(|(start, offset)| (start + ETHERNET_HEADER_SIZE, offset));
+        self.outgoing.push_back((id, Frame { data, checksum }));
+    }
+
+    fn send_arp(&mut self, id: InterfaceId, destination: MacAddress, packet: ArpPacket) {
+        let mut data = Vec::with_capacity(ARP_PACKET_SIZE);
+        packet.write(&mut data);
+        let frame = Frame {
+            data,
+            checksum: None,
+        };
+        self.send_frame(id, destination, ether_type::ARP, frame);
+    }
+
+    fn send_arp_request(&mut self, id: InterfaceId, address: Ipv4Address) {
+        let interface = &self.interfaces[id.0];
+        let request = ArpPacket {
+            operation: ArpPacket::REQUEST,
+            sender_mac: interface.mac.unwrap_or_default(),
+            sender_address: interface
+                .address
+                .map_or(Ipv4Address::UNSPECIFIED, |cidr| cidr.address),
+            target_mac: MacAddress::default(),
+            target_address: address,
+        };
+        self.send_arp(id, MacAddress::BROADCAST, request);
+    }
+
+    /// DHCP messages are broadcast on their interface, whatever the routes are
+    fn send_dhcp(&mut self, id: InterfaceId, message: &[u8]) {
+        let source = match &self.interfaces[id.0].dhcp {
+            Some(dhcp) => dhcp.address(),
+            None => return,
+        };
+        let header = UdpHeader {
+            source_port: dhcp::CLIENT_PORT,
+            destination_port: dhcp::SERVER_PORT,
+        };
+        let packet = self.ip_packet(
+            source,
+            Ipv4Address::BROADCAST,
+            ip_protocol::UDP,
+            UDP_HEADER_SIZE + message.len(),
+            Some(UDP_CHECKSUM_OFFSET),
+            |out, ip| header.write(out, message, ip),
+        );
+        self.send_frame(id, MacAddress::BROADCAST, ether_type::IPV4, packet);
+    }
+
+    fn send_tcp(
+        &mut self,
+        local: SocketAddress,
+        remote: SocketAddress,
+        header: TcpHeader,
+        payload: &[u8],
+    ) {
+        let len = TCP_HEADER_SIZE + if header.mss.is_some() { 4 } else { 0 } + payload.len();
+        let result = self.send_ip(
+            local.address,
+            remote.address,
+            ip_protocol::TCP,
+            len,
+            Some(TCP_CHECKSUM_OFFSET),
+            |out, ip| header.write(out, payload, ip),
+        );
+        if let Err(error) = result {
+            log::debug!("tcp: can't send to {}: {:?}", remote, error);
+        }
+    }
+
+    fn dispatch_tcp(&mut self) {
+        let now = self.now;
+        let handles: Vec<SocketHandle> = self.sockets.keys().copied().collect();
+        for handle in handles {
+            let mut segments = Vec::new();
+            let (local, remote) = match self.sockets.get_mut(&handle).map(|e| &mut e.socket) {
+                Some(Socket::Tcp(socket)) => {
+                    socket.dispatch(now, &mut |header, payload| {
+                        segments.push((header, payload.to_vec()))
+                    });
+                    match (socket.local, socket.remote) {
+                        (Some(local), Some(remote)) => (local, remote),
+                        _ => continue,
+                    }
+                }
+                _ => continue,
+            };
+            for (header, payload) in segments {
+                self.send_tcp(local, remote, header, &payload);
+            }
+        }
+    }
+
+    // Receiving
+
+    fn process_arp(&mut self, id: InterfaceId, data: &[u8]) {
+        let packet = match ArpPacket::parse(data) {
+            Some(packet) => packet,
+            None => return,
+        };
+        let now = self.now;
+        let interface = &mut self.interfaces[id.0];
+        let (mac, address) = match (interface.mac, interface.address) {
+            (Some(mac), Some(cidr)) => (mac, cidr.address),
+            _ => return,
+        };
+        if packet.target_address != address {
+            return;
+        }
+        let waiting = interface
+            .neighbors
+            .insert(packet.sender_address, packet.sender_mac, now);
+        if packet.operation == ArpPacket::REQUEST {
+            let reply = ArpPacket {
+                operation: ArpPacket::REPLY,
+                sender_mac: mac,
+                sender_address: address,
+                target_mac: packet.sender_mac,
+                target_address: packet.sender_address,
+            };
+            self.send_arp(id, packet.sender_mac, reply);
+        }
+        for waiting in waiting {
+            self.send_frame(id, packet.sender_mac, ether_type::IPV4, waiting);
+        }
+    }
+
+    fn process_ipv4(&mut self, id: InterfaceId, packet: &[u8]) {
+        let (ip, payload) = match Ipv4Header::parse(packet) {
+            Some(parsed) => parsed,
+            None => return,
+        };
+        if !self.interfaces[id.0].accepts(ip.destination) {
+            return;
+        }
+        match ip.protocol {
+            ip_protocol::ICMP => self.process_icmp(&ip, payload),
+            ip_protocol::UDP => self.process_udp(id, &ip, packet, payload),
+            ip_protocol::TCP => self.process_tcp(&ip, payload),
+            _ => {}
+        }
+    }
+
+    fn process_icmp(&mut self, ip: &Ipv4Header, message: &[u8]) {
+        let (icmp, payload) = match IcmpHeader::parse(message) {
+            Some(parsed) => parsed,
+            None => return,
+        };
+        match icmp.kind {
+            IcmpHeader::ECHO_REQUEST if self.is_local_address(ip.destination) => {
+                let reply = IcmpHeader {
+                    kind: IcmpHeader::ECHO_REPLY,
+                    ..icmp
+                };
+                let _ = self.send_ip(
+                    ip.destination,
+                    ip.source,
+                    ip_protocol::ICMP,
+                    message.len(),
+                    None,
+                    |out, _| reply.write(out, payload),
+                );
+            }
+            IcmpHeader::ECHO_REPLY => {
+                let source = SocketAddress::new(ip.source, 0);
+                let destination = SocketAddress::new(ip.destination, icmp.identifier());
+                for entry in self.sockets.values_mut() {
+                    if let Socket::Icmp(socket) = &mut entry.socket {
+                        if socket
+                            .local
+                            .is_some_and(|local| local_matches(local, destination))
+                            && socket.accepts_from(source)
+                        {
+                            socket.deliver(source, message);
+                            return;
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn process_udp(&mut self, id: InterfaceId, ip: &Ipv4Header, packet: &[u8], data: &[u8]) {
+        let (udp, payload) = match UdpHeader::parse(data, ip) {
+            Some(parsed) => parsed,
+            None => return,
+        };
+        if udp.destination_port == dhcp::CLIENT_PORT {
+            let now = self.now;
+            if let Some(dhcp) = &mut self.interfaces[id.0].dhcp {
+                if let Some(event) = dhcp.process(payload, now) {
+                    self.apply_dhcp(id, event);
+                }
+                return;
+            }
+        }
+
+        let source = SocketAddress::new(ip.source, udp.source_port);
+        let destination = SocketAddress::new(ip.destination, udp.destination_port);
+        for entry in self.sockets.values_mut() {
+            if let Socket::Udp(socket) = &mut entry.socket {
+                if socket
+                    .local
+                    .is_some_and(|local| local_matches(local, destination))
+                    && socket.accepts_from(source)
+                {
+                    socket.deliver(source, payload);
+                    return;
+                }
+            }
+        }
+        if self.is_local_address(ip.destination) {
+            self.send_port_unreachable(ip, packet);
+        }
+    }
+
+    /// Tells the sender of `packet` that nobody is listening on the port it was sent to
+    fn send_port_unreachable(&mut self, ip: &Ipv4Header, packet: &[u8]) {
+        // The IP header and the first 8 bytes of the payload, so that the sender can tell
+        // which datagram it was
+        let header_len = (packet[0] & 0xf) as usize * 4;
+        let quoted = &packet[..packet.len().min(header_len + 8)];
+        let message = IcmpHeader {
+            kind: IcmpHeader::DESTINATION_UNREACHABLE,
+            code: IcmpHeader::PORT_UNREACHABLE,
+            rest: [0; 4],
+        };
+        let _ = self.send_ip(
+            ip.destination,
+            ip.source,
+            ip_protocol::ICMP,
+            ICMP_HEADER_SIZE + quoted.len(),
+            None,
+            |out, _| message.write(out, quoted),
+        );
+    }
+
+    fn tcp_socket(&mut self, handle: SocketHandle) -> Option<&mut TcpSocket> {
+        match self.sockets.get_mut(&handle).map(|entry| &mut entry.socket) {
+            Some(Socket::Tcp(socket)) => Some(socket),
+            _ => None,
+        }
+    }
+
+    fn process_tcp(&mut self, ip: &Ipv4Header, data: &[u8]) {
+        let (tcp, payload) = match TcpHeader::parse(data, ip) {
+            Some(parsed) => parsed,
+            None => return,
+        };
+        let local = SocketAddress::new(ip.destination, tcp.destination_port);
+        let remote = SocketAddress::new(ip.source, tcp.source_port);
+        let now = self.now;
+
+        let connection = self
+            .sockets
+            .iter()
+            .find_map(|(handle, entry)| match &entry.socket {
+                Socket::Tcp(socket)
+                    if socket.local == Some(local)
+                        && socket.remote == Some(remote)
+                        && !matches!(socket.state, TcpState::Closed | TcpState::Listen) =>
+                {
+                    Some(*handle)
+                }
+                _ => None,
+            });
+        if let Some(handle) = connection {
+            let reset = self
+                .tcp_socket(handle)
+                .and_then(|socket| socket.process(&tcp, payload, now));
+            if let Some(reset) = reset {
+                self.send_tcp(local, remote, reset, &[]);
+            }
+            self.update_listener(handle);
+            return;
+        }
+
+        let listener = self
+            .sockets
+            .iter()
+            .find_map(|(handle, entry)| match &entry.socket {
+                Socket::Tcp(socket)
+                    if socket.state == TcpState::Listen
+                        && socket.local.is_some_and(|l| local_matches(l, local)) =>
+                {
+                    Some(*handle)
+                }
+                _ => None,
+            });
+        if let Some(listener) = listener {
+            if tcp.flags & (tcp_flags::SYN | tcp_flags::ACK | tcp_flags::RST) == tcp_flags::SYN {
+                self.accept_syn(listener, local, remote, &tcp);
+                return;
+            }
+        }
+        if let Some(reset) = tcp::reset_for(&tcp, payload.len()) {
+            self.send_tcp(local, remote, reset, &[]);
+        }
+    }
+
+    /// Starts a connection for a SYN that came to `listener`, if its backlog has room
+    fn accept_syn(
+        &mut self,
+        listener: SocketHandle,
+        local: SocketAddress,
+        remote: SocketAddress,
+        syn: &TcpHeader,
+    ) {
+        let mss = match self.mss_for(remote.address) {
+            Some(mss) => mss,
+            None => return,
+        };
+        let iss = self.iss(local, remote);
+        let handle = self.allocate_handle();
+        let queue = match self.tcp_socket(listener).and_then(|s| s.listener.as_mut()) {
+            Some(queue) => queue,
+            None => return,
+        };
+        if queue.pending.len() + queue.ready.len() >= queue.backlog {
+            // The peer will try again
+            return;
+        }
+        queue.pending.push(handle);
+        let socket = TcpSocket::accept_syn(listener, local, remote, syn, iss, mss);
+        self.sockets.insert(
+            handle,
+            Entry {
+                socket: Socket::Tcp(socket),
+                released: false,
+            },
+        );
+    }
+
+    /// Moves a connection that finished its handshake to its listener's accept queue, and
+    /// forgets connections that failed during it
+    fn update_listener(&mut self, handle: SocketHandle) {
+        let (parent, state, synchronized) = match self.tcp_socket(handle) {
+            Some(socket) => match socket.parent {
+                Some(parent) => (parent, socket.state, socket.is_synchronized()),
+                None => return,
+            },
+            None => return,
+        };
+        let queue = match self.tcp_socket(parent).and_then(|s| s.listener.as_mut()) {
+            Some(queue) => queue,
+            None => return,
+        };
+        let index = match queue.pending.iter().position(|pending| *pending == handle) {
+            Some(index) => index,
+            None => return,
+        };
+        if synchronized {
+            queue.pending.remove(index);
+            queue.ready.push_back(handle);
+        } else if state == TcpState::Closed {
+            queue.pending.remove(index);
+            self.sockets.remove(&handle);
+        }
+    }
+
+    fn remove_closed_sockets(&mut self) {
+        let handles: Vec<SocketHandle> = self.sockets.keys().copied().collect();
+        for handle in handles {
+            self.update_listener(handle);
+        }
+        self.sockets.retain(|_, entry| {
+            !(entry.released
+                && matches!(&entry.socket, Socket::Tcp(socket) if socket.state == TcpState::Closed))
+        });
+    }
+
+    // Sockets
+
+    fn allocate_handle(&mut self) -> SocketHandle {
+        loop {
+            let handle = SocketHandle(self.next_socket);
+            self.next_socket = self.next_socket.wrapping_add(1);
+            if !self.sockets.contains_key(&handle) {
+                return handle;
+            }
+        }
+    }
+
+    fn socket_mut(&mut self, handle: SocketHandle) -> Result<&mut Socket, SocketError> {
+        match self.sockets.get_mut(&handle) {
+            Some(entry) if !entry.released => Ok(&mut entry.socket),
+            _ => Err(SocketError::InvalidSocket),
+        }
+    }
+
+    fn socket_ref(&self, handle: SocketHandle) -> Result<&Socket, SocketError> {
+        match self.sockets.get(&handle) {
+            Some(entry) if !entry.released => Ok(&entry.socket),
+            _ => Err(SocketError::InvalidSocket),
+        }
+    }
+
+    fn port_in_use(&self, kind: SocketKind, port: u16) -> bool {
+        self.sockets.values().any(|entry| {
+            !entry.released
+                && entry.socket.kind() == kind
+                && entry.socket.bound_port() == Some(port)
+        })
+    }
+
+    fn ephemeral_port(&mut self, kind: SocketKind) -> Result<u16, SocketError> {
+        for _ in FIRST_EPHEMERAL_PORT..=u16::MAX {
+            let port = self.next_port;
+            self.next_port = if port == u16::MAX {
+                FIRST_EPHEMERAL_PORT
+            } else {
+                port + 1
+            };
+            if !self.port_in_use(kind, port) {
+                return Ok(port);
+            }
+        }
+        Err(SocketError::AddressInUse)
+    }
+
+    /// Binds the socket to an ephemeral port if it isn't bound yet
+    fn bind_ephemeral(&mut self, handle: SocketHandle) -> Result<SocketAddress, SocketError> {
+        let socket = self.socket_ref(handle)?;
+        if let Some(local) = socket.local() {
+            return Ok(local);
+        }
+        let port = self.ephemeral_port(socket.kind())?;
+        let local = SocketAddress::new(Ipv4Address::UNSPECIFIED, port);
+        self.socket_mut(handle)?.set_local(local);
+        Ok(local)
+    }
+
+    pub fn socket(&mut self, kind: SocketKind) -> SocketHandle {
+        let handle = self.allocate_handle();
+        let entry = Entry {
+            socket: Socket::new(kind),
+            released: false,
+        };
+        self.sockets.insert(handle, entry);
+        handle
+    }
+
+    /// Port 0 picks an unused port. For ICMP sockets, the port is the echo identifier.
+    pub fn bind(
+        &mut self,
+        handle: SocketHandle,
+        address: SocketAddress,
+    ) -> Result<(), SocketError> {
+        let socket = self.socket_ref(handle)?;
+        if socket.local().is_some() {
+            return Err(SocketError::InvalidOperation);
+        }
+        let kind = socket.kind();
+        if !address.address.is_unspecified() && !self.is_local_address(address.address) {
+            return Err(SocketError::AddressNotAvailable);
+        }
+        let port = if address.port == 0 {
+            self.ephemeral_port(kind)?
+        } else if self.port_in_use(kind, address.port) {
+            return Err(SocketError::AddressInUse);
+        } else {
+            address.port
+        };
+        self.socket_mut(handle)?
+            .set_local(SocketAddress::new(address.address, port));
+        Ok(())
+    }
+
+    /// Starts connecting a TCP socket. `send` returns `WouldBlock` until the connection is
+    /// established, and an error if it couldn't be.
+    /// UDP and ICMP sockets only remember `remote` for `send` and `recv`.
+    pub fn connect(
+        &mut self,
+        handle: SocketHandle,
+        remote: SocketAddress,
+    ) -> Result<(), SocketError> {
+        match self.socket_ref(handle)? {
+            Socket::Tcp(socket) if socket.state == TcpState::Listen => {
+                return Err(SocketError::InvalidOperation)
+            }
+            Socket::Tcp(socket) if socket.remote.is_some() => {
+                return Err(SocketError::AlreadyConnected)
+            }
+            _ => {}
+        }
+        let (id, _) = self.route(remote.address).ok_or(SocketError::NoRoute)?;
+        let mut local = self.bind_ephemeral(handle)?;
+        if let Socket::Udp(_) | Socket::Icmp(_) = self.socket_ref(handle)? {
+            let remote = match self.socket_ref(handle)? {
+                Socket::Icmp(_) => SocketAddress::new(remote.address, 0),
+                _ => remote,
+            };
+            match self.socket_mut(handle)? {
+                Socket::Udp(socket) | Socket::Icmp(socket) => socket.remote = Some(remote),
+                Socket::Tcp(_) => unreachable!(),
+            }
+            return Ok(());
+        }
+
+        if local.address.is_unspecified() {
+            local.address = self.source_address(id, remote.address);
+            if local.address.is_unspecified() {
+                // The interface doesn't have an address yet
+                return Err(SocketError::AddressNotAvailable);
+            }
+        }
+        let mss = self.mss_for(remote.address).ok_or(SocketError::NoRoute)?;
+        let iss = self.iss(local, remote);
+        if let Socket::Tcp(socket) = self.socket_mut(handle)? {
+            socket.connect(local, remote, iss, mss);
+        }
+        Ok(())
+    }
+
+    /// Makes a TCP socket accept connections, binding it to an ephemeral port if it isn't
+    /// bound. At most `backlog` connections wait for `accept`.
+    pub fn listen(&mut self, handle: SocketHandle, backlog: usize) -> Result<(), SocketError> {
+        match self.socket_ref(handle)? {
+            Socket::Tcp(socket) if socket.state == TcpState::Closed && socket.remote.is_none() => {}
+            _ => return Err(SocketError::InvalidOperation),
+        }
+        self.bind_ephemeral(handle)?;
+        if let Socket::Tcp(socket) = self.socket_mut(handle)? {
+            socket.listen(backlog.max(1));
+        }
+        Ok(())
+    }
+
+    /// Takes a connection that a listening socket accepted, and returns its peer's address
+    pub fn accept(
+        &mut self,
+        handle: SocketHandle,
+    ) -> Result<(SocketHandle, SocketAddress), SocketError> {
+        let connection = match self.socket_mut(handle)? {
+            Socket::Tcp(TcpSocket {
+                listener: Some(listener),
+                ..
+            }) => listener.ready.pop_front().ok_or(SocketError::WouldBlock)?,
+            _ => return Err(SocketError::InvalidOperation),
+        };
+        let remote = self.socket_ref(connection)?.remote();
+        Ok((connection, remote.unwrap()))
+    }
+
+    /// Sends to the address the socket is connected to. TCP sockets take as much of `data`
+    /// as fits in their buffer, while datagrams are sent whole.
+    pub fn send(&mut self, handle: SocketHandle, data: &[u8]) -> Result<usize, SocketError> {
+        let remote = match self.socket_mut(handle)? {
+            Socket::Tcp(socket) => return socket.send(data),
+            Socket::Udp(socket) | Socket::Icmp(socket) => {
+                socket.remote.ok_or(SocketError::NotConnected)?
+            }
+        };
+        self.send_to(handle, data, remote)
+    }
+
+    /// Sends a datagram. For ICMP sockets, `data` is an echo request, header included.
+    pub fn send_to(
+        &mut self,
+        handle: SocketHandle,
+        data: &[u8],
+        remote: SocketAddress,
+    ) -> Result<usize, SocketError> {
+        let kind = self.socket_ref(handle)?.kind();
+        let local = match kind {
+            SocketKind::Tcp => return Err(SocketError::InvalidOperation),
+            _ => self.bind_ephemeral(handle)?,
+        };
+        match kind {
+            SocketKind::Udp => {
+                let header = UdpHeader {
+                    source_port: local.port,
+                    destination_port: remote.port,
+                };
+                self.send_ip(
+                    local.address,
+                    remote.address,
+                    ip_protocol::UDP,
+                    UDP_HEADER_SIZE + data.len(),
+                    Some(UDP_CHECKSUM_OFFSET),
+                    |out, ip| header.write(out, data, ip),
+                )?;
+            }
+            _ => {
+                if data.len() < ICMP_HEADER_SIZE || data[0] != IcmpHeader::ECHO_REQUEST {
+                    return Err(SocketError::InvalidOperation);
+                }
+                let [id_high, id_low] = local.port.to_be_bytes();
+                let header = IcmpHeader {
+                    kind: IcmpHeader::ECHO_REQUEST,
+                    code: 0,
+                    rest: [id_high, id_low, data[6], data[7]],
+                };
+                self.send_ip(
+                    local.address,
+                    remote.address,
+                    ip_protocol::ICMP,
+                    data.len(),
+                    None,
+                    |out, _| header.write(out, &data[ICMP_HEADER_SIZE..]),
+                )?;
+            }
+        }
+        Ok(data.len())
+    }
+
+    /// Receives at most `max_len` bytes. The rest of a datagram is dropped.
+    pub fn recv(&mut self, handle: SocketHandle, max_len: usize) -> Result<Vec<u8>, SocketError> {
+        self.recv_from(handle, max_len).map(|(data, _)| data)
+    }
+
+    pub fn recv_from(
+        &mut self,
+        handle: SocketHandle,
+        max_len: usize,
+    ) -> Result<(Vec<u8>, SocketAddress), SocketError> {
+        match self.socket_mut(handle)? {
+            Socket::Tcp(socket) => {
+                let remote = socket.remote.ok_or(SocketError::NotConnected)?;
+                socket.recv(max_len).map(|data| (data, remote))
+            }
+            Socket::Udp(socket) | Socket::Icmp(socket) => socket.recv_from(max_len),
+        }
+    }
+
+    /// Gives the handle back. TCP connections are closed gracefully, after the data that's
+    /// still buffered is sent, and so are connections a listener hadn't handed out yet.
+    pub fn close(&mut self, handle: SocketHandle) -> Result<(), SocketError> {
+        let socket = match self.socket_mut(handle)? {
+            Socket::Tcp(socket) => socket,
+            _ => {
+                self.sockets.remove(&handle);
+                return Ok(());
+            }
+        };
+        socket.close();
+        let connections: Vec<SocketHandle> = match socket.listener.take() {
+            Some(listener) => listener.pending.into_iter().chain(listener.ready).collect(),
+            None => Vec::new(),
+        };
+        self.sockets.get_mut(&handle).unwrap().released = true;
+        for connection in connections {
+            let _ = self.close(connection);
+        }
+        Ok(())
+    }
+
+    pub fn readiness(&self, handle: SocketHandle) -> Result<Readiness, SocketError> {
+        Ok(self.socket_ref(handle)?.readiness())
+    }
+
+    pub fn local_address(
+        &self,
+        handle: SocketHandle,
+    ) -> Result<Option<SocketAddress>, SocketError> {
+        Ok(self.socket_ref(handle)?.local())
+    }
+
+    pub fn remote_address(
+        &self,
+        handle: SocketHandle,
+    ) -> Result<Option<SocketAddress>, SocketError> {
+        Ok(self.socket_ref(handle)?.remote())
+    }
+
+    pub fn tcp_state(&self, handle: SocketHandle) -> Result<TcpState, SocketError> {
+        match self.socket_ref(handle)? {
+            Socket::Tcp(socket) => Ok(socket.state),
+            _ => Err(SocketError::InvalidOperation),
+        }
+    }
+}
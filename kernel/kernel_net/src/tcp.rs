@@ -0,0 +1,591 @@
+//! TCP connections, without the stack around them: the state machine of RFC 793, with
+//! retransmission (go-back-N) and flow control, but without congestion control. Segments
+//! that arrive out of order are dropped and the peer sends them again.
+
+use alloc::{collections::VecDeque, vec::Vec};
+
+use crate::{
+    wire::{tcp_flags::*, TcpHeader},
+    Readiness, SocketAddress, SocketError, SocketHandle,
+};
+
+pub(crate) const SEND_BUFFER_SIZE: usize = 16 * 1024;
+pub(crate) const RECEIVE_BUFFER_SIZE: usize = 16 * 1024;
+/// What the peer can send if it doesn't say (RFC 1122, 4.2.2.6)
+const DEFAULT_MSS: usize = 536;
+const INITIAL_RTO: u64 = 1000;
+const MAX_RTO: u64 = 60_000;
+/// After this many retransmissions of the same data, the connection is given up
+const MAX_RETRIES: u32 = 8;
+/// How long the addresses of a closed connection stay reserved, so that late segments
+/// aren't taken for a new connection's. Much shorter than RFC 793's four minutes.
+const TIME_WAIT: u64 = 2000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TcpState {
+    Closed,
+    Listen,
+    SynSent,
+    SynReceived,
+    Established,
+    FinWait1,
+    FinWait2,
+    CloseWait,
+    Closing,
+    LastAck,
+    TimeWait,
+}
+
+use TcpState::*;
+
+/// Sequence numbers wrap around, so they're compared by their distance
+fn seq_lt(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) < 0
+}
+
+fn seq_gt(a: u32, b: u32) -> bool {
+    seq_lt(b, a)
+}
+
+/// The reset that answers `segment`, which didn't belong to any connection.
+/// Resets are never answered.
+pub(crate) fn reset_for(segment: &TcpHeader, payload_len: usize) -> Option<TcpHeader> {
+    if segment.has(RST) {
+        return None;
+    }
+    let mut reset = TcpHeader {
+        source_port: segment.destination_port,
+        destination_port: segment.source_port,
+        ..TcpHeader::default()
+    };
+    if segment.has(ACK) {
+        reset.seq = segment.ack;
+        reset.flags = RST;
+    } else {
+        reset.ack = segment.seq.wrapping_add(segment.sequence_len(payload_len));
+        reset.flags = RST | ACK;
+    }
+    Some(reset)
+}
+
+/// Connections that came to a listening socket
+pub(crate) struct Listener {
+    pub(crate) backlog: usize,
+    /// Still in the handshake
+    pub(crate) pending: Vec<SocketHandle>,
+    /// Waiting for `accept`
+    pub(crate) ready: VecDeque<SocketHandle>,
+}
+
+pub(crate) struct TcpSocket {
+    pub(crate) state: TcpState,
+    pub(crate) local: Option<SocketAddress>,
+    pub(crate) remote: Option<SocketAddress>,
+    pub(crate) listener: Option<Listener>,
+    /// The listener that created this connection
+    pub(crate) parent: Option<SocketHandle>,
+
+    /// Our initial sequence number, which the SYN takes
+    iss: u32,
+    /// The oldest byte that hasn't been acknowledged, which is the first of `send_buffer`
+    snd_una: u32,
+    /// The next byte to send. It goes back to `snd_una` when retransmitting.
+    snd_nxt: u32,
+    /// The furthest `snd_nxt` has been
+    snd_max: u32,
+    /// How much the peer is willing to receive, from `snd_una`
+    snd_wnd: u32,
+    rcv_nxt: u32,
+    /// The largest segment the peer wants
+    mss: usize,
+    /// The largest segment we want, which depends on the interface
+    local_mss: usize,
+
+    send_buffer: VecDeque<u8>,
+    receive_buffer: VecDeque<u8>,
+    /// The socket was closed, so a FIN goes after the data in `send_buffer`
+    closing: bool,
+    fin_sent: bool,
+    fin_received: bool,
+    ack_pending: bool,
+
+    rto: u64,
+    retransmit_at: Option<u64>,
+    retries: u32,
+    time_wait_until: u64,
+    pub(crate) error: Option<SocketError>,
+}
+
+impl TcpSocket {
+    pub(crate) fn new() -> Self {
+        Self {
+            state: Closed,
+            local: None,
+            remote: None,
+            listener: None,
+            parent: None,
+            iss: 0,
+            snd_una: 0,
+            snd_nxt: 0,
+            snd_max: 0,
+            snd_wnd: 0,
+            rcv_nxt: 0,
+            mss: DEFAULT_MSS,
+            local_mss: DEFAULT_MSS,
+            send_buffer: VecDeque::new(),
+            receive_buffer: VecDeque::new(),
+            closing: false,
+            fin_sent: false,
+            fin_received: false,
+            ack_pending: false,
+            rto: INITIAL_RTO,
+            retransmit_at: None,
+            retries: 0,
+            time_wait_until: 0,
+            error: None,
+        }
+    }
+
+    fn start(&mut self, local: SocketAddress, remote: SocketAddress, iss: u32, mss: usize) {
+        self.local = Some(local);
+        self.remote = Some(remote);
+        self.iss = iss;
+        self.snd_una = iss;
+        self.snd_nxt = iss;
+        self.snd_max = iss;
+        self.local_mss = mss;
+        self.mss = DEFAULT_MSS.min(mss);
+    }
+
+    /// Starts the handshake. `mss` is the largest segment the interface can take.
+    pub(crate) fn connect(
+        &mut self,
+        local: SocketAddress,
+        remote: SocketAddress,
+        iss: u32,
+        mss: usize,
+    ) {
+        self.start(local, remote, iss, mss);
+        self.state = SynSent;
+    }
+
+    pub(crate) fn listen(&mut self, backlog: usize) {
+        self.state = Listen;
+        self.listener = Some(Listener {
+            backlog,
+            pending: Vec::new(),
+            ready: VecDeque::new(),
+        });
+    }
+
+    /// A connection for a SYN that came to listener `parent`
+    pub(crate) fn accept_syn(
+        parent: SocketHandle,
+        local: SocketAddress,
+        remote: SocketAddress,
+        syn: &TcpHeader,
+        iss: u32,
+        mss: usize,
+    ) -> Self {
+        let mut socket = Self::new();
+        socket.start(local, remote, iss, mss);
+        socket.parent = Some(parent);
+        socket.state = SynReceived;
+        socket.rcv_nxt = syn.seq.wrapping_add(1);
+        socket.snd_wnd = syn.window as u32;
+        socket.set_peer_mss(syn.mss);
+        socket
+    }
+
+    fn set_peer_mss(&mut self, mss: Option<u16>) {
+        self.mss = mss
+            .map_or(DEFAULT_MSS, |mss| mss as usize)
+            .min(self.local_mss);
+    }
+
+    /// Whether the connection has gotten past the handshake
+    pub(crate) fn is_synchronized(&self) -> bool {
+        !matches!(self.state, Closed | Listen | SynSent | SynReceived)
+    }
+
+    fn abort(&mut self, error: SocketError) {
+        self.state = Closed;
+        self.error = Some(error);
+        self.retransmit_at = None;
+        self.send_buffer.clear();
+    }
+
+    fn enter_time_wait(&mut self, now: u64) {
+        self.state = TimeWait;
+        self.time_wait_until = now + TIME_WAIT;
+        self.retransmit_at = None;
+    }
+
+    fn window(&self) -> u16 {
+        (RECEIVE_BUFFER_SIZE - self.receive_buffer.len()).min(u16::MAX as usize) as u16
+    }
+
+    fn in_flight(&self) -> usize {
+        self.snd_nxt.wrapping_sub(self.snd_una) as usize
+    }
+
+    /// Data in `send_buffer` that hasn't been sent since the last retransmission
+    fn unsent(&self) -> usize {
+        self.send_buffer.len() - self.in_flight().min(self.send_buffer.len())
+    }
+
+    /// Whether the FIN has to be sent, for the first time or again
+    fn fin_due(&self) -> bool {
+        self.closing
+            && self.in_flight() == self.send_buffer.len()
+            && matches!(
+                self.state,
+                Established | CloseWait | FinWait1 | Closing | LastAck
+            )
+    }
+
+    fn wants_to_send(&self) -> bool {
+        match self.state {
+            SynSent | SynReceived => self.snd_nxt == self.iss,
+            Closed | Listen | TimeWait => self.ack_pending,
+            _ => {
+                let window = (self.snd_wnd as usize).saturating_sub(self.in_flight());
+                self.ack_pending || (self.unsent() > 0 && window > 0) || self.fin_due()
+            }
+        }
+    }
+
+    /// When `dispatch` has something to do next
+    pub(crate) fn poll_at(&self) -> Option<u64> {
+        if self.wants_to_send() {
+            return Some(0);
+        }
+        match self.state {
+            TimeWait => Some(self.time_wait_until),
+            _ => self.retransmit_at,
+        }
+    }
+
+    fn header(&self, flags: u8, seq: u32) -> TcpHeader {
+        TcpHeader {
+            source_port: self.local.map_or(0, |local| local.port),
+            destination_port: self.remote.map_or(0, |remote| remote.port),
+            seq,
+            ack: if flags & ACK != 0 { self.rcv_nxt } else { 0 },
+            flags,
+            window: self.window(),
+            mss: None,
+        }
+    }
+
+    /// Records that everything up to `snd_nxt` has been sent
+    fn sent(&mut self, now: u64) {
+        if seq_gt(self.snd_nxt, self.snd_max) {
+            self.snd_max = self.snd_nxt;
+        }
+        self.ack_pending = false;
+        if self.retransmit_at.is_none() {
+            self.retransmit_at = Some(now + self.rto);
+        }
+    }
+
+    /// Runs the timers and passes the segments that have to be sent to `emit`
+    pub(crate) fn dispatch(&mut self, now: u64, emit: &mut dyn FnMut(TcpHeader, &[u8])) {
+        if self.state == TimeWait && now >= self.time_wait_until {
+            self.state = Closed;
+        }
+        let mut probe = false;
+        match self.retransmit_at {
+            Some(at) if at <= now => {
+                self.retransmit_at = None;
+                if self.in_flight() == 0 {
+                    // The peer's window is closed. Send a byte to see if it opened.
+                    probe = true;
+                } else {
+                    // A peer whose window is closed is there, it just isn't reading
+                    if self.snd_wnd != 0 || !self.is_synchronized() {
+                        self.retries += 1;
+                    }
+                    if self.retries > MAX_RETRIES {
+                        self.abort(SocketError::TimedOut);
+                        return;
+                    }
+                    self.rto = (self.rto * 2).min(MAX_RTO);
+                    self.snd_nxt = self.snd_una;
+                }
+            }
+            _ => {}
+        }
+
+        match self.state {
+            Closed | Listen | TimeWait => {}
+            SynSent | SynReceived => {
+                if self.snd_nxt == self.iss {
+                    let flags = if self.state == SynSent {
+                        SYN
+                    } else {
+                        SYN | ACK
+                    };
+                    let mut syn = self.header(flags, self.iss);
+                    syn.mss = Some(self.local_mss.min(u16::MAX as usize) as u16);
+                    emit(syn, &[]);
+                    self.snd_nxt = self.iss.wrapping_add(1);
+                    self.sent(now);
+                }
+                return;
+            }
+            _ => loop {
+                let sent = self.send_buffer.len() - self.unsent();
+                let mut window = (self.snd_wnd as usize).saturating_sub(self.in_flight());
+                if probe && window == 0 {
+                    window = 1;
+                    probe = false;
+                }
+                let len = self.unsent().min(window).min(self.mss);
+                if len == 0 {
+                    break;
+                }
+                let payload: Vec<u8> = self.send_buffer.range(sent..sent + len).copied().collect();
+                let flags = if len == self.unsent() { ACK | PSH } else { ACK };
+                emit(self.header(flags, self.snd_nxt), &payload);
+                self.snd_nxt = self.snd_nxt.wrapping_add(len as u32);
+                self.sent(now);
+            },
+        }
+
+        if self.fin_due() {
+            emit(self.header(FIN | ACK, self.snd_nxt), &[]);
+            self.snd_nxt = self.snd_nxt.wrapping_add(1);
+            self.fin_sent = true;
+            self.sent(now);
+            self.state = match self.state {
+                Established => FinWait1,
+                CloseWait => LastAck,
+                state => state,
+            };
+        }
+        if self.ack_pending {
+            emit(self.header(ACK, self.snd_nxt), &[]);
+            self.ack_pending = false;
+        }
+        if self.retransmit_at.is_none() && self.snd_wnd == 0 && self.unsent() > 0 {
+            self.retransmit_at = Some(now + self.rto);
+        }
+    }
+
+    /// Handles a segment for this connection. Returns a reset to send back if the segment
+    /// makes no sense.
+    pub(crate) fn process(
+        &mut self,
+        segment: &TcpHeader,
+        payload: &[u8],
+        now: u64,
+    ) -> Option<TcpHeader> {
+        match self.state {
+            Closed | Listen => None,
+            SynSent => self.process_syn_sent(segment, payload),
+            _ => self.process_synchronized(segment, payload, now),
+        }
+    }
+
+    fn process_syn_sent(&mut self, segment: &TcpHeader, payload: &[u8]) -> Option<TcpHeader> {
+        if segment.has(ACK) && segment.ack != self.snd_max {
+            return reset_for(segment, payload.len());
+        }
+        if segment.has(RST) {
+            if segment.has(ACK) {
+                self.abort(SocketError::ConnectionRefused);
+            }
+            return None;
+        }
+        // A SYN without an ACK would be a simultaneous open, which isn't supported
+        if segment.has(SYN) && segment.has(ACK) {
+            self.rcv_nxt = segment.seq.wrapping_add(1);
+            self.snd_una = segment.ack;
+            self.snd_wnd = segment.window as u32;
+            self.set_peer_mss(segment.mss);
+            self.state = Established;
+            self.retransmit_at = None;
+            self.retries = 0;
+            self.ack_pending = true;
+        }
+        None
+    }
+
+    fn process_synchronized(
+        &mut self,
+        segment: &TcpHeader,
+        payload: &[u8],
+        now: u64,
+    ) -> Option<TcpHeader> {
+        if segment.has(RST) {
+            // Resets that aren't exactly where we expect could be forged (RFC 5961)
+            if segment.seq == self.rcv_nxt {
+                if self.state == SynReceived {
+                    // The listener just forgets about it
+                    self.state = Closed;
+                } else {
+                    self.abort(SocketError::ConnectionReset);
+                }
+            }
+            return None;
+        }
+        if segment.has(SYN) {
+            // Our SYN-ACK's ACK got lost, or something is confused. The ACK tells the peer
+            // where we are.
+            self.ack_pending = true;
+            return None;
+        }
+        if !segment.has(ACK) {
+            return None;
+        }
+
+        if self.state == SynReceived {
+            if segment.ack != self.snd_max {
+                return reset_for(segment, payload.len());
+            }
+            self.state = Established;
+        }
+        if seq_gt(segment.ack, self.snd_max) {
+            // It acknowledges something we never sent
+            self.ack_pending = true;
+            return None;
+        }
+        if seq_gt(segment.ack, self.snd_una) {
+            let acked = segment.ack.wrapping_sub(self.snd_una) as usize;
+            let data = acked.min(self.send_buffer.len());
+            self.send_buffer.drain(..data);
+            self.snd_una = segment.ack;
+            if seq_lt(self.snd_nxt, self.snd_una) {
+                self.snd_nxt = self.snd_una;
+            }
+            self.retries = 0;
+            self.rto = INITIAL_RTO;
+            self.retransmit_at = if self.snd_una == self.snd_max {
+                None
+            } else {
+                Some(now + self.rto)
+            };
+        }
+        self.snd_wnd = segment.window as u32;
+
+        let fin_acked = self.fin_sent && self.snd_una == self.snd_max;
+        match self.state {
+            FinWait1 if fin_acked => self.state = FinWait2,
+            Closing if fin_acked => self.enter_time_wait(now),
+            LastAck if fin_acked => {
+                self.state = Closed;
+                return None;
+            }
+            _ => {}
+        }
+
+        if segment.sequence_len(payload.len()) == 0 {
+            return None;
+        }
+        if self.fin_received || seq_gt(segment.seq, self.rcv_nxt) {
+            // A retransmission of something we have, or a segment that came before the ones
+            // in front of it. Either way, the peer needs to know where we are.
+            self.ack_pending = true;
+            return None;
+        }
+        let already_received = self.rcv_nxt.wrapping_sub(segment.seq) as usize;
+        if already_received >= payload.len() && !segment.has(FIN) {
+            self.ack_pending = true;
+            return None;
+        }
+        let data = &payload[already_received.min(payload.len())..];
+        let room = RECEIVE_BUFFER_SIZE - self.receive_buffer.len();
+        let accepted = data.len().min(room);
+        self.receive_buffer.extend(&data[..accepted]);
+        self.rcv_nxt = self.rcv_nxt.wrapping_add(accepted as u32);
+        self.ack_pending = true;
+        if accepted < data.len() {
+            // The rest, and the FIN if there is one, will come again
+            return None;
+        }
+
+        if segment.has(FIN) {
+            self.rcv_nxt = self.rcv_nxt.wrapping_add(1);
+            self.fin_received = true;
+            match self.state {
+                Established => self.state = CloseWait,
+                FinWait1 => self.state = Closing,
+                FinWait2 => self.enter_time_wait(now),
+                _ => {}
+            }
+        }
+        None
+    }
+
+    /// Queues as much of `data` as fits in the send buffer
+    pub(crate) fn send(&mut self, data: &[u8]) -> Result<usize, SocketError> {
+        if let Some(error) = self.error {
+            return Err(error);
+        }
+        match self.state {
+            SynSent | SynReceived => Err(SocketError::WouldBlock),
+            Established | CloseWait if !self.closing => {
+                let len = data.len().min(SEND_BUFFER_SIZE - self.send_buffer.len());
+                if len == 0 && !data.is_empty() {
+                    return Err(SocketError::WouldBlock);
+                }
+                self.send_buffer.extend(&data[..len]);
+                Ok(len)
+            }
+            _ => Err(SocketError::NotConnected),
+        }
+    }
+
+    /// Returns an empty buffer once the peer has closed its side and everything it sent was
+    /// read
+    pub(crate) fn recv(&mut self, max_len: usize) -> Result<Vec<u8>, SocketError> {
+        if !self.receive_buffer.is_empty() {
+            let window = self.window() as usize;
+            let len = max_len.min(self.receive_buffer.len());
+            let data = self.receive_buffer.drain(..len).collect();
+            // Tell the peer it can send again, if it couldn't send a full segment before
+            if window < self.mss && self.window() as usize >= self.mss {
+                self.ack_pending = true;
+            }
+            return Ok(data);
+        }
+        if let Some(error) = self.error {
+            return Err(error);
+        }
+        if self.fin_received {
+            return Ok(Vec::new());
+        }
+        match self.state {
+            SynSent | SynReceived | Established | FinWait1 | FinWait2 => {
+                Err(SocketError::WouldBlock)
+            }
+            _ => Err(SocketError::NotConnected),
+        }
+    }
+
+    /// Sends a FIN after the data that's still in the send buffer. Connections that aren't
+    /// established yet are dropped.
+    pub(crate) fn close(&mut self) {
+        match self.state {
+            Closed | Listen | SynSent => self.state = Closed,
+            SynReceived | Established | CloseWait => self.closing = true,
+            _ => {}
+        }
+    }
+
+    pub(crate) fn readiness(&self) -> Readiness {
+        let failed = self.error.is_some() || self.state == Closed;
+        let accepting = self
+            .listener
+            .as_ref()
+            .is_some_and(|listener| !listener.ready.is_empty());
+        Readiness {
+            readable: failed || accepting || !self.receive_buffer.is_empty() || self.fin_received,
+            writable: failed
+                || (matches!(self.state, Established | CloseWait)
+                    && !self.closing
+                    && self.send_buffer.len() < SEND_BUFFER_SIZE),
+        }
+    }
+}
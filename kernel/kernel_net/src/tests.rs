@@ -0,0 +1,649 @@
+use std::vec::Vec;
+
+use crate::{
+    wire::*, Cidr, Frame, InterfaceId, Ipv4Address, MacAddress, Readiness, SocketAddress,
+    SocketError, SocketKind, Stack, TcpState,
+};
+
+const MAC_A: MacAddress = MacAddress([0x52, 0x54, 0, 0x12, 0x34, 0x56]);
+const MAC_B: MacAddress = MacAddress([0x52, 0x54, 0, 0x12, 0x34, 0x57]);
+const ADDRESS_A: Ipv4Address = Ipv4Address([10, 0, 2, 15]);
+const ADDRESS_B: Ipv4Address = Ipv4Address([10, 0, 2, 2]);
+const MTU: usize = 1500;
+
+fn host(mac: MacAddress, address: Ipv4Address) -> (Stack, InterfaceId) {
+    let mut stack = Stack::new();
+    let eth = stack.add_ethernet_interface("eth0".into(), mac, MTU);
+    stack.configure(eth, Some(Cidr::new(address, 24)), None);
+    (stack, eth)
+}
+
+/// Frames that a stack sent, with their checksums filled in like a device would
+fn sent(stack: &mut Stack) -> Vec<Vec<u8>> {
+    let mut frames = Vec::new();
+    while let Some((_, mut frame)) = stack.transmit() {
+        frame.finish_checksum();
+        frames.push(frame.data);
+    }
+    frames
+}
+
+/// Two stacks connected by a cable that loses the frames `lose` picks
+struct Link {
+    a: Stack,
+    b: Stack,
+    a_eth: InterfaceId,
+    b_eth: InterfaceId,
+    now: u64,
+}
+
+impl Link {
+    fn new() -> Self {
+        let (a, a_eth) = host(MAC_A, ADDRESS_A);
+        let (b, b_eth) = host(MAC_B, ADDRESS_B);
+        Self {
+            a,
+            b,
+            a_eth,
+            b_eth,
+            now: 0,
+        }
+    }
+
+    /// Runs both stacks until neither has anything left to send
+    fn run_lossy(&mut self, lose: &mut dyn FnMut(&[u8]) -> bool) {
+        for _ in 0..1000 {
+            self.a.poll(self.now);
+            self.b.poll(self.now);
+            let to_b = sent(&mut self.a);
+            let to_a = sent(&mut self.b);
+            if to_a.is_empty() && to_b.is_empty() {
+                return;
+            }
+            for frame in to_b {
+                if !lose(&frame) {
+                    self.b.receive(self.b_eth, &frame, self.now);
+                }
+            }
+            for frame in to_a {
+                if !lose(&frame) {
+                    self.a.receive(self.a_eth, &frame, self.now);
+                }
+            }
+        }
+        panic!("the stacks never went quiet");
+    }
+
+    fn run(&mut self) {
+        self.run_lossy(&mut |_| false)
+    }
+
+    /// Moves time forward to the next timer of either stack and runs them
+    fn advance(&mut self) {
+        let next = [self.a.poll_at(), self.b.poll_at()]
+            .into_iter()
+            .flatten()
+            .min()
+            .expect("no timers");
+        self.now = self.now.max(next);
+        self.run();
+    }
+}
+
+fn echo_request(sequence: u16, payload: &[u8]) -> Vec<u8> {
+    let [high, low] = sequence.to_be_bytes();
+    let mut message = Vec::new();
+    IcmpHeader {
+        kind: IcmpHeader::ECHO_REQUEST,
+        code: 0,
+        rest: [0, 0, high, low],
+    }
+    .write(&mut message, payload);
+    message
+}
+
+fn tcp_header_of(frame: &[u8]) -> Option<TcpHeader> {
+    let (ethernet, packet) = EthernetHeader::parse(frame)?;
+    if ethernet.ether_type != ether_type::IPV4 {
+        return None;
+    }
+    let (ip, segment) = Ipv4Header::parse(packet)?;
+    if ip.protocol != ip_protocol::TCP {
+        return None;
+    }
+    Some(TcpHeader::parse(segment, &ip)?.0)
+}
+
+fn tcp_flags_of(frame: &[u8]) -> Option<u8> {
+    Some(tcp_header_of(frame)?.flags)
+}
+
+#[test]
+fn partial_checksums_are_finished_like_a_device_would() {
+    let ip = Ipv4Header {
+        source: ADDRESS_A,
+        destination: ADDRESS_B,
+        protocol: ip_protocol::UDP,
+        ttl: Ipv4Header::DEFAULT_TTL,
+        identification: 1,
+    };
+    let udp = UdpHeader {
+        source_port: 1234,
+        destination_port: 53,
+    };
+    let mut frame = Frame {
+        data: Vec::new(),
+        checksum: Some((0, UDP_CHECKSUM_OFFSET)),
+    };
+    udp.write(&mut frame.data, b"hello", &ip);
+    assert!(UdpHeader::parse(&frame.data, &ip).is_none());
+
+    frame.finish_checksum();
+    let (parsed, payload) = UdpHeader::parse(&frame.data, &ip).unwrap();
+    assert_eq!(parsed, udp);
+    assert_eq!(payload, b"hello");
+
+    frame.data[9] ^= 1;
+    assert!(UdpHeader::parse(&frame.data, &ip).is_none());
+}
+
+#[test]
+fn cidr() {
+    let cidr = Cidr::from_netmask(ADDRESS_A, Ipv4Address([255, 255, 255, 0])).unwrap();
+    assert_eq!(cidr, Cidr::new(ADDRESS_A, 24));
+    assert!(cidr.contains(ADDRESS_B));
+    assert!(!cidr.contains(Ipv4Address([10, 0, 3, 2])));
+    assert_eq!(cidr.broadcast(), Ipv4Address([10, 0, 2, 255]));
+    assert_eq!(std::format!("{}", cidr), "10.0.2.15/24");
+    assert!(Cidr::from_netmask(ADDRESS_A, Ipv4Address([255, 0, 255, 0])).is_none());
+}
+
+#[test]
+fn arp_requests_for_our_address_are_answered() {
+    let (mut stack, eth) = host(MAC_A, ADDRESS_A);
+    let mut frame = Vec::new();
+    EthernetHeader {
+        destination: MacAddress::BROADCAST,
+        source: MAC_B,
+        ether_type: ether_type::ARP,
+    }
+    .write(&mut frame);
+    let mut request = ArpPacket {
+        operation: ArpPacket::REQUEST,
+        sender_mac: MAC_B,
+        sender_address: ADDRESS_B,
+        target_mac: MacAddress::default(),
+        target_address: Ipv4Address([10, 0, 2, 16]),
+    };
+    let mut other = frame.clone();
+    request.write(&mut other);
+    stack.receive(eth, &other, 0);
+    assert!(sent(&mut stack).is_empty());
+
+    request.target_address = ADDRESS_A;
+    request.write(&mut frame);
+    stack.receive(eth, &frame, 0);
+    let replies = sent(&mut stack);
+    assert_eq!(replies.len(), 1);
+    let (ethernet, packet) = EthernetHeader::parse(&replies[0]).unwrap();
+    assert_eq!(ethernet.destination, MAC_B);
+    assert_eq!(
+        ArpPacket::parse(packet).unwrap(),
+        ArpPacket {
+            operation: ArpPacket::REPLY,
+            sender_mac: MAC_A,
+            sender_address: ADDRESS_A,
+            target_mac: MAC_B,
+            target_address: ADDRESS_B,
+        }
+    );
+}
+
+#[test]
+fn unanswered_arp_requests_are_retried_then_given_up() {
+    let (mut stack, _) = host(MAC_A, ADDRESS_A);
+    let socket = stack.socket(SocketKind::Udp);
+    let remote = SocketAddress::new(ADDRESS_B, 7);
+    stack.send_to(socket, b"echo", remote).unwrap();
+    stack.send_to(socket, b"echo", remote).unwrap();
+    // One request for both packets
+    assert_eq!(sent(&mut stack).len(), 1);
+
+    let mut requests = 1;
+    while let Some(at) = stack.poll_at() {
+        stack.poll(at);
+        requests += sent(&mut stack).len();
+    }
+    assert_eq!(requests, 3);
+}
+
+#[test]
+fn ping_over_ethernet() {
+    let mut link = Link::new();
+    let socket = link.a.socket(SocketKind::Icmp);
+    let request = echo_request(7, b"ping");
+    let remote = SocketAddress::new(ADDRESS_B, 0);
+    assert_eq!(link.a.send_to(socket, &request, remote), Ok(request.len()));
+    link.run();
+
+    let (reply, source) = link.a.recv_from(socket, 100).unwrap();
+    assert_eq!(source, remote);
+    let (header, payload) = IcmpHeader::parse(&reply).unwrap();
+    assert_eq!(header.kind, IcmpHeader::ECHO_REPLY);
+    assert_eq!(
+        header.identifier(),
+        link.a.local_address(socket).unwrap().unwrap().port
+    );
+    assert_eq!(header.rest[2..], [0, 7]);
+    assert_eq!(payload, b"ping");
+    assert_eq!(link.a.recv(socket, 100), Err(SocketError::WouldBlock));
+}
+
+#[test]
+fn ping_over_loopback() {
+    let (mut stack, _) = host(MAC_A, ADDRESS_A);
+    let socket = stack.socket(SocketKind::Icmp);
+    for address in [Ipv4Address::LOCALHOST, ADDRESS_A] {
+        stack
+            .connect(socket, SocketAddress::new(address, 0))
+            .unwrap();
+        stack.send(socket, &echo_request(1, b"")).unwrap();
+        stack.poll(0);
+        let reply = stack.recv(socket, 100).unwrap();
+        assert_eq!(reply[0], IcmpHeader::ECHO_REPLY);
+    }
+    // Nothing went out on the Ethernet interface
+    assert!(sent(&mut stack).is_empty());
+}
+
+#[test]
+fn udp() {
+    let mut link = Link::new();
+    let server = link.b.socket(SocketKind::Udp);
+    link.b
+        .bind(server, SocketAddress::new(Ipv4Address::UNSPECIFIED, 7))
+        .unwrap();
+    let client = link.a.socket(SocketKind::Udp);
+    link.a
+        .connect(client, SocketAddress::new(ADDRESS_B, 7))
+        .unwrap();
+    assert_eq!(
+        link.a.readiness(client),
+        Ok(Readiness {
+            readable: false,
+            writable: true
+        })
+    );
+    link.a.send(client, b"hello").unwrap();
+    link.run();
+
+    let (data, source) = link.b.recv_from(server, 3).unwrap();
+    assert_eq!(data, b"hel");
+    assert_eq!(source.address, ADDRESS_A);
+    assert_eq!(
+        Some(source.port),
+        link.a
+            .local_address(client)
+            .unwrap()
+            .map(|local| local.port)
+    );
+    link.b.send_to(server, b"world", source).unwrap();
+    link.run();
+    assert!(link.a.readiness(client).unwrap().readable);
+    assert_eq!(link.a.recv(client, 100).unwrap(), b"world");
+
+    let other = link.b.socket(SocketKind::Udp);
+    assert_eq!(
+        link.b
+            .bind(other, SocketAddress::new(Ipv4Address::UNSPECIFIED, 7)),
+        Err(SocketError::AddressInUse)
+    );
+    assert_eq!(
+        link.b.bind(other, SocketAddress::new(ADDRESS_A, 0)),
+        Err(SocketError::AddressNotAvailable)
+    );
+    link.b.close(server).unwrap();
+    link.b
+        .bind(other, SocketAddress::new(Ipv4Address::UNSPECIFIED, 7))
+        .unwrap();
+}
+
+#[test]
+fn trailing_frame_check_sequences_are_ignored() {
+    // Some cards pass frames on with the FCS still at the end
+    let mut link = Link::new();
+    let server = link.b.socket(SocketKind::Udp);
+    link.b
+        .bind(server, SocketAddress::new(Ipv4Address::UNSPECIFIED, 7))
+        .unwrap();
+    let client = link.a.socket(SocketKind::Udp);
+    link.a
+        .connect(client, SocketAddress::new(ADDRESS_B, 7))
+        .unwrap();
+    link.a.send(client, b"hello").unwrap();
+    for _ in 0..10 {
+        link.a.poll(0);
+        link.b.poll(0);
+        for mut frame in sent(&mut link.a) {
+            frame.extend_from_slice(&[0xde, 0xad, 0xbe, 0xef]);
+            link.b.receive(link.b_eth, &frame, 0);
+        }
+        for mut frame in sent(&mut link.b) {
+            frame.extend_from_slice(&[0xde, 0xad, 0xbe, 0xef]);
+            link.a.receive(link.a_eth, &frame, 0);
+        }
+    }
+    assert_eq!(link.b.recv(server, 100).unwrap(), b"hello");
+}
+
+#[test]
+fn udp_to_a_closed_port_is_answered_with_port_unreachable() {
+    let mut link = Link::new();
+    let socket = link.a.socket(SocketKind::Udp);
+    link.a
+        .send_to(socket, b"anyone?", SocketAddress::new(ADDRESS_B, 9))
+        .unwrap();
+    let mut unreachable = Vec::new();
+    link.run_lossy(&mut |frame| {
+        let (_, packet) = EthernetHeader::parse(frame).unwrap();
+        if let Some((ip, message)) = Ipv4Header::parse(packet) {
+            if ip.protocol == ip_protocol::ICMP {
+                unreachable.push(IcmpHeader::parse(message).unwrap().0);
+            }
+        }
+        false
+    });
+    assert_eq!(
+        unreachable,
+        [IcmpHeader {
+            kind: IcmpHeader::DESTINATION_UNREACHABLE,
+            code: IcmpHeader::PORT_UNREACHABLE,
+            rest: [0; 4],
+        }]
+    );
+    assert_eq!(link.a.recv(socket, 100), Err(SocketError::WouldBlock));
+}
+
+#[test]
+fn tcp_over_loopback() {
+    let mut stack = Stack::new();
+    let listener = stack.socket(SocketKind::Tcp);
+    stack
+        .bind(listener, SocketAddress::new(Ipv4Address::UNSPECIFIED, 80))
+        .unwrap();
+    stack.listen(listener, 4).unwrap();
+    assert_eq!(stack.accept(listener), Err(SocketError::WouldBlock));
+
+    let client = stack.socket(SocketKind::Tcp);
+    stack
+        .connect(client, SocketAddress::new(Ipv4Address::LOCALHOST, 80))
+        .unwrap();
+    assert_eq!(stack.send(client, b"x"), Err(SocketError::WouldBlock));
+    stack.poll(0);
+    assert_eq!(stack.tcp_state(client), Ok(TcpState::Established));
+    assert!(stack.readiness(listener).unwrap().readable);
+    let (server, remote) = stack.accept(listener).unwrap();
+    assert_eq!(Some(remote), stack.local_address(client).unwrap());
+
+    assert_eq!(stack.send(client, b"GET /"), Ok(5));
+    stack.poll(0);
+    assert_eq!(stack.recv(server, 100).unwrap(), b"GET /");
+    stack.send(server, b"200 OK").unwrap();
+    stack.close(server).unwrap();
+    stack.poll(0);
+    assert_eq!(stack.recv(client, 100).unwrap(), b"200 OK");
+    assert_eq!(stack.recv(client, 100).unwrap(), b"");
+    assert_eq!(stack.tcp_state(client), Ok(TcpState::CloseWait));
+
+    stack.close(client).unwrap();
+    stack.poll(0);
+    assert_eq!(stack.readiness(client), Err(SocketError::InvalidSocket));
+    // The server's side lingers in TIME-WAIT, then goes away
+    let at = stack.poll_at().unwrap();
+    stack.poll(at);
+    assert_eq!(stack.poll_at(), None);
+}
+
+/// The sequence number of the SYN that A sends to connect to B's `port`, with `secret`
+fn initial_sequence_number(secret: [u8; 32], port: u16) -> u32 {
+    let mut link = Link::new();
+    link.a.set_iss_secret(secret);
+    let client = link.a.socket(SocketKind::Tcp);
+    link.a
+        .connect(client, SocketAddress::new(ADDRESS_B, port))
+        .unwrap();
+    let mut seq = None;
+    link.run_lossy(&mut |frame| {
+        if let Some(header) = tcp_header_of(frame) {
+            if header.flags == tcp_flags::SYN {
+                seq.get_or_insert(header.seq);
+            }
+        }
+        false
+    });
+    seq.expect("no SYN")
+}
+
+#[test]
+fn initial_sequence_numbers_depend_on_the_secret_and_the_addresses() {
+    let secret = [7; 32];
+    assert_eq!(
+        initial_sequence_number(secret, 80),
+        initial_sequence_number(secret, 80)
+    );
+    assert_ne!(
+        initial_sequence_number(secret, 80),
+        initial_sequence_number([8; 32], 80)
+    );
+    assert_ne!(
+        initial_sequence_number(secret, 80),
+        initial_sequence_number(secret, 81)
+    );
+}
+
+#[test]
+fn tcp_connection_refused() {
+    let mut link = Link::new();
+    let client = link.a.socket(SocketKind::Tcp);
+    link.a
+        .connect(client, SocketAddress::new(ADDRESS_B, 80))
+        .unwrap();
+    link.run();
+    assert_eq!(link.a.tcp_state(client), Ok(TcpState::Closed));
+    assert!(link.a.readiness(client).unwrap().writable);
+    assert_eq!(
+        link.a.send(client, b"x"),
+        Err(SocketError::ConnectionRefused)
+    );
+}
+
+#[test]
+fn tcp_transfer_with_lost_segments() {
+    let mut link = Link::new();
+    let listener = link.b.socket(SocketKind::Tcp);
+    link.b
+        .bind(listener, SocketAddress::new(ADDRESS_B, 80))
+        .unwrap();
+    link.b.listen(listener, 1).unwrap();
+    let client = link.a.socket(SocketKind::Tcp);
+    link.a
+        .connect(client, SocketAddress::new(ADDRESS_B, 80))
+        .unwrap();
+    link.run();
+    let (server, _) = link.b.accept(listener).unwrap();
+
+    let data: Vec<u8> = (0..100_000u32).map(|i| (i * 7) as u8).collect();
+    let mut written = 0;
+    let mut received = Vec::new();
+    let mut frames = 0;
+    while received.len() < data.len() {
+        if written < data.len() {
+            written += link.a.send(client, &data[written..]).unwrap_or(0);
+        }
+        // Every seventh data segment gets lost
+        link.run_lossy(&mut |frame| {
+            let data = tcp_flags_of(frame) == Some(tcp_flags::ACK) && frame.len() > 100;
+            frames += data as usize;
+            data && frames % 7 == 0
+        });
+        while let Ok(chunk) = link.b.recv(server, 4096) {
+            received.extend(chunk);
+        }
+        if link.a.readiness(client).unwrap() == Readiness::default() || written == data.len() {
+            link.advance();
+        }
+    }
+    assert_eq!(received, data);
+
+    link.a.close(client).unwrap();
+    link.run();
+    assert_eq!(link.b.recv(server, 100).unwrap(), b"");
+    link.b.close(server).unwrap();
+    link.run();
+    assert_eq!(link.b.tcp_state(server), Err(SocketError::InvalidSocket));
+}
+
+#[test]
+fn tcp_gives_up_on_a_silent_peer() {
+    let mut link = Link::new();
+    let client = link.a.socket(SocketKind::Tcp);
+    link.a
+        .connect(client, SocketAddress::new(ADDRESS_B, 80))
+        .unwrap();
+    let mut syns = 0;
+    let mut lose = |frame: &[u8]| {
+        let syn = tcp_flags_of(frame) == Some(tcp_flags::SYN);
+        syns += syn as usize;
+        syn
+    };
+    link.run_lossy(&mut lose);
+    while link.a.tcp_state(client) == Ok(TcpState::SynSent) {
+        link.now = link.a.poll_at().unwrap();
+        link.run_lossy(&mut lose);
+    }
+    assert_eq!(syns, 9);
+    assert_eq!(link.a.recv(client, 100), Err(SocketError::TimedOut));
+}
+
+#[test]
+fn segments_with_bad_checksums_are_dropped() {
+    let mut link = Link::new();
+    let listener = link.b.socket(SocketKind::Tcp);
+    link.b.listen(listener, 1).unwrap();
+    let port = link.b.local_address(listener).unwrap().unwrap().port;
+    let client = link.a.socket(SocketKind::Tcp);
+    link.a
+        .connect(client, SocketAddress::new(ADDRESS_B, port))
+        .unwrap();
+    link.run();
+    link.b.accept(listener).unwrap();
+
+    link.a.send(client, b"data").unwrap();
+    link.a.poll(0);
+    let mut frames = sent(&mut link.a);
+    assert_eq!(frames.len(), 1);
+    let last = frames[0].len() - 1;
+    frames[0][last] ^= 0xff;
+    link.b.receive(link.b_eth, &frames[0], 0);
+    link.b.poll(0);
+    assert!(sent(&mut link.b).is_empty());
+}
+
+/// A DHCP server's reply to `request`, which the client broadcast
+fn dhcp_reply(request: &[u8], message_type: u8) -> Vec<u8> {
+    let (_, packet) = EthernetHeader::parse(request).unwrap();
+    let (ip, datagram) = Ipv4Header::parse(packet).unwrap();
+    let (_, request) = UdpHeader::parse(datagram, &ip).unwrap();
+
+    let mut message = request[..236].to_vec();
+    message[0] = 2;
+    message[16..20].copy_from_slice(&ADDRESS_A.0);
+    message.extend_from_slice(&[99, 130, 83, 99]);
+    message.extend_from_slice(&[53, 1, message_type]);
+    message.extend_from_slice(&[54, 4, 10, 0, 2, 2]);
+    message.extend_from_slice(&[1, 4, 255, 255, 255, 0]);
+    message.extend_from_slice(&[3, 4, 10, 0, 2, 2]);
+    message.extend_from_slice(&[6, 4, 10, 0, 2, 3]);
+    message.extend_from_slice(&[51, 4, 0, 0, 0x0e, 0x10]);
+    message.push(255);
+
+    let ip = Ipv4Header {
+        source: ADDRESS_B,
+        destination: Ipv4Address::BROADCAST,
+        protocol: ip_protocol::UDP,
+        ttl: Ipv4Header::DEFAULT_TTL,
+        identification: 0,
+    };
+    let mut frame = Vec::new();
+    EthernetHeader {
+        destination: MacAddress::BROADCAST,
+        source: MAC_B,
+        ether_type: ether_type::IPV4,
+    }
+    .write(&mut frame);
+    ip.write(&mut frame, UDP_HEADER_SIZE + message.len());
+    UdpHeader {
+        source_port: 67,
+        destination_port: 68,
+    }
+    .write(&mut frame, &message, &ip);
+    let start = ETHERNET_HEADER_SIZE + IPV4_HEADER_SIZE;
+    kernel_io::checksum::fill(&mut frame, start, UDP_CHECKSUM_OFFSET);
+    frame
+}
+
+fn dhcp_message_type(frame: &[u8]) -> u8 {
+    // Ethernet, IP and UDP headers, then the options, which start with the message type
+    frame[14 + 20 + 8 + 240 + 2]
+}
+
+#[test]
+fn dhcp() {
+    let mut stack = Stack::new();
+    let eth = stack.add_ethernet_interface("eth0".into(), MAC_A, MTU);
+    stack.start_dhcp(eth, 0);
+    stack.poll(0);
+    let discover = sent(&mut stack);
+    assert_eq!(discover.len(), 1);
+    assert_eq!(dhcp_message_type(&discover[0]), 1);
+
+    // Retransmitted until the server answers
+    stack.poll(stack.poll_at().unwrap());
+    let discover = sent(&mut stack);
+    assert_eq!(discover.len(), 1);
+
+    stack.receive(eth, &dhcp_reply(&discover[0], 2), 2000);
+    stack.poll(2000);
+    let request = sent(&mut stack);
+    assert_eq!(dhcp_message_type(&request[0]), 3);
+    stack.receive(eth, &dhcp_reply(&request[0], 5), 2000);
+
+    let interface = stack.interface(eth).unwrap();
+    assert_eq!(interface.address(), Some(Cidr::new(ADDRESS_A, 24)));
+    assert_eq!(interface.gateway(), Some(ADDRESS_B));
+    assert_eq!(interface.dns_servers(), [Ipv4Address([10, 0, 2, 3])]);
+
+    // Renewed halfway through the hour
+    assert_eq!(stack.poll_at(), Some(2000 + 1800 * 1000));
+    stack.poll(2000 + 1800 * 1000);
+    let renew = sent(&mut stack);
+    assert_eq!(dhcp_message_type(&renew[0]), 3);
+    stack.receive(eth, &dhcp_reply(&renew[0], 6), 2000 + 1800 * 1000);
+    assert_eq!(stack.interface(eth).unwrap().address(), None);
+}
+
+#[test]
+fn routing_through_the_gateway() {
+    let (mut stack, eth) = host(MAC_A, ADDRESS_A);
+    let socket = stack.socket(SocketKind::Udp);
+    let remote = SocketAddress::new(Ipv4Address([1, 1, 1, 1]), 53);
+    assert_eq!(
+        stack.send_to(socket, b"", remote),
+        Err(SocketError::NoRoute)
+    );
+    stack.configure(eth, Some(Cidr::new(ADDRESS_A, 24)), Some(ADDRESS_B));
+    stack.send_to(socket, b"", remote).unwrap();
+    let frames = sent(&mut stack);
+    let (_, packet) = EthernetHeader::parse(&frames[0]).unwrap();
+    assert_eq!(ArpPacket::parse(packet).unwrap().target_address, ADDRESS_B);
+}
@@ -0,0 +1,387 @@
+//! Reading and writing the headers of the protocols the stack speaks.
+//! Parsing checks lengths and checksums, so whatever comes out of it is well-formed.
+//! TCP and UDP headers are written with only the pseudo-header's sum in the checksum field,
+//! to be finished by the device or by `kernel_io::checksum::fill`.
+//! See RFC 826 (ARP), 791 (IPv4), 792 (ICMP), 768 (UDP) and 793 (TCP).
+
+use alloc::vec::Vec;
+
+use kernel_io::checksum;
+
+use crate::address::{Ipv4Address, MacAddress};
+
+pub mod ether_type {
+    pub const IPV4: u16 = 0x0800;
+    pub const ARP: u16 = 0x0806;
+}
+
+pub mod ip_protocol {
+    pub const ICMP: u8 = 1;
+    pub const TCP: u8 = 6;
+    pub const UDP: u8 = 17;
+}
+
+pub const ETHERNET_HEADER_SIZE: usize = 14;
+pub const ARP_PACKET_SIZE: usize = 28;
+/// Without options, which are never sent
+pub const IPV4_HEADER_SIZE: usize = 20;
+pub const ICMP_HEADER_SIZE: usize = 8;
+pub const UDP_HEADER_SIZE: usize = 8;
+/// Without options
+pub const TCP_HEADER_SIZE: usize = 20;
+
+/// Where the checksum is in the headers, from the start of the header
+pub const UDP_CHECKSUM_OFFSET: usize = 6;
+pub const TCP_CHECKSUM_OFFSET: usize = 16;
+
+fn read_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_be_bytes([data[offset], data[offset + 1]])
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_ip(data: &[u8], offset: usize) -> Ipv4Address {
+    Ipv4Address(data[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_mac(data: &[u8], offset: usize) -> MacAddress {
+    MacAddress(data[offset..offset + 6].try_into().unwrap())
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EthernetHeader {
+    pub destination: MacAddress,
+    pub source: MacAddress,
+    pub ether_type: u16,
+}
+
+impl EthernetHeader {
+    /// Returns the header and the payload
+    pub fn parse(frame: &[u8]) -> Option<(Self, &[u8])> {
+        if frame.len() < ETHERNET_HEADER_SIZE {
+            return None;
+        }
+        let header = Self {
+            destination: read_mac(frame, 0),
+            source: read_mac(frame, 6),
+            ether_type: read_u16(frame, 12),
+        };
+        Some((header, &frame[ETHERNET_HEADER_SIZE..]))
+    }
+
+    pub fn write(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.destination.0);
+        out.extend_from_slice(&self.source.0);
+        out.extend_from_slice(&self.ether_type.to_be_bytes());
+    }
+}
+
+/// An ARP packet for IPv4 over Ethernet, the only kind there is in practice
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ArpPacket {
+    pub operation: u16,
+    pub sender_mac: MacAddress,
+    pub sender_address: Ipv4Address,
+    pub target_mac: MacAddress,
+    pub target_address: Ipv4Address,
+}
+
+impl ArpPacket {
+    pub const REQUEST: u16 = 1;
+    pub const REPLY: u16 = 2;
+
+    const HARDWARE_ETHERNET: u16 = 1;
+
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        if data.len() < ARP_PACKET_SIZE
+            || read_u16(data, 0) != Self::HARDWARE_ETHERNET
+            || read_u16(data, 2) != ether_type::IPV4
+            || data[4] != 6
+            || data[5] != 4
+        {
+            return None;
+        }
+        Some(Self {
+            operation: read_u16(data, 6),
+            sender_mac: read_mac(data, 8),
+            sender_address: read_ip(data, 14),
+            target_mac: read_mac(data, 18),
+            target_address: read_ip(data, 24),
+        })
+    }
+
+    pub fn write(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&Self::HARDWARE_ETHERNET.to_be_bytes());
+        out.extend_from_slice(&ether_type::IPV4.to_be_bytes());
+        out.extend_from_slice(&[6, 4]);
+        out.extend_from_slice(&self.operation.to_be_bytes());
+        out.extend_from_slice(&self.sender_mac.0);
+        out.extend_from_slice(&self.sender_address.0);
+        out.extend_from_slice(&self.target_mac.0);
+        out.extend_from_slice(&self.target_address.0);
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Ipv4Header {
+    pub source: Ipv4Address,
+    pub destination: Ipv4Address,
+    pub protocol: u8,
+    pub ttl: u8,
+    pub identification: u16,
+}
+
+impl Ipv4Header {
+    pub const DEFAULT_TTL: u8 = 64;
+
+    const DONT_FRAGMENT: u16 = 0x4000;
+    const MORE_FRAGMENTS: u16 = 0x2000;
+    const FRAGMENT_OFFSET: u16 = 0x1fff;
+
+    /// Returns the header and the payload, without the padding the link layer may have
+    /// added. Fragments are rejected, since they aren't reassembled.
+    pub fn parse(packet: &[u8]) -> Option<(Self, &[u8])> {
+        if packet.len() < IPV4_HEADER_SIZE || packet[0] >> 4 != 4 {
+            return None;
+        }
+        let header_len = (packet[0] & 0xf) as usize * 4;
+        let total_len = read_u16(packet, 2) as usize;
+        if header_len < IPV4_HEADER_SIZE || total_len < header_len || total_len > packet.len() {
+            return None;
+        }
+        if checksum::finish(checksum::add(0, &packet[..header_len])) != 0 {
+            return None;
+        }
+        let fragment = read_u16(packet, 6);
+        if fragment & (Self::MORE_FRAGMENTS | Self::FRAGMENT_OFFSET) != 0 {
+            return None;
+        }
+        let header = Self {
+            identification: read_u16(packet, 4),
+            ttl: packet[8],
+            protocol: packet[9],
+            source: read_ip(packet, 12),
+            destination: read_ip(packet, 16),
+        };
+        Some((header, &packet[header_len..total_len]))
+    }
+
+    pub fn write(&self, out: &mut Vec<u8>, payload_len: usize) {
+        let start = out.len();
+        out.extend_from_slice(&[0x45, 0]);
+        out.extend_from_slice(&((IPV4_HEADER_SIZE + payload_len) as u16).to_be_bytes());
+        out.extend_from_slice(&self.identification.to_be_bytes());
+        out.extend_from_slice(&Self::DONT_FRAGMENT.to_be_bytes());
+        out.extend_from_slice(&[self.ttl, self.protocol, 0, 0]);
+        out.extend_from_slice(&self.source.0);
+        out.extend_from_slice(&self.destination.0);
+        let sum = checksum::finish(checksum::add(0, &out[start..]));
+        out[start + 10..start + 12].copy_from_slice(&sum.to_be_bytes());
+    }
+}
+
+/// The sum of the fields of the IP header that the TCP and UDP checksums cover
+pub fn pseudo_header_sum(
+    source: Ipv4Address,
+    destination: Ipv4Address,
+    protocol: u8,
+    len: usize,
+) -> u32 {
+    let sum = checksum::add(0, &source.0);
+    let sum = checksum::add(sum, &destination.0);
+    checksum::add(sum, &[0, protocol, (len >> 8) as u8, len as u8])
+}
+
+/// Whether a TCP or UDP segment received in `ip` has the right checksum
+fn transport_checksum_ok(ip: &Ipv4Header, data: &[u8]) -> bool {
+    let sum = pseudo_header_sum(ip.source, ip.destination, ip.protocol, data.len());
+    checksum::finish(checksum::add(sum, data)) == 0
+}
+
+/// Puts the pseudo-header sum in the checksum field at `offset`, for `checksum::fill`
+fn write_partial_checksum(segment: &mut [u8], offset: usize, ip: &Ipv4Header) {
+    let sum = pseudo_header_sum(ip.source, ip.destination, ip.protocol, segment.len());
+    // The folded sum, not its complement
+    let sum = !checksum::finish(sum);
+    segment[offset..offset + 2].copy_from_slice(&sum.to_be_bytes());
+}
+
+/// An ICMP message. Only the first 4 bytes of the header are common to all messages; the
+/// rest of it is in `rest`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IcmpHeader {
+    pub kind: u8,
+    pub code: u8,
+    pub rest: [u8; 4],
+}
+
+impl IcmpHeader {
+    pub const ECHO_REPLY: u8 = 0;
+    pub const DESTINATION_UNREACHABLE: u8 = 3;
+    pub const ECHO_REQUEST: u8 = 8;
+
+    pub const PORT_UNREACHABLE: u8 = 3;
+
+    pub fn parse(data: &[u8]) -> Option<(Self, &[u8])> {
+        if data.len() < ICMP_HEADER_SIZE || checksum::finish(checksum::add(0, data)) != 0 {
+            return None;
+        }
+        let header = Self {
+            kind: data[0],
+            code: data[1],
+            rest: data[4..8].try_into().unwrap(),
+        };
+        Some((header, &data[ICMP_HEADER_SIZE..]))
+    }
+
+    /// The identifier of an echo request or reply
+    pub fn identifier(&self) -> u16 {
+        read_u16(&self.rest, 0)
+    }
+
+    pub fn write(&self, out: &mut Vec<u8>, payload: &[u8]) {
+        let start = out.len();
+        out.extend_from_slice(&[self.kind, self.code, 0, 0]);
+        out.extend_from_slice(&self.rest);
+        out.extend_from_slice(payload);
+        let sum = checksum::finish(checksum::add(0, &out[start..]));
+        out[start + 2..start + 4].copy_from_slice(&sum.to_be_bytes());
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct UdpHeader {
+    pub source_port: u16,
+    pub destination_port: u16,
+}
+
+impl UdpHeader {
+    /// `data` is the IP payload that `ip` came with. A zero checksum means there is none.
+    pub fn parse<'a>(data: &'a [u8], ip: &Ipv4Header) -> Option<(Self, &'a [u8])> {
+        if data.len() < UDP_HEADER_SIZE {
+            return None;
+        }
+        let len = read_u16(data, 4) as usize;
+        if len < UDP_HEADER_SIZE || len > data.len() {
+            return None;
+        }
+        let data = &data[..len];
+        if read_u16(data, UDP_CHECKSUM_OFFSET) != 0 && !transport_checksum_ok(ip, data) {
+            return None;
+        }
+        let header = Self {
+            source_port: read_u16(data, 0),
+            destination_port: read_u16(data, 2),
+        };
+        Some((header, &data[UDP_HEADER_SIZE..]))
+    }
+
+    /// Writes the header and payload, with a partial checksum
+    pub fn write(&self, out: &mut Vec<u8>, payload: &[u8], ip: &Ipv4Header) {
+        let start = out.len();
+        let len = (UDP_HEADER_SIZE + payload.len()) as u16;
+        out.extend_from_slice(&self.source_port.to_be_bytes());
+        out.extend_from_slice(&self.destination_port.to_be_bytes());
+        out.extend_from_slice(&len.to_be_bytes());
+        out.extend_from_slice(&[0, 0]);
+        out.extend_from_slice(payload);
+        write_partial_checksum(&mut out[start..], UDP_CHECKSUM_OFFSET, ip);
+    }
+}
+
+pub mod tcp_flags {
+    pub const FIN: u8 = 1 << 0;
+    pub const SYN: u8 = 1 << 1;
+    pub const RST: u8 = 1 << 2;
+    pub const PSH: u8 = 1 << 3;
+    pub const ACK: u8 = 1 << 4;
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TcpHeader {
+    pub source_port: u16,
+    pub destination_port: u16,
+    pub seq: u32,
+    pub ack: u32,
+    pub flags: u8,
+    pub window: u16,
+    /// The maximum segment size option, which is only sent with SYN
+    pub mss: Option<u16>,
+}
+
+impl TcpHeader {
+    const OPTION_END: u8 = 0;
+    const OPTION_NOP: u8 = 1;
+    const OPTION_MSS: u8 = 2;
+
+    pub fn parse<'a>(data: &'a [u8], ip: &Ipv4Header) -> Option<(Self, &'a [u8])> {
+        if data.len() < TCP_HEADER_SIZE {
+            return None;
+        }
+        let header_len = (data[12] >> 4) as usize * 4;
+        if header_len < TCP_HEADER_SIZE || header_len > data.len() {
+            return None;
+        }
+        if !transport_checksum_ok(ip, data) {
+            return None;
+        }
+        let mut header = Self {
+            source_port: read_u16(data, 0),
+            destination_port: read_u16(data, 2),
+            seq: read_u32(data, 4),
+            ack: read_u32(data, 8),
+            flags: data[13],
+            window: read_u16(data, 14),
+            mss: None,
+        };
+        let mut options = &data[TCP_HEADER_SIZE..header_len];
+        while let [kind, rest @ ..] = options {
+            match *kind {
+                Self::OPTION_END => break,
+                Self::OPTION_NOP => options = rest,
+                kind => {
+                    let len = match rest.first() {
+                        Some(&len) if len >= 2 && len as usize <= options.len() => len as usize,
+                        _ => break,
+                    };
+                    if kind == Self::OPTION_MSS && len == 4 {
+                        header.mss = Some(read_u16(options, 2));
+                    }
+                    options = &options[len..];
+                }
+            }
+        }
+        Some((header, &data[header_len..]))
+    }
+
+    pub fn has(&self, flag: u8) -> bool {
+        self.flags & flag != 0
+    }
+
+    /// How much sequence space the segment takes up: its data, plus one for SYN and FIN
+    pub fn sequence_len(&self, payload_len: usize) -> u32 {
+        payload_len as u32 + self.has(tcp_flags::SYN) as u32 + self.has(tcp_flags::FIN) as u32
+    }
+
+    /// Writes the header and payload, with a partial checksum
+    pub fn write(&self, out: &mut Vec<u8>, payload: &[u8], ip: &Ipv4Header) {
+        let start = out.len();
+        let header_len = TCP_HEADER_SIZE + if self.mss.is_some() { 4 } else { 0 };
+        out.extend_from_slice(&self.source_port.to_be_bytes());
+        out.extend_from_slice(&self.destination_port.to_be_bytes());
+        out.extend_from_slice(&self.seq.to_be_bytes());
+        out.extend_from_slice(&self.ack.to_be_bytes());
+        out.extend_from_slice(&[(header_len / 4) as u8 * 16, self.flags]);
+        out.extend_from_slice(&self.window.to_be_bytes());
+        // Checksum and urgent pointer
+        out.extend_from_slice(&[0; 4]);
+        if let Some(mss) = self.mss {
+            out.extend_from_slice(&[Self::OPTION_MSS, 4]);
+            out.extend_from_slice(&mss.to_be_bytes());
+        }
+        out.extend_from_slice(payload);
+        write_partial_checksum(&mut out[start..], TCP_CHECKSUM_OFFSET, ip);
+    }
+}
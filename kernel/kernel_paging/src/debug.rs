@@ -1,24 +1,11 @@
-pub struct Uart {
-    address: *mut u8,
-}
-
-impl core::fmt::Write for Uart {
-    fn write_str(&mut self, s: &str) -> core::fmt::Result {
-        for i in s.bytes() {
-            unsafe { self.address.write(i) }
-        }
-        Ok(())
-    }
-}
+use kernel_util::debug::Uart;
 
 pub fn get_uart() -> Uart {
-    Uart {
-        address: if kernel_cpu::read_satp() == 0 {
-            0x1000_0000
-        } else {
-            0x1000_0000u64 + 0xffff_ffc0_0000_0000u64
-        } as _,
-    }
+    Uart::from_address(if kernel_cpu::read_satp() == 0 {
+        0x1000_0000
+    } else {
+        0x1000_0000u64 + 0xffff_ffc0_0000_0000u64
+    } as _)
 }
 
 #[macro_export]
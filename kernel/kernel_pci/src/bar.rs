@@ -0,0 +1,119 @@
+//! Base address registers, which say where a function's registers are
+
+use crate::{register, Address, ConfigSpace};
+
+const BAR_IO: u32 = 1;
+const BAR_TYPE_MASK: u32 = 0b110;
+const BAR_TYPE_64: u32 = 0b100;
+const BAR_PREFETCHABLE: u32 = 1 << 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BarKind {
+    Io,
+    Memory32,
+    Memory64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Bar {
+    pub kind: BarKind,
+    pub prefetchable: bool,
+    /// Where the CPU sees it. For I/O BARs, this is in the host bridge's I/O window.
+    pub address: u64,
+    /// Where it is on the PCI bus, which is what's written to the BAR
+    pub bus_address: u64,
+    pub size: u64,
+}
+
+/// A BAR that has been sized but not given an address yet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Request {
+    pub kind: BarKind,
+    pub prefetchable: bool,
+    pub size: u64,
+}
+
+/// Sizes BAR `index` by writing all ones to it and seeing which bits stick.
+/// Returns `None` if the BAR isn't implemented, and how many slots it takes up.
+/// Decoding has to be turned off in the command register while this is done.
+pub(crate) fn size(
+    config: &dyn ConfigSpace,
+    address: Address,
+    index: usize,
+) -> (Option<Request>, usize) {
+    let offset = register::BAR0 + index as u16 * 4;
+    let original = config.read_u32(address, offset);
+    config.write_u32(address, offset, u32::MAX);
+    let low = config.read_u32(address, offset);
+    config.write_u32(address, offset, original);
+
+    if original & BAR_IO != 0 {
+        // The upper 16 bits don't have to be implemented
+        let mask = low & !0b11;
+        let mask = if mask & 0xffff_0000 == 0 {
+            mask | 0xffff_0000
+        } else {
+            mask
+        };
+        let size = (!mask).wrapping_add(1) as u64;
+        let request = Request {
+            kind: BarKind::Io,
+            prefetchable: false,
+            size,
+        };
+        return (
+            if mask == 0xffff_0000 {
+                None
+            } else {
+                Some(request)
+            },
+            1,
+        );
+    }
+
+    let prefetchable = original & BAR_PREFETCHABLE != 0;
+    if original & BAR_TYPE_MASK == BAR_TYPE_64 {
+        let offset_high = offset + 4;
+        let original_high = config.read_u32(address, offset_high);
+        config.write_u32(address, offset_high, u32::MAX);
+        let high = config.read_u32(address, offset_high);
+        config.write_u32(address, offset_high, original_high);
+
+        let mask = (high as u64) << 32 | (low & !0xf) as u64;
+        let request = Request {
+            kind: BarKind::Memory64,
+            prefetchable,
+            size: (!mask).wrapping_add(1),
+        };
+        return (if mask == 0 { None } else { Some(request) }, 2);
+    }
+
+    let mask = low & !0xf;
+    let request = Request {
+        kind: BarKind::Memory32,
+        prefetchable,
+        size: (!mask).wrapping_add(1) as u64,
+    };
+    (if mask == 0 { None } else { Some(request) }, 1)
+}
+
+/// Writes `bus_address` to BAR `index`
+pub(crate) fn assign(
+    config: &dyn ConfigSpace,
+    address: Address,
+    index: usize,
+    kind: BarKind,
+    bus_address: u64,
+) {
+    let offset = register::BAR0 + index as u16 * 4;
+    let flags = config.read_u32(address, offset) & 0xf;
+    let flags = if kind == BarKind::Io {
+        flags & BAR_IO
+    } else {
+        flags
+    };
+    config.write_u32(address, offset, bus_address as u32 | flags);
+    if kind == BarKind::Memory64 {
+        config.write_u32(address, offset + 4, (bus_address >> 32) as u32);
+    }
+}
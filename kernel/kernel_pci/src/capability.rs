@@ -0,0 +1,144 @@
+//! The capability list, which is a linked list in configuration space of the optional
+//! features a function has.
+//! See the PCI Local Bus Specification 3.0, section 6.7, and the virtio specification 1.1,
+//! section 4.1.4 for the virtio ones.
+
+use alloc::vec::Vec;
+
+use crate::{register, Address, ConfigSpace, STATUS_CAPABILITIES};
+
+pub const ID_MSI: u8 = 0x05;
+pub const ID_VENDOR_SPECIFIC: u8 = 0x09;
+pub const ID_MSI_X: u8 = 0x11;
+
+/// Virtio devices use Red Hat's vendor ID
+pub const VIRTIO_VENDOR: u16 = 0x1af4;
+
+/// The list can't be longer than this, since every capability takes at least 4 bytes of
+/// the 192 after the header. This stops a broken list from looping forever.
+const MAX_CAPABILITIES: usize = 48;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Capability {
+    Msi(Msi),
+    MsiX(MsiX),
+    Virtio(Virtio),
+    Other { id: u8, offset: u8 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Msi {
+    pub offset: u8,
+    /// Whether the message address can be above 4GiB
+    pub is_64bit: bool,
+    pub per_vector_masking: bool,
+    pub max_vectors: u8,
+}
+
+/// Where a table of MSI-X is: a BAR, and an offset in it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BarOffset {
+    pub bar: u8,
+    pub offset: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MsiX {
+    pub offset: u8,
+    pub table_size: u16,
+    pub table: BarOffset,
+    pub pending_bits: BarOffset,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VirtioKind {
+    Common,
+    Notify,
+    Isr,
+    Device,
+    /// An alternative way to reach configuration space
+    PciConfig,
+    Other(u8),
+}
+
+/// A virtio structure, which is somewhere in one of the BARs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Virtio {
+    pub offset: u8,
+    pub kind: VirtioKind,
+    pub bar: u8,
+    pub bar_offset: u32,
+    pub length: u32,
+    /// Only for [`VirtioKind::Notify`]
+    pub notify_off_multiplier: Option<u32>,
+}
+
+fn parse(
+    config: &dyn ConfigSpace,
+    address: Address,
+    vendor: u16,
+    id: u8,
+    offset: u8,
+) -> Capability {
+    let dword = |at: u8| config.read_u32(address, (offset + at) as u16);
+    match id {
+        ID_MSI => {
+            let control = config.read_u16(address, offset as u16 + 2);
+            Capability::Msi(Msi {
+                offset,
+                is_64bit: control & (1 << 7) != 0,
+                per_vector_masking: control & (1 << 8) != 0,
+                max_vectors: 1 << ((control >> 1) & 0b111).min(5),
+            })
+        }
+        ID_MSI_X => {
+            let control = config.read_u16(address, offset as u16 + 2);
+            let bar_offset = |value: u32| BarOffset {
+                bar: (value & 0b111) as u8,
+                offset: value & !0b111,
+            };
+            Capability::MsiX(MsiX {
+                offset,
+                table_size: (control & 0x7ff) + 1,
+                table: bar_offset(dword(4)),
+                pending_bits: bar_offset(dword(8)),
+            })
+        }
+        ID_VENDOR_SPECIFIC if vendor == VIRTIO_VENDOR => {
+            let kind = match config.read_u8(address, offset as u16 + 3) {
+                1 => VirtioKind::Common,
+                2 => VirtioKind::Notify,
+                3 => VirtioKind::Isr,
+                4 => VirtioKind::Device,
+                5 => VirtioKind::PciConfig,
+                other => VirtioKind::Other(other),
+            };
+            Capability::Virtio(Virtio {
+                offset,
+                kind,
+                bar: config.read_u8(address, offset as u16 + 4),
+                bar_offset: dword(8),
+                length: dword(12),
+                notify_off_multiplier: (kind == VirtioKind::Notify).then(|| dword(16)),
+            })
+        }
+        id => Capability::Other { id, offset },
+    }
+}
+
+/// Reads the capability list of the function at `address`, which has `vendor` as its
+/// vendor ID
+pub fn read(config: &dyn ConfigSpace, address: Address, vendor: u16) -> Vec<Capability> {
+    let mut capabilities = Vec::new();
+    if config.read_u16(address, register::STATUS) & STATUS_CAPABILITIES == 0 {
+        return capabilities;
+    }
+    let mut offset = config.read_u8(address, register::CAPABILITIES) & !0b11;
+    while offset != 0 && capabilities.len() < MAX_CAPABILITIES {
+        let header = config.read_u16(address, offset as u16);
+        let id = header as u8;
+        capabilities.push(parse(config, address, vendor, id, offset));
+        offset = (header >> 8) as u8 & !0b11;
+    }
+    capabilities
+}
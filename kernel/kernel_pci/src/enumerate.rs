@@ -0,0 +1,344 @@
+//! Walks the buses behind a host bridge and sets up everything on them
+
+use alloc::vec::Vec;
+
+use crate::{
+    bar::{self, Bar, BarKind, Request},
+    capability, command, register, Address, ConfigSpace, DeviceId, Function, HostBridge, Window,
+    WindowKind, HEADER_BRIDGE, HEADER_ENDPOINT, HEADER_MULTIFUNCTION, HEADER_TYPE_MASK,
+};
+
+/// Bridges forward memory in 1MiB blocks and I/O in 4KiB blocks
+const BRIDGE_MEMORY_ALIGNMENT: u64 = 0x10_0000;
+const BRIDGE_IO_ALIGNMENT: u64 = 0x1000;
+/// Memory BARs are kept in separate pages, so that they can be mapped separately
+const MIN_MEMORY_ALIGNMENT: u64 = 0x1000;
+/// The bottom of I/O space is left alone, since legacy devices decode it
+const IO_START: u64 = 0x1000;
+
+/// Hands out addresses from the host bridge's windows, from the bottom up
+struct Allocator {
+    windows: Vec<(Window, u64)>,
+}
+
+impl Allocator {
+    fn new(windows: &[Window]) -> Self {
+        Self {
+            windows: windows
+                .iter()
+                .map(|window| {
+                    let start = match window.kind {
+                        WindowKind::Io => window.bus_address.max(IO_START),
+                        _ => window.bus_address,
+                    };
+                    (*window, start)
+                })
+                .collect(),
+        }
+    }
+
+    /// The first window of `kind` that memory of `prefetchable` can go in
+    fn window(&mut self, kind: WindowKind, prefetchable: bool) -> Option<&mut (Window, u64)> {
+        self.windows
+            .iter_mut()
+            .find(|(window, _)| window.kind == kind && (prefetchable || !window.prefetchable))
+    }
+
+    fn allocate_in(
+        &mut self,
+        kind: WindowKind,
+        prefetchable: bool,
+        size: u64,
+        align: u64,
+    ) -> Option<Bar> {
+        let (window, next) = self.window(kind, prefetchable)?;
+        let start = (*next + align - 1) & !(align - 1);
+        let end = start.checked_add(size)?;
+        if end > window.bus_address + window.size {
+            return None;
+        }
+        *next = end;
+        Some(Bar {
+            kind: BarKind::Io,
+            prefetchable,
+            address: window.to_cpu(start)?,
+            bus_address: start,
+            size,
+        })
+    }
+
+    /// 64-bit prefetchable memory goes above 4GiB if there's a window for it. Everything
+    /// else has to be below 4GiB, since bridges only forward 32-bit non-prefetchable memory.
+    fn allocate(&mut self, request: Request) -> Option<Bar> {
+        let size = request.size;
+        let bar = match request.kind {
+            BarKind::Io => self.allocate_in(WindowKind::Io, false, size, size),
+            BarKind::Memory64 if request.prefetchable => {
+                let align = size.max(MIN_MEMORY_ALIGNMENT);
+                self.allocate_in(WindowKind::Memory64, true, size, align)
+                    .or_else(|| self.allocate_in(WindowKind::Memory32, true, size, align))
+            }
+            BarKind::Memory32 | BarKind::Memory64 => self.allocate_in(
+                WindowKind::Memory32,
+                request.prefetchable,
+                size,
+                size.max(MIN_MEMORY_ALIGNMENT),
+            ),
+        }?;
+        Some(Bar {
+            kind: request.kind,
+            ..bar
+        })
+    }
+
+    /// Moves the next address in windows of `kind` up to `align`, and returns it
+    fn align(&mut self, kind: WindowKind, align: u64) -> Option<u64> {
+        let (window, next) = self.window(kind, true)?;
+        *next = ((*next + align - 1) & !(align - 1)).min(window.bus_address + window.size);
+        Some(*next)
+    }
+}
+
+struct Enumerator<'c, 'h> {
+    config: &'c dyn ConfigSpace,
+    host: &'h HostBridge,
+    allocator: Allocator,
+    next_bus: u8,
+    functions: Vec<Function<'c>>,
+}
+
+/// Where legacy interrupts of a bus end up on the root bus
+#[derive(Clone, Copy)]
+struct Swizzle {
+    /// The bridge on the root bus that the bus is behind
+    root_bridge: Address,
+    /// How far pins are rotated on the way there, which is the sum of the device numbers
+    /// of the bridges in between
+    rotation: u8,
+}
+
+impl<'c, 'h> Enumerator<'c, 'h> {
+    fn scan_bus(&mut self, bus: u8, swizzle: Option<Swizzle>) {
+        for device in 0..32 {
+            let address = Address::new(bus, device, 0);
+            if self.config.read_u16(address, register::VENDOR_ID) == u16::MAX {
+                continue;
+            }
+            let header_type = self.config.read_u8(address, register::HEADER_TYPE);
+            let functions = if header_type & HEADER_MULTIFUNCTION != 0 {
+                8
+            } else {
+                1
+            };
+            for function in 0..functions {
+                let address = Address::new(bus, device, function);
+                if self.config.read_u16(address, register::VENDOR_ID) != u16::MAX {
+                    self.scan_function(address, swizzle);
+                }
+            }
+        }
+    }
+
+    fn scan_function(&mut self, address: Address, swizzle: Option<Swizzle>) {
+        let config = self.config;
+        let header_type = config.read_u8(address, register::HEADER_TYPE) & HEADER_TYPE_MASK;
+        let bar_count = match header_type {
+            HEADER_ENDPOINT => 6,
+            HEADER_BRIDGE => 2,
+            _ => {
+                log::warn!("pci {}: unknown header type {}", address, header_type);
+                return;
+            }
+        };
+
+        // Decoding has to be off while the BARs are moved around
+        let old_command = config.read_u16(address, register::COMMAND);
+        config.write_u32(
+            address,
+            register::COMMAND,
+            (old_command & !(command::IO_SPACE | command::MEMORY_SPACE)) as u32,
+        );
+        let mut bars = [None; 6];
+        let mut index = 0;
+        while index < bar_count {
+            let (request, slots) = bar::size(config, address, index);
+            if let Some(request) = request {
+                match self.allocator.allocate(request) {
+                    Some(assigned) => {
+                        bar::assign(config, address, index, request.kind, assigned.bus_address);
+                        bars[index] = Some(assigned);
+                    }
+                    None => log::warn!(
+                        "pci {}: no room for BAR {} ({:?}, {:#x} bytes)",
+                        address,
+                        index,
+                        request.kind,
+                        request.size
+                    ),
+                }
+            }
+            index += slots;
+        }
+
+        let mut command = old_command & !(command::IO_SPACE | command::MEMORY_SPACE);
+        if bars.iter().flatten().any(|bar| bar.kind == BarKind::Io) {
+            command |= command::IO_SPACE;
+        }
+        if bars.iter().flatten().any(|bar| bar.kind != BarKind::Io) {
+            command |= command::MEMORY_SPACE;
+        }
+
+        if header_type == HEADER_BRIDGE {
+            // Bridges forward everything behind them, and DMA from there
+            config.write_u32(
+                address,
+                register::COMMAND,
+                (command | command::IO_SPACE | command::MEMORY_SPACE | command::BUS_MASTER) as u32,
+            );
+            self.scan_bridge(address, swizzle);
+            return;
+        }
+        config.write_u32(address, register::COMMAND, command as u32);
+
+        let id = DeviceId::new(
+            config.read_u16(address, register::VENDOR_ID),
+            config.read_u16(address, register::DEVICE_ID),
+        );
+        let class = config.read_u32(address, register::CLASS);
+        let interrupt_pin = config.read_u8(address, register::INTERRUPT_PIN);
+        let interrupt = match (interrupt_pin, swizzle) {
+            (0, _) => None,
+            (pin, None) => self.host.route(address, pin),
+            (pin, Some(swizzle)) => {
+                let pin = (pin - 1 + swizzle.rotation + address.device) % 4 + 1;
+                self.host.route(swizzle.root_bridge, pin)
+            }
+        };
+        self.functions.push(Function {
+            config,
+            address,
+            id,
+            class: (class >> 24) as u8,
+            subclass: (class >> 16) as u8,
+            prog_if: (class >> 8) as u8,
+            revision: class as u8,
+            bars,
+            interrupt_pin,
+            interrupt,
+            capabilities: capability::read(config, address, id.vendor),
+        });
+    }
+
+    /// Gives the bus behind the bridge at `address` a number, scans it, and makes the
+    /// bridge forward the addresses that were given out there
+    fn scan_bridge(&mut self, address: Address, swizzle: Option<Swizzle>) {
+        let config = self.config;
+        if self.next_bus > self.host.bus_end || self.next_bus == 0 {
+            log::warn!("pci {}: out of bus numbers", address);
+            return;
+        }
+        let secondary = self.next_bus;
+        self.next_bus = self.next_bus.wrapping_add(1);
+        let bus_numbers = |subordinate: u8| {
+            address.bus as u32 | (secondary as u32) << 8 | (subordinate as u32) << 16
+        };
+        // Let configuration accesses through to every bus below while they're numbered
+        config.write_u32(
+            address,
+            register::BUS_NUMBERS,
+            bus_numbers(self.host.bus_end),
+        );
+
+        let io_start = self.allocator.align(WindowKind::Io, BRIDGE_IO_ALIGNMENT);
+        let memory_start = self
+            .allocator
+            .align(WindowKind::Memory32, BRIDGE_MEMORY_ALIGNMENT);
+        let prefetchable_start = self
+            .allocator
+            .align(WindowKind::Memory64, BRIDGE_MEMORY_ALIGNMENT);
+
+        let swizzle = Some(match swizzle {
+            None => Swizzle {
+                root_bridge: address,
+                rotation: 0,
+            },
+            Some(swizzle) => Swizzle {
+                rotation: (swizzle.rotation + address.device) % 4,
+                ..swizzle
+            },
+        });
+        self.scan_bus(secondary, swizzle);
+
+        let subordinate = self.next_bus.wrapping_sub(1);
+        config.write_u32(address, register::BUS_NUMBERS, bus_numbers(subordinate));
+
+        // A limit below the base turns a window off
+        let io_end = self.allocator.align(WindowKind::Io, BRIDGE_IO_ALIGNMENT);
+        let (io_base, io_limit) = match (io_start, io_end) {
+            (Some(start), Some(end)) if end > start => (start, end - 1),
+            _ => (BRIDGE_IO_ALIGNMENT, 0),
+        };
+        config.write_u16(
+            address,
+            register::IO_BASE,
+            ((io_base >> 8) & 0xf0) as u16 | (((io_limit >> 8) & 0xf0) as u16) << 8,
+        );
+        config.write_u32(
+            address,
+            register::IO_BASE_UPPER,
+            (io_base >> 16) as u32 & 0xffff | ((io_limit >> 16) as u32) << 16,
+        );
+
+        let memory_end = self
+            .allocator
+            .align(WindowKind::Memory32, BRIDGE_MEMORY_ALIGNMENT);
+        let (memory_base, memory_limit) = match (memory_start, memory_end) {
+            (Some(start), Some(end)) if end > start => (start, end - 1),
+            _ => (BRIDGE_MEMORY_ALIGNMENT, 0),
+        };
+        config.write_u32(
+            address,
+            register::MEMORY_BASE,
+            ((memory_base >> 16) & 0xfff0) as u32 | (((memory_limit >> 16) & 0xfff0) as u32) << 16,
+        );
+
+        let prefetchable_end = self
+            .allocator
+            .align(WindowKind::Memory64, BRIDGE_MEMORY_ALIGNMENT);
+        let (prefetchable_base, prefetchable_limit) = match (prefetchable_start, prefetchable_end) {
+            (Some(start), Some(end)) if end > start => (start, end - 1),
+            _ => (BRIDGE_MEMORY_ALIGNMENT, 0),
+        };
+        config.write_u32(
+            address,
+            register::PREFETCHABLE_BASE,
+            ((prefetchable_base >> 16) & 0xfff0) as u32
+                | (((prefetchable_limit >> 16) & 0xfff0) as u32) << 16,
+        );
+        config.write_u32(
+            address,
+            register::PREFETCHABLE_BASE_UPPER,
+            (prefetchable_base >> 32) as u32,
+        );
+        config.write_u32(
+            address,
+            register::PREFETCHABLE_LIMIT_UPPER,
+            (prefetchable_limit >> 32) as u32,
+        );
+    }
+}
+
+/// Finds every function behind `host`, gives their BARs addresses from its windows, and
+/// routes their legacy interrupts. Bridges are given bus numbers and windows, and aren't
+/// returned. Everything is assumed to be unconfigured, like QEMU leaves it.
+pub fn enumerate<'c>(config: &'c dyn ConfigSpace, host: &HostBridge) -> Vec<Function<'c>> {
+    let mut enumerator = Enumerator {
+        config,
+        host,
+        allocator: Allocator::new(&host.windows),
+        next_bus: host.bus_start.wrapping_add(1),
+        functions: Vec::new(),
+    };
+    enumerator.scan_bus(host.bus_start, None);
+    enumerator.functions
+}
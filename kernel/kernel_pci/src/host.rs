@@ -0,0 +1,204 @@
+//! What the device tree says about a host bridge: the bus numbers behind it, the address
+//! windows it forwards to PCI, and how legacy interrupts are wired to interrupt controllers.
+//! See https://www.devicetree.org/open-firmware/bindings/pci/pci2_1.pdf and
+//! https://www.kernel.org/doc/Documentation/devicetree/bindings/pci/host-generic-pci.txt
+
+use alloc::vec::Vec;
+
+use kernel_fdt::{Cells, Node};
+
+use crate::Address;
+
+/// PCI addresses are 3 cells: the first says which space the address is in (and which
+/// function it's for, in `reg` and `interrupt-map`), and the other two are a 64-bit address
+const ADDRESS_CELLS: usize = 3;
+const SPACE_SHIFT: u32 = 24;
+const SPACE_MASK: u32 = 0b11;
+const SPACE_IO: u32 = 0b01;
+const SPACE_MEMORY32: u32 = 0b10;
+const SPACE_MEMORY64: u32 = 0b11;
+const PREFETCHABLE: u32 = 1 << 30;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HostError {
+    /// `#address-cells`, `#size-cells` or `#interrupt-cells` isn't what PCI uses
+    BadCells,
+    /// A property is shorter than it should be, or refers to an unknown phandle
+    BadProperty(&'static str),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WindowKind {
+    Io,
+    Memory32,
+    Memory64,
+}
+
+/// A range of PCI addresses that the host bridge forwards CPU accesses to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Window {
+    pub kind: WindowKind,
+    pub prefetchable: bool,
+    pub bus_address: u64,
+    /// In the address space of the host bridge's parent
+    pub cpu_address: u64,
+    pub size: u64,
+}
+
+impl Window {
+    /// Where the CPU sees a bus address in this window
+    pub fn to_cpu(&self, bus_address: u64) -> Option<u64> {
+        let offset = bus_address.checked_sub(self.bus_address)?;
+        (offset < self.size).then(|| self.cpu_address + offset)
+    }
+}
+
+/// An entry of `interrupt-map`, with the child address reduced to what legacy interrupts use
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct MapEntry {
+    phys_hi: u32,
+    pin: u32,
+    /// The interrupt specifier for the parent controller, whose first cell is the number
+    specifier: Vec<u32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HostBridge {
+    pub bus_start: u8,
+    pub bus_end: u8,
+    pub windows: Vec<Window>,
+    interrupt_map: Vec<MapEntry>,
+    /// Masks for the first cell of the child address and the pin
+    interrupt_map_mask: (u32, u32),
+}
+
+impl HostBridge {
+    pub fn from_node(node: &Node) -> Result<Self, HostError> {
+        if node.address_cells() as usize != ADDRESS_CELLS || node.size_cells() != 2 {
+            return Err(HostError::BadCells);
+        }
+        let (bus_start, bus_end) = match node.property("bus-range") {
+            Some(range) => {
+                let cells = range.cells();
+                let start = cells.get(0).ok_or(HostError::BadProperty("bus-range"))?;
+                let end = cells.get(1).ok_or(HostError::BadProperty("bus-range"))?;
+                (start.min(255) as u8, end.min(255) as u8)
+            }
+            None => (0, 255),
+        };
+        let parent_address_cells = node
+            .parent()
+            .map(|parent| parent.address_cells() as usize)
+            .unwrap_or(2);
+
+        let mut windows = Vec::new();
+        if let Some(ranges) = node.property("ranges") {
+            let mut rest = ranges.cells();
+            while !rest.is_empty() {
+                let (child, after) = rest
+                    .split_at(ADDRESS_CELLS)
+                    .ok_or(HostError::BadProperty("ranges"))?;
+                let (parent, after) = after
+                    .split_at(parent_address_cells)
+                    .ok_or(HostError::BadProperty("ranges"))?;
+                let (size, after) = after.split_at(2).ok_or(HostError::BadProperty("ranges"))?;
+                rest = after;
+
+                let phys_hi = child.get(0).unwrap_or(0);
+                let kind = match (phys_hi >> SPACE_SHIFT) & SPACE_MASK {
+                    SPACE_IO => WindowKind::Io,
+                    SPACE_MEMORY32 => WindowKind::Memory32,
+                    SPACE_MEMORY64 => WindowKind::Memory64,
+                    // Configuration space, which ECAM covers
+                    _ => continue,
+                };
+                windows.push(Window {
+                    kind,
+                    prefetchable: phys_hi & PREFETCHABLE != 0,
+                    bus_address: child.split_at(1).unwrap().1.to_u64(),
+                    cpu_address: parent.to_u64(),
+                    size: size.to_u64(),
+                });
+            }
+        }
+
+        let (interrupt_map, interrupt_map_mask) = match node.property("interrupt-map") {
+            Some(map) => (
+                parse_interrupt_map(node, map.cells())?,
+                match node.property("interrupt-map-mask") {
+                    Some(mask) => {
+                        let mask = mask.cells();
+                        let phys_hi = mask.get(0);
+                        let pin = mask.get(ADDRESS_CELLS);
+                        phys_hi
+                            .zip(pin)
+                            .ok_or(HostError::BadProperty("interrupt-map-mask"))?
+                    }
+                    None => (u32::MAX, u32::MAX),
+                },
+            ),
+            None => (Vec::new(), (u32::MAX, u32::MAX)),
+        };
+
+        Ok(Self {
+            bus_start,
+            bus_end,
+            windows,
+            interrupt_map,
+            interrupt_map_mask,
+        })
+    }
+
+    /// The interrupt number that `pin` (1 for INTA) of the function at `address` on the
+    /// root bus goes to. Functions behind bridges have to be swizzled to a root bus
+    /// address first.
+    pub fn route(&self, address: Address, pin: u8) -> Option<u32> {
+        let (phys_hi_mask, pin_mask) = self.interrupt_map_mask;
+        let phys_hi = address.phys_hi() & phys_hi_mask;
+        let pin = pin as u32 & pin_mask;
+        self.interrupt_map
+            .iter()
+            .find(|entry| entry.phys_hi & phys_hi_mask == phys_hi && entry.pin & pin_mask == pin)
+            .and_then(|entry| entry.specifier.first().copied())
+    }
+}
+
+fn parse_interrupt_map(node: &Node, mut rest: Cells) -> Result<Vec<MapEntry>, HostError> {
+    const PROPERTY: &str = "interrupt-map";
+    if node.interrupt_cells() != Some(1) {
+        return Err(HostError::BadCells);
+    }
+    let fdt = node.fdt();
+    let mut entries = Vec::new();
+    while !rest.is_empty() {
+        let (child, after) = rest
+            .split_at(ADDRESS_CELLS + 1)
+            .ok_or(HostError::BadProperty(PROPERTY))?;
+        let (phandle, after) = after.split_at(1).ok_or(HostError::BadProperty(PROPERTY))?;
+        let parent = phandle
+            .get(0)
+            .and_then(|phandle| fdt.find_phandle(phandle))
+            .ok_or(HostError::BadProperty(PROPERTY))?;
+        // Unlike in `reg`, a missing `#address-cells` means 0 here
+        let parent_address_cells = parent
+            .property("#address-cells")
+            .and_then(|cells| cells.as_u32())
+            .unwrap_or(0) as usize;
+        let parent_interrupt_cells = parent
+            .interrupt_cells()
+            .ok_or(HostError::BadProperty(PROPERTY))? as usize;
+        let (_, after) = after
+            .split_at(parent_address_cells)
+            .ok_or(HostError::BadProperty(PROPERTY))?;
+        let (specifier, after) = after
+            .split_at(parent_interrupt_cells)
+            .ok_or(HostError::BadProperty(PROPERTY))?;
+        rest = after;
+        entries.push(MapEntry {
+            phys_hi: child.get(0).unwrap(),
+            pin: child.get(ADDRESS_CELLS).unwrap(),
+            specifier: specifier.collect(),
+        });
+    }
+    Ok(entries)
+}
@@ -0,0 +1,264 @@
+//! PCI: finding the functions behind a host bridge, giving their BARs addresses, routing
+//! their legacy interrupts and reading their capabilities.
+//! Configuration space is accessed through ECAM, like on QEMU's virt machine
+//! ("pci-host-ecam-generic" in the device tree).
+//! See the PCI Local Bus Specification 3.0 (chapter 6) and the PCI-to-PCI Bridge
+//! Architecture Specification 1.2 (chapter 3).
+
+#![cfg_attr(not(test), no_std)]
+
+extern crate alloc;
+
+mod bar;
+pub mod capability;
+mod enumerate;
+mod host;
+#[cfg(test)]
+mod tests;
+
+use alloc::vec::Vec;
+use core::fmt;
+
+pub use bar::{Bar, BarKind};
+pub use capability::Capability;
+pub use enumerate::enumerate;
+pub use host::{HostBridge, HostError, Window, WindowKind};
+
+/// Offsets of registers in the configuration header
+pub mod register {
+    pub const VENDOR_ID: u16 = 0x00;
+    pub const DEVICE_ID: u16 = 0x02;
+    pub const COMMAND: u16 = 0x04;
+    pub const STATUS: u16 = 0x06;
+    /// Revision, programming interface, subclass and class, from the lowest byte up
+    pub const CLASS: u16 = 0x08;
+    pub const HEADER_TYPE: u16 = 0x0e;
+    pub const BAR0: u16 = 0x10;
+    pub const CAPABILITIES: u16 = 0x34;
+    pub const INTERRUPT_PIN: u16 = 0x3d;
+
+    // Bridges (header type 1)
+    /// Primary, secondary and subordinate bus numbers, from the lowest byte up
+    pub const BUS_NUMBERS: u16 = 0x18;
+    pub const IO_BASE: u16 = 0x1c;
+    pub const IO_LIMIT: u16 = 0x1d;
+    pub const MEMORY_BASE: u16 = 0x20;
+    pub const MEMORY_LIMIT: u16 = 0x22;
+    pub const PREFETCHABLE_BASE: u16 = 0x24;
+    pub const PREFETCHABLE_LIMIT: u16 = 0x26;
+    pub const PREFETCHABLE_BASE_UPPER: u16 = 0x28;
+    pub const PREFETCHABLE_LIMIT_UPPER: u16 = 0x2c;
+    pub const IO_BASE_UPPER: u16 = 0x30;
+}
+
+/// Bits of the command register
+pub mod command {
+    pub const IO_SPACE: u16 = 1 << 0;
+    pub const MEMORY_SPACE: u16 = 1 << 1;
+    pub const BUS_MASTER: u16 = 1 << 2;
+    pub const INTERRUPT_DISABLE: u16 = 1 << 10;
+}
+
+const STATUS_CAPABILITIES: u16 = 1 << 4;
+const HEADER_TYPE_MASK: u8 = 0x7f;
+const HEADER_MULTIFUNCTION: u8 = 0x80;
+const HEADER_ENDPOINT: u8 = 0;
+const HEADER_BRIDGE: u8 = 1;
+
+/// Where a function is in configuration space
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Address {
+    pub bus: u8,
+    /// Up to 31
+    pub device: u8,
+    /// Up to 7
+    pub function: u8,
+}
+
+impl Address {
+    pub const fn new(bus: u8, device: u8, function: u8) -> Self {
+        Self {
+            bus,
+            device,
+            function,
+        }
+    }
+
+    /// The first cell of the function's address in the device tree, without the address
+    /// space bits
+    pub fn phys_hi(&self) -> u32 {
+        (self.bus as u32) << 16 | (self.device as u32) << 11 | (self.function as u32) << 8
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:02x}:{:02x}.{}", self.bus, self.device, self.function)
+    }
+}
+
+/// Reads and writes the configuration space of every function behind a host bridge
+pub trait ConfigSpace: Send + Sync {
+    /// `offset` has to be a multiple of 4. Functions that aren't there read as all ones.
+    fn read_u32(&self, address: Address, offset: u16) -> u32;
+    fn write_u32(&self, address: Address, offset: u16, value: u32);
+
+    fn read_u16(&self, address: Address, offset: u16) -> u16 {
+        (self.read_u32(address, offset & !3) >> ((offset & 2) * 8)) as u16
+    }
+
+    fn read_u8(&self, address: Address, offset: u16) -> u8 {
+        (self.read_u32(address, offset & !3) >> ((offset & 3) * 8)) as u8
+    }
+
+    /// Writes the other half of the register back as it was read, so it can't be used
+    /// next to registers where writing ones clears bits, like the status register
+    fn write_u16(&self, address: Address, offset: u16, value: u16) {
+        let shift = (offset & 2) * 8;
+        let old = self.read_u32(address, offset & !3) & !(0xffff << shift);
+        self.write_u32(address, offset & !3, old | (value as u32) << shift);
+    }
+}
+
+/// The enhanced configuration access mechanism: the configuration space of every function
+/// is mapped in memory, 4KiB each
+pub struct Ecam {
+    base: usize,
+    bus_start: u8,
+    bus_end: u8,
+}
+
+impl Ecam {
+    /// # Safety
+    /// The ECAM region for buses `bus_start..=bus_end` has to be mapped at `base`
+    pub unsafe fn new(base: usize, bus_start: u8, bus_end: u8) -> Self {
+        Self {
+            base,
+            bus_start,
+            bus_end,
+        }
+    }
+
+    fn register(&self, address: Address, offset: u16) -> Option<*mut u32> {
+        if address.bus < self.bus_start || address.bus > self.bus_end {
+            return None;
+        }
+        let offset = ((address.bus - self.bus_start) as usize) << 20
+            | (address.device as usize & 0x1f) << 15
+            | (address.function as usize & 0x7) << 12
+            | (offset as usize & 0xffc);
+        Some((self.base + offset) as *mut u32)
+    }
+}
+
+impl ConfigSpace for Ecam {
+    fn read_u32(&self, address: Address, offset: u16) -> u32 {
+        match self.register(address, offset) {
+            Some(register) => unsafe { register.read_volatile() },
+            None => u32::MAX,
+        }
+    }
+
+    fn write_u32(&self, address: Address, offset: u16, value: u32) {
+        if let Some(register) = self.register(address, offset) {
+            unsafe { register.write_volatile(value) }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeviceId {
+    pub vendor: u16,
+    pub device: u16,
+}
+
+impl DeviceId {
+    pub const fn new(vendor: u16, device: u16) -> Self {
+        Self { vendor, device }
+    }
+}
+
+/// A function that was found and set up by [`enumerate`]
+#[derive(Clone)]
+pub struct Function<'c> {
+    config: &'c dyn ConfigSpace,
+    pub address: Address,
+    pub id: DeviceId,
+    pub class: u8,
+    pub subclass: u8,
+    pub prog_if: u8,
+    pub revision: u8,
+    /// The BARs that were given an address. A 64-bit BAR takes up two slots, and is in
+    /// the first one.
+    pub bars: [Option<Bar>; 6],
+    /// The legacy interrupt pin, from 1 for INTA to 4 for INTD, or 0 if it has none
+    pub interrupt_pin: u8,
+    /// The interrupt number that the host bridge's `interrupt-map` routes the pin to
+    pub interrupt: Option<u32>,
+    pub capabilities: Vec<Capability>,
+}
+
+impl<'c> fmt::Debug for Function<'c> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Function")
+            .field("address", &self.address)
+            .field("id", &self.id)
+            .field("class", &(self.class, self.subclass, self.prog_if))
+            .field("bars", &self.bars)
+            .field("interrupt", &self.interrupt)
+            .finish()
+    }
+}
+
+impl<'c> Function<'c> {
+    pub fn config(&self) -> &'c dyn ConfigSpace {
+        self.config
+    }
+
+    pub fn bar(&self, index: usize) -> Option<Bar> {
+        *self.bars.get(index)?
+    }
+
+    pub fn command(&self) -> u16 {
+        self.config.read_u16(self.address, register::COMMAND)
+    }
+
+    pub fn set_command(&self, command: u16) {
+        // Writing zeroes to the status register doesn't change it
+        self.config
+            .write_u32(self.address, register::COMMAND, command as u32);
+    }
+
+    /// Lets the function do DMA
+    pub fn enable_bus_master(&self) {
+        self.set_command(self.command() | command::BUS_MASTER);
+    }
+
+    pub fn msi(&self) -> Option<&capability::Msi> {
+        self.capabilities
+            .iter()
+            .find_map(|capability| match capability {
+                Capability::Msi(msi) => Some(msi),
+                _ => None,
+            })
+    }
+
+    pub fn msix(&self) -> Option<&capability::MsiX> {
+        self.capabilities
+            .iter()
+            .find_map(|capability| match capability {
+                Capability::MsiX(msix) => Some(msix),
+                _ => None,
+            })
+    }
+
+    /// The virtio structures of a virtio-pci device, in the order they're listed
+    pub fn virtio_capabilities(&self) -> impl Iterator<Item = &capability::Virtio> {
+        self.capabilities
+            .iter()
+            .filter_map(|capability| match capability {
+                Capability::Virtio(virtio) => Some(virtio),
+                _ => None,
+            })
+    }
+}
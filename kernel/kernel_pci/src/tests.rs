@@ -0,0 +1,463 @@
+use std::{collections::BTreeMap, sync::Mutex, vec::Vec};
+
+use kernel_fdt::{Fdt, FdtBuilder};
+
+use crate::{
+    capability::{BarOffset, Msi, MsiX, Virtio, VirtioKind},
+    command, enumerate, register, Address, Bar, BarKind, Capability, ConfigSpace, DeviceId,
+    HostBridge, Window, WindowKind, STATUS_CAPABILITIES,
+};
+
+const PLIC: u32 = 3;
+
+/// The host bridge node of QEMU's virt machine
+fn virt_tree() -> Vec<u8> {
+    let mut builder = FdtBuilder::new();
+    builder
+        .begin_node("")
+        .property_u32("#address-cells", 2)
+        .property_u32("#size-cells", 2)
+        .begin_node("soc")
+        .property_u32("#address-cells", 2)
+        .property_u32("#size-cells", 2)
+        .flag("ranges")
+        .begin_node("plic@c000000")
+        .property_u32("phandle", PLIC)
+        .property_u32("#interrupt-cells", 1)
+        .property_u32("#address-cells", 0)
+        .flag("interrupt-controller")
+        .end_node();
+
+    let mut interrupt_map = Vec::new();
+    for slot in 0..4 {
+        for pin in 1..=4 {
+            interrupt_map.extend([slot << 11, 0, 0, pin, PLIC, 0x20 + (slot + pin - 1) % 4]);
+        }
+    }
+    builder
+        .begin_node("pci@30000000")
+        .property_cells("interrupt-map-mask", &[0x1800, 0, 0, 7])
+        .property_cells("interrupt-map", &interrupt_map)
+        .property_u32("#interrupt-cells", 1)
+        .property_cells(
+            "ranges",
+            &[
+                // I/O, 32-bit memory and 64-bit memory
+                [0x0100_0000, 0, 0, 0, 0x0300_0000, 0, 0x1_0000],
+                [0x0200_0000, 0, 0x4000_0000, 0, 0x4000_0000, 0, 0x4000_0000],
+                [0x0300_0000, 4, 0, 4, 0, 4, 0],
+            ]
+            .concat(),
+        )
+        .property_cells("reg", &[0, 0x3000_0000, 0, 0x1000_0000])
+        .property_cells("bus-range", &[0, 0xff])
+        .property_u32("#address-cells", 3)
+        .property_u32("#size-cells", 2)
+        .property_str("device_type", "pci")
+        .property_str("compatible", "pci-host-ecam-generic")
+        .end_node();
+
+    builder.end_node().end_node();
+    builder.finish()
+}
+
+fn virt_host() -> HostBridge {
+    let blob = virt_tree();
+    let fdt = Fdt::new(&blob).unwrap();
+    HostBridge::from_node(&fdt.find_node("/soc/pci").unwrap()).unwrap()
+}
+
+struct FakeFunction {
+    registers: [u32; 64],
+    /// The bits of each BAR that can be written
+    bar_masks: [u32; 6],
+}
+
+impl FakeFunction {
+    fn new(id: DeviceId, class: u32, header_type: u8, pin: u8) -> Self {
+        let mut registers = [0; 64];
+        registers[0] = id.vendor as u32 | (id.device as u32) << 16;
+        registers[2] = class;
+        registers[3] = (header_type as u32) << 16;
+        registers[15] = (pin as u32) << 8;
+        Self {
+            registers,
+            bar_masks: [0; 6],
+        }
+    }
+
+    /// `flags` are the read-only low bits, which say what kind of BAR it is
+    fn bar(mut self, index: usize, flags: u32, size: u64) -> Self {
+        let mask = !(size - 1);
+        self.registers[4 + index] = flags;
+        self.bar_masks[index] = mask as u32 & !0xf & !flags;
+        if flags & 0b110 == 0b100 {
+            self.bar_masks[index + 1] = (mask >> 32) as u32;
+        }
+        self
+    }
+
+    /// Capabilities as their offset and contents. The list is linked in the given order.
+    fn capabilities(mut self, capabilities: &[(u8, &[u32])]) -> Self {
+        self.registers[1] |= (STATUS_CAPABILITIES as u32) << 16;
+        self.registers[register::CAPABILITIES as usize / 4] = capabilities[0].0 as u32;
+        for (i, (offset, dwords)) in capabilities.iter().enumerate() {
+            let next = capabilities.get(i + 1).map(|next| next.0).unwrap_or(0);
+            let start = *offset as usize / 4;
+            self.registers[start..start + dwords.len()].copy_from_slice(dwords);
+            self.registers[start] |= (next as u32) << 8;
+        }
+        self
+    }
+}
+
+#[derive(Default)]
+struct FakeConfig {
+    functions: Mutex<BTreeMap<Address, FakeFunction>>,
+}
+
+impl FakeConfig {
+    fn add(&self, address: Address, function: FakeFunction) {
+        self.functions.lock().unwrap().insert(address, function);
+    }
+
+    fn register(&self, address: Address, offset: u16) -> u32 {
+        self.read_u32(address, offset)
+    }
+}
+
+impl ConfigSpace for FakeConfig {
+    fn read_u32(&self, address: Address, offset: u16) -> u32 {
+        match self.functions.lock().unwrap().get(&address) {
+            Some(function) => function.registers[offset as usize / 4],
+            None => u32::MAX,
+        }
+    }
+
+    fn write_u32(&self, address: Address, offset: u16, value: u32) {
+        let mut functions = self.functions.lock().unwrap();
+        let function = functions.get_mut(&address).unwrap();
+        let index = offset as usize / 4;
+        let is_endpoint = function.registers[3] >> 16 & 0x7f == 0;
+        let bars = if is_endpoint { 4..10 } else { 4..6 };
+        function.registers[index] = if bars.contains(&index) {
+            let mask = function.bar_masks[index - 4];
+            (value & mask) | (function.registers[index] & !mask & 0xf)
+        } else if index == 1 {
+            // The status register only has read-only and write-one-to-clear bits
+            value & 0xffff | function.registers[index] & 0xffff_0000
+        } else {
+            value
+        };
+    }
+}
+
+const HOST_BRIDGE: DeviceId = DeviceId::new(0x1b36, 0x0008);
+const NE2000: DeviceId = DeviceId::new(0x10ec, 0x8029);
+const VIRTIO_NET: DeviceId = DeviceId::new(0x1af4, 0x1041);
+const BRIDGE: DeviceId = DeviceId::new(0x1b36, 0x0001);
+const E1000: DeviceId = DeviceId::new(0x8086, 0x100e);
+const NETWORK: u32 = 0x0200_0000;
+
+#[test]
+fn parses_host_bridge() {
+    let host = virt_host();
+    assert_eq!((host.bus_start, host.bus_end), (0, 0xff));
+    assert_eq!(
+        host.windows,
+        [
+            Window {
+                kind: WindowKind::Io,
+                prefetchable: false,
+                bus_address: 0,
+                cpu_address: 0x300_0000,
+                size: 0x1_0000
+            },
+            Window {
+                kind: WindowKind::Memory32,
+                prefetchable: false,
+                bus_address: 0x4000_0000,
+                cpu_address: 0x4000_0000,
+                size: 0x4000_0000
+            },
+            Window {
+                kind: WindowKind::Memory64,
+                prefetchable: false,
+                bus_address: 0x4_0000_0000,
+                cpu_address: 0x4_0000_0000,
+                size: 0x4_0000_0000
+            },
+        ]
+    );
+    assert_eq!(host.windows[0].to_cpu(0x1000), Some(0x300_1000));
+    assert_eq!(host.windows[0].to_cpu(0x1_0000), None);
+
+    // The slot number wraps around every 4 slots
+    assert_eq!(host.route(Address::new(0, 0, 0), 1), Some(0x20));
+    assert_eq!(host.route(Address::new(0, 1, 0), 1), Some(0x21));
+    assert_eq!(host.route(Address::new(0, 1, 0), 4), Some(0x20));
+    assert_eq!(host.route(Address::new(0, 6, 3), 2), Some(0x23));
+    assert_eq!(host.route(Address::new(0, 1, 0), 5), None);
+}
+
+#[test]
+fn rejects_bad_host_bridges() {
+    let mut builder = FdtBuilder::new();
+    builder
+        .begin_node("")
+        .begin_node("pci")
+        .property_u32("#address-cells", 2)
+        .property_u32("#size-cells", 2)
+        .end_node()
+        .begin_node("pci2")
+        .property_u32("#address-cells", 3)
+        .property_u32("#size-cells", 2)
+        .property_u32("#interrupt-cells", 1)
+        .property_cells("interrupt-map", &[0, 0, 0, 1, 99, 0x20])
+        .end_node()
+        .end_node();
+    let blob = builder.finish();
+    let fdt = Fdt::new(&blob).unwrap();
+    assert_eq!(
+        HostBridge::from_node(&fdt.find_node("/pci").unwrap()),
+        Err(crate::HostError::BadCells)
+    );
+    assert_eq!(
+        HostBridge::from_node(&fdt.find_node("/pci2").unwrap()),
+        Err(crate::HostError::BadProperty("interrupt-map"))
+    );
+}
+
+fn virt_devices() -> FakeConfig {
+    let config = FakeConfig::default();
+    config.add(
+        Address::new(0, 0, 0),
+        FakeFunction::new(HOST_BRIDGE, 0x0600_0000, 0, 0),
+    );
+    config.add(
+        Address::new(0, 1, 0),
+        FakeFunction::new(NE2000, NETWORK, 0, 1).bar(0, 0b01, 0x100),
+    );
+    config.add(
+        Address::new(0, 2, 0),
+        FakeFunction::new(VIRTIO_NET, NETWORK | 1, 0, 1)
+            .bar(1, 0, 0x1000)
+            .bar(4, 0b1100, 0x4000)
+            .capabilities(&[
+                // MSI-X with 3 vectors, the table at the start of BAR 1 and the pending bits after it
+                (0x98, &[0x11 | 2 << 16, 1, 0x801]),
+                // Common configuration
+                (0x84, &[0x09 | 16 << 16 | 1 << 24, 4, 0, 0x1000]),
+                // Notifications
+                (0x70, &[0x09 | 20 << 16 | 2 << 24, 4, 0x3000, 0x1000, 4]),
+                // MSI with 64-bit addresses and 4 vectors
+                (0x60, &[0x05 | (0x80 | 2 << 1) << 16]),
+            ]),
+    );
+    config
+}
+
+#[test]
+fn enumerates_bus() {
+    let host = virt_host();
+    let config = virt_devices();
+    let functions = enumerate(&config, &host);
+    let ids: Vec<DeviceId> = functions.iter().map(|function| function.id).collect();
+    assert_eq!(ids, [HOST_BRIDGE, NE2000, VIRTIO_NET]);
+
+    let ne2000 = &functions[1];
+    assert_eq!(ne2000.address, Address::new(0, 1, 0));
+    assert_eq!((ne2000.class, ne2000.subclass), (2, 0));
+    // The bottom of I/O space is skipped
+    assert_eq!(
+        ne2000.bar(0),
+        Some(Bar {
+            kind: BarKind::Io,
+            prefetchable: false,
+            address: 0x300_1000,
+            bus_address: 0x1000,
+            size: 0x100
+        })
+    );
+    assert_eq!(config.register(ne2000.address, register::BAR0), 0x1001);
+    assert_eq!(ne2000.command() & command::IO_SPACE, command::IO_SPACE);
+    assert_eq!(ne2000.command() & command::MEMORY_SPACE, 0);
+    assert_eq!((ne2000.interrupt_pin, ne2000.interrupt), (1, Some(0x21)));
+
+    let virtio = &functions[2];
+    assert_eq!(virtio.bar(0), None);
+    assert_eq!(virtio.bar(1).unwrap().address, 0x4000_0000);
+    let bar4 = virtio.bar(4).unwrap();
+    assert_eq!((bar4.kind, bar4.prefetchable), (BarKind::Memory64, true));
+    assert_eq!(bar4.address, 0x4_0000_0000);
+    assert_eq!(config.register(virtio.address, register::BAR0 + 16), 0xc);
+    assert_eq!(config.register(virtio.address, register::BAR0 + 20), 4);
+    assert_eq!(
+        virtio.command() & command::MEMORY_SPACE,
+        command::MEMORY_SPACE
+    );
+    assert_eq!(virtio.interrupt, Some(0x22));
+
+    virtio.enable_bus_master();
+    assert_eq!(
+        virtio.command(),
+        command::MEMORY_SPACE | command::BUS_MASTER
+    );
+}
+
+#[test]
+fn reads_capabilities() {
+    let host = virt_host();
+    let config = virt_devices();
+    let functions = enumerate(&config, &host);
+    assert!(functions[1].capabilities.is_empty());
+
+    let virtio = &functions[2];
+    assert_eq!(
+        virtio.msix(),
+        Some(&MsiX {
+            offset: 0x98,
+            table_size: 3,
+            table: BarOffset { bar: 1, offset: 0 },
+            pending_bits: BarOffset {
+                bar: 1,
+                offset: 0x800
+            },
+        })
+    );
+    assert_eq!(
+        virtio.msi(),
+        Some(&Msi {
+            offset: 0x60,
+            is_64bit: true,
+            per_vector_masking: false,
+            max_vectors: 4,
+        })
+    );
+    let virtio_capabilities: Vec<&Virtio> = virtio.virtio_capabilities().collect();
+    assert_eq!(
+        virtio_capabilities,
+        [
+            &Virtio {
+                offset: 0x84,
+                kind: VirtioKind::Common,
+                bar: 4,
+                bar_offset: 0,
+                length: 0x1000,
+                notify_off_multiplier: None,
+            },
+            &Virtio {
+                offset: 0x70,
+                kind: VirtioKind::Notify,
+                bar: 4,
+                bar_offset: 0x3000,
+                length: 0x1000,
+                notify_off_multiplier: Some(4),
+            }
+        ]
+    );
+    assert_eq!(virtio.capabilities.len(), 4);
+
+    // Vendor-specific capabilities of other vendors aren't virtio's
+    let config = FakeConfig::default();
+    config.add(
+        Address::new(0, 0, 0),
+        FakeFunction::new(E1000, NETWORK, 0, 0)
+            .capabilities(&[(0x40, &[0x09 | 16 << 16 | 1 << 24, 0, 0, 0])]),
+    );
+    let functions = enumerate(&config, &host);
+    assert_eq!(
+        functions[0].capabilities,
+        [Capability::Other {
+            id: 0x09,
+            offset: 0x40
+        }]
+    );
+}
+
+#[test]
+fn enumerates_behind_bridges() {
+    let host = virt_host();
+    let config = virt_devices();
+    config.add(
+        Address::new(0, 3, 0),
+        FakeFunction::new(BRIDGE, 0x0604_0000, 1, 0),
+    );
+    // Bus 1 is the first one that's handed out
+    config.add(
+        Address::new(1, 0, 0),
+        FakeFunction::new(E1000, NETWORK, 0, 1).bar(0, 0, 0x2_0000),
+    );
+    let functions = enumerate(&config, &host);
+    let ids: Vec<DeviceId> = functions.iter().map(|function| function.id).collect();
+    assert_eq!(ids, [HOST_BRIDGE, NE2000, VIRTIO_NET, E1000]);
+
+    let bridge = Address::new(0, 3, 0);
+    assert_eq!(config.register(bridge, register::BUS_NUMBERS), 0x01_01_00);
+    let e1000 = &functions[3];
+    assert_eq!(e1000.address, Address::new(1, 0, 0));
+    // Memory behind bridges starts at a 1MiB boundary
+    assert_eq!(e1000.bar(0).unwrap().address, 0x4010_0000);
+    // The bridge forwards 0x4010_0000..=0x401f_ffff
+    assert_eq!(
+        config.register(bridge, register::MEMORY_BASE),
+        0x4010 | 0x4010 << 16
+    );
+    // Nothing behind the bridge uses I/O or prefetchable memory, so those are off
+    let io = config.read_u16(bridge, register::IO_BASE);
+    assert!(io >> 8 < io & 0xff);
+    let prefetchable = config.register(bridge, register::PREFETCHABLE_BASE);
+    assert!(prefetchable >> 16 < prefetchable & 0xffff);
+    assert_eq!(
+        config.read_u16(bridge, register::COMMAND),
+        command::IO_SPACE | command::MEMORY_SPACE | command::BUS_MASTER
+    );
+    // INTA of device 0 behind the bridge in slot 3 comes in as INTA of slot 3
+    assert_eq!(e1000.interrupt, Some(0x23));
+}
+
+#[test]
+fn finds_multifunction_devices() {
+    let host = virt_host();
+    let config = FakeConfig::default();
+    config.add(
+        Address::new(0, 4, 0),
+        FakeFunction::new(E1000, NETWORK, 0x80, 1),
+    );
+    config.add(
+        Address::new(0, 4, 2),
+        FakeFunction::new(E1000, NETWORK, 0, 2),
+    );
+    // Without the multifunction bit, only function 0 is looked at
+    config.add(
+        Address::new(0, 5, 0),
+        FakeFunction::new(NE2000, NETWORK, 0, 1),
+    );
+    config.add(
+        Address::new(0, 5, 1),
+        FakeFunction::new(NE2000, NETWORK, 0, 1),
+    );
+    let functions = enumerate(&config, &host);
+    let addresses: Vec<String> = functions
+        .iter()
+        .map(|function| function.address.to_string())
+        .collect();
+    assert_eq!(addresses, ["00:04.0", "00:04.2", "00:05.0"]);
+    assert_eq!(functions[1].interrupt, Some(0x21));
+}
+
+#[test]
+fn leaves_bars_that_dont_fit() {
+    let host = virt_host();
+    let config = FakeConfig::default();
+    // Bigger than the whole 32-bit window
+    config.add(
+        Address::new(0, 1, 0),
+        FakeFunction::new(E1000, NETWORK, 0, 0)
+            .bar(0, 0, 0x8000_0000)
+            .bar(1, 0, 0x1000),
+    );
+    let functions = enumerate(&config, &host);
+    assert_eq!(functions[0].bar(0), None);
+    assert_eq!(functions[0].bar(1).unwrap().address, 0x4000_0000);
+}
@@ -0,0 +1,74 @@
+//! The files a process has open, and its current directory
+
+use alloc::{boxed::Box, collections::BTreeMap, string::String, sync::Arc};
+
+use kernel_io::file::File;
+use kernel_lock::future::Mutex as AsyncMutex;
+
+/// Shared between file descriptors that were duplicated, so that they share the cursor too
+pub type OpenFile = Arc<AsyncMutex<Box<dyn File>>>;
+
+/// This is kept in the process, behind a lock that can't be held across an `await`.
+/// Open the file first, then insert it here.
+pub struct FileTable {
+    /// Always absolute and normalized
+    current_directory: String,
+    descriptors: BTreeMap<usize, OpenFile>,
+}
+
+impl Default for FileTable {
+    fn default() -> Self {
+        Self {
+            current_directory: String::from("/"),
+            descriptors: BTreeMap::new(),
+        }
+    }
+}
+
+impl FileTable {
+    pub fn current_directory(&self) -> &str {
+        &self.current_directory
+    }
+
+    /// `path` has to be absolute and normalized, and the caller has to check that the
+    /// directory exists. Relative paths can be made absolute with
+    /// `kernel_vfs::path::normalize(files.current_directory(), path)`.
+    pub fn set_current_directory(&mut self, path: String) {
+        self.current_directory = path;
+    }
+
+    /// Returns the lowest file descriptor that isn't in use
+    fn free_descriptor(&self) -> usize {
+        self.descriptors
+            .keys()
+            .enumerate()
+            .find(|(index, fd)| index != *fd)
+            .map(|(index, _)| index)
+            .unwrap_or(self.descriptors.len())
+    }
+
+    pub fn insert(&mut self, file: Box<dyn File>) -> usize {
+        self.insert_open(Arc::new(AsyncMutex::new(file)))
+    }
+
+    fn insert_open(&mut self, file: OpenFile) -> usize {
+        let fd = self.free_descriptor();
+        self.descriptors.insert(fd, file);
+        fd
+    }
+
+    pub fn get(&self, fd: usize) -> Option<OpenFile> {
+        self.descriptors.get(&fd).cloned()
+    }
+
+    /// The file is closed once every descriptor for it is removed
+    pub fn remove(&mut self, fd: usize) -> Option<OpenFile> {
+        self.descriptors.remove(&fd)
+    }
+
+    /// Makes a new descriptor for the same open file
+    pub fn duplicate(&mut self, fd: usize) -> Option<usize> {
+        let file = self.get(fd)?;
+        Some(self.insert_open(file))
+    }
+}
@@ -2,6 +2,9 @@
 
 extern crate alloc;
 
+pub mod file_table;
+pub mod shared_region;
+
 use alloc::{
     boxed::Box,
     string::String,
@@ -12,7 +15,7 @@ use alloc::{
 use core::{
     borrow::BorrowMut,
     future::Future,
-    sync::atomic::AtomicBool,
+    sync::atomic::{AtomicBool, AtomicU64},
     task::{Poll, Waker},
 };
 
@@ -23,6 +26,8 @@ use kernel_lock::shared::Mutex;
 use kernel_trap_frame::TrapFrame;
 use kernel_util::{boxed_slice_with_alignment, maybe_waker::{MaybeWaker, wake_all_that_are_ready}};
 use kernel_resource_map::ResourceMap;
+use file_table::FileTable;
+use shared_region::SharedRegion;
 
 extern "C" {
     fn store_to_trap_frame_and_run_function(a: *mut TrapFrame, b: usize, c: usize);
@@ -43,8 +48,23 @@ impl Default for ProcessState {
     }
 }
 
+/// Bits of `Process::capabilities`: what a process may do that affects more than itself.
+/// Only the kernel grants them, when it starts the process.
+pub mod capabilities {
+    /// Powering off and rebooting the machine
+    pub const POWER: usize = 1 << 0;
+}
+
+/// The next ID that `Process::new_supervisor` gives out. 0 is left for the kernel itself.
+static NEXT_PROCESS_ID: AtomicU64 = AtomicU64::new(1);
+
 #[derive(Default)]
 pub struct Process {
+    /// Unique for as long as the kernel runs. The kernel tells receivers which process sent
+    /// a buffer by this, so unlike queue IDs, it can't be made up.
+    pub id: u64,
+    /// A combination of the bits in [`capabilities`]
+    pub capabilities: usize,
     pub is_supervisor: bool,
     pub trap_frame: Box<TrapFrame>,
     pub name: Option<String>,
@@ -52,6 +72,13 @@ pub struct Process {
     pub wake_on_paused: Arc<Mutex<ProcessWakerStruct>>,
     pub page_table: Option<Arc<kernel_paging::ArchTable>>,
     pub this: ProcessContainerWeak,
+    /// Shared regions this process has a handle to, by handle
+    pub shared_region_handles: BTreeMap<usize, Arc<SharedRegion>>,
+    /// Shared regions mapped into this process, by virtual address.
+    /// These keep the region alive even after the handle is closed.
+    pub shared_region_mappings: BTreeMap<usize, Arc<SharedRegion>>,
+    /// Open files and the current directory
+    pub files: FileTable,
 }
 
 #[derive(Default, Debug)]
@@ -70,11 +97,18 @@ impl ProcessWakerStruct {
     }
 
     fn wake_up(this: &Mutex<Self>, source: u64) -> bool {
+        // Only the process's own sources are remembered, so that nothing else can make the
+        // maps grow
+        if !this.lock().waking_sources_enabled.contains_key(&source) {
+            return false;
+        }
+        // Always remember that the source fired, even if it isn't enabled yet.
+        // Otherwise a source that fires before the process gets to enable it would be lost.
+        this.lock().woken_sources.insert(source, true);
         if this.lock().waking_sources_enabled.get(&source).map(|s| *s).unwrap_or(false) {
             let wakers = this.lock().take_wakers();
             let wakers: Vec<MaybeWaker> = wake_all_that_are_ready(wakers.into_iter()).collect();
             this.lock().wakers.extend(wakers.into_iter());
-            this.lock().woken_sources.insert(source, true);
             true
         } else {
             false
@@ -84,14 +118,23 @@ impl ProcessWakerStruct {
     pub fn is_woken(&self, source: u64) -> Option<bool> {
         self.woken_sources.get(&source).copied()
     }
+    
+    /// Returns the index of the first source in `sources` that has been woken
+    pub fn first_woken(&self, sources: &[u64]) -> Option<usize> {
+        sources.iter().position(|source| self.is_woken(*source) == Some(true))
+    }
 
 
     fn add_waker(&mut self, waker: MaybeWaker) {
         self.wakers.push(waker);
     }
     
+    /// Lets `source` wake the process up. Does nothing if `source` isn't one of the
+    /// process's own sources, so that processes can't make up IDs.
     pub fn enable_source(&mut self, source: u64) {
-        self.waking_sources_enabled.insert(source, true);
+        if let Some(enabled) = self.waking_sources_enabled.get_mut(&source) {
+            *enabled = true;
+        }
     }
     
     
@@ -112,6 +155,17 @@ impl ProcessWakerStruct {
         }
     }
     
+    /// Makes `source` not woken and not enabled again, so that it can be waited on once more.
+    /// Returns false if `source` isn't one of the process's own sources.
+    fn rearm_source(&mut self, source: u64) -> bool {
+        if !self.waking_sources_enabled.contains_key(&source) {
+            return false;
+        }
+        self.woken_sources.insert(source, false);
+        self.waking_sources_enabled.insert(source, false);
+        true
+    }
+
     fn new_disabled_source(&mut self) -> u64 {
         let id = self.generate_source_id();
         self.woken_sources.insert(id, false);
@@ -157,6 +211,7 @@ impl Process {
         virt_to_phys: fn(usize) -> usize,
     ) -> Arc<Mutex<Self>> {
         let mut this = Self {
+            id: NEXT_PROCESS_ID.fetch_add(1, Ordering::Relaxed),
             is_supervisor: true,
             kernel_allocated_stack: Some(boxed_slice_with_alignment(4096 * 8, 4096, &0)),
             ..Default::default()
@@ -185,11 +240,25 @@ impl Process {
 
     pub fn waker(&mut self) -> (MaybeWaker, u64) {
         let id = self.wake_on_paused.lock().new_disabled_source();
+        (self.waker_for_source(id), id)
+    }
+
+    /// Like `waker`, but reuses the existing source `id` instead of making a new one, so
+    /// that a future can be waited on again without using up another ID.
+    /// `None` if `id` doesn't belong to this process.
+    pub fn rearmed_waker(&mut self, id: u64) -> Option<MaybeWaker> {
+        if self.wake_on_paused.lock().rearm_source(id) {
+            Some(self.waker_for_source(id))
+        } else {
+            None
+        }
+    }
+
+    fn waker_for_source(&self, id: u64) -> MaybeWaker {
         let this = self.wake_on_paused.clone();
-        let waker: MaybeWaker = (Arc::new(move || {
+        (Arc::new(move || {
             ProcessWakerStruct::wake_up(&this, id)
-        }) as Arc<dyn Fn() -> bool + 'static>).into();
-        (waker, id)
+        }) as Arc<dyn Fn() -> bool + 'static>).into()
     }
 
     pub fn wait_until_woken(&mut self) -> WaitUntilReady {
@@ -0,0 +1,30 @@
+use alloc::{boxed::Box, sync::Arc};
+
+use kernel_util::boxed_slice_with_alignment;
+
+/// Memory that can be mapped into several processes at once.
+/// The pages are freed when the last `Arc` to it is dropped, which happens once
+/// every process has closed its handle and every process that mapped it is gone.
+#[derive(Debug)]
+pub struct SharedRegion {
+    pages: Box<[u8]>,
+}
+
+impl SharedRegion {
+    /// Allocates a zeroed region. `size` is rounded up to a whole number of pages.
+    pub fn new(size: usize) -> Arc<Self> {
+        let size = size.div_ceil(4096) * 4096;
+        Arc::new(Self {
+            pages: boxed_slice_with_alignment(size, 4096, &0),
+        })
+    }
+
+    /// Address of the region in the kernel's address space
+    pub fn kernel_address(&self) -> usize {
+        self.pages.as_ptr() as usize
+    }
+
+    pub fn size(&self) -> usize {
+        self.pages.len()
+    }
+}
@@ -0,0 +1,52 @@
+//! Entropy from how long the same small piece of work takes each time.
+//! Caches, pipelines, interrupts and, in a VM, the host make the time vary a little, and the
+//! lowest bits of the differences are hard to predict. Not much, so the bytes are credited
+//! with `BITS_PER_BYTE` bits each, and only if none of their measurements were stuck.
+//! Like in the Linux kernel's jitterentropy, a measurement is stuck if it, or its first or
+//! second difference from the measurements before it, is zero, which is what a counter that
+//! doesn't tick or ticks too evenly gives.
+
+use crate::chacha;
+
+/// How many bits of entropy a byte from [`collect`] is assumed to have
+pub const BITS_PER_BYTE: usize = 1;
+
+/// Measurements that are folded into each byte
+const SAMPLES_PER_BYTE: usize = 8;
+
+/// Fills `out` with bytes made from timing a workload with `read_counter`, which should be
+/// a fine-grained counter like the cycle counter. Returns how many bits of entropy the bytes
+/// are credited with, which is 0 if every byte had a stuck measurement.
+pub fn collect(mut read_counter: impl FnMut() -> u64, out: &mut [u8]) -> usize {
+    let mut work = [0; chacha::KEY_SIZE];
+    let (mut last_delta, mut last_delta2) = (0u64, 0u64);
+    let mut healthy_bytes = 0;
+    for byte in out.iter_mut() {
+        let mut stuck = false;
+        for _ in 0..SAMPLES_PER_BYTE {
+            let start = read_counter();
+            // The result is fed back in, so that the work can't be skipped
+            let block = chacha::block(&work, start as u32, &[0; 12]);
+            work.copy_from_slice(&block[..chacha::KEY_SIZE]);
+            let delta = read_counter().wrapping_sub(start);
+            *byte = byte.rotate_left(3) ^ fold(delta);
+
+            let delta2 = delta.wrapping_sub(last_delta);
+            let delta3 = delta2.wrapping_sub(last_delta2);
+            stuck |= delta == 0 || delta2 == 0 || delta3 == 0;
+            (last_delta, last_delta2) = (delta, delta2);
+        }
+        if !stuck {
+            healthy_bytes += 1;
+        }
+    }
+    healthy_bytes * BITS_PER_BYTE
+}
+
+/// XORs all the bytes of `value` together
+fn fold(value: u64) -> u8 {
+    value
+        .to_le_bytes()
+        .iter()
+        .fold(0, |folded, byte| folded ^ byte)
+}
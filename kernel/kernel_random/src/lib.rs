@@ -0,0 +1,81 @@
+//! The kernel's cryptographically secure random number generator.
+//!
+//! [`Csprng`] turns a 256-bit key into output with ChaCha20, and replaces the key with the
+//! first part of the keystream every time it's used ("fast key erasure"), so that output
+//! that was already handed out can't be recovered from the generator's state later.
+//! Entropy is mixed in by XORing it into the key and rekeying. Sources say how many bits of
+//! entropy they added, and the generator counts as seeded once that's at least 256.
+//! See https://blog.cr.yp.to/20170723-random.html
+
+#![cfg_attr(not(test), no_std)]
+
+pub mod chacha;
+pub mod jitter;
+#[cfg(test)]
+mod tests;
+
+use chacha::{BLOCK_SIZE, KEY_SIZE};
+
+/// Bits of entropy after which the generator is seeded
+pub const SEED_BITS: usize = 256;
+
+/// Keeps the keystreams used for output and for mixing in entropy apart
+const OUTPUT_NONCE: [u8; 12] = [0; 12];
+const MIX_NONCE: [u8; 12] = [1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+
+pub struct Csprng {
+    key: [u8; KEY_SIZE],
+    /// Bits of entropy that have been added, as estimated by their sources
+    entropy_bits: usize,
+}
+
+impl Csprng {
+    /// A generator without any entropy, whose output is predictable until it's seeded
+    pub const fn new() -> Self {
+        Self {
+            key: [0; KEY_SIZE],
+            entropy_bits: 0,
+        }
+    }
+
+    /// Mixes `data` into the key. `entropy_bits` is how much of it is unpredictable.
+    pub fn add_entropy(&mut self, data: &[u8], entropy_bits: usize) {
+        for chunk in data.chunks(KEY_SIZE) {
+            for (key, byte) in self.key.iter_mut().zip(chunk) {
+                *key ^= byte;
+            }
+            let block = chacha::block(&self.key, 0, &MIX_NONCE);
+            self.key.copy_from_slice(&block[..KEY_SIZE]);
+        }
+        self.entropy_bits = self.entropy_bits.saturating_add(entropy_bits);
+    }
+
+    pub fn is_seeded(&self) -> bool {
+        self.entropy_bits >= SEED_BITS
+    }
+
+    /// Fills `out` with random bytes, and replaces the key
+    pub fn fill(&mut self, out: &mut [u8]) {
+        let first = chacha::block(&self.key, 0, &OUTPUT_NONCE);
+        let (next_key, rest) = first.split_at(KEY_SIZE);
+        let (start, out) = out.split_at_mut(out.len().min(rest.len()));
+        start.copy_from_slice(&rest[..start.len()]);
+        for (counter, chunk) in out.chunks_mut(BLOCK_SIZE).enumerate() {
+            let block = chacha::block(&self.key, counter as u32 + 1, &OUTPUT_NONCE);
+            chunk.copy_from_slice(&block[..chunk.len()]);
+        }
+        self.key.copy_from_slice(next_key);
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        let mut bytes = [0; 4];
+        self.fill(&mut bytes);
+        u32::from_le_bytes(bytes)
+    }
+}
+
+impl Default for Csprng {
+    fn default() -> Self {
+        Self::new()
+    }
+}
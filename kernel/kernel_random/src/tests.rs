@@ -0,0 +1,169 @@
+use std::{vec, vec::Vec};
+
+use crate::{chacha, jitter, Csprng, SEED_BITS};
+
+fn hex(text: &str) -> Vec<u8> {
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&text[i..i + 2], 16).unwrap())
+        .collect()
+}
+
+#[test]
+fn chacha20_block_matches_rfc_8439() {
+    // Section 2.3.2
+    let key: [u8; 32] = core::array::from_fn(|i| i as u8);
+    let nonce = [0, 0, 0, 0x09, 0, 0, 0, 0x4a, 0, 0, 0, 0];
+    let expected = hex(concat!(
+        "10f1e7e4d13b5915500fdd1fa32071c4c7d1f4c733c068030422aa9ac3d46c4e",
+        "d2826446079faa0914c2d705d98b02a2b5129cd1de164eb9cbd083e8a2503c4e",
+    ));
+    assert_eq!(chacha::block(&key, 1, &nonce).to_vec(), expected);
+}
+
+#[test]
+fn output_skips_the_next_key() {
+    let mut rng = Csprng::new();
+    let mut out = vec![0; 100];
+    rng.fill(&mut out);
+    // The keystream of the zero key, without its first 32 bytes
+    assert_eq!(out[..16], hex("da41597c5157488d7724e03fb8d84a37"));
+    assert_eq!(out[32..48], hex("9f07e7be5551387a98ba977c732d080d"));
+
+    // The first 32 bytes became the key
+    let mut next = [0; 16];
+    rng.fill(&mut next);
+    assert_eq!(next.to_vec(), hex("afbdad2845b93cdbb2fe6463d2fe162a"));
+}
+
+#[test]
+fn output_never_repeats() {
+    let mut rng = Csprng::new();
+    let mut first = [0; 32];
+    let mut second = [0; 32];
+    rng.fill(&mut first);
+    rng.fill(&mut second);
+    assert_ne!(first, second);
+}
+
+#[test]
+fn fills_any_length() {
+    for len in [0, 1, 31, 32, 33, 64, 65, 1000] {
+        let mut rng = Csprng::new();
+        let mut out = vec![0; len];
+        rng.fill(&mut out);
+        if len >= 32 {
+            assert!(out.iter().any(|&byte| byte != 0));
+        }
+    }
+}
+
+#[test]
+fn entropy_changes_the_output() {
+    let mut a = Csprng::new();
+    let mut b = Csprng::new();
+    a.add_entropy(b"some entropy", 0);
+    b.add_entropy(b"other entropy", 0);
+    let mut out_a = [0; 32];
+    let mut out_b = [0; 32];
+    a.fill(&mut out_a);
+    b.fill(&mut out_b);
+    assert_ne!(out_a, out_b);
+
+    // The same entropy gives the same output
+    let mut c = Csprng::new();
+    c.add_entropy(b"some entropy", 0);
+    let mut out_c = [0; 32];
+    c.fill(&mut out_c);
+    assert_eq!(out_a, out_c);
+}
+
+#[test]
+fn seeded_after_enough_entropy() {
+    let mut rng = Csprng::new();
+    assert!(!rng.is_seeded());
+    rng.add_entropy(&[1; 16], SEED_BITS / 2);
+    assert!(!rng.is_seeded());
+    rng.add_entropy(&[2; 16], SEED_BITS / 2);
+    assert!(rng.is_seeded());
+}
+
+#[test]
+fn jitter_depends_on_the_timing() {
+    let mut steady = 0;
+    let mut out_steady = [0; 16];
+    jitter::collect(
+        || {
+            steady += 10;
+            steady
+        },
+        &mut out_steady,
+    );
+
+    let mut uneven: u64 = 0;
+    let mut samples = 0u64;
+    let mut out_uneven = [0; 16];
+    jitter::collect(
+        || {
+            samples += 1;
+            uneven += 10 + samples * samples % 7;
+            uneven
+        },
+        &mut out_uneven,
+    );
+    assert_ne!(out_steady, out_uneven);
+}
+
+/// A counter that goes up by an unpredictable-looking amount every time it's read
+fn noisy_counter() -> impl FnMut() -> u64 {
+    let (mut counter, mut state) = (0u64, 0x2545_f491_4f6c_dd1du64);
+    move || {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        counter += 100 + state % 1000;
+        counter
+    }
+}
+
+#[test]
+fn jitter_credits_healthy_bytes() {
+    let mut out = [0; 32];
+    let bits = jitter::collect(noisy_counter(), &mut out);
+    assert_eq!(bits, out.len() * jitter::BITS_PER_BYTE);
+}
+
+#[test]
+fn jitter_health_check() {
+    let mut out = [0; 32];
+    // A counter that doesn't tick
+    assert_eq!(jitter::collect(|| 5, &mut out), 0);
+    // One that ticks evenly, or speeds up evenly
+    let mut steady = 0;
+    let steady_counter = || {
+        steady += 10;
+        steady
+    };
+    assert_eq!(jitter::collect(steady_counter, &mut out), 0);
+    let (mut reads, mut accelerating) = (0, 0);
+    let accelerating_counter = || {
+        reads += 1;
+        accelerating += reads;
+        accelerating
+    };
+    assert_eq!(jitter::collect(accelerating_counter, &mut out), 0);
+
+    // Bytes with a stuck measurement aren't credited, but the others still are
+    let mut noisy = noisy_counter();
+    let (mut reads, mut last) = (0, 0);
+    let sometimes_stuck = || {
+        reads += 1;
+        // Every 64th measurement, which is in every 8th byte, takes no time
+        if reads % 128 != 0 {
+            last = noisy();
+        }
+        last
+    };
+    let bits = jitter::collect(sometimes_stuck, &mut out);
+    assert_eq!(bits, (out.len() - out.len() / 8) * jitter::BITS_PER_BYTE);
+}
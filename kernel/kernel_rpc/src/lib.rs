@@ -0,0 +1,101 @@
+//! Typed remote procedure calls between processes, over buffer queues.
+//!
+//! Services are declared with the `service!` macro, which generates a trait for the server
+//! and a client stub. Argument and return types must implement `Wire`, which can be derived.
+//! ```ignore
+//! kernel_rpc::service! {
+//!     pub service Counter {
+//!         version: 1,
+//!         client: CounterClient,
+//!         methods {
+//!             0 => fn add(amount: u64) -> u64;
+//!             1 => fn reset() -> ();
+//!         }
+//!     }
+//! }
+//! ```
+#![cfg_attr(not(test), no_std)]
+
+extern crate alloc;
+// So that the derive macro can refer to `::kernel_rpc` from inside this crate
+extern crate self as kernel_rpc;
+
+#[cfg(test)]
+mod tests;
+#[cfg(target_arch = "riscv64")]
+pub mod transport;
+pub mod wire;
+
+#[cfg(target_arch = "riscv64")]
+pub use kernel_api::BufferQueue;
+pub use kernel_rpc_derive::Wire;
+#[cfg(target_arch = "riscv64")]
+pub use transport::{handle_request, serve, Connection, DispatchError, RequestHeader, RpcError};
+pub use wire::{from_bytes, to_bytes, Reader, Wire, WireError, Writer};
+
+#[macro_export]
+macro_rules! service {
+    (
+        $(#[$meta:meta])*
+        $vis:vis service $name:ident {
+            version: $version:expr,
+            client: $client:ident,
+            methods {
+                $($id:literal => fn $method:ident($($arg:ident: $arg_ty:ty),* $(,)?) -> $ret:ty;)*
+            }
+        }
+    ) => {
+        $(#[$meta])*
+        $vis trait $name {
+            const VERSION: u16 = $version;
+
+            $(fn $method(&mut self, $($arg: $arg_ty),*) -> $ret;)*
+
+            /// Decodes the arguments for `method`, runs it and encodes the return value
+            fn dispatch(
+                &mut self,
+                method: u16,
+                reader: &mut $crate::Reader<'_>,
+                writer: &mut $crate::Writer,
+            ) -> ::core::result::Result<(), $crate::DispatchError> {
+                match method {
+                    $($id => {
+                        let ($($arg,)*): ($($arg_ty,)*) = $crate::Wire::decode(reader)?;
+                        let ret: $ret = self.$method($($arg),*);
+                        $crate::Wire::encode(&ret, writer);
+                        Ok(())
+                    })*
+                    _ => Err($crate::DispatchError::UnknownMethod(method)),
+                }
+            }
+
+            /// Serves requests that arrive at `queue` forever
+            fn serve(&mut self, queue: &$crate::BufferQueue) -> !
+            where
+                Self: Sized,
+            {
+                $crate::serve(queue, Self::VERSION, |header, _sender, reader, writer| {
+                    self.dispatch(header.method, reader, writer)
+                })
+            }
+        }
+
+        $vis struct $client {
+            connection: $crate::Connection,
+        }
+
+        impl $client {
+            pub const VERSION: u16 = $version;
+
+            pub fn new(connection: $crate::Connection) -> Self {
+                Self { connection }
+            }
+
+            $(
+                pub fn $method(&self, $($arg: $arg_ty),*) -> ::core::result::Result<$ret, $crate::RpcError> {
+                    self.connection.call(Self::VERSION, $id, &($($arg,)*))
+                }
+            )*
+        }
+    };
+}
@@ -0,0 +1,193 @@
+use alloc::{string::String, vec, vec::Vec};
+
+use crate::{from_bytes, to_bytes, Reader, Wire, WireError, Writer};
+
+#[derive(Wire, Debug, Clone, PartialEq)]
+struct Point {
+    x: i32,
+    y: i32,
+}
+
+#[derive(Wire, Debug, PartialEq)]
+struct Named(String, Option<u8>);
+
+#[derive(Wire, Debug, PartialEq)]
+enum Shape {
+    Empty,
+    Circle(Point, u32),
+    Polygon { name: String, points: Vec<Point> },
+}
+
+#[derive(Wire, Debug, Clone, PartialEq)]
+struct Unit;
+
+#[derive(Wire, Debug, PartialEq)]
+struct Wrapper<T> {
+    inner: T,
+}
+
+fn round_trip<T: Wire + PartialEq + core::fmt::Debug>(value: T) {
+    let bytes = to_bytes(&value);
+    assert_eq!(from_bytes::<T>(&bytes), Ok(value));
+}
+
+#[test]
+fn integers_are_little_endian() {
+    assert_eq!(to_bytes(&0x1234u16), [0x34, 0x12]);
+    assert_eq!(to_bytes(&-2i32), [0xfe, 0xff, 0xff, 0xff]);
+    assert_eq!(to_bytes(&1usize), [1, 0, 0, 0, 0, 0, 0, 0]);
+}
+
+#[test]
+fn builtin_types_round_trip() {
+    round_trip(u8::MAX);
+    round_trip(i64::MIN);
+    round_trip(u128::MAX);
+    round_trip(usize::MAX);
+    round_trip(-1isize);
+    round_trip(true);
+    round_trip(());
+    round_trip(Some(5u16));
+    round_trip(None::<u16>);
+    round_trip(Ok::<u8, String>(1));
+    round_trip(Err::<u8, String>(String::from("no")));
+    round_trip(vec![1u32, 2, 3]);
+    round_trip(Vec::<u32>::new());
+    round_trip(String::from("héllo"));
+    round_trip([7u8; 4]);
+    round_trip((1u8, String::from("two"), 3u64));
+}
+
+#[test]
+fn vecs_of_zero_sized_types_round_trip() {
+    round_trip(vec![(); 3]);
+    round_trip(vec![Unit; 1000]);
+    assert_eq!(to_bytes(&vec![Unit; 2]), [2, 0, 0, 0]);
+}
+
+#[test]
+fn sizes_that_dont_fit_are_errors() {
+    let bytes = to_bytes(&u64::MAX);
+    match usize::try_from(u64::MAX) {
+        Ok(max) => assert_eq!(from_bytes::<usize>(&bytes), Ok(max)),
+        Err(_) => assert_eq!(from_bytes::<usize>(&bytes), Err(WireError::OutOfRange)),
+    }
+    let bytes = to_bytes(&i64::MIN);
+    match isize::try_from(i64::MIN) {
+        Ok(min) => assert_eq!(from_bytes::<isize>(&bytes), Ok(min)),
+        Err(_) => assert_eq!(from_bytes::<isize>(&bytes), Err(WireError::OutOfRange)),
+    }
+}
+
+#[test]
+fn variable_length_values_have_a_u32_prefix() {
+    assert_eq!(to_bytes(&String::from("ab")), [2, 0, 0, 0, b'a', b'b']);
+    assert_eq!(to_bytes(&vec![1u8]), [1, 0, 0, 0, 1]);
+}
+
+#[test]
+fn derived_types_round_trip() {
+    round_trip(Point { x: -1, y: 2 });
+    round_trip(Named(String::from("name"), Some(3)));
+    round_trip(Shape::Empty);
+    round_trip(Shape::Circle(Point { x: 0, y: 0 }, 10));
+    round_trip(Shape::Polygon {
+        name: String::from("triangle"),
+        points: vec![
+            Point { x: 0, y: 0 },
+            Point { x: 1, y: 0 },
+            Point { x: 0, y: 1 },
+        ],
+    });
+    round_trip(Wrapper {
+        inner: vec![true, false],
+    });
+}
+
+#[test]
+fn derived_enums_start_with_the_variant_index() {
+    assert_eq!(to_bytes(&Shape::Empty), [0, 0, 0, 0]);
+    let bytes = to_bytes(&Shape::Circle(Point { x: 1, y: 2 }, 3));
+    assert_eq!(&bytes[..4], [1, 0, 0, 0]);
+    assert_eq!(bytes.len(), 4 + 4 + 4 + 4);
+}
+
+#[test]
+fn truncated_input_is_an_error() {
+    assert_eq!(from_bytes::<u32>(&[1, 2, 3]), Err(WireError::UnexpectedEnd));
+    assert_eq!(
+        from_bytes::<Option<u8>>(&[1]),
+        Err(WireError::UnexpectedEnd)
+    );
+    let bytes = to_bytes(&Shape::Polygon {
+        name: String::from("square"),
+        points: vec![Point { x: 0, y: 0 }; 4],
+    });
+    for len in 0..bytes.len() {
+        assert!(from_bytes::<Shape>(&bytes[..len]).is_err(), "{} bytes", len);
+    }
+}
+
+#[test]
+fn invalid_tags_are_errors() {
+    assert_eq!(from_bytes::<bool>(&[2]), Err(WireError::InvalidTag(2)));
+    assert_eq!(
+        from_bytes::<Option<u8>>(&[9, 0]),
+        Err(WireError::InvalidTag(9))
+    );
+    assert_eq!(
+        from_bytes::<Result<u8, u8>>(&[3, 0]),
+        Err(WireError::InvalidTag(3))
+    );
+    assert_eq!(
+        from_bytes::<Shape>(&[3, 0, 0, 0]),
+        Err(WireError::InvalidTag(3))
+    );
+}
+
+#[test]
+fn lengths_longer_than_the_message_are_errors() {
+    // Would allocate 4 GiB if the length were trusted
+    assert_eq!(
+        from_bytes::<Vec<u8>>(&[0xff, 0xff, 0xff, 0xff, 1]),
+        Err(WireError::InvalidLength(u32::MAX))
+    );
+    assert_eq!(
+        from_bytes::<String>(&[3, 0, 0, 0, b'a']),
+        Err(WireError::InvalidLength(3))
+    );
+}
+
+#[test]
+fn invalid_utf8_is_an_error() {
+    assert_eq!(
+        from_bytes::<String>(&[2, 0, 0, 0, 0xc3, 0x28]),
+        Err(WireError::InvalidUtf8)
+    );
+}
+
+#[test]
+fn trailing_bytes_are_an_error() {
+    assert_eq!(
+        from_bytes::<u8>(&[1, 2, 3]),
+        Err(WireError::TrailingBytes(2))
+    );
+}
+
+#[test]
+fn reader_reads_values_in_order() {
+    let mut writer = Writer::new();
+    5u8.encode(&mut writer);
+    String::from("x").encode(&mut writer);
+    writer.write_bytes(b"raw");
+    assert_eq!(writer.len(), 1 + 4 + 1 + 3);
+    let bytes = writer.into_inner();
+
+    let mut reader = Reader::new(&bytes);
+    assert_eq!(u8::decode(&mut reader), Ok(5));
+    assert_eq!(String::decode(&mut reader).as_deref(), Ok("x"));
+    assert_eq!(reader.remaining(), 3);
+    assert_eq!(reader.read_bytes(4), Err(WireError::UnexpectedEnd));
+    assert_eq!(reader.read_bytes(3), Ok(&b"raw"[..]));
+    assert_eq!(reader.finish(), Ok(()));
+}
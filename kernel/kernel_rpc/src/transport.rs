@@ -0,0 +1,221 @@
+//! Sending RPC messages over buffer queues.
+//!
+//! A request is a `RequestHeader` followed by the method's arguments as a tuple.
+//! It's copied into the service's queue, and the server copies the response
+//! (a `ResponseStatus` followed by the return value) into the queue in `reply_queue`.
+
+use alloc::{vec, vec::Vec};
+use core::mem::MaybeUninit;
+
+use kernel_api::BufferQueue;
+
+use crate::{Reader, Wire, WireError, Writer};
+
+/// Version of the message headers. This changes when the header layout or the encoding of
+/// primitive types changes, while each service has its own version for its methods.
+pub const WIRE_VERSION: u16 = 1;
+
+/// Requests and responses can't be larger than a page
+pub const MAX_MESSAGE_SIZE: usize = 4096;
+
+#[derive(Wire, Debug)]
+pub struct RequestHeader {
+    pub wire_version: u16,
+    pub service_version: u16,
+    pub method: u16,
+    /// Where the response goes. The kernel doesn't check it, so servers tell callers apart
+    /// by the sender that the kernel gives them with the request instead.
+    pub reply_queue: u64,
+}
+
+#[derive(Wire, Debug, PartialEq, Eq)]
+pub enum ResponseStatus {
+    Ok,
+    UnknownMethod(u16),
+    VersionMismatch { wire_version: u16, service_version: u16 },
+    BadRequest(WireError),
+}
+
+/// Errors that the client gets when calling a method
+#[derive(Debug, PartialEq, Eq)]
+pub enum RpcError {
+    /// The response couldn't be decoded
+    Wire(WireError),
+    /// The server rejected the request
+    Rejected(ResponseStatus),
+    MessageTooLarge,
+}
+
+impl From<WireError> for RpcError {
+    fn from(error: WireError) -> Self {
+        Self::Wire(error)
+    }
+}
+
+/// Errors that a server's `dispatch` function can return
+#[derive(Debug)]
+pub enum DispatchError {
+    UnknownMethod(u16),
+    Wire(WireError),
+}
+
+impl From<WireError> for DispatchError {
+    fn from(error: WireError) -> Self {
+        Self::Wire(error)
+    }
+}
+
+impl Wire for WireError {
+    fn encode(&self, writer: &mut Writer) {
+        let (tag, value): (u8, u64) = match self {
+            Self::UnexpectedEnd => (0, 0),
+            Self::InvalidTag(tag) => (1, *tag as u64),
+            Self::InvalidUtf8 => (2, 0),
+            Self::InvalidLength(len) => (3, *len as u64),
+            Self::TrailingBytes(len) => (4, *len as u64),
+            Self::OutOfRange => (5, 0),
+        };
+        tag.encode(writer);
+        value.encode(writer);
+    }
+    fn decode(reader: &mut Reader<'_>) -> Result<Self, WireError> {
+        let tag = u8::decode(reader)?;
+        let value = u64::decode(reader)?;
+        Ok(match tag {
+            0 => Self::UnexpectedEnd,
+            1 => Self::InvalidTag(value as u32),
+            2 => Self::InvalidUtf8,
+            3 => Self::InvalidLength(value as u32),
+            4 => Self::TrailingBytes(value as usize),
+            5 => Self::OutOfRange,
+            tag => return Err(WireError::InvalidTag(tag as u32)),
+        })
+    }
+}
+
+fn as_uninit(buffer: &mut [u8]) -> &mut [MaybeUninit<u8>] {
+    // SAFETY: MaybeUninit<u8> has the same layout as u8, and the kernel only ever writes
+    // initialized bytes into the buffer.
+    unsafe { &mut *(buffer as *mut [u8] as *mut [MaybeUninit<u8>]) }
+}
+
+/// Blocks until there's a message in `queue`, and copies it into `buffer`.
+/// Returns the size of the message and the ID of the process that sent it.
+fn receive(queue: &BufferQueue, buffer: &mut [u8]) -> (usize, u64) {
+    loop {
+        match queue.copy_claim_buffer_with_sender(as_uninit(buffer)) {
+            Ok((size, _remaining, sender)) => return (size, sender),
+            Err(future) => future.wait_for_complete(),
+        }
+    }
+}
+
+/// The client side of a service: a queue to send requests to, and a queue to get responses from
+pub struct Connection {
+    service: BufferQueue,
+    reply: BufferQueue,
+}
+
+impl Connection {
+    pub fn new(service: BufferQueue, reply: BufferQueue) -> Self {
+        Self { service, reply }
+    }
+
+    /// Calls method `method` and waits for the response
+    pub fn call<A: Wire, R: Wire>(
+        &self,
+        service_version: u16,
+        method: u16,
+        args: &A,
+    ) -> Result<R, RpcError> {
+        let mut writer = Writer::new();
+        RequestHeader {
+            wire_version: WIRE_VERSION,
+            service_version,
+            method,
+            reply_queue: self.reply.id(),
+        }
+        .encode(&mut writer);
+        args.encode(&mut writer);
+        if writer.len() > MAX_MESSAGE_SIZE {
+            return Err(RpcError::MessageTooLarge);
+        }
+        self.service.copy_out_buffer(&writer.into_inner());
+
+        let mut buffer = vec![0u8; MAX_MESSAGE_SIZE];
+        let (size, _) = receive(&self.reply, &mut buffer);
+        let mut reader = Reader::new(&buffer[..size]);
+        match ResponseStatus::decode(&mut reader)? {
+            ResponseStatus::Ok => {
+                let value = R::decode(&mut reader)?;
+                reader.finish()?;
+                Ok(value)
+            }
+            status => Err(RpcError::Rejected(status)),
+        }
+    }
+}
+
+/// Handles one request message from process `sender`: decodes its header, passes it, the
+/// sender and the arguments to `dispatch`, and returns the queue to send the response to
+/// with the response itself. Returns `None` if the message doesn't even have a header.
+pub fn handle_request(
+    message: &[u8],
+    sender: u64,
+    service_version: u16,
+    dispatch: impl FnOnce(
+        &RequestHeader,
+        u64,
+        &mut Reader<'_>,
+        &mut Writer,
+    ) -> Result<(), DispatchError>,
+) -> Option<(u64, Vec<u8>)> {
+    let mut reader = Reader::new(message);
+    // Without a header, we don't know where to send the response to
+    let header = RequestHeader::decode(&mut reader).ok()?;
+    let mut payload = Writer::new();
+    let status = if header.wire_version != WIRE_VERSION || header.service_version != service_version
+    {
+        ResponseStatus::VersionMismatch {
+            wire_version: WIRE_VERSION,
+            service_version,
+        }
+    } else {
+        match dispatch(&header, sender, &mut reader, &mut payload)
+            .and_then(|()| reader.finish().map_err(DispatchError::from))
+        {
+            Ok(()) => ResponseStatus::Ok,
+            Err(DispatchError::UnknownMethod(method)) => ResponseStatus::UnknownMethod(method),
+            Err(DispatchError::Wire(error)) => ResponseStatus::BadRequest(error),
+        }
+    };
+    let mut response = Writer::new();
+    status.encode(&mut response);
+    if status == ResponseStatus::Ok {
+        response.write_bytes(&payload.into_inner());
+    }
+    Some((header.reply_queue, response.into_inner()))
+}
+
+/// Receives requests from `queue` forever and passes them to `dispatch` with their sender,
+/// which should decode the arguments, run the method and encode the return value.
+pub fn serve(
+    queue: &BufferQueue,
+    service_version: u16,
+    mut dispatch: impl FnMut(
+        &RequestHeader,
+        u64,
+        &mut Reader<'_>,
+        &mut Writer,
+    ) -> Result<(), DispatchError>,
+) -> ! {
+    let mut buffer = vec![0u8; MAX_MESSAGE_SIZE];
+    loop {
+        let (size, sender) = receive(queue, &mut buffer);
+        if let Some((reply_queue, response)) =
+            handle_request(&buffer[..size], sender, service_version, &mut dispatch)
+        {
+            BufferQueue::new(reply_queue).copy_out_buffer(&response);
+        }
+    }
+}
@@ -0,0 +1,294 @@
+//! The wire format used for RPC messages.
+//!
+//! Everything is little-endian. `usize` and `isize` are always sent as 64 bits.
+//! Variable-length values (`Vec`, `String`, slices) are prefixed with their length as a `u32`.
+//! `Option` and `bool` are a single byte. Enums derived with `#[derive(Wire)]` start with their
+//! variant index as a `u32`.
+
+use alloc::{boxed::Box, string::String, vec::Vec};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WireError {
+    /// The message ended before the value was complete
+    UnexpectedEnd,
+    /// An enum, `Option` or `bool` had a tag that doesn't correspond to any value
+    InvalidTag(u32),
+    InvalidUtf8,
+    /// A length prefix was larger than the rest of the message
+    InvalidLength(u32),
+    /// There was data left over after decoding the message
+    TrailingBytes(usize),
+    /// A `usize` or `isize` was too large for this machine
+    OutOfRange,
+}
+
+#[derive(Default)]
+pub struct Writer {
+    buffer: Vec<u8>,
+}
+
+impl Writer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    pub fn len(&self) -> usize {
+        self.buffer.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buffer.is_empty()
+    }
+
+    pub fn into_inner(self) -> Vec<u8> {
+        self.buffer
+    }
+}
+
+pub struct Reader<'data> {
+    data: &'data [u8],
+}
+
+impl<'data> Reader<'data> {
+    pub fn new(data: &'data [u8]) -> Self {
+        Self { data }
+    }
+
+    pub fn read_bytes(&mut self, len: usize) -> Result<&'data [u8], WireError> {
+        if len > self.data.len() {
+            return Err(WireError::UnexpectedEnd);
+        }
+        let (bytes, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.data.len()
+    }
+
+    /// Returns an error if there's data that hasn't been read
+    pub fn finish(self) -> Result<(), WireError> {
+        if self.data.is_empty() {
+            Ok(())
+        } else {
+            Err(WireError::TrailingBytes(self.data.len()))
+        }
+    }
+
+    fn read_length(&mut self) -> Result<usize, WireError> {
+        let len = u32::decode(self)?;
+        // Every element takes at least 1 byte, except for zero-sized types.
+        // This check isn't exact but prevents huge allocations from garbage lengths.
+        if len as usize > self.data.len() {
+            return Err(WireError::InvalidLength(len));
+        }
+        Ok(len as usize)
+    }
+}
+
+/// A type that can be sent in an RPC message. Use `#[derive(Wire)]` to implement this.
+pub trait Wire: Sized {
+    fn encode(&self, writer: &mut Writer);
+    fn decode(reader: &mut Reader<'_>) -> Result<Self, WireError>;
+}
+
+/// Encodes a value into a new buffer
+pub fn to_bytes<T: Wire>(value: &T) -> Vec<u8> {
+    let mut writer = Writer::new();
+    value.encode(&mut writer);
+    writer.into_inner()
+}
+
+/// Decodes a value, and fails if there are any bytes left over
+pub fn from_bytes<T: Wire>(data: &[u8]) -> Result<T, WireError> {
+    let mut reader = Reader::new(data);
+    let value = T::decode(&mut reader)?;
+    reader.finish()?;
+    Ok(value)
+}
+
+macro_rules! impl_wire_for_integer {
+    ($($ty:ty),*) => {
+        $(
+            impl Wire for $ty {
+                fn encode(&self, writer: &mut Writer) {
+                    writer.write_bytes(&self.to_le_bytes());
+                }
+                fn decode(reader: &mut Reader<'_>) -> Result<Self, WireError> {
+                    let bytes = reader.read_bytes(core::mem::size_of::<$ty>())?;
+                    Ok(<$ty>::from_le_bytes(bytes.try_into().unwrap()))
+                }
+            }
+        )*
+    };
+}
+
+impl_wire_for_integer!(u8, u16, u32, u64, u128, i8, i16, i32, i64, i128);
+
+impl Wire for usize {
+    fn encode(&self, writer: &mut Writer) {
+        (*self as u64).encode(writer)
+    }
+    fn decode(reader: &mut Reader<'_>) -> Result<Self, WireError> {
+        usize::try_from(u64::decode(reader)?).map_err(|_| WireError::OutOfRange)
+    }
+}
+
+impl Wire for isize {
+    fn encode(&self, writer: &mut Writer) {
+        (*self as i64).encode(writer)
+    }
+    fn decode(reader: &mut Reader<'_>) -> Result<Self, WireError> {
+        isize::try_from(i64::decode(reader)?).map_err(|_| WireError::OutOfRange)
+    }
+}
+
+impl Wire for bool {
+    fn encode(&self, writer: &mut Writer) {
+        (*self as u8).encode(writer)
+    }
+    fn decode(reader: &mut Reader<'_>) -> Result<Self, WireError> {
+        match u8::decode(reader)? {
+            0 => Ok(false),
+            1 => Ok(true),
+            tag => Err(WireError::InvalidTag(tag as u32)),
+        }
+    }
+}
+
+impl Wire for () {
+    fn encode(&self, _writer: &mut Writer) {}
+    fn decode(_reader: &mut Reader<'_>) -> Result<Self, WireError> {
+        Ok(())
+    }
+}
+
+impl<T: Wire> Wire for Option<T> {
+    fn encode(&self, writer: &mut Writer) {
+        match self {
+            None => 0u8.encode(writer),
+            Some(value) => {
+                1u8.encode(writer);
+                value.encode(writer);
+            }
+        }
+    }
+    fn decode(reader: &mut Reader<'_>) -> Result<Self, WireError> {
+        match u8::decode(reader)? {
+            0 => Ok(None),
+            1 => Ok(Some(T::decode(reader)?)),
+            tag => Err(WireError::InvalidTag(tag as u32)),
+        }
+    }
+}
+
+impl<T: Wire, E: Wire> Wire for Result<T, E> {
+    fn encode(&self, writer: &mut Writer) {
+        match self {
+            Ok(value) => {
+                0u8.encode(writer);
+                value.encode(writer);
+            }
+            Err(error) => {
+                1u8.encode(writer);
+                error.encode(writer);
+            }
+        }
+    }
+    fn decode(reader: &mut Reader<'_>) -> Result<Self, WireError> {
+        match u8::decode(reader)? {
+            0 => Ok(Ok(T::decode(reader)?)),
+            1 => Ok(Err(E::decode(reader)?)),
+            tag => Err(WireError::InvalidTag(tag as u32)),
+        }
+    }
+}
+
+impl<T: Wire> Wire for Vec<T> {
+    fn encode(&self, writer: &mut Writer) {
+        (self.len() as u32).encode(writer);
+        for item in self.iter() {
+            item.encode(writer);
+        }
+    }
+    fn decode(reader: &mut Reader<'_>) -> Result<Self, WireError> {
+        // Zero-sized elements take no bytes in the message, so any length fits, and the
+        // `Vec` doesn't allocate for them
+        let len = if core::mem::size_of::<T>() == 0 {
+            u32::decode(reader)? as usize
+        } else {
+            reader.read_length()?
+        };
+        let mut vec = Vec::with_capacity(len);
+        for _ in 0..len {
+            vec.push(T::decode(reader)?);
+        }
+        Ok(vec)
+    }
+}
+
+impl<T: Wire> Wire for Box<T> {
+    fn encode(&self, writer: &mut Writer) {
+        (**self).encode(writer)
+    }
+    fn decode(reader: &mut Reader<'_>) -> Result<Self, WireError> {
+        Ok(Box::new(T::decode(reader)?))
+    }
+}
+
+impl Wire for String {
+    fn encode(&self, writer: &mut Writer) {
+        (self.len() as u32).encode(writer);
+        writer.write_bytes(self.as_bytes());
+    }
+    fn decode(reader: &mut Reader<'_>) -> Result<Self, WireError> {
+        let len = reader.read_length()?;
+        let bytes = reader.read_bytes(len)?;
+        core::str::from_utf8(bytes)
+            .map(String::from)
+            .map_err(|_| WireError::InvalidUtf8)
+    }
+}
+
+impl<T: Wire, const N: usize> Wire for [T; N] {
+    fn encode(&self, writer: &mut Writer) {
+        for item in self.iter() {
+            item.encode(writer);
+        }
+    }
+    fn decode(reader: &mut Reader<'_>) -> Result<Self, WireError> {
+        let mut items = Vec::with_capacity(N);
+        for _ in 0..N {
+            items.push(T::decode(reader)?);
+        }
+        // The length is always N, so this can't fail
+        Ok(items.try_into().ok().unwrap())
+    }
+}
+
+macro_rules! impl_wire_for_tuple {
+    ($($name:ident),+) => {
+        impl<$($name: Wire),+> Wire for ($($name,)+) {
+            #[allow(non_snake_case)]
+            fn encode(&self, writer: &mut Writer) {
+                let ($($name,)+) = self;
+                $($name.encode(writer);)+
+            }
+            fn decode(reader: &mut Reader<'_>) -> Result<Self, WireError> {
+                Ok(($($name::decode(reader)?,)+))
+            }
+        }
+    };
+}
+
+impl_wire_for_tuple!(A);
+impl_wire_for_tuple!(A, B);
+impl_wire_for_tuple!(A, B, C);
+impl_wire_for_tuple!(A, B, C, D);
+impl_wire_for_tuple!(A, B, C, D, E);
+impl_wire_for_tuple!(A, B, C, D, E, F);
@@ -0,0 +1,114 @@
+//! `#[derive(Wire)]` for `kernel_rpc`.
+//!
+//! Structs are encoded as their fields in declaration order.
+//! Enums are encoded as the variant index as a `u32`, followed by the variant's fields.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote};
+use syn::{parse_macro_input, parse_quote, Data, DeriveInput, Fields, GenericParam};
+
+#[proc_macro_derive(Wire)]
+pub fn derive_wire(input: TokenStream) -> TokenStream {
+    let mut input = parse_macro_input!(input as DeriveInput);
+
+    for param in input.generics.params.iter_mut() {
+        if let GenericParam::Type(param) = param {
+            param.bounds.push(parse_quote!(::kernel_rpc::Wire));
+        }
+    }
+
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    let (encode, decode) = match &input.data {
+        Data::Struct(data) => {
+            let bindings = field_bindings(&data.fields);
+            let pattern = fields_pattern(&data.fields, &bindings);
+            (
+                quote! {
+                    let #name #pattern = self;
+                    #(::kernel_rpc::Wire::encode(#bindings, writer);)*
+                },
+                quote! {
+                    #(let #bindings = ::kernel_rpc::Wire::decode(reader)?;)*
+                    Ok(#name #pattern)
+                },
+            )
+        }
+        Data::Enum(data) => {
+            let mut encode_arms = Vec::new();
+            let mut decode_arms = Vec::new();
+            for (index, variant) in data.variants.iter().enumerate() {
+                let index = index as u32;
+                let variant_name = &variant.ident;
+                let bindings = field_bindings(&variant.fields);
+                let pattern = fields_pattern(&variant.fields, &bindings);
+                encode_arms.push(quote! {
+                    #name::#variant_name #pattern => {
+                        ::kernel_rpc::Wire::encode(&#index, writer);
+                        #(::kernel_rpc::Wire::encode(#bindings, writer);)*
+                    }
+                });
+                decode_arms.push(quote! {
+                    #index => {
+                        #(let #bindings = ::kernel_rpc::Wire::decode(reader)?;)*
+                        Ok(#name::#variant_name #pattern)
+                    }
+                });
+            }
+            (
+                quote! {
+                    match self {
+                        #(#encode_arms)*
+                    }
+                },
+                quote! {
+                    let tag: u32 = ::kernel_rpc::Wire::decode(reader)?;
+                    match tag {
+                        #(#decode_arms)*
+                        tag => Err(::kernel_rpc::WireError::InvalidTag(tag)),
+                    }
+                },
+            )
+        }
+        Data::Union(_) => {
+            return syn::Error::new_spanned(&input.ident, "unions can't be sent over the wire")
+                .to_compile_error()
+                .into();
+        }
+    };
+
+    quote! {
+        impl #impl_generics ::kernel_rpc::Wire for #name #ty_generics #where_clause {
+            #[allow(unused_variables)]
+            fn encode(&self, writer: &mut ::kernel_rpc::Writer) {
+                #encode
+            }
+            #[allow(unused_variables)]
+            fn decode(reader: &mut ::kernel_rpc::Reader<'_>) -> ::core::result::Result<Self, ::kernel_rpc::WireError> {
+                #decode
+            }
+        }
+    }
+    .into()
+}
+
+fn field_bindings(fields: &Fields) -> Vec<syn::Ident> {
+    (0..fields.len())
+        .map(|index| format_ident!("field_{}", index))
+        .collect()
+}
+
+/// Binds every field to its name in `bindings`.
+/// This works both as a pattern and as a constructor.
+fn fields_pattern(fields: &Fields, bindings: &[syn::Ident]) -> TokenStream2 {
+    match fields {
+        Fields::Named(named) => {
+            let names = named.named.iter().map(|field| &field.ident);
+            quote! { { #(#names: #bindings),* } }
+        }
+        Fields::Unnamed(_) => quote! { ( #(#bindings),* ) },
+        Fields::Unit => quote! {},
+    }
+}
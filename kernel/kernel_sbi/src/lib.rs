@@ -138,6 +138,45 @@ pub fn set_absolute_timer(time: u64) -> Result<(), SBIError> {
     unsafe { call_sbi_1(0x54494D45, 0, time as usize).map(|_| {}) }
 }
 
+const BASE_EXTENSION: usize = 0x10;
+const SRST_EXTENSION: usize = 0x53525354;
+
+/// Whether the SBI implementation has the extension `extension_id`
+pub fn probe_extension(extension_id: usize) -> bool {
+    // SAFETY: Probing doesn't change anything
+    unsafe { call_sbi_1(BASE_EXTENSION, 3, extension_id) }.map_or(false, |available| available != 0)
+}
+
+/// Whether `system_reset` is available
+pub fn has_system_reset() -> bool {
+    probe_extension(SRST_EXTENSION)
+}
+
+#[repr(usize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResetType {
+    Shutdown = 0,
+    ColdReboot = 1,
+    WarmReboot = 2,
+}
+
+#[repr(usize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResetReason {
+    NoReason = 0,
+    SystemFailure = 1,
+}
+
+/// Shuts down or reboots the whole machine. Only returns if that failed.
+pub fn system_reset(reset_type: ResetType, reason: ResetReason) -> SBIError {
+    // SAFETY: Same as `shutdown`
+    // See https://github.com/riscv/riscv-sbi-doc/blob/master/riscv-sbi.adoc#system-reset-extension-eid-0x53525354-srst
+    match unsafe { call_sbi_2(SRST_EXTENSION, 0, reset_type as usize, reason as usize) } {
+        Ok(_) => SBIError::Failed,
+        Err(error) => error,
+    }
+}
+
 pub fn shutdown(reason: usize) {
     // SAFETY: Shutting down is safe, because the whole machine state gets erased.
     // But destructors don't get called
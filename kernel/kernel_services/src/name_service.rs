@@ -0,0 +1,138 @@
+//! Maps hierarchical names like `dev/uart0` or `fs/root` to buffer queues.
+//!
+//! The service listens on `NAME_SERVICE_QUEUE`. Names are made of components separated by `/`.
+//! Leading, trailing and repeated slashes are ignored, so `/dev//uart0/` is the same as `dev/uart0`.
+
+use alloc::{string::String, vec, vec::Vec};
+use core::mem::MaybeUninit;
+
+use kernel_api::BufferQueue;
+use kernel_rpc::{from_bytes, to_bytes, Connection, RpcError};
+
+pub use crate::names::{normalize_name, NameError, MAX_WATCHES_PER_CALLER};
+use crate::{names::Names, NAME_SERVICE_QUEUE, NETWORK_SERVICE_QUEUE};
+
+kernel_rpc::service! {
+    /// `watch` returns the queue if the name is already registered. Otherwise,
+    /// `(name, queue)` is copied into `notify_queue` once it is.
+    /// `list` returns the names of the entries directly inside a directory.
+    /// Only the process that registered a name can `unregister` it. A process can watch at
+    /// most `MAX_WATCHES_PER_CALLER` names at once.
+    pub service NameService {
+        version: 1,
+        client: NameServiceClient,
+        methods {
+            0 => fn register(name: String, queue: u64) -> Result<(), NameError>;
+            1 => fn lookup(name: String) -> Result<Option<u64>, NameError>;
+            2 => fn unregister(name: String) -> Result<u64, NameError>;
+            3 => fn watch(name: String, notify_queue: u64) -> Result<Option<u64>, NameError>;
+            4 => fn list(directory: String) -> Result<Vec<String>, NameError>;
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct NameServer {
+    names: Names,
+    /// The process that sent the request that's being handled
+    caller: u64,
+}
+
+impl NameServer {
+    /// Creates a name server with the well-known system queues already registered
+    pub fn new() -> Self {
+        let mut server = Self::default();
+        for (name, queue) in [
+            ("sys/names", NAME_SERVICE_QUEUE),
+            ("net/ip", NETWORK_SERVICE_QUEUE),
+        ] {
+            server.names.register(name, queue, None).unwrap();
+        }
+        server
+    }
+}
+
+impl NameService for NameServer {
+    fn register(&mut self, name: String, queue: u64) -> Result<(), NameError> {
+        let name = self.names.register(&name, queue, Some(self.caller))?;
+        let watchers = self.names.take_watchers(&name);
+        if !watchers.is_empty() {
+            let notification = to_bytes(&(name, queue));
+            for watcher in watchers {
+                BufferQueue::new(watcher).copy_out_buffer(&notification);
+            }
+        }
+        Ok(())
+    }
+
+    fn lookup(&mut self, name: String) -> Result<Option<u64>, NameError> {
+        self.names.lookup(&name)
+    }
+
+    fn unregister(&mut self, name: String) -> Result<u64, NameError> {
+        self.names.unregister(&name, self.caller)
+    }
+
+    fn watch(&mut self, name: String, notify_queue: u64) -> Result<Option<u64>, NameError> {
+        self.names.watch(&name, self.caller, notify_queue)
+    }
+
+    fn list(&mut self, directory: String) -> Result<Vec<String>, NameError> {
+        self.names.list(&directory)
+    }
+}
+
+pub fn name_service() -> ! {
+    let mut server = NameServer::new();
+    // Served by hand to know who's calling
+    kernel_rpc::serve(
+        &BufferQueue::new(NAME_SERVICE_QUEUE),
+        NameServer::VERSION,
+        |header, sender, reader, writer| {
+            server.caller = sender;
+            server.dispatch(header.method, reader, writer)
+        },
+    )
+}
+
+impl NameServiceClient {
+    /// Connects to the name service, receiving responses in `reply`
+    pub fn connect(reply: BufferQueue) -> Self {
+        Self::new(Connection::new(BufferQueue::new(NAME_SERVICE_QUEUE), reply))
+    }
+
+    /// Blocks until `name` is registered, and returns its queue.
+    /// `notify_queue` must not be used for anything else while waiting.
+    pub fn wait_for(
+        &self,
+        name: &str,
+        notify_queue: &BufferQueue,
+    ) -> Result<Result<u64, NameError>, RpcError> {
+        let name = match normalize_name(name) {
+            Ok(name) => name,
+            Err(error) => return Ok(Err(error)),
+        };
+        match self.watch(name.clone(), notify_queue.id())? {
+            Ok(Some(queue)) => return Ok(Ok(queue)),
+            Ok(None) => (),
+            Err(error) => return Ok(Err(error)),
+        }
+        let mut buffer = vec![MaybeUninit::<u8>::uninit(); 256];
+        loop {
+            let size = match notify_queue.copy_claim_buffer(&mut buffer) {
+                Ok((size, _)) => size,
+                Err(future) => {
+                    future.wait_for_complete();
+                    continue;
+                }
+            };
+            // SAFETY: The kernel initialized the first `size` bytes
+            let data = unsafe { &*(&buffer[..size] as *const [MaybeUninit<u8>] as *const [u8]) };
+            if let Ok((registered, queue)) = from_bytes::<(String, u64)>(data) {
+                if registered == name {
+                    return Ok(Ok(queue));
+                }
+            }
+        }
+    }
+}
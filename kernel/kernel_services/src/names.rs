@@ -0,0 +1,163 @@
+//! The table of names behind the name service. It doesn't send any messages, so it's built
+//! for the host too and tested there.
+
+use alloc::{
+    collections::BTreeMap,
+    string::{String, ToString},
+    vec::Vec,
+};
+
+use kernel_rpc::Wire;
+
+#[derive(Wire, Debug, Clone, PartialEq, Eq)]
+pub enum NameError {
+    /// The name was empty, or had a `.` or `..` component
+    InvalidName,
+    AlreadyRegistered,
+    NotRegistered,
+    /// The name was registered by someone else
+    NotOwner,
+    /// The caller is already watching `MAX_WATCHES_PER_CALLER` names
+    TooManyWatches,
+}
+
+/// The most watches one caller can have waiting at once
+pub const MAX_WATCHES_PER_CALLER: usize = 16;
+
+/// Turns a name into its canonical form, without empty components
+pub fn normalize_name(name: &str) -> Result<String, NameError> {
+    let mut normalized = String::new();
+    for component in name.split('/').filter(|c| !c.is_empty()) {
+        if component == "." || component == ".." {
+            return Err(NameError::InvalidName);
+        }
+        if !normalized.is_empty() {
+            normalized.push('/');
+        }
+        normalized.push_str(component);
+    }
+    if normalized.is_empty() {
+        Err(NameError::InvalidName)
+    } else {
+        Ok(normalized)
+    }
+}
+
+struct Entry {
+    queue: u64,
+    /// The process that registered the name, which is the only one that can unregister it.
+    /// Names registered at boot have no owner and stay forever.
+    owner: Option<u64>,
+}
+
+/// Someone waiting for a name to be registered
+struct Watch {
+    caller: u64,
+    notify_queue: u64,
+}
+
+#[derive(Default)]
+pub struct Names {
+    entries: BTreeMap<String, Entry>,
+    watches: BTreeMap<String, Vec<Watch>>,
+}
+
+impl Names {
+    /// Registers `name` for `queue`, and returns the name in its canonical form
+    pub fn register(
+        &mut self,
+        name: &str,
+        queue: u64,
+        owner: Option<u64>,
+    ) -> Result<String, NameError> {
+        let name = normalize_name(name)?;
+        if self.entries.contains_key(&name) {
+            return Err(NameError::AlreadyRegistered);
+        }
+        self.entries.insert(name.clone(), Entry { queue, owner });
+        Ok(name)
+    }
+
+    pub fn lookup(&self, name: &str) -> Result<Option<u64>, NameError> {
+        Ok(self
+            .entries
+            .get(&normalize_name(name)?)
+            .map(|entry| entry.queue))
+    }
+
+    /// Removes `name` if `caller` registered it, and returns its queue
+    pub fn unregister(&mut self, name: &str, caller: u64) -> Result<u64, NameError> {
+        let name = normalize_name(name)?;
+        let entry = self.entries.get(&name).ok_or(NameError::NotRegistered)?;
+        if entry.owner != Some(caller) {
+            return Err(NameError::NotOwner);
+        }
+        Ok(self.entries.remove(&name).unwrap().queue)
+    }
+
+    /// Returns `name`'s queue if it's registered. Otherwise, remembers that `caller` wants to
+    /// be told in `notify_queue` once it is, which `take_watchers` returns then.
+    pub fn watch(
+        &mut self,
+        name: &str,
+        caller: u64,
+        notify_queue: u64,
+    ) -> Result<Option<u64>, NameError> {
+        let name = normalize_name(name)?;
+        if let Some(entry) = self.entries.get(&name) {
+            return Ok(Some(entry.queue));
+        }
+        let watches = self.watches.get(&name);
+        if watches.is_some_and(|watches| {
+            watches
+                .iter()
+                .any(|watch| watch.caller == caller && watch.notify_queue == notify_queue)
+        }) {
+            return Ok(None);
+        }
+        let count = self
+            .watches
+            .values()
+            .flatten()
+            .filter(|watch| watch.caller == caller)
+            .count();
+        if count >= MAX_WATCHES_PER_CALLER {
+            return Err(NameError::TooManyWatches);
+        }
+        self.watches.entry(name).or_default().push(Watch {
+            caller,
+            notify_queue,
+        });
+        Ok(None)
+    }
+
+    /// Forgets the watches for `name`, which was just registered, and returns the queues to
+    /// notify
+    pub fn take_watchers(&mut self, name: &str) -> Vec<u64> {
+        self.watches
+            .remove(name)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|watch| watch.notify_queue)
+            .collect()
+    }
+
+    /// Returns the names of the entries directly inside `directory`. Subdirectories are
+    /// listed once, however many names they have.
+    pub fn list(&self, directory: &str) -> Result<Vec<String>, NameError> {
+        let prefix = if directory.split('/').all(|c| c.is_empty()) {
+            String::new()
+        } else {
+            normalize_name(directory)? + "/"
+        };
+        let mut entries: Vec<String> = self
+            .entries
+            .keys()
+            .filter_map(|name| name.strip_prefix(prefix.as_str()))
+            .map(|rest| rest.split('/').next().unwrap().to_string())
+            .collect();
+        // Names are sorted, so entries in the same subdirectory are next to each other
+        entries.dedup();
+        Ok(entries)
+    }
+}
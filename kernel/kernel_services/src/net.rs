@@ -0,0 +1,190 @@
+//! Sockets on the kernel's TCP/IP stack, which is registered as `net/ip`.
+//!
+//! Sockets are named by handles that any process can use. Calls never block: operations
+//! that can't complete yet return `SocketError::WouldBlock`, and `watch` says when to try
+//! again. Addresses are IPv4 addresses in network order.
+
+use alloc::{string::String, vec, vec::Vec};
+use core::mem::MaybeUninit;
+
+use kernel_api::BufferQueue;
+use kernel_rpc::{from_bytes, Connection, RpcError, Wire};
+
+use crate::NETWORK_SERVICE_QUEUE;
+
+/// `send` takes at most this much at once, and `recv` returns at most this much, so that
+/// messages fit in `kernel_rpc::transport::MAX_MESSAGE_SIZE`
+pub const MAX_DATA_SIZE: usize = 2048;
+/// The most sockets that one process can have open
+pub const MAX_SOCKETS_PER_CALLER: usize = 64;
+/// The most sockets that one process can be watching at once
+pub const MAX_WATCHES_PER_CALLER: usize = 64;
+
+#[derive(Wire, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SocketKind {
+    Udp,
+    Tcp,
+    /// Sends echo requests and receives echo replies, ICMP header included. The identifier
+    /// is the socket's port, and the stack fills it in.
+    Icmp,
+}
+
+#[derive(Wire, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SocketAddress {
+    pub address: [u8; 4],
+    pub port: u16,
+}
+
+#[derive(Wire, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SocketError {
+    /// Wait until the socket is ready and try again
+    WouldBlock,
+    InvalidSocket,
+    /// The operation doesn't apply to this kind of socket, or not in its current state
+    InvalidOperation,
+    AddressInUse,
+    AddressNotAvailable,
+    NotConnected,
+    AlreadyConnected,
+    ConnectionRefused,
+    ConnectionReset,
+    TimedOut,
+    NoRoute,
+    MessageTooLarge,
+    /// The caller has `MAX_SOCKETS_PER_CALLER` sockets already
+    TooManySockets,
+    /// The caller is watching `MAX_WATCHES_PER_CALLER` sockets already
+    TooManyWatches,
+    /// `notify_queue` wasn't allocated by the caller
+    InvalidQueue,
+}
+
+/// Which operations would make progress. A socket with an error is both, since the
+/// operation would return it.
+#[derive(Wire, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Readiness {
+    /// `recv` or `accept`
+    pub readable: bool,
+    /// `send`
+    pub writable: bool,
+}
+
+impl Readiness {
+    /// Whether any of the operations in `interest` would make progress
+    pub fn satisfies(&self, interest: Readiness) -> bool {
+        (self.readable && interest.readable) || (self.writable && interest.writable)
+    }
+}
+
+#[derive(Wire, Debug, Clone, PartialEq, Eq)]
+pub struct InterfaceInfo {
+    pub name: String,
+    /// `None` for loopback
+    pub mac_address: Option<[u8; 6]>,
+    pub mtu: u32,
+    /// The address and the length of the network prefix
+    pub address: Option<([u8; 4], u8)>,
+    pub gateway: Option<[u8; 4]>,
+    pub dns_servers: Vec<[u8; 4]>,
+}
+
+kernel_rpc::service! {
+    /// `bind` with port 0 picks a free port. `connect` on a TCP socket starts the handshake,
+    /// and `send` returns `WouldBlock` until it's done. `send` on TCP returns how much of
+    /// `data` fit in the send buffer, and `recv` returns an empty buffer once the peer closed
+    /// the connection.
+    /// `watch` returns the socket's readiness. If it doesn't satisfy `interest`,
+    /// `(socket, readiness)` is copied into `notify_queue` once it does. `notify_queue` has to
+    /// be one that the caller allocated, and watching a socket again with the same queue
+    /// replaces the old `interest`.
+    /// A socket belongs to the process that created or accepted it. Requests for it from
+    /// other processes fail with `InvalidSocket`.
+    pub service Network {
+        version: 2,
+        client: NetworkClient,
+        methods {
+            0 => fn socket(kind: SocketKind) -> Result<u32, SocketError>;
+            1 => fn bind(socket: u32, address: SocketAddress) -> Result<(), SocketError>;
+            2 => fn connect(socket: u32, remote: SocketAddress) -> Result<(), SocketError>;
+            3 => fn listen(socket: u32, backlog: u32) -> Result<(), SocketError>;
+            4 => fn accept(socket: u32) -> Result<(u32, SocketAddress), SocketError>;
+            5 => fn send(socket: u32, data: Vec<u8>) -> Result<u32, SocketError>;
+            6 => fn send_to(socket: u32, data: Vec<u8>, remote: SocketAddress) -> Result<u32, SocketError>;
+            7 => fn recv(socket: u32, max_len: u32) -> Result<Vec<u8>, SocketError>;
+            8 => fn recv_from(socket: u32, max_len: u32) -> Result<(Vec<u8>, SocketAddress), SocketError>;
+            9 => fn close(socket: u32) -> Result<(), SocketError>;
+            10 => fn local_address(socket: u32) -> Result<Option<SocketAddress>, SocketError>;
+            11 => fn watch(socket: u32, interest: Readiness, notify_queue: u64) -> Result<Readiness, SocketError>;
+            12 => fn interfaces() -> Vec<InterfaceInfo>;
+        }
+    }
+}
+
+impl NetworkClient {
+    /// Opens a connection to the network service, receiving responses in `reply`.
+    /// (`connect` is the method that connects sockets.)
+    pub fn open(reply: BufferQueue) -> Self {
+        Self::new(Connection::new(
+            BufferQueue::new(NETWORK_SERVICE_QUEUE),
+            reply,
+        ))
+    }
+
+    /// Blocks until `socket` satisfies `interest`, and returns its readiness.
+    /// `notify_queue` must not be used for anything else while waiting.
+    pub fn wait(
+        &self,
+        socket: u32,
+        interest: Readiness,
+        notify_queue: &BufferQueue,
+    ) -> Result<Result<Readiness, SocketError>, RpcError> {
+        match self.watch(socket, interest, notify_queue.id())? {
+            Ok(readiness) if readiness.satisfies(interest) => return Ok(Ok(readiness)),
+            Ok(_) => (),
+            Err(error) => return Ok(Err(error)),
+        }
+        let mut buffer = vec![MaybeUninit::<u8>::uninit(); 64];
+        loop {
+            let size = match notify_queue.copy_claim_buffer(&mut buffer) {
+                Ok((size, _)) => size,
+                Err(future) => {
+                    future.wait_for_complete();
+                    continue;
+                }
+            };
+            // SAFETY: The kernel initialized the first `size` bytes
+            let data = unsafe { &*(&buffer[..size] as *const [MaybeUninit<u8>] as *const [u8]) };
+            if let Ok((notified, readiness)) = from_bytes::<(u32, Readiness)>(data) {
+                if notified == socket {
+                    return Ok(Ok(readiness));
+                }
+            }
+        }
+    }
+
+    /// Sends all of `data` on a TCP socket, waiting for room in the send buffer as needed
+    pub fn send_all(
+        &self,
+        socket: u32,
+        mut data: &[u8],
+        notify_queue: &BufferQueue,
+    ) -> Result<Result<(), SocketError>, RpcError> {
+        let writable = Readiness {
+            readable: false,
+            writable: true,
+        };
+        while !data.is_empty() {
+            let chunk = &data[..data.len().min(MAX_DATA_SIZE)];
+            match self.send(socket, chunk.to_vec())? {
+                Ok(sent) => data = &data[sent as usize..],
+                Err(SocketError::WouldBlock) => {
+                    if let Err(error) = self.wait(socket, writable, notify_queue)? {
+                        return Ok(Err(error));
+                    }
+                }
+                Err(error) => return Ok(Err(error)),
+            }
+        }
+        Ok(Ok(()))
+    }
+}
@@ -0,0 +1,7 @@
+use kernel_api::BufferQueue;
+
+/// Gets a queue that nobody else is using, for example to receive responses from services.
+/// Only this process can receive from it.
+pub fn allocate_queue() -> BufferQueue {
+    BufferQueue::allocate().expect("too many queues")
+}
@@ -0,0 +1,30 @@
+//! Access to serial ports. Drivers register their queue in the name service as `dev/<name>`.
+
+use alloc::vec::Vec;
+
+kernel_rpc::service! {
+    /// `write` returns how many bytes were queued for transmission, which is at least one.
+    /// `read` waits until at least one byte has been received, and returns at most `max_len` bytes.
+    pub service Serial {
+        version: 1,
+        client: SerialClient,
+        methods {
+            0 => fn write(data: Vec<u8>) -> u32;
+            1 => fn read(max_len: u32) -> Vec<u8>;
+        }
+    }
+}
+
+impl SerialClient {
+    /// Writes all of `data`, making as many calls as needed
+    pub fn write_all(&self, mut data: &[u8]) -> Result<(), kernel_rpc::RpcError> {
+        // Stay well below the maximum message size
+        const CHUNK_SIZE: usize = 1024;
+        while !data.is_empty() {
+            let chunk = &data[..data.len().min(CHUNK_SIZE)];
+            let written = self.write(chunk.to_vec())? as usize;
+            data = &data[written..];
+        }
+        Ok(())
+    }
+}
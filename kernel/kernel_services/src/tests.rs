@@ -0,0 +1,117 @@
+use alloc::{string::String, vec::Vec};
+
+use crate::names::{normalize_name, NameError, Names, MAX_WATCHES_PER_CALLER};
+
+fn names(list: &[&str]) -> Names {
+    let mut names = Names::default();
+    for (queue, name) in list.iter().enumerate() {
+        names.register(name, queue as u64, Some(1)).unwrap();
+    }
+    names
+}
+
+fn strings(list: &[&str]) -> Vec<String> {
+    list.iter().map(|s| String::from(*s)).collect()
+}
+
+#[test]
+fn normalize_removes_empty_components() {
+    assert_eq!(normalize_name("dev/uart0").as_deref(), Ok("dev/uart0"));
+    assert_eq!(normalize_name("/dev//uart0/").as_deref(), Ok("dev/uart0"));
+    assert_eq!(normalize_name("uart0").as_deref(), Ok("uart0"));
+}
+
+#[test]
+fn normalize_rejects_empty_and_relative_names() {
+    assert_eq!(normalize_name(""), Err(NameError::InvalidName));
+    assert_eq!(normalize_name("///"), Err(NameError::InvalidName));
+    assert_eq!(normalize_name("dev/./uart0"), Err(NameError::InvalidName));
+    assert_eq!(
+        normalize_name("dev/../sys/names"),
+        Err(NameError::InvalidName)
+    );
+    // Only whole components are special
+    assert_eq!(normalize_name("dev/.uart").as_deref(), Ok("dev/.uart"));
+}
+
+#[test]
+fn lookup_uses_the_canonical_name() {
+    let names = names(&["dev/uart0"]);
+    assert_eq!(names.lookup("/dev/uart0/"), Ok(Some(0)));
+    assert_eq!(names.lookup("dev/uart1"), Ok(None));
+    assert_eq!(names.lookup(".."), Err(NameError::InvalidName));
+}
+
+#[test]
+fn names_are_registered_once() {
+    let mut names = names(&["dev/uart0"]);
+    assert_eq!(
+        names.register("//dev/uart0", 5, Some(2)),
+        Err(NameError::AlreadyRegistered)
+    );
+    assert_eq!(names.lookup("dev/uart0"), Ok(Some(0)));
+}
+
+#[test]
+fn only_the_owner_can_unregister() {
+    let mut names = Names::default();
+    names.register("fs/root", 7, Some(100)).unwrap();
+    names.register("sys/names", 0, None).unwrap();
+    assert_eq!(names.unregister("fs/root", 200), Err(NameError::NotOwner));
+    assert_eq!(names.unregister("sys/names", 100), Err(NameError::NotOwner));
+    assert_eq!(
+        names.unregister("fs/other", 100),
+        Err(NameError::NotRegistered)
+    );
+    assert_eq!(names.unregister("/fs/root", 100), Ok(7));
+    assert_eq!(names.lookup("fs/root"), Ok(None));
+}
+
+#[test]
+fn watchers_are_told_once_the_name_is_registered() {
+    let mut names = Names::default();
+    names.register("sys/names", 0, None).unwrap();
+    assert_eq!(names.watch("sys/names", 100, 50), Ok(Some(0)));
+    assert_eq!(names.watch("fs/root", 100, 50), Ok(None));
+    // Watching the same name in the same queue again doesn't add another watch
+    assert_eq!(names.watch("/fs/root/", 100, 50), Ok(None));
+    assert_eq!(names.watch("fs/root", 200, 60), Ok(None));
+    names.register("fs/root", 7, Some(300)).unwrap();
+    assert_eq!(names.take_watchers("fs/root"), [50, 60]);
+    assert_eq!(names.take_watchers("fs/root"), []);
+}
+
+#[test]
+fn watches_are_limited_per_caller() {
+    let mut names = Names::default();
+    for i in 0..MAX_WATCHES_PER_CALLER {
+        assert_eq!(names.watch(&format!("dev/{}", i), 100, 50), Ok(None));
+    }
+    assert_eq!(
+        names.watch("dev/more", 100, 50),
+        Err(NameError::TooManyWatches)
+    );
+    // Other callers have their own limit
+    assert_eq!(names.watch("dev/more", 200, 60), Ok(None));
+    // Watches stop counting once the name is registered
+    names.register("dev/0", 1, Some(300)).unwrap();
+    names.take_watchers("dev/0");
+    assert_eq!(names.watch("dev/more", 100, 50), Ok(None));
+}
+
+#[test]
+fn list_shows_direct_entries_once() {
+    let names = names(&["dev/uart0", "dev/blk/0", "dev/blk/1", "net/ip", "sys/names"]);
+    assert_eq!(names.list("/"), Ok(strings(&["dev", "net", "sys"])));
+    assert_eq!(names.list(""), Ok(strings(&["dev", "net", "sys"])));
+    assert_eq!(names.list("dev"), Ok(strings(&["blk", "uart0"])));
+    assert_eq!(names.list("/dev/blk/"), Ok(strings(&["0", "1"])));
+    assert_eq!(names.list("dev/uart0"), Ok(Vec::new()));
+    assert_eq!(names.list("missing"), Ok(Vec::new()));
+}
+
+#[test]
+fn list_doesnt_match_partial_components() {
+    let names = names(&["dev/uart0", "devices/other"]);
+    assert_eq!(names.list("dev"), Ok(strings(&["uart0"])));
+}
@@ -4,6 +4,13 @@ use kernel_cpu::Registers;
 use kernel_process::{Process, ProcessContainer, ProcessState};
 use num_enum::*;
 
+/// The most futures `WaitAny` waits on at once, so that the kernel doesn't copy an arbitrary
+/// amount of memory out of the process
+pub const MAX_WAIT_ANY_FUTURES: usize = 256;
+
+/// The sender of buffers that the kernel sends itself. Processes' IDs start at 1.
+pub const KERNEL_SENDER: u64 = 0;
+
 #[derive(FromPrimitive, IntoPrimitive, PartialEq, Eq, PartialOrd, Ord)]
 #[repr(usize)]
 pub enum SyscallNumbers {
@@ -11,13 +18,25 @@ pub enum SyscallNumbers {
     EnableFuture = 2,
     Sleep = 3,
     PollFuture = 4,
+    WaitAny = 5,
     WaitForInterrupt = 10,
     MoveBufferOut = 0x10,
     BorrowBufferOut = 0x11,
     BorrowMutBufferOut = 0x12,
     CopyBufferOut = 0x13,
+    AllocateQueue = 0x14,
+    FreeQueue = 0x15,
     MapBufferIn = 0x20,
     CopyBufferIn = 0x21,
+    CreateSharedRegion = 0x30,
+    MapSharedRegion = 0x31,
+    CloseSharedRegion = 0x32,
+    SendSharedRegion = 0x33,
+    ReceiveSharedRegion = 0x34,
+    GetRandom = 0x40,
+    ClockGetTime = 0x50,
+    PowerOff = 0x60,
+    Reboot = 0x61,
     #[default]
     Unknown,
 }
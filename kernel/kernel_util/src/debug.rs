@@ -1,3 +1,10 @@
+/// Offset of the line status register from the transmitter holding register
+const LSR: usize = 5;
+/// Line status bit that's set when the transmitter can take another byte
+const LSR_THR_EMPTY: u8 = 1 << 5;
+
+/// Writes to an ns16550a-compatible UART directly, waiting for the transmitter before every
+/// byte. This works before the UART driver is up, and when it can't be locked.
 pub struct Uart {
     address: *mut u8,
 }
@@ -5,7 +12,10 @@ pub struct Uart {
 impl core::fmt::Write for Uart {
     fn write_str(&mut self, s: &str) -> core::fmt::Result {
         for i in s.bytes() {
-            unsafe { self.address.write(i) }
+            unsafe {
+                while self.address.add(LSR).read_volatile() & LSR_THR_EMPTY == 0 {}
+                self.address.write_volatile(i)
+            }
         }
         Ok(())
     }
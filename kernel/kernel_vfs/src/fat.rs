@@ -0,0 +1,158 @@
+//! Lets a [`kernel_fat32::FileSystem`] be mounted in the VFS
+
+use alloc::{boxed::Box, format, string::String, sync::Arc, vec::Vec};
+use core::any::Any;
+
+use async_trait::async_trait;
+use kernel_io::{BlockDevice, Read, Seek, SeekFrom, Write};
+
+use crate::{DirEntry, File, FileSystem, FileType, Inode, Metadata, Result, VfsError};
+
+pub struct FatFileSystem<D: BlockDevice> {
+    fs: Arc<kernel_fat32::FileSystem<D>>,
+}
+
+impl<D: BlockDevice + Send + Sync + 'static> FatFileSystem<D> {
+    pub async fn mount(device: D) -> Result<Self> {
+        Ok(Self {
+            fs: Arc::new(kernel_fat32::FileSystem::mount(device).await?),
+        })
+    }
+}
+
+impl<D: BlockDevice + Send + Sync + 'static> FileSystem for FatFileSystem<D> {
+    fn root(&self) -> Arc<dyn Inode> {
+        Arc::new(FatInode {
+            fs: self.fs.clone(),
+            path: String::new(),
+        })
+    }
+}
+
+/// FAT doesn't have inodes, so files are identified by their path
+struct FatInode<D: BlockDevice> {
+    fs: Arc<kernel_fat32::FileSystem<D>>,
+    path: String,
+}
+
+impl<D: BlockDevice + Send + Sync + 'static> FatInode<D> {
+    fn child_path(&self, name: &str) -> String {
+        format!("{}/{}", self.path, name)
+    }
+
+    fn child(&self, name: &str) -> Arc<dyn Inode> {
+        Arc::new(FatInode {
+            fs: self.fs.clone(),
+            path: self.child_path(name),
+        })
+    }
+}
+
+fn file_type(entry: &kernel_fat32::DirEntry) -> FileType {
+    if entry.is_dir() {
+        FileType::Directory
+    } else {
+        FileType::File
+    }
+}
+
+#[async_trait]
+impl<D: BlockDevice + Send + Sync + 'static> Inode for FatInode<D> {
+    async fn stat(&self) -> Result<Metadata> {
+        let entry = self.fs.metadata(&self.path).await?;
+        Ok(Metadata {
+            file_type: file_type(&entry),
+            size: entry.size(),
+        })
+    }
+
+    async fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>> {
+        let path = self.child_path(name);
+        self.fs.metadata(&path).await?;
+        Ok(Arc::new(FatInode {
+            fs: self.fs.clone(),
+            path,
+        }))
+    }
+
+    async fn readdir(&self) -> Result<Vec<DirEntry>> {
+        Ok(self
+            .fs
+            .read_dir(&self.path)
+            .await?
+            .iter()
+            .map(|entry| DirEntry {
+                name: entry.name().into(),
+                file_type: file_type(entry),
+            })
+            .collect())
+    }
+
+    async fn open(&self) -> Result<Box<dyn File>> {
+        Ok(Box::new(FatFile(self.fs.open(&self.path).await?)))
+    }
+
+    async fn create(&self, name: &str, file_type: FileType) -> Result<Arc<dyn Inode>> {
+        let path = self.child_path(name);
+        match file_type {
+            FileType::File => {
+                // Unlike `FileSystem::create`, this doesn't truncate existing files
+                if self.fs.metadata(&path).await.is_ok() {
+                    return Err(VfsError::AlreadyExists);
+                }
+                self.fs.create(&path).await?;
+            }
+            FileType::Directory => self.fs.create_dir(&path).await?,
+        }
+        Ok(self.child(name))
+    }
+
+    async fn unlink(&self, name: &str) -> Result<()> {
+        Ok(self.fs.remove(&self.child_path(name)).await?)
+    }
+
+    async fn rename(&self, name: &str, new_parent: &dyn Inode, new_name: &str) -> Result<()> {
+        let new_parent = new_parent
+            .as_any()
+            .downcast_ref::<Self>()
+            .filter(|new_parent| Arc::ptr_eq(&new_parent.fs, &self.fs))
+            .ok_or(VfsError::CrossDevice)?;
+        Ok(self
+            .fs
+            .rename(&self.child_path(name), &new_parent.child_path(new_name))
+            .await?)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+struct FatFile<D: BlockDevice>(kernel_fat32::File<D>);
+
+#[async_trait]
+impl<D: BlockDevice + Send + Sync> Read for FatFile<D> {
+    type Error = VfsError;
+
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        Ok(self.0.read(buf).await?)
+    }
+}
+
+#[async_trait]
+impl<D: BlockDevice + Send + Sync> Write for FatFile<D> {
+    type Error = VfsError;
+
+    async fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        Ok(self.0.write(buf).await?)
+    }
+}
+
+#[async_trait]
+impl<D: BlockDevice + Send + Sync> Seek for FatFile<D> {
+    type Error = VfsError;
+
+    async fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
+        Ok(self.0.seek(pos).await?)
+    }
+}
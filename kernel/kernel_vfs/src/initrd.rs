@@ -0,0 +1,311 @@
+//! A read-only filesystem backed by an archive in memory, for the initial ramdisk.
+//! Both newc cpio (what `cpio -H newc` and the Linux kernel use) and ustar archives are
+//! supported. Only regular files and directories are kept; links, devices and the
+//! like are skipped.
+
+use alloc::{boxed::Box, collections::BTreeMap, string::String, sync::Arc, vec::Vec};
+use core::any::Any;
+
+use async_trait::async_trait;
+use kernel_io::{Read, Seek, SeekFrom, Write};
+
+use crate::{
+    path::components, DirEntry, File, FileSystem, FileType, Inode, Metadata, Result, VfsError,
+};
+
+const CPIO_HEADER_SIZE: usize = 110;
+const CPIO_TRAILER: &[u8] = b"TRAILER!!!";
+
+const TAR_BLOCK_SIZE: usize = 512;
+
+/// Mode bits for the file type, in cpio archives
+const S_IFMT: u32 = 0o170000;
+const S_IFDIR: u32 = 0o040000;
+const S_IFREG: u32 = 0o100000;
+
+/// The tree is built up mutably while parsing, then frozen into [`Node`]s
+enum Builder {
+    File(&'static [u8]),
+    Directory(BTreeMap<String, Builder>),
+}
+
+impl Builder {
+    /// Adds `path` to the tree, creating the directories that lead to it.
+    /// Later entries replace earlier ones with the same path.
+    fn insert(&mut self, path: &str, node: Builder) -> Result<()> {
+        let mut components: Vec<&str> = components(path).collect();
+        // Paths that go up can't be represented, and could escape the archive
+        if components.contains(&"..") {
+            return Ok(());
+        }
+        let name = match components.pop() {
+            Some(name) => name,
+            // The root itself (`.`)
+            None => return Ok(()),
+        };
+        let mut directory = self;
+        for component in components {
+            let children = match directory {
+                Builder::Directory(children) => children,
+                Builder::File(_) => return Err(VfsError::Io),
+            };
+            directory = children
+                .entry(component.into())
+                .or_insert_with(|| Builder::Directory(BTreeMap::new()));
+        }
+        let children = match directory {
+            Builder::Directory(children) => children,
+            Builder::File(_) => return Err(VfsError::Io),
+        };
+        match (children.get(name), &node) {
+            // Keep the contents of a directory that was created implicitly
+            (Some(Builder::Directory(_)), Builder::Directory(_)) => {}
+            _ => {
+                children.insert(name.into(), node);
+            }
+        }
+        Ok(())
+    }
+
+    fn freeze(self) -> Arc<Node> {
+        Arc::new(match self {
+            Builder::File(data) => Node::File(data),
+            Builder::Directory(children) => Node::Directory(
+                children
+                    .into_iter()
+                    .map(|(name, child)| (name, child.freeze()))
+                    .collect(),
+            ),
+        })
+    }
+}
+
+enum Node {
+    File(&'static [u8]),
+    Directory(BTreeMap<String, Arc<Node>>),
+}
+
+impl Node {
+    fn file_type(&self) -> FileType {
+        match self {
+            Node::File(_) => FileType::File,
+            Node::Directory(_) => FileType::Directory,
+        }
+    }
+}
+
+pub struct Initrd {
+    root: Arc<Node>,
+}
+
+impl Initrd {
+    /// Parses a newc cpio or ustar archive. The archive has to outlive the kernel,
+    /// since files point into it instead of being copied.
+    pub fn parse(data: &'static [u8]) -> Result<Self> {
+        let mut root = Builder::Directory(BTreeMap::new());
+        if data.starts_with(b"070701") || data.starts_with(b"070702") {
+            parse_cpio(data, &mut root)?;
+        } else if data.len() >= TAR_BLOCK_SIZE && &data[257..262] == b"ustar" {
+            parse_tar(data, &mut root)?;
+        } else {
+            return Err(VfsError::Unsupported);
+        }
+        Ok(Self {
+            root: root.freeze(),
+        })
+    }
+}
+
+impl FileSystem for Initrd {
+    fn root(&self) -> Arc<dyn Inode> {
+        Arc::new(InitrdInode(self.root.clone()))
+    }
+}
+
+fn parse_hex(field: &[u8]) -> Result<u32> {
+    let field = core::str::from_utf8(field).map_err(|_| VfsError::Io)?;
+    u32::from_str_radix(field, 16).map_err(|_| VfsError::Io)
+}
+
+/// Numbers in tar headers are octal, padded with spaces or NULs
+fn parse_octal(field: &[u8]) -> Result<usize> {
+    let field = core::str::from_utf8(field).map_err(|_| VfsError::Io)?;
+    let field = field.trim_matches(|c| c == ' ' || c == '\0');
+    if field.is_empty() {
+        return Ok(0);
+    }
+    usize::from_str_radix(field, 8).map_err(|_| VfsError::Io)
+}
+
+/// A NUL-terminated string in a fixed-size field
+fn parse_str(field: &[u8]) -> Result<&str> {
+    let end = field.iter().position(|c| *c == 0).unwrap_or(field.len());
+    core::str::from_utf8(&field[..end]).map_err(|_| VfsError::Io)
+}
+
+fn slice(data: &'static [u8], start: usize, len: usize) -> Result<&'static [u8]> {
+    data.get(start..start.checked_add(len).ok_or(VfsError::Io)?)
+        .ok_or(VfsError::Io)
+}
+
+fn parse_cpio(data: &'static [u8], root: &mut Builder) -> Result<()> {
+    let mut offset = 0;
+    loop {
+        let header = slice(data, offset, CPIO_HEADER_SIZE)?;
+        if &header[..5] != b"07070" {
+            return Err(VfsError::Io);
+        }
+        // Each field is 8 hex digits, after the 6-digit magic
+        let field = |index: usize| parse_hex(&header[6 + index * 8..6 + (index + 1) * 8]);
+        let mode = field(1)?;
+        let file_size = field(6)? as usize;
+        let name_size = field(11)? as usize;
+
+        // The name includes the terminating NUL
+        let name = slice(data, offset + CPIO_HEADER_SIZE, name_size)?;
+        let name = parse_str(name)?;
+        if name.as_bytes() == CPIO_TRAILER {
+            return Ok(());
+        }
+        // Both the header with the name and the file data are padded to 4 bytes
+        let data_start = (offset + CPIO_HEADER_SIZE + name_size + 3) & !3;
+        let contents = slice(data, data_start, file_size)?;
+        offset = (data_start + file_size + 3) & !3;
+
+        match mode & S_IFMT {
+            S_IFREG => root.insert(name, Builder::File(contents))?,
+            S_IFDIR => root.insert(name, Builder::Directory(BTreeMap::new()))?,
+            _ => {}
+        }
+    }
+}
+
+fn parse_tar(data: &'static [u8], root: &mut Builder) -> Result<()> {
+    let mut offset = 0;
+    loop {
+        // Some tools don't write the two zero blocks at the end
+        if offset == data.len() {
+            return Ok(());
+        }
+        let header = slice(data, offset, TAR_BLOCK_SIZE)?;
+        if header.iter().all(|byte| *byte == 0) {
+            return Ok(());
+        }
+        let name = parse_str(&header[0..100])?;
+        let size = parse_octal(&header[124..136])?;
+        let type_flag = header[156];
+        let prefix = parse_str(&header[345..500])?;
+
+        let contents = slice(data, offset + TAR_BLOCK_SIZE, size)?;
+        offset += TAR_BLOCK_SIZE + size.div_ceil(TAR_BLOCK_SIZE) * TAR_BLOCK_SIZE;
+
+        let path = if prefix.is_empty() {
+            String::from(name)
+        } else {
+            alloc::format!("{}/{}", prefix, name)
+        };
+        match type_flag {
+            b'0' | b'\0' | b'7' => root.insert(&path, Builder::File(contents))?,
+            b'5' => root.insert(&path, Builder::Directory(BTreeMap::new()))?,
+            _ => {}
+        }
+    }
+}
+
+struct InitrdInode(Arc<Node>);
+
+impl InitrdInode {
+    fn children(&self) -> Result<&BTreeMap<String, Arc<Node>>> {
+        match &*self.0 {
+            Node::Directory(children) => Ok(children),
+            Node::File(_) => Err(VfsError::NotADirectory),
+        }
+    }
+}
+
+#[async_trait]
+impl Inode for InitrdInode {
+    async fn stat(&self) -> Result<Metadata> {
+        Ok(Metadata {
+            file_type: self.0.file_type(),
+            size: match &*self.0 {
+                Node::File(data) => data.len() as u64,
+                Node::Directory(_) => 0,
+            },
+        })
+    }
+
+    async fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>> {
+        let child = self.children()?.get(name).ok_or(VfsError::NotFound)?;
+        Ok(Arc::new(InitrdInode(child.clone())))
+    }
+
+    async fn readdir(&self) -> Result<Vec<DirEntry>> {
+        Ok(self
+            .children()?
+            .iter()
+            .map(|(name, child)| DirEntry {
+                name: name.clone(),
+                file_type: child.file_type(),
+            })
+            .collect())
+    }
+
+    async fn open(&self) -> Result<Box<dyn File>> {
+        match &*self.0 {
+            Node::File(data) => Ok(Box::new(InitrdFile { data, position: 0 })),
+            Node::Directory(_) => Err(VfsError::IsADirectory),
+        }
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+struct InitrdFile {
+    data: &'static [u8],
+    position: usize,
+}
+
+#[async_trait]
+impl Read for InitrdFile {
+    type Error = VfsError;
+
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let rest = &self.data[self.position..];
+        let len = rest.len().min(buf.len());
+        buf[..len].copy_from_slice(&rest[..len]);
+        self.position += len;
+        Ok(len)
+    }
+}
+
+#[async_trait]
+impl Write for InitrdFile {
+    type Error = VfsError;
+
+    async fn write(&mut self, _buf: &[u8]) -> Result<usize> {
+        Err(VfsError::ReadOnly)
+    }
+}
+
+#[async_trait]
+impl Seek for InitrdFile {
+    type Error = VfsError;
+
+    async fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
+        let (base, offset) = match pos {
+            SeekFrom::Start(offset) => (0, offset as i64),
+            SeekFrom::End(offset) => (self.data.len() as i64, offset),
+            SeekFrom::Current(offset) => (self.position as i64, offset),
+        };
+        let position = base + offset;
+        // Like the FAT filesystem, this doesn't allow seeking past the end
+        if position < 0 || position > self.data.len() as i64 {
+            return Err(VfsError::InvalidSeek);
+        }
+        self.position = position as usize;
+        Ok(position as u64)
+    }
+}
@@ -0,0 +1,79 @@
+//! The virtual filesystem: a single namespace made of filesystems mounted at different paths.
+//! Filesystems implement [`FileSystem`] and [`Inode`], and open files are [`File`]s, which
+//! are defined in [`kernel_io`] so that processes can hold them without depending on the VFS.
+
+#![cfg_attr(not(test), no_std)]
+
+extern crate alloc;
+
+pub mod fat;
+pub mod initrd;
+pub mod mount;
+pub mod path;
+#[cfg(test)]
+mod tests;
+
+use alloc::{boxed::Box, string::String, sync::Arc, vec::Vec};
+use core::any::Any;
+
+use async_trait::async_trait;
+pub use kernel_io::file::{File, VfsError};
+pub use mount::Vfs;
+
+/// The namespace shared by the whole kernel
+pub static VFS: Vfs = Vfs::new();
+
+pub type Result<T> = core::result::Result<T, VfsError>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileType {
+    File,
+    Directory,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Metadata {
+    pub file_type: FileType,
+    /// Size in bytes
+    pub size: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirEntry {
+    pub name: String,
+    pub file_type: FileType,
+}
+
+pub trait FileSystem: Send + Sync {
+    fn root(&self) -> Arc<dyn Inode>;
+}
+
+/// A file or directory in a filesystem.
+/// Methods that take a `name` operate on the children of a directory.
+#[async_trait]
+pub trait Inode: Send + Sync {
+    async fn stat(&self) -> Result<Metadata>;
+
+    async fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>>;
+
+    async fn readdir(&self) -> Result<Vec<DirEntry>>;
+
+    async fn open(&self) -> Result<Box<dyn File>>;
+
+    async fn create(&self, _name: &str, _file_type: FileType) -> Result<Arc<dyn Inode>> {
+        Err(VfsError::ReadOnly)
+    }
+
+    /// Removes a file, or a directory if it's empty
+    async fn unlink(&self, _name: &str) -> Result<()> {
+        Err(VfsError::ReadOnly)
+    }
+
+    /// `new_parent` is in the same filesystem as `self`
+    async fn rename(&self, _name: &str, _new_parent: &dyn Inode, _new_name: &str) -> Result<()> {
+        Err(VfsError::ReadOnly)
+    }
+
+    /// Used by filesystems to get their own inode type back in `rename`
+    fn as_any(&self) -> &dyn Any;
+}
@@ -0,0 +1,153 @@
+//! The mount table, and operations on paths that go through it
+
+use alloc::{boxed::Box, string::String, sync::Arc, vec::Vec};
+
+use crate::{
+    path::{components, normalize, split_parent, strip_prefix},
+    DirEntry, File, FileSystem, FileType, Inode, Metadata, Result, VfsError,
+};
+
+struct Mount {
+    /// Normalized
+    path: String,
+    fs: Arc<dyn FileSystem>,
+}
+
+/// A mount table. Relative paths are taken relative to the root.
+pub struct Vfs {
+    mounts: spin::RwLock<Vec<Mount>>,
+}
+
+impl Vfs {
+    pub const fn new() -> Self {
+        Self {
+            mounts: spin::RwLock::new(Vec::new()),
+        }
+    }
+
+    /// Mounts `fs` at `path`. Anything other than the root has to be
+    /// mounted on an existing directory, which it hides.
+    pub async fn mount(&self, path: &str, fs: Arc<dyn FileSystem>) -> Result<()> {
+        let path = normalize("/", path);
+        if path != "/" && self.stat(&path).await?.file_type != FileType::Directory {
+            return Err(VfsError::NotADirectory);
+        }
+        let mut mounts = self.mounts.write();
+        if mounts.iter().any(|mount| mount.path == path) {
+            return Err(VfsError::Busy);
+        }
+        mounts.push(Mount { path, fs });
+        Ok(())
+    }
+
+    /// Fails if there's another filesystem mounted inside this one
+    pub fn unmount(&self, path: &str) -> Result<Arc<dyn FileSystem>> {
+        let path = normalize("/", path);
+        let mut mounts = self.mounts.write();
+        let index = mounts
+            .iter()
+            .position(|mount| mount.path == path)
+            .ok_or(VfsError::NotFound)?;
+        if mounts
+            .iter()
+            .any(|mount| mount.path != path && strip_prefix(&mount.path, &path).is_some())
+        {
+            return Err(VfsError::Busy);
+        }
+        Ok(mounts.remove(index).fs)
+    }
+
+    /// Paths where a filesystem is mounted
+    pub fn mount_points(&self) -> Vec<String> {
+        self.mounts
+            .read()
+            .iter()
+            .map(|mount| mount.path.clone())
+            .collect()
+    }
+
+    /// Returns the filesystem that has `path` in it, and the rest of the path inside it.
+    /// `path` has to be normalized.
+    fn find_mount<'a>(&self, path: &'a str) -> Result<(Arc<dyn FileSystem>, &'a str)> {
+        self.mounts
+            .read()
+            .iter()
+            .filter_map(|mount| Some((mount, strip_prefix(path, &mount.path)?)))
+            .max_by_key(|(mount, _)| mount.path.len())
+            .map(|(mount, rest)| (mount.fs.clone(), rest))
+            .ok_or(VfsError::NotFound)
+    }
+
+    fn is_mount_point(&self, path: &str) -> bool {
+        self.mounts.read().iter().any(|mount| mount.path == path)
+    }
+
+    async fn resolve_normalized(
+        &self,
+        path: &str,
+    ) -> Result<(Arc<dyn FileSystem>, Arc<dyn Inode>)> {
+        let (fs, rest) = self.find_mount(path)?;
+        let mut inode = fs.root();
+        for component in components(rest) {
+            inode = inode.lookup(component).await?;
+        }
+        Ok((fs, inode))
+    }
+
+    pub async fn resolve(&self, path: &str) -> Result<Arc<dyn Inode>> {
+        Ok(self.resolve_normalized(&normalize("/", path)).await?.1)
+    }
+
+    /// Returns the filesystem and directory that contain `path`, and the
+    /// name of `path` in that directory
+    async fn resolve_parent(
+        &self,
+        path: &str,
+    ) -> Result<(Arc<dyn FileSystem>, Arc<dyn Inode>, String)> {
+        let path = normalize("/", path);
+        if self.is_mount_point(&path) {
+            return Err(VfsError::Busy);
+        }
+        let (parent, name) = split_parent(&path).ok_or(VfsError::Busy)?;
+        let (fs, inode) = self.resolve_normalized(parent).await?;
+        Ok((fs, inode, name.into()))
+    }
+
+    pub async fn open(&self, path: &str) -> Result<Box<dyn File>> {
+        self.resolve(path).await?.open().await
+    }
+
+    pub async fn stat(&self, path: &str) -> Result<Metadata> {
+        self.resolve(path).await?.stat().await
+    }
+
+    pub async fn readdir(&self, path: &str) -> Result<Vec<DirEntry>> {
+        self.resolve(path).await?.readdir().await
+    }
+
+    pub async fn create(&self, path: &str, file_type: FileType) -> Result<Arc<dyn Inode>> {
+        let (_, parent, name) = self.resolve_parent(path).await?;
+        parent.create(&name, file_type).await
+    }
+
+    pub async fn unlink(&self, path: &str) -> Result<()> {
+        let (_, parent, name) = self.resolve_parent(path).await?;
+        parent.unlink(&name).await
+    }
+
+    /// Both paths have to be in the same filesystem
+    pub async fn rename(&self, from: &str, to: &str) -> Result<()> {
+        let (from_fs, from_parent, from_name) = self.resolve_parent(from).await?;
+        let (to_fs, to_parent, to_name) = self.resolve_parent(to).await?;
+        if Arc::as_ptr(&from_fs) as *const u8 != Arc::as_ptr(&to_fs) as *const u8 {
+            return Err(VfsError::CrossDevice);
+        }
+        from_parent.rename(&from_name, &*to_parent, &to_name).await
+    }
+}
+
+impl Default for Vfs {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,57 @@
+//! Paths are `/`-separated. The VFS only deals with absolute, normalized paths,
+//! and `..` is resolved lexically since there are no symbolic links.
+
+use alloc::{string::String, vec::Vec};
+
+/// The components of a path, skipping empty ones and `.`
+pub fn components(path: &str) -> impl Iterator<Item = &str> {
+    path.split('/')
+        .filter(|component| !component.is_empty() && *component != ".")
+}
+
+/// Turns `path` into an absolute path without `.`, `..` or repeated slashes.
+/// Relative paths start at `base`, which has to be absolute.
+pub fn normalize(base: &str, path: &str) -> String {
+    let mut stack: Vec<&str> = Vec::new();
+    let start = if path.starts_with('/') { "" } else { base };
+    for component in components(start).chain(components(path)) {
+        if component == ".." {
+            stack.pop();
+        } else {
+            stack.push(component);
+        }
+    }
+    let mut normalized = String::new();
+    for component in stack {
+        normalized.push('/');
+        normalized.push_str(component);
+    }
+    if normalized.is_empty() {
+        normalized.push('/');
+    }
+    normalized
+}
+
+/// Splits a normalized path into its parent and its last component.
+/// Returns None for the root.
+pub fn split_parent(path: &str) -> Option<(&str, &str)> {
+    let (parent, name) = path.rsplit_once('/')?;
+    if name.is_empty() {
+        return None;
+    }
+    Some((if parent.is_empty() { "/" } else { parent }, name))
+}
+
+/// If `path` is `prefix` or inside it, returns the rest of the path.
+/// Both have to be normalized.
+pub fn strip_prefix<'a>(path: &'a str, prefix: &str) -> Option<&'a str> {
+    if prefix == "/" {
+        return Some(path);
+    }
+    let rest = path.strip_prefix(prefix)?;
+    if rest.is_empty() || rest.starts_with('/') {
+        Some(rest)
+    } else {
+        None
+    }
+}
@@ -0,0 +1,376 @@
+use std::{
+    boxed::Box,
+    future::Future,
+    pin::Pin,
+    string::String,
+    sync::Arc,
+    task::{Context, Poll, RawWaker, RawWakerVTable, Waker},
+    vec,
+    vec::Vec,
+};
+
+use crate::{
+    initrd::Initrd,
+    path::{components, normalize, split_parent, strip_prefix},
+    FileSystem, FileType, Vfs, VfsError,
+};
+
+/// Runs a future to completion. Nothing here actually waits, so it can just be polled in a loop.
+fn block_on<F: Future>(future: F) -> F::Output {
+    fn noop_raw_waker() -> RawWaker {
+        fn clone(_: *const ()) -> RawWaker {
+            noop_raw_waker()
+        }
+        fn noop(_: *const ()) {}
+        static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, noop, noop, noop);
+        RawWaker::new(core::ptr::null(), &VTABLE)
+    }
+    let waker = unsafe { Waker::from_raw(noop_raw_waker()) };
+    let mut context = Context::from_waker(&waker);
+    let mut future = Box::pin(future);
+    loop {
+        if let Poll::Ready(output) = Pin::as_mut(&mut future).poll(&mut context) {
+            return output;
+        }
+    }
+}
+
+/// Makes a ustar archive. Entries without contents are directories.
+fn tar(entries: &[(&str, Option<&[u8]>)]) -> Vec<u8> {
+    let mut archive = Vec::new();
+    for (name, contents) in entries {
+        let mut header = [0u8; 512];
+        header[..name.len()].copy_from_slice(name.as_bytes());
+        let size = contents.map_or(0, <[u8]>::len);
+        header[100..108].copy_from_slice(b"0000644\0");
+        header[124..136].copy_from_slice(std::format!("{:011o}\0", size).as_bytes());
+        header[156] = if contents.is_some() { b'0' } else { b'5' };
+        header[257..263].copy_from_slice(b"ustar\0");
+        header[263..265].copy_from_slice(b"00");
+        // The checksum is calculated with the checksum field set to spaces
+        header[148..156].fill(b' ');
+        let checksum: u32 = header.iter().map(|byte| *byte as u32).sum();
+        header[148..156].copy_from_slice(std::format!("{:06o}\0 ", checksum).as_bytes());
+        archive.extend_from_slice(&header);
+        if let Some(contents) = contents {
+            archive.extend_from_slice(contents);
+            archive.resize(archive.len().next_multiple_of(512), 0);
+        }
+    }
+    archive.resize(archive.len() + 1024, 0);
+    archive
+}
+
+const S_IFDIR: u32 = 0o040000;
+const S_IFREG: u32 = 0o100000;
+const S_IFLNK: u32 = 0o120000;
+
+/// Makes a newc cpio archive out of `(name, mode, contents)` entries
+fn cpio(entries: &[(&str, u32, &[u8])], trailer: bool) -> Vec<u8> {
+    let mut archive = Vec::new();
+    let mut add = |name: &str, mode: u32, contents: &[u8]| {
+        let fields = [0, mode, 0, 0, 1, 0, contents.len() as u32, 0, 0, 0, 0];
+        archive.extend_from_slice(b"070701");
+        for field in fields {
+            archive.extend_from_slice(std::format!("{:08x}", field).as_bytes());
+        }
+        // The name size includes the NUL, and the checksum is 0
+        archive.extend_from_slice(std::format!("{:08x}{:08x}", name.len() + 1, 0).as_bytes());
+        archive.extend_from_slice(name.as_bytes());
+        archive.push(0);
+        archive.resize(archive.len().next_multiple_of(4), 0);
+        archive.extend_from_slice(contents);
+        archive.resize(archive.len().next_multiple_of(4), 0);
+    };
+    for (name, mode, contents) in entries {
+        add(name, *mode, contents);
+    }
+    if trailer {
+        add("TRAILER!!!", 0, b"");
+    }
+    archive
+}
+
+/// Initrds point into their archive, so it has to live forever
+fn parse(archive: Vec<u8>) -> crate::Result<Initrd> {
+    Initrd::parse(Box::leak(archive.into_boxed_slice()))
+}
+
+fn initrd(entries: &[(&str, Option<&[u8]>)]) -> Arc<dyn FileSystem> {
+    Arc::new(parse(tar(entries)).unwrap())
+}
+
+fn mounted(fs: Initrd) -> Vfs {
+    let vfs = Vfs::new();
+    block_on(vfs.mount("/", Arc::new(fs))).unwrap();
+    vfs
+}
+
+fn read(vfs: &Vfs, path: &str) -> Vec<u8> {
+    block_on(async {
+        let mut file = vfs.open(path).await.unwrap();
+        let mut contents = Vec::new();
+        file.read_to_end(&mut contents).await.unwrap();
+        contents
+    })
+}
+
+fn names(vfs: &Vfs, path: &str) -> Vec<String> {
+    block_on(vfs.readdir(path))
+        .unwrap()
+        .into_iter()
+        .map(|entry| entry.name)
+        .collect()
+}
+
+#[test]
+fn normalize_paths() {
+    assert_eq!(normalize("/", ""), "/");
+    assert_eq!(normalize("/", "a//b/./c/"), "/a/b/c");
+    assert_eq!(normalize("/a/b", "c"), "/a/b/c");
+    assert_eq!(normalize("/a/b", "../c"), "/a/c");
+    assert_eq!(normalize("/a/b", "/c"), "/c");
+    assert_eq!(normalize("/a/b", "."), "/a/b");
+    // There's nothing above the root
+    assert_eq!(normalize("/a", "../../.."), "/");
+    assert_eq!(normalize("/", "/../a/.."), "/");
+}
+
+#[test]
+fn path_components() {
+    assert_eq!(
+        components("/a/./b//c/").collect::<Vec<_>>(),
+        ["a", "b", "c"]
+    );
+    assert_eq!(components("../a").collect::<Vec<_>>(), ["..", "a"]);
+    assert_eq!(components("/").count(), 0);
+}
+
+#[test]
+fn split_parents() {
+    assert_eq!(split_parent("/a/b/c"), Some(("/a/b", "c")));
+    assert_eq!(split_parent("/a"), Some(("/", "a")));
+    assert_eq!(split_parent("/"), None);
+}
+
+#[test]
+fn strip_prefixes() {
+    assert_eq!(strip_prefix("/a/b", "/a"), Some("/b"));
+    assert_eq!(strip_prefix("/a", "/a"), Some(""));
+    assert_eq!(strip_prefix("/a/b", "/"), Some("/a/b"));
+    assert_eq!(strip_prefix("/", "/"), Some("/"));
+    // Only whole components match
+    assert_eq!(strip_prefix("/ab", "/a"), None);
+    assert_eq!(strip_prefix("/a", "/a/b"), None);
+}
+
+#[test]
+fn nothing_is_mounted() {
+    let vfs = Vfs::new();
+    assert_eq!(block_on(vfs.stat("/")), Err(VfsError::NotFound));
+}
+
+#[test]
+fn resolve_through_mounts() {
+    let vfs = Vfs::new();
+    let root = initrd(&[
+        ("etc", None),
+        ("etc/hostname", Some(b"root\n")),
+        ("mnt", None),
+    ]);
+    block_on(vfs.mount("/", root)).unwrap();
+    assert_eq!(read(&vfs, "/etc/hostname"), b"root\n");
+    assert_eq!(read(&vfs, "etc/../etc/./hostname"), b"root\n");
+    assert_eq!(names(&vfs, "/"), ["etc", "mnt"]);
+
+    // The innermost filesystem wins, and hides what was there
+    let etc = initrd(&[("passwd", Some(b"user\n"))]);
+    block_on(vfs.mount("/etc/", etc)).unwrap();
+    assert_eq!(read(&vfs, "/etc/passwd"), b"user\n");
+    assert_eq!(names(&vfs, "/etc"), ["passwd"]);
+    assert_eq!(block_on(vfs.stat("/etc/hostname")), Err(VfsError::NotFound));
+    assert_eq!(vfs.mount_points(), ["/", "/etc"]);
+
+    let stat = block_on(vfs.stat("/etc/passwd")).unwrap();
+    assert_eq!(stat.file_type, FileType::File);
+    assert_eq!(stat.size, 5);
+    assert!(matches!(
+        block_on(vfs.open("/etc")),
+        Err(VfsError::IsADirectory)
+    ));
+
+    // Unmounting shows it again
+    vfs.unmount("/etc").unwrap();
+    assert_eq!(read(&vfs, "/etc/hostname"), b"root\n");
+}
+
+#[test]
+fn mount_points_are_checked() {
+    let vfs = Vfs::new();
+    let root = initrd(&[("file", Some(b"")), ("dir", None)]);
+    block_on(vfs.mount("/", root.clone())).unwrap();
+    assert_eq!(
+        block_on(vfs.mount("/file", root.clone())),
+        Err(VfsError::NotADirectory)
+    );
+    assert_eq!(
+        block_on(vfs.mount("/missing", root.clone())),
+        Err(VfsError::NotFound)
+    );
+    assert_eq!(block_on(vfs.mount("/", root.clone())), Err(VfsError::Busy));
+
+    block_on(vfs.mount("/dir", root.clone())).unwrap();
+    assert_eq!(block_on(vfs.mount("/dir/", root)), Err(VfsError::Busy));
+    // The root has another filesystem inside it
+    assert!(matches!(vfs.unmount("/"), Err(VfsError::Busy)));
+    assert!(matches!(vfs.unmount("/file"), Err(VfsError::NotFound)));
+    // A mount point can't be removed or replaced
+    assert_eq!(block_on(vfs.unlink("/dir")), Err(VfsError::Busy));
+    assert!(matches!(
+        block_on(vfs.create("/dir", FileType::Directory)),
+        Err(VfsError::Busy)
+    ));
+    assert_eq!(block_on(vfs.rename("/file", "/dir")), Err(VfsError::Busy));
+    assert!(vfs.unmount("/dir").is_ok());
+    assert!(vfs.unmount("/").is_ok());
+    assert!(vfs.mount_points().is_empty());
+}
+
+#[test]
+fn operations_go_to_the_right_filesystem() {
+    let vfs = Vfs::new();
+    block_on(vfs.mount("/", initrd(&[("a", None), ("b", None)]))).unwrap();
+    block_on(vfs.mount("/a", initrd(&[("file", Some(b"a"))]))).unwrap();
+    block_on(vfs.mount("/b", initrd(&[("file", Some(b"b"))]))).unwrap();
+    assert_eq!(read(&vfs, "/a/file"), b"a");
+    assert_eq!(read(&vfs, "/b/file"), b"b");
+    assert_eq!(read(&vfs, "/a/../b/file"), b"b");
+    assert_eq!(
+        block_on(vfs.rename("/a/file", "/b/moved")),
+        Err(VfsError::CrossDevice)
+    );
+    // Initrds are read-only
+    assert_eq!(
+        block_on(vfs.rename("/a/file", "/a/moved")),
+        Err(VfsError::ReadOnly)
+    );
+    assert_eq!(block_on(vfs.unlink("/a/file")), Err(VfsError::ReadOnly));
+    assert!(matches!(
+        block_on(vfs.create("/a/new", FileType::File)),
+        Err(VfsError::ReadOnly)
+    ));
+    assert_eq!(names(&vfs, "/a"), vec![String::from("file")]);
+}
+
+#[test]
+fn cpio_archive() {
+    let archive = cpio(
+        &[
+            (".", S_IFDIR | 0o755, b""),
+            ("bin", S_IFDIR | 0o755, b""),
+            ("bin/init", S_IFREG | 0o755, b"\x7fELF"),
+            // The directories leading to it are created
+            ("etc/motd", S_IFREG | 0o644, b"hello\n"),
+            ("etc/link", S_IFLNK | 0o777, b"motd"),
+            ("./empty", S_IFREG | 0o644, b""),
+        ],
+        true,
+    );
+    let vfs = mounted(parse(archive).unwrap());
+    assert_eq!(names(&vfs, "/"), ["bin", "empty", "etc"]);
+    // Symbolic links are skipped
+    assert_eq!(names(&vfs, "/etc"), ["motd"]);
+    assert_eq!(read(&vfs, "/bin/init"), b"\x7fELF");
+    assert_eq!(read(&vfs, "/etc/motd"), b"hello\n");
+    assert_eq!(read(&vfs, "/empty"), b"");
+    assert_eq!(
+        block_on(vfs.stat("/etc")).unwrap().file_type,
+        FileType::Directory
+    );
+}
+
+#[test]
+fn ustar_archive() {
+    let long_directory = "a".repeat(120);
+    let mut archive = tar(&[
+        ("dir/", None),
+        ("dir/file", Some(b"contents")),
+        ("spans a block", Some(&[7; 600])),
+    ]);
+    // A name that only fits with the prefix field
+    let mut header = [0u8; 512];
+    header[..4].copy_from_slice(b"long");
+    header[124..136].copy_from_slice(b"00000000002\0");
+    header[156] = b'0';
+    header[257..263].copy_from_slice(b"ustar\0");
+    header[345..345 + long_directory.len()].copy_from_slice(long_directory.as_bytes());
+    let end = archive.len() - 1024;
+    let mut entry = header.to_vec();
+    entry.extend_from_slice(b"hi");
+    entry.resize(1024, 0);
+    archive.splice(end..end, entry);
+
+    let vfs = mounted(parse(archive).unwrap());
+    assert_eq!(
+        names(&vfs, "/"),
+        [long_directory.as_str(), "dir", "spans a block"]
+    );
+    assert_eq!(read(&vfs, "/dir/file"), b"contents");
+    assert_eq!(read(&vfs, "/spans a block"), [7; 600]);
+    assert_eq!(read(&vfs, &std::format!("/{}/long", long_directory)), b"hi");
+}
+
+#[test]
+fn paths_that_go_up_are_skipped() {
+    let archive = cpio(
+        &[
+            ("../outside", S_IFREG | 0o644, b"1"),
+            ("dir/../../outside", S_IFREG | 0o644, b"2"),
+            ("dir/../inside", S_IFREG | 0o644, b"3"),
+            ("dir/..", S_IFDIR | 0o755, b""),
+            ("ok", S_IFREG | 0o644, b"4"),
+        ],
+        true,
+    );
+    let vfs = mounted(parse(archive).unwrap());
+    assert_eq!(names(&vfs, "/"), ["ok"]);
+
+    let vfs = mounted(parse(tar(&[("../outside", Some(b"1")), ("ok", Some(b"2"))])).unwrap());
+    assert_eq!(names(&vfs, "/"), ["ok"]);
+}
+
+#[test]
+fn truncated_archives_are_errors() {
+    let archive = cpio(&[("file", S_IFREG | 0o644, b"contents")], true);
+    // Nothing comes after the trailer's name, so its padding doesn't matter
+    for len in 1..archive.len() - 3 {
+        assert!(parse(archive[..len].to_vec()).is_err(), "{} bytes", len);
+    }
+
+    let archive = tar(&[("dir", None), ("file", Some(&[1; 1000]))]);
+    // Archives can end after any entry, without the zero blocks
+    let entries_end = archive.len() - 1024;
+    for len in (1..entries_end).filter(|len| *len != 512) {
+        assert!(parse(archive[..len].to_vec()).is_err(), "{} bytes", len);
+    }
+    let vfs = mounted(parse(archive[..512].to_vec()).unwrap());
+    assert_eq!(names(&vfs, "/"), ["dir"]);
+    let vfs = mounted(parse(archive[..entries_end].to_vec()).unwrap());
+    assert_eq!(names(&vfs, "/"), ["dir", "file"]);
+}
+
+#[test]
+fn cpio_needs_a_trailer() {
+    let archive = cpio(&[("file", S_IFREG | 0o644, b"contents")], false);
+    assert_eq!(parse(archive).err(), Some(VfsError::Io));
+}
+
+#[test]
+fn unknown_formats_are_unsupported() {
+    assert_eq!(parse(vec![0; 1024]).err(), Some(VfsError::Unsupported));
+    assert_eq!(parse(b"hello".to_vec()).err(), Some(VfsError::Unsupported));
+    let mut archive = cpio(&[("file", S_IFREG, b"")], true);
+    // The mode isn't a hex number
+    archive[6 + 8] = b'x';
+    assert_eq!(parse(archive).err(), Some(VfsError::Io));
+}
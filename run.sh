@@ -24,11 +24,19 @@ if [ "$INT" == "yes" ]; then
 fi
 
 if [ "$GFX" == "yes" ]; then 
-	export QEMUOPTS="-device virtio-gpu-device $QEMUOPTS"
+	export QEMUOPTS="-device virtio-gpu-device -device virtio-keyboard-device $QEMUOPTS"
 else
 	export QEMUOPTS="-nographic $QEMUOPTS"
 fi
 
+# QEMU network backend for the NIC, whose id has to be net0
+if [ -z "$NETDEV" ]; then
+	export NETDEV="user,id=net0"
+fi
+
+if [ -n "$INITRD" ]; then
+	export QEMUOPTS="-initrd $INITRD $QEMUOPTS"
+fi
 
 du -h kernel_payload.bin
 
@@ -43,5 +51,10 @@ qemu-system-riscv$BITS $QEMUOPTS \
 	-device virtio-blk-device,drive=hda \
 	-smp 1 \
 	-m 128M \
-	-device ne2k_pci \
-	-kernel $3
+	-netdev $NETDEV \
+	-device ne2k_pci,netdev=net0 \
+	-netdev user,id=net1 \
+	-device virtio-net-device,netdev=net1 \
+	-device virtio-rng-device \
+	-kernel $3 \
+	${APPEND:+-append "$APPEND"}
@@ -0,0 +1,19 @@
+#!/bin/bash
+
+# Builds the kernel with its tests and runs them in QEMU, which exits with status 0 if they
+# all passed. rustc only collects `#[test_case]`s when it's passed `--test`.
+
+set -ex
+cd `dirname $0`
+
+cd kernel/kernel_main
+cargo rustc --features test -- --test
+cd ../..
+
+./link.sh 64 riscv64gc-unknown-none-elf
+
+cd kernel/kernel_bootloader
+cargo build
+cd ../..
+
+./run.sh 64 riscv64gc-unknown-none-elf kernel/target/riscv64gc-unknown-none-elf/debug/kernel_bootloader